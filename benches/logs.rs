@@ -0,0 +1,121 @@
+//! Ingestion and response format benchmarks on a corpus built from the test fixture,
+//! which covers messages, replies, user notices and moderation actions. Run with `cargo bench`
+
+use axum::body::to_bytes;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use rustlog::{
+    db::schema::{StructuredMessage, UnstructuredMessage},
+    logs::{
+        extract::{extract_channel_and_user_from_raw, extract_raw_timestamp},
+        stream::LogsStream,
+    },
+    web::{responders::logs::FORMATTERS, schema::FormatOptions},
+};
+use tmi::IrcMessageRef;
+use tokio::runtime::Runtime;
+
+const FIXTURE: &str = include_str!("../tests/fixtures/forsen.irc");
+/// About an hour of chat in a busy channel
+const CORPUS_SIZE: usize = 10_000;
+
+struct RawLine {
+    channel_id: String,
+    user_id: String,
+    timestamp: u64,
+    raw: String,
+}
+
+fn raw_corpus() -> Vec<RawLine> {
+    FIXTURE
+        .lines()
+        .cycle()
+        .take(CORPUS_SIZE)
+        .map(|line| {
+            let irc_message = IrcMessageRef::parse(line).unwrap();
+            let (channel_id, user_id) = extract_channel_and_user_from_raw(&irc_message).unwrap();
+            RawLine {
+                channel_id: channel_id.to_owned(),
+                user_id: user_id.unwrap_or_default().to_owned(),
+                timestamp: extract_raw_timestamp(&irc_message).unwrap(),
+                raw: line.to_owned(),
+            }
+        })
+        .collect()
+}
+
+fn structured_corpus(lines: &[RawLine]) -> Vec<StructuredMessage<'static>> {
+    lines
+        .iter()
+        .map(|line| {
+            let unstructured = UnstructuredMessage {
+                channel_id: &line.channel_id,
+                user_id: &line.user_id,
+                timestamp: line.timestamp,
+                raw: &line.raw,
+            };
+            StructuredMessage::from_unstructured(&unstructured)
+                .unwrap()
+                .into_owned()
+        })
+        .collect()
+}
+
+fn ingestion(c: &mut Criterion) {
+    let lines = raw_corpus();
+    let messages = structured_corpus(&lines);
+
+    let mut group = c.benchmark_group("ingestion");
+    group.throughput(Throughput::Elements(CORPUS_SIZE as u64));
+
+    group.bench_function("from_unstructured", |b| {
+        b.iter(|| {
+            for line in &lines {
+                let unstructured = UnstructuredMessage {
+                    channel_id: &line.channel_id,
+                    user_id: &line.user_id,
+                    timestamp: line.timestamp,
+                    raw: &line.raw,
+                };
+                black_box(StructuredMessage::from_unstructured(&unstructured).unwrap());
+            }
+        })
+    });
+
+    group.bench_function("all_tags", |b| {
+        b.iter(|| {
+            for msg in &messages {
+                black_box(msg.all_tags(true));
+            }
+        })
+    });
+
+    group.finish();
+}
+
+fn formatting(c: &mut Criterion) {
+    let messages = structured_corpus(&raw_corpus());
+    let runtime = Runtime::new().unwrap();
+
+    let mut group = c.benchmark_group("formatting");
+    group.throughput(Throughput::Elements(CORPUS_SIZE as u64));
+
+    for formatter in FORMATTERS {
+        group.bench_function(formatter.name(), |b| {
+            b.to_async(&runtime).iter_batched(
+                || LogsStream::new_provided(messages.clone()).unwrap(),
+                |stream| async move {
+                    let body = formatter
+                        .body(stream, FormatOptions::default(), None)
+                        .unwrap();
+                    to_bytes(body, usize::MAX).await.unwrap()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, ingestion, formatting);
+criterion_main!(benches);
@@ -0,0 +1,240 @@
+use crate::{
+    app::App,
+    db::alerts::{read_saved_searches, read_search_matches, write_alert_matches, SavedSearchRow},
+    ids::{ChannelId, UserId},
+    web::schema::{AlertMatch, SavedSearch},
+    ShutdownRx,
+};
+use anyhow::{anyhow, bail, Context};
+use chrono::{DateTime, Utc};
+use reqwest::{header::CONTENT_TYPE, redirect};
+use serde::Serialize;
+use std::{
+    collections::HashMap,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+use tokio::{net::lookup_host, time::sleep};
+use tracing::{debug, error, info};
+use url::{Host, Url};
+use uuid::Uuid;
+
+#[derive(Serialize)]
+struct AlertWebhookBody {
+    search: SavedSearch,
+    /// Oldest first
+    matches: Vec<AlertMatch>,
+}
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let interval = Duration::from_secs(app.config.alerts.interval);
+
+    // Messages are written in batches, so recent messages may not be stored yet
+    let write_delay = chrono::Duration::seconds(app.config.clickhouse_flush_interval as i64 * 2);
+    let started_at = Utc::now() - write_delay;
+    let mut checked_until = HashMap::new();
+
+    loop {
+        tokio::select! {
+            _ = sleep(interval) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down alert evaluator");
+                break;
+            }
+        }
+
+        let to = Utc::now() - write_delay;
+        evaluate_searches(&app, &mut checked_until, started_at, to).await;
+    }
+}
+
+/// Runs every saved search against the messages sent since it was last evaluated.
+/// A failing search is retried from the same point in the next run, without holding back the others
+async fn evaluate_searches(
+    app: &App,
+    checked_until: &mut HashMap<Uuid, DateTime<Utc>>,
+    started_at: DateTime<Utc>,
+    to: DateTime<Utc>,
+) {
+    let searches = match read_saved_searches(&app.db, None).await {
+        Ok(searches) => searches,
+        Err(err) => {
+            error!("Could not read saved searches: {err:#}");
+            return;
+        }
+    };
+    checked_until.retain(|id, _| searches.iter().any(|search| search.id == *id));
+
+    for search in searches {
+        // Searches only match messages sent after they were created
+        let created_at = DateTime::from_timestamp(search.created_at.into(), 0).unwrap_or_default();
+        let from = checked_until
+            .get(&search.id)
+            .copied()
+            .unwrap_or(started_at)
+            .max(created_at);
+        let channel_id = ChannelId::from(search.channel_id.as_str());
+        let user_id = (!search.user_id.is_empty()).then(|| UserId::from(search.user_id.as_str()));
+        if from >= to || app.is_opted_out(&channel_id, user_id.as_ref()) {
+            continue;
+        }
+
+        let id = search.id;
+        match evaluate_search(app, search, from, to).await {
+            Ok(()) => {
+                checked_until.insert(id, to);
+            }
+            Err(err) => error!("Could not evaluate saved search {id}: {err:#}"),
+        }
+    }
+}
+
+async fn evaluate_search(
+    app: &App,
+    search: SavedSearchRow,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    let matches = read_search_matches(&app.db, &search, from, to).await?;
+    if matches.is_empty() {
+        return Ok(());
+    }
+
+    write_alert_matches(&app.db, &matches).await?;
+    info!(
+        "Saved search {} of {} matched {} messages",
+        search.id,
+        search.owner,
+        matches.len()
+    );
+
+    // The matches are stored, so a failed delivery is not retried
+    if !search.webhook_url.is_empty() {
+        let id = search.id;
+        let matches = matches.into_iter().map(AlertMatch::from).collect();
+        if let Err(err) = deliver_matches(search, matches).await {
+            error!("Could not deliver matches of saved search {id}: {err:#}");
+        }
+    }
+
+    Ok(())
+}
+
+async fn deliver_matches(search: SavedSearchRow, matches: Vec<AlertMatch>) -> anyhow::Result<()> {
+    let url = Url::parse(&search.webhook_url).context("Invalid webhook url")?;
+    // The request goes to the checked address, so the host can not resolve to another one in between
+    let addr = resolve_webhook(&url).await?;
+    let mut builder = reqwest::Client::builder().redirect(redirect::Policy::none());
+    if let Some(Host::Domain(domain)) = url.host() {
+        builder = builder.resolve(domain, addr);
+    }
+    let http_client = builder.build()?;
+
+    let body = AlertWebhookBody {
+        search: search.into(),
+        matches,
+    };
+
+    let response = http_client
+        .post(url)
+        .header(CONTENT_TYPE, "application/json")
+        .body(serde_json::to_vec(&body)?)
+        .send()
+        .await
+        .context("Could not send request")?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Webhook responded with status {status}"))
+    }
+}
+
+/// Resolves the host of a webhook url. Webhooks are called from inside the network, so hosts with loopback,
+/// private or link-local addresses are rejected
+pub async fn resolve_webhook(url: &Url) -> anyhow::Result<SocketAddr> {
+    let port = url
+        .port_or_known_default()
+        .context("Webhook url has no port")?;
+    let addrs: Vec<SocketAddr> = match url.host() {
+        Some(Host::Domain(domain)) => lookup_host((domain, port))
+            .await
+            .context("Could not resolve webhook host")?
+            .collect(),
+        Some(Host::Ipv4(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        Some(Host::Ipv6(ip)) => vec![SocketAddr::new(ip.into(), port)],
+        None => bail!("Webhook url has no host"),
+    };
+
+    if let Some(addr) = addrs.iter().find(|addr| !is_public_address(addr.ip())) {
+        bail!(
+            "Webhook host resolves to the non-public address {}",
+            addr.ip()
+        );
+    }
+    addrs
+        .into_iter()
+        .next()
+        .context("Webhook host has no addresses")
+}
+
+fn is_public_address(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [first, second, ..] = ip.octets();
+            // 100.64.0.0/10 is used for carrier-grade NAT
+            let shared = first == 100 && second & 0xc0 == 64;
+            !(ip.is_private()
+                || ip.is_loopback()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || shared)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public_address(ip.into()),
+            None => {
+                let first = ip.segments()[0];
+                // fc00::/7 are unique local and fe80::/10 link-local addresses
+                let unique_local = first & 0xfe00 == 0xfc00;
+                let link_local = first & 0xffc0 == 0xfe80;
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || unique_local
+                    || link_local)
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::is_public_address;
+
+    #[test]
+    fn rejects_internal_addresses() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "::ffff:127.0.0.1",
+        ] {
+            assert!(!is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+
+        for ip in ["1.1.1.1", "2606:4700:4700::1111"] {
+            assert!(is_public_address(ip.parse().unwrap()), "{ip}");
+        }
+    }
+}
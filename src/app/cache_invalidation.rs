@@ -0,0 +1,109 @@
+//! Propagates purges, opt-outs and the retention to the logs cache, which would otherwise keep serving the
+//! deleted messages of complete days
+
+use super::{logs_cache::LogsCache, App};
+use crate::{
+    db::{
+        purge::{
+            read_purge_ranges, read_users_message_ranges, wait_for_mutations, ChannelMessageRange,
+            PurgeScope,
+        },
+        retention::{read_expired_ranges, RetentionScope},
+    },
+    Result,
+};
+use chrono::{DateTime, Utc};
+use std::time::Duration;
+use tokio::time::timeout;
+use tracing::{error, info, warn};
+
+/// Mutations which take longer are assumed to be stuck
+const MUTATION_WAIT_LIMIT_SECONDS: u64 = 6 * 3600;
+
+/// Where the user's messages in the scope may be cached. Has to be read before they are deleted
+pub async fn read_cached_purge_ranges(
+    app: &App,
+    scope: &PurgeScope,
+) -> Result<Vec<ChannelMessageRange>> {
+    if app.logs_cache.is_none() {
+        return Ok(Vec::new());
+    }
+    read_purge_ranges(&app.db.primary(), scope).await
+}
+
+/// Like [`read_cached_purge_ranges`], for all messages of the users
+pub async fn read_cached_user_ranges(
+    app: &App,
+    user_ids: &[String],
+) -> Result<Vec<ChannelMessageRange>> {
+    if app.logs_cache.is_none() {
+        return Ok(Vec::new());
+    }
+    read_users_message_ranges(&app.db.primary(), user_ids).await
+}
+
+/// Like [`read_cached_purge_ranges`], for the messages expired by the retention
+pub async fn read_cached_expired_ranges(
+    app: &App,
+    scope: RetentionScope<'_>,
+    held_users: &[String],
+    before: DateTime<Utc>,
+) -> Result<Vec<ChannelMessageRange>> {
+    if app.logs_cache.is_none() {
+        return Ok(Vec::new());
+    }
+    read_expired_ranges(&app.db.primary(), scope, held_users, before).await
+}
+
+/// Invalidates the cached days of the ranges in the background. If the messages are deleted by a mutation,
+/// the days are invalidated again once it is done, as they could have been cached again in the meantime
+pub fn invalidate_cached_logs(app: &App, ranges: Vec<ChannelMessageRange>, after_mutations: bool) {
+    let Some(cache) = app.logs_cache.clone() else {
+        return;
+    };
+    if ranges.is_empty() {
+        return;
+    }
+
+    let app = app.clone();
+    tokio::spawn(async move {
+        invalidate_ranges(&app, &cache, &ranges).await;
+        if !after_mutations {
+            return;
+        }
+
+        let wait_limit = Duration::from_secs(MUTATION_WAIT_LIMIT_SECONDS);
+        match timeout(wait_limit, wait_for_mutations(&app.db.primary())).await {
+            Ok(Ok(())) => invalidate_ranges(&app, &cache, &ranges).await,
+            Ok(Err(err)) => error!("Could not wait for the purge mutations: {err}"),
+            Err(_) => warn!(
+                "Purge mutations are still running after {MUTATION_WAIT_LIMIT_SECONDS}s, the logs cache is not invalidated again"
+            ),
+        }
+    });
+}
+
+async fn invalidate_ranges(app: &App, cache: &LogsCache, ranges: &[ChannelMessageRange]) {
+    let mut invalidated = 0;
+    for range in ranges {
+        let (Some(first), Some(last)) = (
+            DateTime::from_timestamp_millis(range.first as i64),
+            DateTime::from_timestamp_millis(range.last as i64),
+        ) else {
+            continue;
+        };
+
+        // Days are cached in the channel's timezone
+        let timezone = app.config.channel_timezone(&range.channel_id);
+        let from = first.with_timezone(&timezone).date_naive();
+        let to = last.with_timezone(&timezone).date_naive();
+        invalidated += cache.invalidate(&range.channel_id, from, to).await;
+    }
+
+    if invalidated > 0 {
+        info!(
+            "Invalidated {invalidated} cached day logs in {} channels",
+            ranges.len()
+        );
+    }
+}
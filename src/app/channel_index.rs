@@ -0,0 +1,182 @@
+use super::{helix_queue::HelixPriority, App};
+use crate::{db::read_logged_channel_ids, web::schema::Channel, Result, ShutdownRx};
+use std::{
+    collections::HashSet,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
+use tokio::time::sleep;
+use tracing::{debug, error};
+
+const REFRESH_INTERVAL_SECONDS: u64 = 600;
+
+/// Logins and IDs of every logged channel, searched by the channel picker
+#[derive(Default)]
+pub struct ChannelIndex {
+    channels: RwLock<Arc<Vec<Channel>>>,
+}
+
+/// Lower is a better match
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum MatchRank {
+    Exact,
+    Prefix,
+    Substring,
+    IdPrefix,
+    Subsequence,
+}
+
+impl ChannelIndex {
+    pub fn replace(&self, mut channels: Vec<Channel>) {
+        channels.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+        *self.channels.write().unwrap() = Arc::new(channels);
+    }
+
+    pub fn contains_id(&self, channel_id: &str) -> bool {
+        self.channels
+            .read()
+            .unwrap()
+            .iter()
+            .any(|channel| channel.user_id == channel_id)
+    }
+
+    /// Channels matching the query, best matches first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<Channel> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return Vec::new();
+        }
+
+        let channels = self.channels.read().unwrap().clone();
+        let mut matches: Vec<_> = channels
+            .iter()
+            .filter_map(|channel| {
+                match_channel(channel, &query).map(|(rank, score)| (rank, score, channel))
+            })
+            .collect();
+        matches.sort_unstable_by(|a, b| (&a.0, a.1, &a.2.name).cmp(&(&b.0, b.1, &b.2.name)));
+
+        matches
+            .into_iter()
+            .take(limit)
+            .map(|(_, _, channel)| channel.clone())
+            .collect()
+    }
+}
+
+/// Ranks how well the channel matches, with a score to order matches of the same rank
+fn match_channel(channel: &Channel, query: &str) -> Option<(MatchRank, usize)> {
+    let name = channel.name.as_str();
+
+    if name == query || channel.user_id == query {
+        Some((MatchRank::Exact, 0))
+    } else if name.starts_with(query) {
+        Some((MatchRank::Prefix, name.len()))
+    } else if let Some(position) = name.find(query) {
+        Some((MatchRank::Substring, position))
+    } else if channel.user_id.starts_with(query) {
+        Some((MatchRank::IdPrefix, channel.user_id.len()))
+    } else {
+        subsequence_span(name, query).map(|span| (MatchRank::Subsequence, span))
+    }
+}
+
+/// Length of the shortest prefix of `name` containing all characters of the query in order
+fn subsequence_span(name: &str, query: &str) -> Option<usize> {
+    let mut query_chars = query.chars().peekable();
+
+    for (i, c) in name.char_indices() {
+        if query_chars.next_if_eq(&c).is_some() && query_chars.peek().is_none() {
+            return Some(i + c.len_utf8());
+        }
+    }
+    None
+}
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    loop {
+        if let Err(err) = HelixPriority::Background.scope(refresh(&app)).await {
+            error!("Could not refresh channel index: {err}");
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(REFRESH_INTERVAL_SECONDS)) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down channel index refresh");
+                break;
+            }
+        }
+    }
+}
+
+/// Indexes the joined channels and every channel which has logs, except opted out ones
+async fn refresh(app: &App) -> Result<()> {
+    let mut channel_ids: HashSet<String> = app.config.channels.read().unwrap().clone();
+    channel_ids.extend(read_logged_channel_ids(&app.db).await?);
+    channel_ids.retain(|channel_id| !app.has_opted_out(channel_id));
+
+    let archived_ids = app.config.archived_channels.read().unwrap().clone();
+    let channels: Vec<Channel> = app
+        .get_channel_logins(channel_ids.into_iter().collect())
+        .await?
+        .into_iter()
+        .map(|(user_id, name)| Channel {
+            archived: archived_ids.contains(&user_id),
+            name,
+            user_id,
+        })
+        .collect();
+
+    debug!("Indexed {} channels", channels.len());
+    app.channel_index.replace(channels);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ChannelIndex;
+    use crate::web::schema::Channel;
+    use pretty_assertions::assert_eq;
+
+    fn channel(name: &str, user_id: &str) -> Channel {
+        Channel {
+            name: name.to_owned(),
+            user_id: user_id.to_owned(),
+            archived: false,
+        }
+    }
+
+    #[test]
+    fn ranks_matches() {
+        let index = ChannelIndex::default();
+        index.replace(vec![
+            channel("forsen", "22484632"),
+            channel("forsenlol", "1"),
+            channel("notforsen", "2"),
+            channel("f_o_r_s_e_n", "3"),
+            channel("xqc", "71092938"),
+            channel("pajlada", "11148817"),
+        ]);
+
+        let names: Vec<String> = index
+            .search("Forsen", 10)
+            .into_iter()
+            .map(|channel| channel.name)
+            .collect();
+        assert_eq!(
+            vec!["forsen", "forsenlol", "notforsen", "f_o_r_s_e_n"],
+            names
+        );
+
+        let names: Vec<String> = index
+            .search("7109", 10)
+            .into_iter()
+            .map(|channel| channel.name)
+            .collect();
+        assert_eq!(vec!["xqc"], names);
+
+        assert!(index.search("  ", 10).is_empty());
+        assert_eq!(1, index.search("forsen", 1).len());
+    }
+}
@@ -0,0 +1,267 @@
+//! Shared rate limiting of the Helix requests. Twitch reports the remaining points of the token's bucket
+//! in the `Ratelimit-*` headers of every response, background requests leave a reserve of points for
+//! lookups which a user is waiting for
+
+use axum::{
+    body::Bytes,
+    http::{HeaderMap, Request, Response},
+};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, register_int_gauge, IntCounterVec, IntGauge};
+use std::{
+    future::Future,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
+use tokio::time::sleep;
+use twitch_api::{client::BoxedFuture, HttpClient};
+
+lazy_static! {
+    static ref RATELIMIT_REMAINING_GAUGE: IntGauge = register_int_gauge!(
+        "rustlog_helix_ratelimit_remaining",
+        "Points left in the Helix rate limit bucket as of the last response"
+    )
+    .unwrap();
+    static ref RATELIMIT_LIMIT_GAUGE: IntGauge = register_int_gauge!(
+        "rustlog_helix_ratelimit_limit",
+        "Size of the Helix rate limit bucket"
+    )
+    .unwrap();
+    static ref QUEUE_WAITS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "rustlog_helix_queue_waits_total",
+        "How many Helix requests had to wait for the rate limit, by priority",
+        &["priority"]
+    )
+    .unwrap();
+}
+
+/// Points which background requests leave for interactive ones
+const BACKGROUND_RESERVE: u32 = 100;
+/// Shortest wait for the bucket, the reset time only has a precision of seconds
+const MIN_WAIT_SECONDS: u64 = 1;
+/// Twitch refills the whole bucket within a minute
+const REFILL_SECONDS: u64 = 60;
+
+tokio::task_local! {
+    static PRIORITY: HelixPriority;
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum HelixPriority {
+    /// Lookups for requests and commands, the default
+    Interactive,
+    /// Periodic tasks like the stream poller, which can wait for the bucket to refill
+    Background,
+}
+
+impl HelixPriority {
+    fn name(self) -> &'static str {
+        match self {
+            Self::Interactive => "interactive",
+            Self::Background => "background",
+        }
+    }
+
+    /// Helix requests made by the future use this priority
+    pub async fn scope<F: Future>(self, future: F) -> F::Output {
+        PRIORITY.scope(self, future).await
+    }
+
+    fn current() -> Self {
+        PRIORITY
+            .try_with(|priority| *priority)
+            .unwrap_or(Self::Interactive)
+    }
+}
+
+/// State of the bucket as reported by the last response
+#[derive(Debug, Default)]
+struct Bucket {
+    /// `None` until the first response
+    remaining: Option<u32>,
+    limit: u32,
+    /// Unix timestamp at which the bucket is full again
+    reset: u64,
+}
+
+impl Bucket {
+    /// Takes a point, or returns the seconds until the bucket is refilled
+    fn take(
+        &mut self,
+        priority: HelixPriority,
+        interactive_waiting: bool,
+        now: u64,
+    ) -> Result<(), u64> {
+        let Some(remaining) = self.remaining.as_mut() else {
+            return Ok(());
+        };
+        // The next response reports the actual bucket, until then it is assumed to be full for a refill interval
+        if now >= self.reset {
+            *remaining = self.limit;
+            self.reset = now + REFILL_SECONDS;
+        }
+
+        let reserve = match priority {
+            HelixPriority::Interactive => 0,
+            HelixPriority::Background if interactive_waiting => *remaining,
+            HelixPriority::Background => BACKGROUND_RESERVE.min(self.limit / 2),
+        };
+        if *remaining > reserve {
+            *remaining -= 1;
+            Ok(())
+        } else {
+            Err(self.reset.saturating_sub(now).max(MIN_WAIT_SECONDS))
+        }
+    }
+
+    fn update(&mut self, headers: &HeaderMap) {
+        let header = |name: &str| -> Option<u64> { headers.get(name)?.to_str().ok()?.parse().ok() };
+        let (Some(limit), Some(remaining), Some(reset)) = (
+            header("ratelimit-limit"),
+            header("ratelimit-remaining"),
+            header("ratelimit-reset"),
+        ) else {
+            return;
+        };
+
+        self.limit = limit as u32;
+        self.remaining = Some(remaining as u32);
+        self.reset = reset;
+        RATELIMIT_LIMIT_GAUGE.set(limit as i64);
+        RATELIMIT_REMAINING_GAUGE.set(remaining as i64);
+    }
+}
+
+/// Waits for the rate limit before every request and reads the bucket from the responses
+#[derive(Clone, Default)]
+pub struct HelixHttpClient {
+    client: reqwest::Client,
+    bucket: Arc<Mutex<Bucket>>,
+    interactive_waiting: Arc<AtomicUsize>,
+}
+
+impl HelixHttpClient {
+    /// The client without rate limiting, for requests which do not go to Helix like token validation
+    pub fn inner(&self) -> &reqwest::Client {
+        &self.client
+    }
+
+    async fn acquire(&self, priority: HelixPriority) {
+        let mut waited = false;
+        // Also released if the request is cancelled while waiting
+        let mut _interactive_waiting = None;
+        loop {
+            let now = Utc::now().timestamp() as u64;
+            let interactive_waiting = self.interactive_waiting.load(Ordering::Relaxed) > 0;
+            let result = self
+                .bucket
+                .lock()
+                .unwrap()
+                .take(priority, interactive_waiting, now);
+            let Err(wait_seconds) = result else {
+                return;
+            };
+
+            if !waited {
+                waited = true;
+                QUEUE_WAITS_COUNTER
+                    .with_label_values(&[priority.name()])
+                    .inc();
+                if priority == HelixPriority::Interactive {
+                    _interactive_waiting = Some(WaitingGuard::new(&self.interactive_waiting));
+                }
+            }
+            sleep(Duration::from_secs(wait_seconds)).await;
+        }
+    }
+}
+
+struct WaitingGuard<'a>(&'a AtomicUsize);
+
+impl<'a> WaitingGuard<'a> {
+    fn new(waiting: &'a AtomicUsize) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for WaitingGuard<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl HttpClient for HelixHttpClient {
+    type Error = <reqwest::Client as HttpClient>::Error;
+
+    fn req(
+        &self,
+        request: Request<Bytes>,
+    ) -> BoxedFuture<'_, Result<Response<Bytes>, Self::Error>> {
+        Box::pin(async move {
+            self.acquire(HelixPriority::current()).await;
+            let response = HttpClient::req(&self.client, request).await?;
+            self.bucket.lock().unwrap().update(response.headers());
+            Ok(response)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Bucket, HelixPriority};
+    use axum::http::HeaderMap;
+    use pretty_assertions::assert_eq;
+
+    fn bucket(remaining: u32) -> Bucket {
+        let mut headers = HeaderMap::new();
+        headers.insert("ratelimit-limit", "800".parse().unwrap());
+        headers.insert(
+            "ratelimit-remaining",
+            remaining.to_string().parse().unwrap(),
+        );
+        headers.insert("ratelimit-reset", "1000".parse().unwrap());
+
+        let mut bucket = Bucket::default();
+        bucket.update(&headers);
+        bucket
+    }
+
+    #[test]
+    fn background_requests_leave_a_reserve() {
+        let mut bucket = bucket(101);
+
+        assert_eq!(bucket.take(HelixPriority::Background, false, 990), Ok(()));
+        assert_eq!(bucket.take(HelixPriority::Background, false, 990), Err(10));
+        assert_eq!(bucket.take(HelixPriority::Interactive, false, 990), Ok(()));
+        assert_eq!(bucket.remaining, Some(99));
+    }
+
+    #[test]
+    fn background_requests_wait_for_interactive_ones() {
+        let mut bucket = bucket(500);
+
+        assert_eq!(bucket.take(HelixPriority::Background, true, 990), Err(10));
+        assert_eq!(bucket.take(HelixPriority::Interactive, true, 990), Ok(()));
+    }
+
+    #[test]
+    fn bucket_is_refilled_after_reset() {
+        let mut bucket = bucket(0);
+
+        assert_eq!(bucket.take(HelixPriority::Interactive, false, 999), Err(1));
+        assert_eq!(bucket.take(HelixPriority::Interactive, false, 1000), Ok(()));
+        assert_eq!(bucket.remaining, Some(799));
+    }
+
+    #[test]
+    fn requests_are_not_limited_before_the_first_response() {
+        let mut bucket = Bucket::default();
+
+        assert_eq!(bucket.take(HelixPriority::Background, true, 0), Ok(()));
+    }
+}
@@ -0,0 +1,154 @@
+use crate::config::LogsCacheConfig;
+use anyhow::Context;
+use axum::body::Bytes;
+use chrono::NaiveDate;
+use chrono_tz::Tz;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use object_store::{path::Path, ObjectStore};
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use tracing::{debug, error};
+use url::Url;
+
+lazy_static! {
+    static ref CACHE_REQUESTS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "rustlog_logs_cache_requests_total",
+        "How many day log requests were served from the logs cache",
+        &["result"]
+    )
+    .unwrap();
+}
+
+/// Rendered logs of days which can no longer change, stored gzip compressed on disk or in object storage
+pub struct LogsCache {
+    store: Box<dyn ObjectStore>,
+    prefix: Path,
+}
+
+pub struct LogsCacheKey {
+    pub channel_id: String,
+    pub date: NaiveDate,
+    /// Timezone of the channel, which decides where the day starts
+    pub timezone: Tz,
+    /// Name of the response format
+    pub format: String,
+    pub reverse: bool,
+}
+
+impl LogsCache {
+    pub fn new(config: &LogsCacheConfig) -> anyhow::Result<Self> {
+        let url = Url::parse(&config.url).context("Invalid logs cache url")?;
+        let options = config
+            .options
+            .iter()
+            .map(|(key, value)| (key, value.expose()));
+        let (store, prefix) = object_store::parse_url_opts(&url, options)
+            .context("Could not create logs cache store")?;
+
+        Ok(Self { store, prefix })
+    }
+
+    /// Returns the compressed logs, or `None` if they have not been cached yet
+    pub async fn get(&self, key: &LogsCacheKey) -> Option<Bytes> {
+        let path = self.path(key);
+
+        let result = match self.store.get(&path).await {
+            Ok(result) => result.bytes().await,
+            Err(err) => Err(err),
+        };
+
+        match result {
+            Ok(blob) => {
+                CACHE_REQUESTS_COUNTER.with_label_values(&["hit"]).inc();
+                Some(blob)
+            }
+            Err(object_store::Error::NotFound { .. }) => {
+                CACHE_REQUESTS_COUNTER.with_label_values(&["miss"]).inc();
+                None
+            }
+            Err(err) => {
+                error!("Could not read {path} from logs cache: {err}");
+                CACHE_REQUESTS_COUNTER.with_label_values(&["error"]).inc();
+                None
+            }
+        }
+    }
+
+    pub async fn put(&self, key: &LogsCacheKey, blob: Vec<u8>) {
+        let path = self.path(key);
+        let size = blob.len();
+
+        match self.store.put(&path, blob.into()).await {
+            Ok(_) => debug!("Cached {path} ({size} bytes)"),
+            Err(err) => error!("Could not write {path} to logs cache: {err}"),
+        }
+    }
+
+    /// Deletes the cached logs of the channel's days from `from` to `to` in every format, returns how many were deleted
+    pub async fn invalidate(&self, channel_id: &str, from: NaiveDate, to: NaiveDate) -> usize {
+        let prefix = self.prefix.child(channel_id);
+        let prefix = &prefix;
+        let paths: Vec<Path> = self
+            .store
+            .list(Some(prefix))
+            .filter_map(|meta| async move {
+                match meta {
+                    Ok(meta) => Some(meta.location),
+                    Err(err) => {
+                        error!("Could not list {prefix} in logs cache: {err}");
+                        None
+                    }
+                }
+            })
+            .collect()
+            .await;
+
+        let mut deleted = 0;
+        for path in paths {
+            if !cached_date(&path).is_some_and(|date| (from..=to).contains(&date)) {
+                continue;
+            }
+
+            match self.store.delete(&path).await {
+                Ok(()) => deleted += 1,
+                Err(object_store::Error::NotFound { .. }) => (),
+                Err(err) => error!("Could not delete {path} from logs cache: {err}"),
+            }
+        }
+
+        deleted
+    }
+
+    fn path(&self, key: &LogsCacheKey) -> Path {
+        let order = if key.reverse { "-reverse" } else { "" };
+        let timezone = key.timezone.name().replace('/', "_");
+        self.prefix
+            .child(key.channel_id.as_str())
+            .child(format!("{}.{timezone}.{}{order}.gz", key.date, key.format))
+    }
+}
+
+/// Date of a path built by [`LogsCache::path`]
+fn cached_date(path: &Path) -> Option<NaiveDate> {
+    let (date, _) = path.filename()?.split_once('.')?;
+    date.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::cached_date;
+    use chrono::NaiveDate;
+    use object_store::path::Path;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn dates_of_cached_paths() {
+        assert_eq!(
+            cached_date(&Path::from(
+                "logs/22484632/2024-03-01.Europe_Berlin.json-reverse.gz"
+            )),
+            NaiveDate::from_ymd_opt(2024, 3, 1)
+        );
+        assert_eq!(cached_date(&Path::from("logs/22484632")), None);
+    }
+}
@@ -1,36 +1,135 @@
 pub mod cache;
+pub mod cache_invalidation;
+pub mod channel_index;
+pub mod helix_queue;
+pub mod logs_cache;
+pub mod opt_out_denials;
+pub mod user_warmup;
 
-use self::cache::UsersCache;
+use self::{
+    cache::UsersCache, channel_index::ChannelIndex, helix_queue::HelixHttpClient,
+    logs_cache::LogsCache, opt_out_denials::OPT_OUT_DENIALS,
+};
 use crate::{
-    config::Config,
-    db::writer::FlushBuffer,
+    config::{Config, EndpointGroup},
+    db::{
+        aliases::read_channel_logins,
+        pool::{DbPool, QueryClass},
+        schema::StructuredMessage,
+        writer::FlushBuffer,
+    },
     error::Error,
+    ids::{ChannelId, UserId},
+    watchdog::Liveness,
+    web::{
+        export_quotas::ExportQuotas,
+        schema::{ChannelIdType, ChannelParam, LogsParams, OptOutDenialReason, UserParam},
+    },
     Result,
 };
 use anyhow::Context;
-use dashmap::DashSet;
-use std::{collections::HashMap, sync::Arc};
-use tracing::{debug};
-use twitch_api::{helix::users::GetUsersRequest, twitch_oauth2::AppAccessToken, HelixClient};
+use dashmap::{DashMap, DashSet};
+use futures::{stream, StreamExt};
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::broadcast;
+use tracing::{debug, warn};
+use twitch_api::{
+    helix::{channels::GetFollowedChannels, teams::GetTeamsRequest, users::GetUsersRequest},
+    twitch_oauth2::{AccessToken, AppAccessToken, UserToken},
+    HelixClient,
+};
 
 #[derive(Clone)]
 pub struct App {
-    pub helix_client: HelixClient<'static, reqwest::Client>,
+    pub helix_client: HelixClient<'static, HelixHttpClient>,
     pub token: Arc<AppAccessToken>,
     pub users: UsersCache,
-    pub optout_codes: Arc<DashSet<String>>,
-    pub db: Arc<clickhouse::Client>,
+    /// OAuth states of opt-outs which have been started and not finished yet
+    pub optout_codes: Arc<DashMap<String, PendingOptOut>>,
+    /// Users who opted out through `/optout`, in addition to the config's `optOut`
+    pub opted_out_users: Arc<DashSet<String>>,
+    /// Codes for `!rustlog consent` which have been handed out and not used yet, with when they were created
+    pub consent_codes: Arc<DashMap<String, Instant>>,
+    /// Users who consented to their logs being served, see `requireUserConsent`
+    pub consented_users: Arc<DashSet<String>>,
+    pub db: Arc<DbPool>,
     pub config: Arc<Config>,
     pub flush_buffer: FlushBuffer,
+    /// Every logged message as it is received
+    pub live_tx: broadcast::Sender<Arc<StructuredMessage<'static>>>,
+    pub logs_cache: Option<Arc<LogsCache>>,
+    pub channel_index: Arc<ChannelIndex>,
+    /// Channels which were live at the last stream poll
+    pub live_channels: Arc<RwLock<HashSet<String>>>,
+    /// Heartbeats of the bot and the writers
+    pub liveness: Liveness,
+    /// Bytes downloaded today through the bulk log endpoints, by the HTTP, gRPC and GraphQL servers
+    pub export_quotas: ExportQuotas,
+}
+
+lazy_static! {
+    static ref HELIX_REQUESTS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "rustlog_helix_requests_total",
+        "How many requests were made to the Twitch API, by endpoint and whether they succeeded",
+        &["endpoint", "result"]
+    )
+    .unwrap();
+}
+
+/// Users per Helix request, the maximum the endpoint accepts
+const HELIX_USERS_BATCH_SIZE: usize = 100;
+/// Helix user requests which are made at the same time
+const HELIX_USERS_CONCURRENCY: usize = 4;
+/// How long a consent code can be sent in chat
+const CONSENT_CODE_TIMEOUT: Duration = Duration::from_secs(600);
+/// Consent codes which are kept, further ones are rejected until some have been used or expired
+const MAX_PENDING_CONSENT_CODES: usize = 10_000;
+
+/// An opt-out waiting for the user to be redirected back from Twitch
+pub struct PendingOptOut {
+    /// Whether the user's logs should be deleted
+    pub purge: bool,
+    pub started_at: Instant,
+}
+
+/// Result of [`App::lookup_users`]
+pub struct UsersLookup {
+    /// Logins by ID
+    pub users: HashMap<String, String>,
+    /// The first error of the failed requests, their users are missing from `users`
+    pub error: Option<Error>,
 }
 
 impl App {
+    /// Logins by ID of the users. Fails if any of the requests failed,
+    /// use [`App::lookup_users`] to get the users of the requests which succeeded
     pub async fn get_users(
         &self,
         ids: Vec<String>,
         names: Vec<String>,
         ignore_cache: bool,
     ) -> Result<HashMap<String, String>> {
+        let lookup = self.lookup_users(ids, names, ignore_cache).await;
+        match lookup.error {
+            Some(err) => Err(err),
+            None => Ok(lookup.users),
+        }
+    }
+
+    /// Requests the users in batches, a failed batch does not fail the whole lookup.
+    /// Users which were not returned by a successful request are cached as missing
+    pub async fn lookup_users(
+        &self,
+        ids: Vec<String>,
+        names: Vec<String>,
+        ignore_cache: bool,
+    ) -> UsersLookup {
         let mut users = HashMap::new();
         let mut ids_to_request = Vec::new();
         let mut names_to_request = Vec::new();
@@ -60,23 +159,40 @@ impl App {
             }
         }
 
-        let mut new_users = Vec::with_capacity(ids_to_request.len() + names_to_request.len());
-
         // There are no chunks if the vec is empty, so there is no empty request made
-        for chunk in ids_to_request.chunks(100) {
-            debug!("Requesting user info for ids {chunk:?}");
-
-            let request = GetUsersRequest::ids(chunk);
-            let response = self.helix_client.req_get(request, &*self.token).await?;
-            new_users.extend(response.data);
-        }
+        let requests = ids_to_request
+            .chunks(HELIX_USERS_BATCH_SIZE)
+            .map(|chunk| (GetUsersRequest::ids(chunk), chunk))
+            .chain(
+                names_to_request
+                    .chunks(HELIX_USERS_BATCH_SIZE)
+                    .map(|chunk| (GetUsersRequest::logins(chunk), chunk)),
+            );
+        let mut responses = stream::iter(requests)
+            .map(|(request, chunk)| async move {
+                debug!("Requesting user info for {chunk:?}");
+                let response = self.helix_client.req_get(request, &*self.token).await;
+                record_helix_request("users", &response);
+                (response, chunk)
+            })
+            .buffer_unordered(HELIX_USERS_CONCURRENCY);
 
-        for chunk in names_to_request.chunks(100) {
-            debug!("Requesting user info for names {chunk:?}");
+        let mut new_users = Vec::with_capacity(ids_to_request.len() + names_to_request.len());
+        let mut failed = HashSet::new();
+        let mut error = None;
 
-            let request = GetUsersRequest::logins(chunk);
-            let response = self.helix_client.req_get(request, &*self.token).await?;
-            new_users.extend(response.data);
+        while let Some((response, chunk)) = responses.next().await {
+            match response {
+                Ok(response) => new_users.extend(response.data),
+                Err(err) => {
+                    warn!(
+                        "Could not request user info for {} users: {err}",
+                        chunk.len()
+                    );
+                    failed.extend(chunk.iter().map(String::as_str));
+                    error.get_or_insert(Error::from(err));
+                }
+            }
         }
 
         for user in new_users {
@@ -88,19 +204,117 @@ impl App {
             users.insert(id, login);
         }
 
-        // Banned users which were not returned by the api
-        for id in ids_to_request {
-            if !users.contains_key(id.as_str()) {
-                self.users.insert_optional(Some(id), None);
+        // Banned users which were not returned by the api.
+        // Users of failed requests are left out, they might exist
+        for id in &ids_to_request {
+            if !users.contains_key(id) && !failed.contains(id.as_str()) {
+                self.users.insert_optional(Some(id.clone()), None);
             }
         }
-        for name in names_to_request {
-            if !users.values().any(|login| login == name.as_str()) {
-                self.users.insert_optional(None, Some(name));
+        for name in &names_to_request {
+            if !failed.contains(name.as_str()) && !users.values().any(|login| login == name) {
+                self.users.insert_optional(None, Some(name.clone()));
             }
         }
 
-        Ok(users)
+        UsersLookup { users, error }
+    }
+
+    /// Logins of the channels by ID. Channels which Helix does not return, e.g. because they are banned
+    /// or the request failed, get the login they were last logged with
+    pub async fn get_channel_logins(
+        &self,
+        channel_ids: Vec<String>,
+    ) -> Result<HashMap<String, String>> {
+        let lookup = self.lookup_users(channel_ids.clone(), vec![], false).await;
+        if let Some(err) = lookup.error {
+            warn!("Could not resolve all channels: {err}");
+        }
+
+        let mut logins = lookup.users;
+        let unresolved: Vec<String> = channel_ids
+            .into_iter()
+            .filter(|channel_id| !logins.contains_key(channel_id))
+            .collect();
+        let db = self.db.profile(QueryClass::Logs);
+        logins.extend(read_channel_logins(&db, &unresolved).await?);
+
+        Ok(logins)
+    }
+
+    /// Current logins of the channels by ID, requested from Helix without the cache.
+    /// Fails if a channel is not returned, as an old login could now belong to a different channel
+    pub async fn get_current_channel_logins(
+        &self,
+        channel_ids: Vec<String>,
+    ) -> Result<HashMap<String, String>> {
+        let lookup = self.lookup_users(channel_ids.clone(), vec![], true).await;
+        if let Some(err) = lookup.error {
+            return Err(err);
+        }
+
+        let missing: Vec<String> = channel_ids
+            .into_iter()
+            .filter(|channel_id| !lookup.users.contains_key(channel_id))
+            .collect();
+        if !missing.is_empty() {
+            return Err(Error::InvalidParam(format!(
+                "Channels not found on Twitch: {}",
+                missing.join(", ")
+            )));
+        }
+
+        Ok(lookup.users)
+    }
+
+    /// Logins of the team's members, by ID
+    pub async fn get_team_members(&self, team: &str) -> Result<HashMap<String, String>> {
+        let request = GetTeamsRequest::name(team);
+        let response = self.helix_client.req_get(request, &*self.token).await?;
+
+        let members = response
+            .data
+            .into_iter()
+            .flat_map(|team| team.users)
+            .map(|user| (user.user_id.to_string(), user.user_login.to_string()))
+            .collect();
+        Ok(members)
+    }
+
+    /// Logins of the channels the user follows, by ID.
+    /// Requires a user access token of that user with the `user:read:follows` scope
+    pub async fn get_followed_channels(
+        &self,
+        user_id: &str,
+        user_token: &str,
+    ) -> Result<HashMap<String, String>> {
+        let token = UserToken::from_token(
+            self.helix_client.get_client().inner(),
+            AccessToken::from(user_token),
+        )
+        .await
+        .map_err(|_| Error::InvalidParam("Invalid user access token".to_owned()))?;
+        if token.user_id.as_str() != user_id {
+            return Err(Error::InvalidParam(
+                "The user access token belongs to a different user".to_owned(),
+            ));
+        }
+
+        let request = GetFollowedChannels::new(user_id).first(100);
+        let mut page = Some(self.helix_client.req_get(request, &token).await?);
+        let mut channels = HashMap::new();
+
+        while let Some(response) = page {
+            for channel in &response.data {
+                channels.insert(
+                    channel.broadcaster_id.to_string(),
+                    channel.broadcaster_login.to_string(),
+                );
+            }
+            page = response.get_next(&self.helix_client, &token).await?;
+        }
+
+        Ok(channels)
     }
 
     pub async fn get_user_id_by_name(&self, name: &str) -> Result<String> {
@@ -125,17 +339,185 @@ impl App {
         }
     }
 
-    pub fn check_opted_out(&self, channel_id: &str, user_id: Option<&str>) -> Result<()> {
-        if self.config.opt_out.contains_key(channel_id) {
-            return Err(Error::ChannelOptedOut);
+    /// Joined, archived or known to have logs
+    pub fn is_channel_tracked(&self, channel_id: &str) -> bool {
+        self.config.channels.read().unwrap().contains(channel_id)
+            || self
+                .config
+                .archived_channels
+                .read()
+                .unwrap()
+                .contains(channel_id)
+            || self.channel_index.contains_id(channel_id)
+    }
+
+    /// Tells a channel which is not logged on this instance apart from a range without messages
+    pub fn explain_not_found(&self, err: Error, channel_id: &ChannelId) -> Error {
+        match err {
+            Error::NotFound if !self.is_channel_tracked(channel_id.as_str()) => {
+                Error::ChannelNotTracked
+            }
+            Error::NotFound => Error::NoMessagesInRange,
+            err => err,
+        }
+    }
+
+    pub fn is_live(&self, channel_id: &str) -> bool {
+        self.live_channels.read().unwrap().contains(channel_id)
+    }
+
+    /// Resolves a channel given by login or ID, as in the logs paths
+    pub async fn resolve_channel_id(
+        &self,
+        channel_id_type: ChannelIdType,
+        channel: String,
+    ) -> Result<ChannelId> {
+        let channel_id = match channel_id_type {
+            ChannelIdType::Name => self.get_user_id_by_name(&channel).await?,
+            ChannelIdType::Id => channel,
+        };
+        Ok(channel_id.into())
+    }
+
+    /// Resolves a channel given by login or ID, as in the query params
+    pub async fn resolve_channel_param(&self, channel: ChannelParam) -> Result<ChannelId> {
+        let channel_id = match channel {
+            ChannelParam::ChannelId(id) => id,
+            ChannelParam::Channel(name) => self.get_user_id_by_name(&name).await?,
+        };
+        Ok(channel_id.into())
+    }
+
+    /// Like [`App::resolve_channel_param`], for a user
+    pub async fn resolve_user_param(&self, user: UserParam) -> Result<UserId> {
+        let user_id = match user {
+            UserParam::UserId(id) => id,
+            UserParam::User(name) => self.get_user_id_by_name(&name).await?,
+        };
+        Ok(user_id.into())
+    }
+
+    /// Denied requests are counted for the admin stats
+    pub fn check_opted_out(&self, channel_id: &ChannelId, user_id: Option<&UserId>) -> Result<()> {
+        let Some(reason) = self.opt_out_reason(channel_id, user_id) else {
+            return Ok(());
+        };
+        OPT_OUT_DENIALS.record(reason, channel_id.as_str(), user_id.map(UserId::as_str));
+
+        Err(match reason {
+            OptOutDenialReason::ChannelOptedOut => Error::ChannelOptedOut,
+            OptOutDenialReason::UserOptedOut => Error::UserOptedOut,
+            OptOutDenialReason::UserLogsDisabled => Error::UserLogsDisabled,
+            OptOutDenialReason::UserNotConsented => Error::UserNotConsented,
+        })
+    }
+
+    /// For the GraphQL and gRPC APIs, the REST routes are checked by their path
+    pub fn check_endpoint_group(&self, group: EndpointGroup) -> Result<()> {
+        if self.config.disabled_endpoints.contains(&group) {
+            Err(Error::EndpointDisabled)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Whether the user or channel opted out, either in the config or through `/optout`
+    pub fn has_opted_out(&self, id: &str) -> bool {
+        self.config.opt_out.contains_key(id) || self.opted_out_users.contains(id)
+    }
+
+    pub fn opted_out_ids(&self) -> Vec<String> {
+        self.config
+            .opt_out
+            .iter()
+            .map(|entry| entry.key().clone())
+            .chain(self.opted_out_users.iter().map(|user_id| user_id.clone()))
+            .collect()
+    }
+
+    /// Users who consented, or `None` if everyone's logs are served because `requireUserConsent` is not set
+    pub fn consented_ids(&self) -> Option<Vec<String>> {
+        self.config.require_user_consent.then(|| {
+            self.consented_users
+                .iter()
+                .map(|user_id| user_id.clone())
+                .collect()
+        })
+    }
+
+    /// Like [`App::check_opted_out`], without counting it as a denied request
+    pub fn is_opted_out(&self, channel_id: &ChannelId, user_id: Option<&UserId>) -> bool {
+        self.opt_out_reason(channel_id, user_id).is_some()
+    }
+
+    /// Hands out the code for `!rustlog consent`, unless too many codes are pending
+    pub fn add_consent_code(&self, code: String) -> Result<()> {
+        self.consent_codes
+            .retain(|_, created_at| created_at.elapsed() < CONSENT_CODE_TIMEOUT);
+        if self.consent_codes.len() >= MAX_PENDING_CONSENT_CODES {
+            return Err(Error::Busy);
+        }
+
+        self.consent_codes.insert(code, Instant::now());
+        Ok(())
+    }
+
+    /// Whether the code was handed out and has not expired. It can only be used once
+    pub fn take_consent_code(&self, code: &str) -> bool {
+        self.consent_codes
+            .remove(code)
+            .is_some_and(|(_, created_at)| created_at.elapsed() < CONSENT_CODE_TIMEOUT)
+    }
+
+    /// Always true unless `requireUserConsent` is set
+    pub fn has_consented(&self, user_id: &str) -> bool {
+        !self.config.require_user_consent || self.consented_users.contains(user_id)
+    }
+
+    fn opt_out_reason(
+        &self,
+        channel_id: &ChannelId,
+        user_id: Option<&UserId>,
+    ) -> Option<OptOutDenialReason> {
+        if self.has_opted_out(channel_id.as_str()) {
+            return Some(OptOutDenialReason::ChannelOptedOut);
         }
 
         if let Some(user_id) = user_id {
-            if self.config.opt_out.contains_key(user_id) {
-                return Err(Error::UserOptedOut);
+            if self.config.user_logs_disabled(channel_id.as_str()) {
+                return Some(OptOutDenialReason::UserLogsDisabled);
+            }
+
+            if self.has_opted_out(user_id.as_str()) {
+                return Some(OptOutDenialReason::UserOptedOut);
+            }
+
+            if !self.has_consented(user_id.as_str()) {
+                return Some(OptOutDenialReason::UserNotConsented);
             }
         }
 
-        Ok(())
+        None
     }
+
+    pub fn stats_excluded_users(&self, include_bots: bool) -> &[String] {
+        if include_bots {
+            &[]
+        } else {
+            &self.config.bot_user_ids
+        }
+    }
+
+    /// Users whose messages are skipped in logs, the bots if `excludeBots` is set
+    pub fn logs_excluded_users(&self, params: &LogsParams) -> &[String] {
+        self.stats_excluded_users(!params.filter.exclude_bots)
+    }
+}
+
+/// Counts a request to the Helix endpoint in the metrics
+pub fn record_helix_request<T, E>(endpoint: &str, response: &std::result::Result<T, E>) {
+    let result = if response.is_ok() { "success" } else { "error" };
+    HELIX_REQUESTS_COUNTER
+        .with_label_values(&[endpoint, result])
+        .inc();
 }
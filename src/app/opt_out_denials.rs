@@ -0,0 +1,158 @@
+use crate::web::schema::{OptOutDenial, OptOutDenialReason, OptOutDenials};
+use chrono::{DateTime, Utc};
+use hmac::{Hmac, Mac};
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter_vec, IntCounterVec};
+use rand::{thread_rng, RngCore};
+use sha2::Sha256;
+use std::{
+    collections::HashMap,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Mutex,
+    },
+};
+
+/// Distinct channels and users tracked at once, so scraping many different users can't grow it without bounds
+const MAX_ENTRIES: usize = 10_000;
+
+lazy_static! {
+    static ref OPT_OUT_DENIALS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "rustlog_opt_out_denials_total",
+        "Requests which were denied because the channel or user opted out",
+        &["reason"]
+    )
+    .unwrap();
+    pub static ref OPT_OUT_DENIALS: DenialTracker = DenialTracker::default();
+}
+
+/// Requests denied by opt-outs since startup, by hashed channel and user id
+pub struct DenialTracker {
+    since: DateTime<Utc>,
+    /// Random for every process, so the hashes can't be reversed by hashing every known id
+    hash_key: [u8; 32],
+    /// Also counts denials which are not tracked individually
+    total: AtomicU64,
+    entries: Mutex<HashMap<DenialKey, DenialCount>>,
+}
+
+#[derive(PartialEq, Eq, Hash)]
+struct DenialKey {
+    reason: OptOutDenialReason,
+    channel: String,
+    user: Option<String>,
+}
+
+struct DenialCount {
+    count: u64,
+    last_denied_at: DateTime<Utc>,
+}
+
+impl Default for DenialTracker {
+    fn default() -> Self {
+        let mut hash_key = [0; 32];
+        thread_rng().fill_bytes(&mut hash_key);
+
+        Self {
+            since: Utc::now(),
+            hash_key,
+            total: AtomicU64::new(0),
+            entries: Mutex::default(),
+        }
+    }
+}
+
+impl DenialTracker {
+    pub fn record(&self, reason: OptOutDenialReason, channel_id: &str, user_id: Option<&str>) {
+        OPT_OUT_DENIALS_COUNTER
+            .with_label_values(&[reason.as_ref()])
+            .inc();
+        self.total.fetch_add(1, Ordering::Relaxed);
+
+        let key = DenialKey {
+            reason,
+            channel: self.anonymize(channel_id),
+            user: user_id.map(|user_id| self.anonymize(user_id)),
+        };
+        let now = Utc::now();
+
+        let mut entries = self.entries.lock().unwrap();
+        if let Some(entry) = entries.get_mut(&key) {
+            entry.count += 1;
+            entry.last_denied_at = now;
+        } else if entries.len() < MAX_ENTRIES {
+            entries.insert(
+                key,
+                DenialCount {
+                    count: 1,
+                    last_denied_at: now,
+                },
+            );
+        }
+    }
+
+    /// Most denied first
+    pub fn snapshot(&self) -> OptOutDenials {
+        let entries = self.entries.lock().unwrap();
+        let mut denials: Vec<OptOutDenial> = entries
+            .iter()
+            .map(|(key, count)| OptOutDenial {
+                reason: key.reason,
+                channel: key.channel.clone(),
+                user: key.user.clone(),
+                count: count.count,
+                last_denied_at: count.last_denied_at,
+            })
+            .collect();
+        drop(entries);
+        denials.sort_unstable_by(|a, b| b.count.cmp(&a.count));
+
+        OptOutDenials {
+            since: self.since,
+            total: self.total.load(Ordering::Relaxed),
+            denials,
+        }
+    }
+
+    /// Start of the HMAC-SHA256 of the id, the same id has the same hash until the process restarts
+    fn anonymize(&self, id: &str) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.hash_key).expect("HMAC accepts keys of any size");
+        mac.update(id.as_bytes());
+        mac.finalize().into_bytes()[..8]
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DenialTracker;
+    use crate::web::schema::OptOutDenialReason;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn counts_denials_by_hashed_id() {
+        let tracker = DenialTracker::default();
+        tracker.record(OptOutDenialReason::UserOptedOut, "22484632", Some("1"));
+        tracker.record(OptOutDenialReason::UserOptedOut, "22484632", Some("1"));
+        tracker.record(OptOutDenialReason::ChannelOptedOut, "11148817", None);
+
+        let snapshot = tracker.snapshot();
+        assert_eq!(3, snapshot.total);
+        assert_eq!(2, snapshot.denials[0].count);
+        assert_eq!(Some(tracker.anonymize("1")), snapshot.denials[0].user);
+        assert_eq!(None, snapshot.denials[1].user);
+
+        // Unkeyed sha256("1") and the hash of another process differ
+        assert_ne!(
+            Some("6b86b273ff34fce1"),
+            snapshot.denials[0].user.as_deref()
+        );
+        assert_ne!(
+            DenialTracker::default().anonymize("1"),
+            tracker.anonymize("1")
+        );
+    }
+}
@@ -0,0 +1,58 @@
+use super::{helix_queue::HelixPriority, App};
+use crate::{db::channel_users::read_recent_top_chatter_ids, Result, ShutdownRx};
+use chrono::Utc;
+use std::{collections::HashSet, time::Duration};
+use tokio::time::sleep;
+use tracing::{debug, error};
+
+/// Shorter than the expiry of the users cache, so warmed users don't expire in between
+const WARMUP_INTERVAL_SECONDS: u64 = 3600;
+const TOP_CHATTERS_PER_CHANNEL: u64 = 100;
+/// How far back chatters are considered recent
+const TOP_CHATTERS_DAYS: i64 = 7;
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    loop {
+        if let Err(err) = HelixPriority::Background.scope(warm_users(&app)).await {
+            error!("Could not warm users cache: {err}");
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(WARMUP_INTERVAL_SECONDS)) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down users cache warmup");
+                break;
+            }
+        }
+    }
+}
+
+/// Resolves the joined channels and their recent top chatters, so requests after a restart
+/// don't have to wait for the Twitch API
+async fn warm_users(app: &App) -> Result<()> {
+    let channel_ids: Vec<String> = app
+        .config
+        .channels
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|channel_id| !app.has_opted_out(channel_id))
+        .cloned()
+        .collect();
+
+    let since = Utc::now() - chrono::Duration::days(TOP_CHATTERS_DAYS);
+    let chatter_ids =
+        read_recent_top_chatter_ids(&app.db, &channel_ids, since, TOP_CHATTERS_PER_CHANNEL).await?;
+
+    let mut user_ids: HashSet<String> = channel_ids.into_iter().collect();
+    user_ids.extend(chatter_ids);
+    user_ids.retain(|user_id| !app.has_opted_out(user_id));
+
+    // The cache is bypassed so users which were warmed last time are refreshed as well
+    let users = app
+        .get_users(user_ids.into_iter().collect(), vec![], true)
+        .await?;
+    debug!("Warmed users cache with {} users", users.len());
+
+    Ok(())
+}
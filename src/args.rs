@@ -1,4 +1,4 @@
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 
 #[derive(Parser)]
 #[clap(author, version, about, long_about = None)]
@@ -21,4 +21,54 @@ pub enum Command {
         #[clap(short, long, default_value_t = 1)]
         jobs: usize,
     },
+    /// Import logs written by another logger, skipping messages which are already stored
+    Import {
+        /// Layout of the logs
+        #[clap(short, long, value_enum, default_value_t = ImportFormat::Justlog)]
+        format: ImportFormat,
+        /// The logs folder
+        #[clap(short, long, value_parser)]
+        dir: String,
+        /// List of channel ids to import (None specified = import all)
+        #[clap(short, long, value_parser)]
+        channel_id: Vec<String>,
+        /// Parallel import jobs
+        #[clap(short, long, default_value_t = 1)]
+        jobs: usize,
+    },
+    /// Restore a backup into an empty database
+    Restore {
+        /// Name of the backup, as listed by the admin API
+        #[clap(short, long, value_parser)]
+        name: String,
+    },
+    /// Render a channel's logs into a static archive of daily HTML and NDJSON files with an index page
+    Export {
+        /// ID of the channel to export
+        #[clap(short, long, value_parser)]
+        channel_id: String,
+        /// Where to write the archive, e.g. `file:///srv/archive/forsen` or `s3://bucket/forsen`
+        #[clap(short, long, value_parser)]
+        output: String,
+        /// Options of the output store as `key=value`, e.g. `aws_region=eu-central-1`
+        #[clap(long = "option", value_parser = parse_key_value)]
+        options: Vec<(String, String)>,
+        /// Continue an interrupted export, skipping the days which have already been written
+        #[clap(long)]
+        resume: bool,
+    },
+    /// Check the database, Twitch credentials and IRC connection and print a report, without starting the logger
+    Doctor,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImportFormat {
+    /// Raw IRC lines in daily `channel.txt` or `channel.txt.gz` files under `<channel id>/<year>/<month>/<day>`
+    Justlog,
+}
+
+fn parse_key_value(arg: &str) -> Result<(String, String), String> {
+    arg.split_once('=')
+        .map(|(key, value)| (key.to_owned(), value.to_owned()))
+        .ok_or_else(|| format!("Expected key=value, got {arg}"))
 }
@@ -0,0 +1,153 @@
+use crate::{
+    app::App,
+    config::BackupConfig,
+    db::backups::{read_last_successful_backup, write_backup, BackupRow},
+    ShutdownRx,
+};
+use anyhow::anyhow;
+use chrono::Utc;
+use clickhouse::Client;
+use std::{
+    sync::atomic::{AtomicBool, Ordering},
+    time::Duration,
+};
+use thiserror::Error;
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+/// Delay before a failed backup is attempted again, unless the interval is shorter
+const RETRY_DELAY_SECONDS: u64 = 3600;
+
+static BACKUP_RUNNING: AtomicBool = AtomicBool::new(false);
+
+#[derive(Error, Debug)]
+#[error("A backup is already running")]
+pub struct BackupAlreadyRunning;
+
+/// Clears the running flag even if the backup's future is dropped, e.g. when the request is cancelled
+struct RunningGuard;
+
+impl Drop for RunningGuard {
+    fn drop(&mut self) {
+        BACKUP_RUNNING.store(false, Ordering::SeqCst);
+    }
+}
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let Some(config) = &app.config.backup else {
+        debug!("No backup destination configured, backup scheduler is idle");
+        shutdown_rx.changed().await.ok();
+        return;
+    };
+
+    let mut last_failed = false;
+
+    loop {
+        let mut delay = match read_last_successful_backup(&app.db).await {
+            Ok(Some(last_backup)) => {
+                let due = i64::from(last_backup) + config.interval as i64;
+                Duration::from_secs((due - Utc::now().timestamp()).max(0) as u64)
+            }
+            Ok(None) => Duration::ZERO,
+            Err(err) => {
+                error!("Could not read backup catalog: {err}");
+                Duration::from_secs(config.interval)
+            }
+        };
+        // The last successful backup is still overdue after a failure, which would otherwise retry immediately
+        if last_failed {
+            delay = delay.max(Duration::from_secs(
+                RETRY_DELAY_SECONDS.min(config.interval),
+            ));
+        }
+
+        tokio::select! {
+            _ = sleep(delay) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down backup scheduler");
+                break;
+            }
+        }
+
+        last_failed = match create_backup(&app).await {
+            Ok(row) => !row.success,
+            Err(err) => {
+                error!("Could not create backup: {err:#}");
+                true
+            }
+        };
+    }
+}
+
+/// Backs up the whole database and records the result in the backup catalog
+pub async fn create_backup(app: &App) -> anyhow::Result<BackupRow> {
+    let config = app
+        .config
+        .backup
+        .as_ref()
+        .ok_or_else(|| anyhow!("No backup destination configured"))?;
+
+    if BACKUP_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err(BackupAlreadyRunning.into());
+    }
+    let running = RunningGuard;
+
+    let started_at = Utc::now();
+    let name = format!("rustlog-{}", started_at.format("%Y%m%d-%H%M%S"));
+    let url = backup_url(config, &name);
+    info!("Creating backup {name}");
+
+    let result = app
+        .db
+        .query(&format!(
+            "BACKUP DATABASE {} TO S3(?, ?, ?)",
+            app.config.clickhouse_db
+        ))
+        .bind(&url)
+        .bind(config.access_key_id.expose())
+        .bind(config.secret_access_key.expose())
+        .execute()
+        .await;
+    drop(running);
+
+    match &result {
+        Ok(()) => info!("Created backup {name}"),
+        Err(err) => error!("Backup {name} failed: {err}"),
+    }
+
+    let row = BackupRow {
+        name,
+        url,
+        started_at: started_at.timestamp() as u32,
+        finished_at: Utc::now().timestamp() as u32,
+        success: result.is_ok(),
+        error: result.err().map(|err| err.to_string()).unwrap_or_default(),
+    };
+    write_backup(&app.db, &row).await?;
+
+    Ok(row)
+}
+
+/// Restores the whole database from a backup. Existing tables are not overwritten, so this has to be run against an empty database
+pub async fn restore(
+    db: &Client,
+    db_name: &str,
+    config: &BackupConfig,
+    name: &str,
+) -> anyhow::Result<()> {
+    info!("Restoring backup {name}");
+
+    db.query(&format!("RESTORE DATABASE {db_name} FROM S3(?, ?, ?)"))
+        .bind(backup_url(config, name))
+        .bind(config.access_key_id.expose())
+        .bind(config.secret_access_key.expose())
+        .execute()
+        .await?;
+
+    info!("Restored backup {name}");
+    Ok(())
+}
+
+fn backup_url(config: &BackupConfig, name: &str) -> String {
+    format!("{}/{name}", config.destination.trim_end_matches('/'))
+}
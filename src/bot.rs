@@ -1,34 +1,58 @@
 use crate::{
     app::App,
-    db::schema::{StructuredMessage, UnstructuredMessage},
-    logs::extract::{extract_channel_and_user_from_raw, extract_raw_timestamp},
+    db::{
+        consent::write_user_consent,
+        schema::{StructuredMessage, UnstructuredMessage},
+        unparsed::UnparsedMessage,
+    },
+    ids::UserId,
+    logs::{
+        dedup::RecentIds,
+        extract::{extract_channel_and_user_from_raw, extract_raw_timestamp},
+    },
+    transport::{take_original_bytes, SanitizingTransport},
+    watchdog::{Heartbeat, HEARTBEAT_INTERVAL_SECONDS},
+    web::schema::PauseMode,
     ShutdownRx,
 };
 use anyhow::anyhow;
 use chrono::Utc;
 use lazy_static::lazy_static;
-use prometheus::{register_int_counter_vec, IntCounterVec};
-use std::time::Duration;
+use prometheus::{register_int_counter, register_int_counter_vec, IntCounter, IntCounterVec};
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
 use tokio::{
-    sync::mpsc::{Receiver, Sender},
-    time::sleep,
+    sync::{
+        mpsc::{Receiver, Sender},
+        oneshot,
+    },
+    time::{interval, sleep},
 };
 use tracing::{debug, error, info, log::warn, trace};
 use twitch_irc::{
     login::LoginCredentials,
-    message::{AsRawIRC, IRCMessage, ServerMessage},
-    ClientConfig, SecureTCPTransport, TwitchIRCClient,
+    message::{AsRawIRC, IRCMessage, ServerMessage, TwitchUserBasics},
+    ClientConfig, TwitchIRCClient,
 };
 
 const CHANNEL_REJOIN_INTERVAL_SECONDS: u64 = 3600;
 const CHANNELS_REFETCH_RETRY_INTERVAL_SECONDS: u64 = 5;
+/// Messages replayed after a reconnect arrive within seconds, so this covers even busy instances
+const RECENT_MESSAGE_IDS: usize = 50_000;
 
-type TwitchClient<C> = TwitchIRCClient<SecureTCPTransport, C>;
+type TwitchClient<C> = TwitchIRCClient<SanitizingTransport, C>;
 
 #[derive(Debug)]
 pub enum BotMessage {
     JoinChannels(Vec<String>),
     PartChannels(Vec<String>),
+    ArchiveChannels(Vec<String>),
+    PauseChannels(Vec<String>, PauseMode),
+    ResumeChannels(Vec<String>),
+    /// Whether the bot wants to be and is joined to the channel with the given login
+    ChannelStatus(String, oneshot::Sender<(bool, bool)>),
 }
 
 lazy_static! {
@@ -38,6 +62,22 @@ lazy_static! {
         &["channel_id"]
     )
     .unwrap();
+    static ref MESSAGES_UNPARSED_COUNTER: IntCounter = register_int_counter!(
+        "rustlog_messages_unparsed",
+        "How many messages could not be parsed"
+    )
+    .unwrap();
+    static ref MESSAGES_SHED_COUNTERS: IntCounterVec = register_int_counter_vec!(
+        "rustlog_messages_shed",
+        "How many messages were dropped because the database writer was falling behind",
+        &["channel_id"]
+    )
+    .unwrap();
+    static ref MESSAGES_DUPLICATE_COUNTER: IntCounter = register_int_counter!(
+        "rustlog_messages_duplicate",
+        "How many messages were dropped because they had already been received"
+    )
+    .unwrap();
 }
 
 const COMMAND_PREFIX: &str = "!rustlog ";
@@ -46,22 +86,37 @@ pub async fn run<C: LoginCredentials>(
     login_credentials: C,
     app: App,
     writer_tx: Sender<StructuredMessage<'static>>,
+    unparsed_tx: Sender<UnparsedMessage>,
     shutdown_rx: ShutdownRx,
     command_rx: Receiver<BotMessage>,
+    heartbeat: Heartbeat,
 ) {
-    let bot = Bot::new(app, writer_tx);
-    bot.run(login_credentials, shutdown_rx, command_rx).await;
+    let bot = Bot::new(app, writer_tx, unparsed_tx);
+    bot.run(login_credentials, shutdown_rx, command_rx, heartbeat)
+        .await;
 }
 
 #[derive(Clone)]
-struct Bot {
+pub(crate) struct Bot {
     app: App,
     writer_tx: Sender<StructuredMessage<'static>>,
+    unparsed_tx: Sender<UnparsedMessage>,
+    /// Shared by the live feed and the writer, so neither sees a message twice
+    recent_ids: Arc<Mutex<RecentIds>>,
 }
 
 impl Bot {
-    pub fn new(app: App, writer_tx: Sender<StructuredMessage<'static>>) -> Bot {
-        Self { app, writer_tx }
+    pub fn new(
+        app: App,
+        writer_tx: Sender<StructuredMessage<'static>>,
+        unparsed_tx: Sender<UnparsedMessage>,
+    ) -> Bot {
+        Self {
+            app,
+            writer_tx,
+            unparsed_tx,
+            recent_ids: Arc::new(Mutex::new(RecentIds::new(RECENT_MESSAGE_IDS))),
+        }
     }
 
     pub async fn run<C: LoginCredentials>(
@@ -69,15 +124,22 @@ impl Bot {
         login_credentials: C,
         mut shutdown_rx: ShutdownRx,
         mut command_rx: Receiver<BotMessage>,
+        heartbeat: Heartbeat,
     ) {
         let client_config = ClientConfig::new_simple(login_credentials);
-        let (mut receiver, client) = TwitchIRCClient::<SecureTCPTransport, C>::new(client_config);
+        let (mut receiver, client) = TwitchIRCClient::<SanitizingTransport, C>::new(client_config);
 
         let app = self.app.clone();
         let join_client = client.clone();
         tokio::spawn(async move {
             loop {
-                let channel_ids = app.config.channels.read().unwrap().clone();
+                let mut channel_ids = app.config.channels.read().unwrap().clone();
+                channel_ids.retain(|channel_id| {
+                    app.config
+                        .paused_channels
+                        .get(channel_id)
+                        .map_or(true, |mode| *mode != PauseMode::Part)
+                });
 
                 let interval = match app
                     .get_users(Vec::from_iter(channel_ids), vec![], true)
@@ -131,12 +193,54 @@ impl Bot {
                             error!("Could not join channels: {err}");
                         }
                     }
+                    BotMessage::ArchiveChannels(channels) => {
+                        if let Err(err) = bot
+                            .update_channels(
+                                &msg_client,
+                                &channels.iter().map(String::as_str).collect::<Vec<_>>(),
+                                ChannelAction::Archive,
+                            )
+                            .await
+                        {
+                            error!("Could not archive channels: {err}");
+                        }
+                    }
+                    BotMessage::PauseChannels(channels, mode) => {
+                        if let Err(err) = bot
+                            .update_channels(
+                                &msg_client,
+                                &channels.iter().map(String::as_str).collect::<Vec<_>>(),
+                                ChannelAction::Pause(mode),
+                            )
+                            .await
+                        {
+                            error!("Could not pause channels: {err}");
+                        }
+                    }
+                    BotMessage::ResumeChannels(channels) => {
+                        if let Err(err) = bot
+                            .update_channels(
+                                &msg_client,
+                                &channels.iter().map(String::as_str).collect::<Vec<_>>(),
+                                ChannelAction::Resume,
+                            )
+                            .await
+                        {
+                            error!("Could not resume channels: {err}");
+                        }
+                    }
+                    BotMessage::ChannelStatus(channel_login, status_tx) => {
+                        let status = msg_client.get_channel_status(channel_login).await;
+                        let _ = status_tx.send(status);
+                    }
                 }
             }
         });
 
+        let mut heartbeat_ticker = interval(Duration::from_secs(HEARTBEAT_INTERVAL_SECONDS));
         loop {
             tokio::select! {
+                _ = heartbeat_ticker.tick() => heartbeat.beat(),
                 Some(msg) = receiver.recv() => {
                     if let Err(e) = self.handle_message(msg, &client).await {
                         error!("Could not handle message: {e}");
@@ -158,10 +262,7 @@ impl Bot {
         if let ServerMessage::Privmsg(privmsg) = &msg {
             trace!("Processing message {}", privmsg.message_text);
             if let Some(cmd) = privmsg.message_text.strip_prefix(COMMAND_PREFIX) {
-                if let Err(err) = self
-                    .handle_command(cmd, client, &privmsg.sender.login)
-                    .await
-                {
+                if let Err(err) = self.handle_command(cmd, client, &privmsg.sender).await {
                     warn!("Could not handle command {cmd}: {err:#}");
                 }
             }
@@ -186,13 +287,18 @@ impl Bot {
         }
     }
 
-    async fn write_message(&self, msg: ServerMessage) -> anyhow::Result<()> {
+    pub(crate) async fn write_message(&self, msg: ServerMessage) -> anyhow::Result<()> {
         // Ignore
         if matches!(msg, ServerMessage::RoomState(_)) {
             return Ok(());
         }
 
-        let irc_message = IRCMessage::from(msg);
+        let overloaded = self.app.flush_buffer.is_overloaded();
+        let low_priority_type = matches!(msg, ServerMessage::Join(_) | ServerMessage::Part(_));
+
+        let mut irc_message = IRCMessage::from(msg);
+        // Taken before the line is serialized again, so the tag is not stored
+        let original_bytes = take_original_bytes(&mut irc_message);
 
         if let Some((channel_id, maybe_user_id)) = extract_channel_and_user_from_raw(&irc_message) {
             if !channel_id.is_empty() {
@@ -201,11 +307,24 @@ impl Bot {
                     .inc();
             }
 
+            if self.app.config.paused_channels.contains_key(channel_id) {
+                return Ok(());
+            }
+
+            if overloaded
+                && (low_priority_type || self.app.config.low_priority_channels.contains(channel_id))
+            {
+                MESSAGES_SHED_COUNTERS
+                    .with_label_values(&[channel_id])
+                    .inc();
+                return Ok(());
+            }
+
             let timestamp = extract_raw_timestamp(&irc_message)
                 .unwrap_or_else(|| Utc::now().timestamp_millis().try_into().unwrap());
             let user_id = maybe_user_id.unwrap_or_default().to_owned();
 
-            if self.app.config.opt_out.contains_key(&user_id) {
+            if self.app.has_opted_out(&user_id) {
                 return Ok(());
             }
 
@@ -217,11 +336,38 @@ impl Bot {
                 raw: &raw_irc,
             };
             match StructuredMessage::from_unstructured(&unstructured) {
-                Ok(msg) => {
-                    self.writer_tx.send(msg.into_owned()).await?;
+                Ok(mut msg) => {
+                    if !self
+                        .app
+                        .config
+                        .stores_message_type(channel_id, msg.message_type)
+                    {
+                        return Ok(());
+                    }
+                    if let Some(id) = msg.uuid() {
+                        if !self.recent_ids.lock().unwrap().insert(id) {
+                            MESSAGES_DUPLICATE_COUNTER.inc();
+                            return Ok(());
+                        }
+                    }
+                    if let Some(original) = &original_bytes {
+                        msg.set_sanitized_original(original);
+                    }
+                    if self.app.config.normalize_text {
+                        msg.normalize_text();
+                    }
+                    let msg = msg.into_owned();
+                    if self.app.live_tx.receiver_count() > 0 {
+                        let _ = self.app.live_tx.send(Arc::new(msg.clone()));
+                    }
+                    self.writer_tx.send(msg).await?;
                 }
                 Err(err) => {
                     error!("Could not convert message {unstructured:?} to be logged: {err}");
+                    MESSAGES_UNPARSED_COUNTER.inc();
+                    self.unparsed_tx
+                        .send(UnparsedMessage::new(&unstructured, &err))
+                        .await?;
                 }
             }
         }
@@ -233,9 +379,10 @@ impl Bot {
         &self,
         cmd: &str,
         client: &TwitchClient<C>,
-        sender_login: &str,
+        sender: &TwitchUserBasics,
     ) -> anyhow::Result<()> {
         debug!("Processing command {cmd}");
+        let sender_login = sender.login.as_str();
         let mut split = cmd.split_whitespace();
         if let Some(action) = split.next() {
             let args: Vec<&str> = split.collect();
@@ -251,6 +398,25 @@ impl Bot {
                     self.update_channels(client, &args, ChannelAction::Part)
                         .await?
                 }
+                "archive" => {
+                    self.check_admin(sender_login)?;
+                    self.update_channels(client, &args, ChannelAction::Archive)
+                        .await?
+                }
+                "consent" => {
+                    let code = args
+                        .first()
+                        .ok_or_else(|| anyhow!("No consent code given"))?;
+                    if !self.app.take_consent_code(code) {
+                        return Err(anyhow!("Unknown or expired consent code"));
+                    }
+                    self.set_user_consent(&UserId::from(sender.id.as_str()), true)
+                        .await?
+                }
+                "revoke-consent" => {
+                    self.set_user_consent(&UserId::from(sender.id.as_str()), false)
+                        .await?
+                }
                 _ => (),
             }
         }
@@ -258,6 +424,17 @@ impl Bot {
         Ok(())
     }
 
+    async fn set_user_consent(&self, user_id: &UserId, consented: bool) -> anyhow::Result<()> {
+        write_user_consent(&self.app.db.primary(), user_id, consented).await?;
+        if consented {
+            self.app.consented_users.insert(user_id.to_string());
+        } else {
+            self.app.consented_users.remove(user_id.as_str());
+        }
+        info!("User {user_id} set their consent to {consented}");
+        Ok(())
+    }
+
     async fn update_channels<C: LoginCredentials>(
         &self,
         client: &TwitchClient<C>,
@@ -279,19 +456,43 @@ impl Bot {
 
         {
             let mut config_channels = self.app.config.channels.write().unwrap();
+            let mut archived_channels = self.app.config.archived_channels.write().unwrap();
 
             for (channel_id, channel_name) in channels {
                 match action {
                     ChannelAction::Join => {
                         info!("Joining channel {channel_name}");
+                        archived_channels.remove(&channel_id);
                         config_channels.insert(channel_id);
                         client.join(channel_name)?;
                     }
                     ChannelAction::Part => {
                         info!("Parting channel {channel_name}");
                         config_channels.remove(&channel_id);
+                        self.app.config.paused_channels.remove(&channel_id);
+                        client.part(channel_name);
+                    }
+                    ChannelAction::Archive => {
+                        info!("Archiving channel {channel_name}");
+                        config_channels.remove(&channel_id);
+                        self.app.config.paused_channels.remove(&channel_id);
+                        archived_channels.insert(channel_id);
                         client.part(channel_name);
                     }
+                    ChannelAction::Pause(mode) => {
+                        info!("Pausing channel {channel_name} ({mode:?})");
+                        if mode == PauseMode::Part {
+                            client.part(channel_name);
+                        }
+                        self.app.config.paused_channels.insert(channel_id, mode);
+                    }
+                    ChannelAction::Resume => {
+                        info!("Resuming channel {channel_name}");
+                        let paused = self.app.config.paused_channels.remove(&channel_id);
+                        if let Some((_, PauseMode::Part)) = paused {
+                            client.join(channel_name)?;
+                        }
+                    }
                 }
             }
         }
@@ -302,7 +503,13 @@ impl Bot {
     }
 }
 
+#[derive(Clone, Copy)]
 enum ChannelAction {
     Join,
     Part,
+    /// Part the channel but keep listing it as archived
+    Archive,
+    /// Stop logging the channel but keep it configured
+    Pause(PauseMode),
+    Resume,
 }
@@ -0,0 +1,155 @@
+use crate::{logs::normalize::normalize_text, Result};
+use chrono::{DateTime, Utc};
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+pub const SAVED_SEARCHES_TABLE: &str = "saved_search";
+pub const ALERT_MATCHES_TABLE: &str = "alert_match";
+/// Most matches stored for a single search in one evaluation
+const MAX_MATCHES_PER_RUN: u64 = 1000;
+
+/// Every change writes a new version of the row, deleted searches are kept with `deleted` set
+#[derive(Row, Serialize, Deserialize, Debug, Clone)]
+pub struct SavedSearchRow {
+    #[serde(with = "clickhouse::serde::uuid")]
+    pub id: Uuid,
+    /// Name of the API key which created the search
+    pub owner: String,
+    pub channel_id: String,
+    /// Empty if messages of all users are searched
+    pub user_id: String,
+    pub query: String,
+    /// Empty if no webhook is called
+    pub webhook_url: String,
+    pub created_at: u32,
+    pub updated_at: u64,
+    pub deleted: u8,
+}
+
+#[derive(Row, Serialize, Deserialize, Debug, Clone)]
+pub struct AlertMatchRow {
+    #[serde(with = "clickhouse::serde::uuid")]
+    pub search_id: Uuid,
+    pub channel_id: String,
+    pub user_id: String,
+    pub user_login: String,
+    pub timestamp: u64,
+    pub text: String,
+    pub matched_at: u32,
+}
+
+pub async fn write_saved_search(db: &Client, search: &SavedSearchRow) -> Result<()> {
+    let mut insert = db.insert(SAVED_SEARCHES_TABLE)?;
+    insert.write(search).await?;
+    insert.end().await?;
+
+    Ok(())
+}
+
+/// Searches of the given owner, or of all owners if none is given
+pub async fn read_saved_searches(db: &Client, owner: Option<&str>) -> Result<Vec<SavedSearchRow>> {
+    let owner_condition = if owner.is_some() { "AND owner = ?" } else { "" };
+    let query = format!(
+        "SELECT ?fields FROM saved_search FINAL WHERE deleted = 0 {owner_condition} ORDER BY created_at ASC"
+    );
+
+    let mut query = db.query(&query);
+    if let Some(owner) = owner {
+        query = query.bind(owner);
+    }
+    let searches = query.fetch_all().await?;
+
+    Ok(searches)
+}
+
+pub async fn read_saved_search(
+    db: &Client,
+    owner: &str,
+    id: Uuid,
+) -> Result<Option<SavedSearchRow>> {
+    let search = db
+        .query("SELECT ?fields FROM saved_search FINAL WHERE owner = ? AND id = ? AND deleted = 0")
+        .bind(owner)
+        .bind(id.to_string())
+        .fetch_optional()
+        .await?;
+
+    Ok(search)
+}
+
+/// Messages matching the saved search which were sent in the given range, oldest first
+pub async fn read_search_matches(
+    db: &Client,
+    search: &SavedSearchRow,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<Vec<AlertMatchRow>> {
+    let user_condition = if search.user_id.is_empty() {
+        ""
+    } else {
+        "AND user_id = ?"
+    };
+    let query = format!(
+        "SELECT toUUID(?) AS search_id, channel_id, user_id, user_login, timestamp, text, toUInt32(now()) AS matched_at
+        FROM message_structured
+        WHERE channel_id = ? {user_condition} AND timestamp >= ? AND timestamp < ?
+            AND (positionCaseInsensitive(text, ?) != 0 OR positionCaseInsensitive(text_normalized, ?) != 0)
+        ORDER BY timestamp ASC
+        LIMIT ?"
+    );
+
+    let mut query = db
+        .query(&query)
+        .bind(search.id.to_string())
+        .bind(&search.channel_id);
+    if !search.user_id.is_empty() {
+        query = query.bind(&search.user_id);
+    }
+    let matches = query
+        .bind(from.timestamp_millis() as f64 / 1000.0)
+        .bind(to.timestamp_millis() as f64 / 1000.0)
+        .bind(&search.query)
+        .bind(normalize_text(&search.query))
+        .bind(MAX_MATCHES_PER_RUN)
+        .fetch_all()
+        .await?;
+
+    Ok(matches)
+}
+
+pub async fn write_alert_matches(db: &Client, matches: &[AlertMatchRow]) -> Result<()> {
+    if matches.is_empty() {
+        return Ok(());
+    }
+
+    let mut insert = db.insert(ALERT_MATCHES_TABLE)?;
+    for alert_match in matches {
+        insert.write(alert_match).await?;
+    }
+    insert.end().await?;
+
+    Ok(())
+}
+
+/// Stored matches of a search, newest first
+pub async fn read_alert_matches(
+    db: &Client,
+    search_id: Uuid,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<AlertMatchRow>> {
+    let matches = db
+        .query(
+            "SELECT ?fields FROM alert_match WHERE search_id = ?
+            ORDER BY timestamp DESC
+            LIMIT ? OFFSET ?",
+        )
+        .bind(search_id.to_string())
+        .bind(limit)
+        .bind(offset)
+        .fetch_all()
+        .await?;
+
+    Ok(matches)
+}
@@ -0,0 +1,43 @@
+use clickhouse::Client;
+use std::collections::HashMap;
+
+use crate::Result;
+
+/// The channel which most recently used the login, also if the channel has been renamed since
+pub async fn read_channel_alias(db: &Client, login: &str) -> Result<Option<String>> {
+    let channel_id = db
+        .query(
+            "SELECT channel_id FROM channel_login_alias
+            WHERE channel_login = ?
+            GROUP BY channel_id
+            ORDER BY max(last_seen) DESC
+            LIMIT 1",
+        )
+        .bind(login)
+        .fetch_optional::<String>()
+        .await?;
+
+    Ok(channel_id)
+}
+
+/// The logins the channels were last logged with, by ID
+pub async fn read_channel_logins(
+    db: &Client,
+    channel_ids: &[String],
+) -> Result<HashMap<String, String>> {
+    if channel_ids.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let logins = db
+        .query(
+            "SELECT channel_id, argMax(channel_login, last_seen) FROM channel_login_alias
+            WHERE has(?, channel_id)
+            GROUP BY channel_id",
+        )
+        .bind(channel_ids)
+        .fetch_all::<(String, String)>()
+        .await?;
+
+    Ok(logins.into_iter().collect())
+}
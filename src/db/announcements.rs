@@ -0,0 +1,33 @@
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+
+use crate::{ids::ChannelId, web::schema::RangeParams, Result};
+
+#[derive(Row, Deserialize)]
+pub struct AnnouncementRow {
+    pub user_id: String,
+    pub user_login: String,
+    pub timestamp: u64,
+    pub text: String,
+    pub color: String,
+}
+
+pub async fn read_announcements(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<Vec<AnnouncementRow>> {
+    let announcements = db
+        .query(
+            "SELECT user_id, user_login, timestamp, text, extra_tags['msg-param-color'] AS color FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = 4 AND extra_tags['msg-id'] = 'announcement'
+            ORDER BY timestamp DESC",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .fetch_all::<AnnouncementRow>()
+        .await?;
+
+    Ok(announcements)
+}
@@ -0,0 +1,49 @@
+use crate::Result;
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+
+const AUDIT_LOG_TABLE: &str = "audit_log";
+
+/// An admin action which has to be traceable later, e.g. placing a legal hold
+#[derive(Row, Serialize, Deserialize)]
+pub struct AuditLogRow {
+    pub timestamp: u32,
+    /// Owner of the API key the action was made with
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub details: String,
+}
+
+pub async fn write_audit_entry(
+    db: &Client,
+    actor: &str,
+    action: &str,
+    target: &str,
+    details: &str,
+) -> Result<()> {
+    let mut insert = db.insert(AUDIT_LOG_TABLE)?;
+    insert
+        .write(&AuditLogRow {
+            timestamp: chrono::Utc::now().timestamp() as u32,
+            actor: actor.to_owned(),
+            action: action.to_owned(),
+            target: target.to_owned(),
+            details: details.to_owned(),
+        })
+        .await?;
+    insert.end().await?;
+
+    Ok(())
+}
+
+/// Newest entries first
+pub async fn read_audit_log(db: &Client, limit: u64) -> Result<Vec<AuditLogRow>> {
+    let rows = db
+        .query("SELECT ?fields FROM audit_log ORDER BY timestamp DESC LIMIT ?")
+        .bind(limit)
+        .fetch_all()
+        .await?;
+
+    Ok(rows)
+}
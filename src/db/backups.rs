@@ -0,0 +1,50 @@
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::{web::schema::BackupEntry, Result};
+
+pub const BACKUPS_TABLE: &str = "backup";
+
+#[derive(Row, Serialize, Deserialize)]
+pub struct BackupRow {
+    pub name: String,
+    /// Destination without credentials
+    pub url: String,
+    pub started_at: u32,
+    pub finished_at: u32,
+    pub success: bool,
+    pub error: String,
+}
+
+pub async fn write_backup(db: &Client, row: &BackupRow) -> Result<()> {
+    let mut insert = db.insert(BACKUPS_TABLE)?;
+    insert.write(row).await?;
+    insert.end().await?;
+
+    Ok(())
+}
+
+pub async fn read_backups(db: &Client) -> Result<Vec<BackupEntry>> {
+    let backups = db
+        .query(
+            "SELECT name, url, started_at, finished_at, success, error FROM backup
+            ORDER BY started_at DESC",
+        )
+        .fetch_all::<BackupRow>()
+        .await?
+        .into_iter()
+        .map(BackupEntry::from)
+        .collect();
+
+    Ok(backups)
+}
+
+/// Unix timestamp of when the most recent successful backup was started
+pub async fn read_last_successful_backup(db: &Client) -> Result<Option<u32>> {
+    let started_at = db
+        .query("SELECT max(started_at) FROM backup WHERE success")
+        .fetch_one::<u32>()
+        .await?;
+
+    Ok(Some(started_at).filter(|started_at| *started_at != 0))
+}
@@ -0,0 +1,93 @@
+use chrono::{DateTime, Utc};
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+use std::collections::HashMap;
+
+use super::schema::MessageType;
+use crate::{ids::ChannelId, Result};
+
+#[derive(Row, Deserialize)]
+pub struct ChannelUserRow {
+    pub user_id: String,
+    pub user_login: String,
+    pub last_seen: u32,
+}
+
+/// Users who sent a message in the channel with a login starting with the prefix.
+/// An exact match comes first, then the most recently seen users
+pub async fn read_channel_users(
+    db: &Client,
+    channel_id: &ChannelId,
+    prefix: &str,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<ChannelUserRow>> {
+    let users = db
+        .query(
+            "SELECT user_id, user_login, max(last_seen) AS last_seen FROM channel_user
+            WHERE channel_id = ? AND startsWith(user_login, ?) AND NOT has(?, user_id)
+            GROUP BY user_login, user_id
+            ORDER BY user_login = ? DESC, last_seen DESC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(prefix)
+        .bind(excluded_user_ids)
+        .bind(prefix)
+        .bind(limit)
+        .fetch_all::<ChannelUserRow>()
+        .await?;
+
+    Ok(users)
+}
+
+/// User IDs of the logins which have chatted in any logged channel, by login
+pub async fn read_user_ids_by_login(
+    db: &Client,
+    logins: &[String],
+) -> Result<HashMap<String, String>> {
+    if logins.is_empty() {
+        return Ok(HashMap::new());
+    }
+
+    let users = db
+        .query(
+            "SELECT user_login, argMax(user_id, last_seen) FROM channel_user
+            WHERE has(?, user_login) AND user_id != ''
+            GROUP BY user_login",
+        )
+        .bind(logins)
+        .fetch_all::<(String, String)>()
+        .await?;
+
+    Ok(users.into_iter().collect())
+}
+
+/// IDs of the users with the most chat messages since `since` in each of the channels
+pub async fn read_recent_top_chatter_ids(
+    db: &Client,
+    channel_ids: &[String],
+    since: DateTime<Utc>,
+    limit_per_channel: u64,
+) -> Result<Vec<String>> {
+    if channel_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let user_ids = db
+        .query(
+            "SELECT user_id FROM message_structured
+            WHERE has(?, channel_id) AND timestamp >= ? AND message_type = ? AND user_id != ''
+            GROUP BY channel_id, user_id
+            ORDER BY count() DESC
+            LIMIT ? BY channel_id",
+        )
+        .bind(channel_ids)
+        .bind(since.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(limit_per_channel)
+        .fetch_all::<String>()
+        .await?;
+
+    Ok(user_ids)
+}
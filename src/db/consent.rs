@@ -0,0 +1,37 @@
+use crate::{ids::UserId, Result};
+use clickhouse::{Client, Row};
+use serde::Serialize;
+
+const USER_CONSENT_TABLE: &str = "user_consent";
+
+#[derive(Row, Serialize)]
+struct UserConsentRow<'a> {
+    user_id: &'a str,
+    consented: u8,
+    updated_at: u32,
+}
+
+/// Users whose logs can be read when user consent is required
+pub async fn read_consented_users(db: &Client) -> Result<Vec<String>> {
+    let user_ids = db
+        .query("SELECT user_id FROM user_consent FINAL WHERE consented = 1")
+        .fetch_all::<String>()
+        .await?;
+
+    Ok(user_ids)
+}
+
+/// Records that the user gave or withdrew their consent
+pub async fn write_user_consent(db: &Client, user_id: &UserId, consented: bool) -> Result<()> {
+    let mut insert = db.insert(USER_CONSENT_TABLE)?;
+    insert
+        .write(&UserConsentRow {
+            user_id: user_id.as_str(),
+            consented: consented.into(),
+            updated_at: chrono::Utc::now().timestamp() as u32,
+        })
+        .await?;
+    insert.end().await?;
+
+    Ok(())
+}
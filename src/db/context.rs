@@ -0,0 +1,62 @@
+use super::{read_recent_messages, schema::StructuredMessage, writer::FlushBuffer};
+use crate::{error::Error, ids::ChannelId, Result};
+use chrono::{DateTime, Duration};
+use clickhouse::Client;
+use uuid::Uuid;
+
+/// How far after the message its following messages are looked up, so the query does not scan the rest of the channel
+const AFTER_LOOKAHEAD_DAYS: i64 = 1;
+
+/// A message with the channel messages sent around it
+pub struct MessageContext {
+    /// Oldest first
+    pub before: Vec<StructuredMessage<'static>>,
+    pub message: StructuredMessage<'static>,
+    /// Oldest first
+    pub after: Vec<StructuredMessage<'static>>,
+}
+
+/// Reads a stored message with up to `context` messages sent before and after it in the channel
+pub async fn read_message_context(
+    db: &Client,
+    channel_id: &ChannelId,
+    id: Uuid,
+    context: u64,
+    flush_buffer: &FlushBuffer,
+) -> Result<MessageContext> {
+    let message = db
+        .query("SELECT ?fields FROM message_structured WHERE channel_id = ? AND id = ? LIMIT 1")
+        .bind(channel_id)
+        .bind(id.to_string())
+        .fetch_optional::<StructuredMessage<'static>>()
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    let sent_at =
+        DateTime::from_timestamp_millis(message.timestamp as i64).ok_or(Error::Internal)?;
+    let mut before =
+        read_recent_messages(db, channel_id, None, sent_at, None, context, flush_buffer).await?;
+    before.reverse();
+
+    // Messages sent in the same millisecond are shown after the message
+    let after = db
+        .query(
+            "SELECT ?fields FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND id != ?
+            ORDER BY timestamp ASC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(message.timestamp as f64 / 1000.0)
+        .bind((sent_at + Duration::days(AFTER_LOOKAHEAD_DAYS)).timestamp())
+        .bind(id.to_string())
+        .bind(context)
+        .fetch_all()
+        .await?;
+
+    Ok(MessageContext {
+        before,
+        message,
+        after,
+    })
+}
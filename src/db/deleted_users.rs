@@ -0,0 +1,163 @@
+use super::purge::MESSAGE_COPY_TABLES;
+use crate::Result;
+use clickhouse::{Client, Row};
+use rand::{thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+
+const MISSING_USER_TABLE: &str = "missing_user";
+/// Hex digits of the pseudonyms of deleted users
+const PSEUDONYM_LENGTH: usize = 16;
+
+/// A logged user who could not be found in the Twitch API
+#[derive(Row, Serialize, Deserialize, Debug, Clone)]
+pub struct MissingUserRow {
+    pub user_id: String,
+    pub first_missing_at: u32,
+    pub last_checked_at: u32,
+    pub handled: u8,
+}
+
+/// IDs of the logged users in one of `partitions` groups, so every user is checked once per cycle
+pub async fn read_user_ids_partition(
+    db: &Client,
+    partition: u64,
+    partitions: u64,
+) -> Result<Vec<String>> {
+    let user_ids = db
+        .query(
+            "SELECT DISTINCT user_id FROM channel_user
+            WHERE user_id != '' AND NOT startsWith(user_id, 'deleted-') AND cityHash64(user_id) % ? = ?",
+        )
+        .bind(partitions)
+        .bind(partition)
+        .fetch_all::<String>()
+        .await?;
+
+    Ok(user_ids)
+}
+
+pub async fn read_missing_users(db: &Client, user_ids: &[String]) -> Result<Vec<MissingUserRow>> {
+    if user_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let users = db
+        .query("SELECT ?fields FROM missing_user FINAL WHERE has(?, user_id)")
+        .bind(user_ids)
+        .fetch_all::<MissingUserRow>()
+        .await?;
+
+    Ok(users)
+}
+
+pub async fn write_missing_users(db: &Client, users: &[MissingUserRow]) -> Result<()> {
+    if users.is_empty() {
+        return Ok(());
+    }
+
+    let mut insert = db.insert(MISSING_USER_TABLE)?;
+    for user in users {
+        insert.write(user).await?;
+    }
+    insert.end().await?;
+
+    Ok(())
+}
+
+/// Forgets users who can be found again, e.g. after their suspension was lifted
+pub async fn delete_missing_users(db: &Client, user_ids: &[String]) -> Result<()> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    db.query("DELETE FROM missing_user WHERE has(?, user_id)")
+        .bind(user_ids)
+        .execute()
+        .await?;
+
+    Ok(())
+}
+
+/// Deletes all messages of the users and their copies, except the ones in `held_channels`.
+/// The messages are removed in the background by a mutation
+pub async fn purge_users(db: &Client, user_ids: &[String], held_channels: &[String]) -> Result<()> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    db.query(
+        "ALTER TABLE message_structured DELETE WHERE has(?, user_id) AND NOT has(?, channel_id)",
+    )
+    .bind(user_ids)
+    .bind(held_channels)
+    .execute()
+    .await?;
+    for table in MESSAGE_COPY_TABLES {
+        db.query(&format!(
+            "DELETE FROM {table} WHERE has(?, user_id) AND NOT has(?, channel_id)"
+        ))
+        .bind(user_ids)
+        .bind(held_channels)
+        .execute()
+        .await?;
+    }
+    db.query("DELETE FROM channel_user WHERE has(?, user_id)")
+        .bind(user_ids)
+        .execute()
+        .await?;
+    db.query("DELETE FROM user_display_name WHERE has(?, user_id)")
+        .bind(user_ids)
+        .execute()
+        .await?;
+
+    Ok(())
+}
+
+/// Replaces the id, login and display name of the users' messages with a random pseudonym,
+/// so the messages stay in the channel logs without identifying the user.
+/// A hash of the id could be reversed by hashing every possible id
+pub async fn pseudonymize_users(
+    db: &Client,
+    user_ids: &[String],
+    held_channels: &[String],
+) -> Result<()> {
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    let pseudonyms: Vec<String> = {
+        let mut rng = thread_rng();
+        user_ids
+            .iter()
+            .map(|_| {
+                (0..PSEUDONYM_LENGTH)
+                    .map(|_| char::from_digit(rng.gen_range(0..16), 16).unwrap())
+                    .collect()
+            })
+            .collect()
+    };
+
+    db.query(
+        "INSERT INTO message_structured
+        SELECT * REPLACE (
+            concat('deleted-', transform(user_id, ?, ?, '')) AS user_id,
+            concat('deleted_', transform(user_id, ?, ?, '')) AS user_login,
+            '' AS display_name,
+            '' AS client_nonce,
+            [] AS extra_tags,
+            [] AS raw_invalid
+        )
+        FROM message_structured
+        WHERE has(?, user_id) AND NOT has(?, channel_id)",
+    )
+    .bind(user_ids)
+    .bind(&pseudonyms)
+    .bind(user_ids)
+    .bind(&pseudonyms)
+    .bind(user_ids)
+    .bind(held_channels)
+    .execute()
+    .await?;
+
+    purge_users(db, user_ids, held_channels).await
+}
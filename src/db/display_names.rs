@@ -0,0 +1,28 @@
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+
+use crate::{ids::UserId, Result};
+
+#[derive(Row, Deserialize)]
+pub struct DisplayNameRow {
+    pub display_name: String,
+    pub first_seen: u32,
+    pub last_seen: u32,
+}
+
+/// Distinct display names of the user, oldest first
+pub async fn read_display_names(db: &Client, user_id: &UserId) -> Result<Vec<DisplayNameRow>> {
+    let names = db
+        .query(
+            "SELECT display_name, min(first_seen) AS first_seen, max(last_seen) AS last_seen
+            FROM user_display_name
+            WHERE user_id = ?
+            GROUP BY display_name
+            ORDER BY first_seen ASC",
+        )
+        .bind(user_id)
+        .fetch_all::<DisplayNameRow>()
+        .await?;
+
+    Ok(names)
+}
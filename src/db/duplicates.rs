@@ -0,0 +1,99 @@
+use super::schema::MESSAGES_STRUCTURED_TABLE;
+use crate::{
+    web::schema::{DuplicateCount, RangeParams},
+    Result,
+};
+use chrono::{DateTime, Utc};
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+use std::collections::HashSet;
+use tracing::info;
+use uuid::Uuid;
+
+/// Messages without an ID (e.g. timeouts) can't be told apart from each other by it
+const DUPLICATES_SUBQUERY: &str = "
+    SELECT channel_id, id, count() AS copies FROM message_structured
+    WHERE timestamp >= ? AND timestamp < ? AND id != toUUID('00000000-0000-0000-0000-000000000000')
+    GROUP BY channel_id, id
+    HAVING copies > 1";
+
+/// Channels with messages which have been stored more than once, most affected first
+pub async fn read_duplicate_counts(
+    db: &Client,
+    params: RangeParams,
+) -> Result<Vec<DuplicateCount>> {
+    let counts = db
+        .query(&format!(
+            "SELECT channel_id, count() AS duplicate_ids, sum(copies - 1) AS extra_rows
+            FROM ({DUPLICATES_SUBQUERY})
+            GROUP BY channel_id
+            ORDER BY extra_rows DESC"
+        ))
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .fetch_all()
+        .await?;
+
+    Ok(counts)
+}
+
+/// Removes identical copies of rows in the partitions which contain duplicates in the range.
+/// Returns the cleaned partitions
+pub async fn remove_duplicates(db: &Client, params: RangeParams) -> Result<Vec<u32>> {
+    let partitions = db
+        .query(&format!(
+            "SELECT DISTINCT toYYYYMM(timestamp) AS partition FROM message_structured
+            WHERE timestamp >= ? AND timestamp < ? AND (channel_id, id) IN (SELECT channel_id, id FROM ({DUPLICATES_SUBQUERY}))
+            ORDER BY partition"
+        ))
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .fetch_all::<u32>()
+        .await?;
+
+    for partition in &partitions {
+        info!("Removing duplicate rows from partition {partition} of {MESSAGES_STRUCTURED_TABLE}");
+        // Rows which differ in any column are kept, so only true copies are removed
+        db.query(&format!(
+            "OPTIMIZE TABLE {MESSAGES_STRUCTURED_TABLE} PARTITION {partition} FINAL DEDUPLICATE"
+        ))
+        .execute()
+        .await?;
+    }
+
+    Ok(partitions)
+}
+
+#[derive(Row, Deserialize)]
+struct MessageKey {
+    timestamp: u64,
+    #[serde(with = "clickhouse::serde::uuid")]
+    id: Uuid,
+}
+
+/// Timestamps and IDs of the channel's stored messages in the range, which identify them for imports.
+/// Messages without an ID have the nil UUID
+pub async fn read_message_keys(
+    db: &Client,
+    channel_id: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Result<HashSet<(u64, Uuid)>> {
+    let keys = db
+        .query(
+            "SELECT timestamp, id FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ?",
+        )
+        .bind(channel_id)
+        .bind(from.timestamp_millis() as f64 / 1000.0)
+        .bind(to.timestamp_millis() as f64 / 1000.0)
+        .fetch_all::<MessageKey>()
+        .await?;
+
+    Ok(keys
+        .into_iter()
+        .map(|key| (key.timestamp, key.id))
+        .collect())
+}
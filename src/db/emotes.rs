@@ -0,0 +1,41 @@
+use clickhouse::{Client, Row};
+use serde::Serialize;
+
+use crate::{emotes::EmoteProvider, Result};
+
+pub const EMOTE_SETS_TABLE: &str = "emote_sets";
+
+#[derive(Row, Serialize)]
+pub struct ThirdPartyEmote {
+    pub channel_id: String,
+    pub provider: String,
+    pub emote_id: String,
+    pub name: String,
+    pub updated_at: u32,
+}
+
+/// Replaces the stored emotes of a provider in a channel, so that removed emotes are not counted anymore
+pub async fn replace_emote_set(
+    db: &Client,
+    channel_id: &str,
+    provider: EmoteProvider,
+    emotes: &[ThirdPartyEmote],
+) -> Result<()> {
+    db.query("DELETE FROM emote_sets WHERE channel_id = ? AND provider = ?")
+        .bind(channel_id)
+        .bind(provider.to_string())
+        .execute()
+        .await?;
+
+    if emotes.is_empty() {
+        return Ok(());
+    }
+
+    let mut insert = db.insert(EMOTE_SETS_TABLE)?;
+    for emote in emotes {
+        insert.write(emote).await?;
+    }
+    insert.end().await?;
+
+    Ok(())
+}
@@ -0,0 +1,71 @@
+use clickhouse::Client;
+
+use crate::{ids::ChannelId, web::schema::RangeParams, Result};
+
+/// Minutes (as unix timestamps) in which the channel was live according to the `stream` table,
+/// but no message was logged
+pub async fn read_silent_live_minutes(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<Vec<u32>> {
+    let minutes = db
+        .query(
+            "SELECT DISTINCT minute FROM (
+                SELECT arrayJoin(range(
+                    toUInt32(toStartOfMinute(greatest(started_at, toDateTime(?)))),
+                    toUInt32(least(ended_at, toDateTime(?))),
+                    60
+                )) AS minute
+                FROM stream FINAL
+                WHERE channel_id = ? AND started_at < toDateTime(?) AND ended_at >= toDateTime(?)
+            )
+            WHERE minute NOT IN (
+                SELECT DISTINCT toUInt32(toStartOfMinute(timestamp)) FROM message_structured
+                WHERE channel_id = ? AND timestamp >= ? AND timestamp < ?
+            )
+            ORDER BY minute",
+        )
+        .bind(params.from.timestamp())
+        .bind(params.to.timestamp())
+        .bind(channel_id)
+        .bind(params.to.timestamp())
+        .bind(params.from.timestamp())
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .fetch_all::<u32>()
+        .await?;
+
+    Ok(minutes)
+}
+
+/// Merges consecutive minutes into `(start, minute count)` gaps, keeping gaps of at least `min_minutes`
+pub fn group_gaps(minutes: &[u32], min_minutes: u32) -> Vec<(u32, u32)> {
+    let mut gaps: Vec<(u32, u32)> = Vec::new();
+
+    for &minute in minutes {
+        match gaps.last_mut() {
+            Some((start, length)) if *start + *length * 60 == minute => *length += 1,
+            _ => gaps.push((minute, 1)),
+        }
+    }
+
+    gaps.retain(|(_, length)| *length >= min_minutes);
+    gaps
+}
+
+#[cfg(test)]
+mod tests {
+    use super::group_gaps;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn groups_consecutive_minutes() {
+        let minutes = [0, 60, 120, 300, 600, 660];
+
+        assert_eq!(vec![(0, 3), (300, 1), (600, 2)], group_gaps(&minutes, 1));
+        assert_eq!(vec![(0, 3), (600, 2)], group_gaps(&minutes, 2));
+        assert!(group_gaps(&[], 1).is_empty());
+    }
+}
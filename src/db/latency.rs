@@ -0,0 +1,124 @@
+use crate::web::schema::{ChannelLatency, LatencyQuantiles};
+use chrono::Utc;
+use lazy_static::lazy_static;
+use prometheus::{register_gauge_vec, GaugeVec};
+use std::{
+    collections::{HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// Latest samples kept for each channel
+const CHANNEL_SAMPLES: usize = 500;
+/// Latest samples kept across all channels
+const TOTAL_SAMPLES: usize = 10_000;
+
+lazy_static! {
+    static ref INGESTION_LATENCY_GAUGE: GaugeVec = register_gauge_vec!(
+        "rustlog_ingestion_latency_seconds",
+        "Time from a message being sent (tmi-sent-ts) until it has been written to the database",
+        &["quantile"]
+    )
+    .unwrap();
+    pub static ref INGESTION_LATENCY: LatencyTracker = LatencyTracker::default();
+}
+
+/// Sampled end-to-end ingestion latencies in milliseconds
+#[derive(Default)]
+pub struct LatencyTracker {
+    samples: Mutex<Samples>,
+}
+
+#[derive(Default)]
+struct Samples {
+    channels: HashMap<String, VecDeque<u64>>,
+    total: VecDeque<u64>,
+}
+
+impl LatencyTracker {
+    /// Records the latency of messages which have just been written, given by channel and sent timestamp
+    pub fn record<'a>(&self, written: impl IntoIterator<Item = (&'a str, u64)>) {
+        let now = Utc::now().timestamp_millis() as u64;
+        let mut samples = self.samples.lock().unwrap();
+
+        for (channel_id, sent_at) in written {
+            // A sender clock ahead of ours shows up as zero latency
+            let latency = now.saturating_sub(sent_at);
+
+            let channel = samples.channels.entry(channel_id.to_owned()).or_default();
+            push_sample(channel, latency, CHANNEL_SAMPLES);
+            push_sample(&mut samples.total, latency, TOTAL_SAMPLES);
+        }
+
+        let total = quantiles(&samples.total);
+        drop(samples);
+
+        INGESTION_LATENCY_GAUGE
+            .with_label_values(&["0.5"])
+            .set(total.p50_ms as f64 / 1000.0);
+        INGESTION_LATENCY_GAUGE
+            .with_label_values(&["0.99"])
+            .set(total.p99_ms as f64 / 1000.0);
+    }
+
+    pub fn total(&self) -> LatencyQuantiles {
+        quantiles(&self.samples.lock().unwrap().total)
+    }
+
+    /// Latencies of each channel, slowest first
+    pub fn channels(&self) -> Vec<ChannelLatency> {
+        let samples = self.samples.lock().unwrap();
+        let mut channels: Vec<ChannelLatency> = samples
+            .channels
+            .iter()
+            .map(|(channel_id, samples)| ChannelLatency {
+                channel_id: channel_id.clone(),
+                latency: quantiles(samples),
+            })
+            .collect();
+        channels.sort_unstable_by(|a, b| b.latency.p99_ms.cmp(&a.latency.p99_ms));
+        channels
+    }
+}
+
+fn push_sample(samples: &mut VecDeque<u64>, latency: u64, capacity: usize) {
+    if samples.len() == capacity {
+        samples.pop_front();
+    }
+    samples.push_back(latency);
+}
+
+fn quantiles(samples: &VecDeque<u64>) -> LatencyQuantiles {
+    let mut sorted: Vec<u64> = samples.iter().copied().collect();
+    sorted.sort_unstable();
+
+    LatencyQuantiles {
+        p50_ms: quantile(&sorted, 0.5),
+        p99_ms: quantile(&sorted, 0.99),
+        samples: sorted.len(),
+    }
+}
+
+/// Nearest-rank quantile of sorted samples, 0 if there are none
+fn quantile(sorted: &[u64], q: f64) -> u64 {
+    if sorted.is_empty() {
+        return 0;
+    }
+    let rank = (q * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::quantile;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn nearest_rank_quantiles() {
+        let sorted: Vec<u64> = (1..=100).collect();
+
+        assert_eq!(50, quantile(&sorted, 0.5));
+        assert_eq!(99, quantile(&sorted, 0.99));
+        assert_eq!(7, quantile(&[7], 0.99));
+        assert_eq!(0, quantile(&[], 0.5));
+    }
+}
@@ -0,0 +1,74 @@
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+
+use crate::{
+    ids::ChannelId,
+    web::schema::{DomainCount, LinksParams, RangeParams},
+    Result,
+};
+
+const DEFAULT_LINKS_LIMIT: u64 = 1000;
+
+#[derive(Row, Deserialize)]
+pub struct LinkRow {
+    pub user_id: String,
+    pub user_login: String,
+    pub timestamp: u64,
+    pub domain: String,
+    pub url: String,
+}
+
+pub async fn read_links(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: &LinksParams,
+) -> Result<Vec<LinkRow>> {
+    let mut query =
+        "SELECT ?fields FROM links WHERE channel_id = ? AND timestamp >= ? AND timestamp < ?"
+            .to_owned();
+    if params.domain.is_some() {
+        query.push_str(" AND domain = ?");
+    }
+    query.push_str(" ORDER BY timestamp DESC LIMIT ?");
+
+    let mut query = db
+        .query(&query)
+        .bind(channel_id)
+        .bind(params.range.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.range.to.timestamp_millis() as f64 / 1000.0);
+    if let Some(domain) = &params.domain {
+        query = query.bind(domain);
+    }
+
+    let links = query
+        .bind(params.limit.unwrap_or(DEFAULT_LINKS_LIMIT))
+        .fetch_all::<LinkRow>()
+        .await?;
+    Ok(links)
+}
+
+pub async fn read_top_domains(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<DomainCount>> {
+    let domains = db
+        .query(
+            "SELECT domain, count() AS link_count FROM links
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND domain != '' AND NOT has(?, user_id)
+            GROUP BY domain
+            ORDER BY link_count DESC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(excluded_user_ids)
+        .bind(limit)
+        .fetch_all::<DomainCount>()
+        .await?;
+
+    Ok(domains)
+}
@@ -0,0 +1,66 @@
+use super::{apply_window_limit, schema::MessageType};
+use crate::{
+    logs::{
+        normalize::normalize_text,
+        schema::LogRangeParams,
+        stream::{FlushBufferResponse, LogsStream},
+    },
+    web::schema::LogsQueryBody,
+    Result,
+};
+
+/// Messages of several channels matching all filters of the query.
+/// Only messages which have been written to the database are returned
+pub async fn read_logs_query(
+    db: &clickhouse::Client,
+    body: &LogsQueryBody,
+    message_types: &[MessageType],
+) -> Result<LogsStream> {
+    let params = LogRangeParams {
+        from: body.from,
+        to: body.to,
+        logs_params: body.logs_params(),
+    };
+
+    let mut conditions = String::new();
+    if !body.users.is_empty() {
+        conditions.push_str(" AND has(?, user_id)");
+    }
+    if !message_types.is_empty() {
+        conditions.push_str(" AND has(?, message_type)");
+    }
+    if body.flagged {
+        conditions.push_str(" AND automod_flags != ''");
+    }
+    if body.text.is_some() {
+        conditions.push_str(
+            " AND (positionCaseInsensitive(text, ?) != 0 OR positionCaseInsensitive(text_normalized, ?) != 0)",
+        );
+    }
+
+    let suffix = if body.reverse { "DESC" } else { "ASC" };
+    let mut query = format!("SELECT ?fields FROM message_structured WHERE has(?, channel_id) AND timestamp >= ? AND timestamp < ?{conditions} ORDER BY timestamp {suffix}");
+    // Buffered messages are not merged, so the offset is part of the query
+    let offset = apply_window_limit(&mut query, body.limit, body.offset, false);
+
+    let mut query = db
+        .query(&query)
+        .bind(&body.channels)
+        .bind(body.from.timestamp_millis() as f64 / 1000.0)
+        .bind(body.to.timestamp_millis() as f64 / 1000.0);
+    if !body.users.is_empty() {
+        query = query.bind(&body.users);
+    }
+    if !message_types.is_empty() {
+        let message_types: Vec<u8> = message_types.iter().map(|t| *t as u8).collect();
+        query = query.bind(message_types);
+    }
+    if let Some(text) = &body.text {
+        query = query.bind(text).bind(normalize_text(text));
+    }
+    let cursor = query.fetch()?;
+
+    let flush_params = FlushBufferResponse::new(None, String::new(), None, params);
+    let stream = LogsStream::new_cursor(cursor, flush_params).await?;
+    Ok(stream.windowed(offset, body.limit))
+}
@@ -0,0 +1,565 @@
+//! Compares the live tables with the schema the migrations create, so manual DDL changes which could
+//! corrupt data (e.g. a changed column type or sorting key) are noticed
+
+use crate::{
+    config::SchemaDriftAction,
+    web::schema::{SchemaDifference, SchemaDifferenceKind},
+    Result,
+};
+use anyhow::bail;
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+use std::collections::HashMap;
+use tracing::warn;
+
+struct ExpectedTable {
+    name: &'static str,
+    /// Name, type and codec of each column. The codec is empty for the default compression
+    columns: &'static [(&'static str, &'static str, &'static str)],
+    sorting_key: &'static str,
+}
+
+/// The tables after all migrations have run, has to be updated along with every migration which changes a table
+const EXPECTED_TABLES: &[ExpectedTable] = &[
+    ExpectedTable {
+        name: "message_structured",
+        columns: &[
+            ("channel_id", "LowCardinality(String)", "ZSTD(8)"),
+            ("channel_login", "LowCardinality(String)", "ZSTD(8)"),
+            ("timestamp", "DateTime64(3)", "T64, ZSTD(5)"),
+            ("id", "UUID", "ZSTD(1)"),
+            ("message_type", "UInt8", "ZSTD(8)"),
+            ("user_id", "String", "ZSTD(8)"),
+            ("user_login", "String", "ZSTD(8)"),
+            ("display_name", "String", "ZSTD(8)"),
+            ("color", "Nullable(UInt32)", "ZSTD(8)"),
+            ("user_type", "LowCardinality(String)", "ZSTD(8)"),
+            ("badges", "Array(LowCardinality(String))", "ZSTD(8)"),
+            ("badge_info", "String", "ZSTD(8)"),
+            ("client_nonce", "String", "ZSTD(1)"),
+            ("emotes", "String", "ZSTD(8)"),
+            ("automod_flags", "String", "ZSTD(8)"),
+            ("text", "String", "ZSTD(8)"),
+            ("message_flags", "UInt16", "ZSTD(8)"),
+            (
+                "extra_tags",
+                "Map(LowCardinality(String), String)",
+                "ZSTD(8)",
+            ),
+            ("raw_invalid", "Array(UInt8)", "ZSTD(8)"),
+            ("text_normalized", "String", "ZSTD(8)"),
+        ],
+        sorting_key: "channel_id, user_id, timestamp",
+    },
+    ExpectedTable {
+        name: "reports",
+        columns: &[
+            ("channel_id", "LowCardinality(String)", ""),
+            ("period", "LowCardinality(String)", ""),
+            ("period_start", "DateTime", ""),
+            ("generated_at", "DateTime", ""),
+            ("data", "String", "ZSTD(5)"),
+        ],
+        sorting_key: "channel_id, period, period_start",
+    },
+    ExpectedTable {
+        name: "report_deliveries",
+        columns: &[
+            ("channel_id", "LowCardinality(String)", ""),
+            ("period", "LowCardinality(String)", ""),
+            ("period_start", "DateTime", ""),
+            ("url", "String", ""),
+            ("attempts", "UInt8", ""),
+            ("success", "Bool", ""),
+            ("error", "String", ""),
+            ("delivered_at", "DateTime", ""),
+        ],
+        sorting_key: "channel_id, delivered_at",
+    },
+    ExpectedTable {
+        name: "emote_sets",
+        columns: &[
+            ("channel_id", "LowCardinality(String)", ""),
+            ("provider", "LowCardinality(String)", ""),
+            ("emote_id", "String", ""),
+            ("name", "String", ""),
+            ("updated_at", "DateTime", ""),
+        ],
+        sorting_key: "channel_id, provider, emote_id",
+    },
+    ExpectedTable {
+        name: "links",
+        columns: &[
+            ("channel_id", "LowCardinality(String)", ""),
+            ("user_id", "String", "ZSTD(8)"),
+            ("user_login", "String", "ZSTD(8)"),
+            ("timestamp", "DateTime64(3)", "T64, ZSTD(5)"),
+            ("domain", "LowCardinality(String)", ""),
+            ("url", "String", "ZSTD(8)"),
+        ],
+        sorting_key: "channel_id, timestamp",
+    },
+    ExpectedTable {
+        name: "api_usage",
+        columns: &[
+            ("timestamp", "DateTime", ""),
+            ("route", "LowCardinality(String)", ""),
+            ("api_key", "LowCardinality(String)", ""),
+            ("ip", "String", ""),
+            ("status", "UInt16", ""),
+            ("rows", "UInt64", ""),
+            ("bytes", "UInt64", ""),
+        ],
+        sorting_key: "timestamp",
+    },
+    ExpectedTable {
+        name: "message_unparsed",
+        columns: &[
+            ("id", "UUID", ""),
+            ("channel_id", "LowCardinality(String)", ""),
+            ("user_id", "String", ""),
+            ("timestamp", "DateTime64(3)", ""),
+            ("raw", "String", ""),
+            ("error", "String", ""),
+        ],
+        sorting_key: "channel_id, timestamp",
+    },
+    ExpectedTable {
+        name: "stream",
+        columns: &[
+            ("stream_id", "String", ""),
+            ("channel_id", "LowCardinality(String)", ""),
+            ("started_at", "DateTime", ""),
+            ("ended_at", "DateTime", ""),
+            ("title", "String", ""),
+            ("game_name", "LowCardinality(String)", ""),
+            ("updated_at", "DateTime", ""),
+            ("video_id", "String", ""),
+            ("ended", "Bool", ""),
+        ],
+        sorting_key: "channel_id, stream_id",
+    },
+    ExpectedTable {
+        name: "backup",
+        columns: &[
+            ("name", "String", ""),
+            ("url", "String", ""),
+            ("started_at", "DateTime", ""),
+            ("finished_at", "DateTime", ""),
+            ("success", "Bool", ""),
+            ("error", "String", ""),
+        ],
+        sorting_key: "started_at",
+    },
+    ExpectedTable {
+        name: "channel_user",
+        columns: &[
+            ("channel_id", "LowCardinality(String)", ""),
+            ("user_login", "LowCardinality(String)", ""),
+            ("user_id", "String", ""),
+            ("last_seen", "SimpleAggregateFunction(max, DateTime)", ""),
+        ],
+        sorting_key: "channel_id, user_login, user_id",
+    },
+    ExpectedTable {
+        name: "saved_search",
+        columns: &[
+            ("id", "UUID", ""),
+            ("owner", "LowCardinality(String)", ""),
+            ("channel_id", "String", ""),
+            ("user_id", "String", ""),
+            ("query", "String", ""),
+            ("webhook_url", "String", ""),
+            ("created_at", "DateTime", ""),
+            ("updated_at", "DateTime64(3)", ""),
+            ("deleted", "UInt8", ""),
+        ],
+        sorting_key: "owner, id",
+    },
+    ExpectedTable {
+        name: "alert_match",
+        columns: &[
+            ("search_id", "UUID", ""),
+            ("channel_id", "LowCardinality(String)", ""),
+            ("user_id", "String", ""),
+            ("user_login", "String", ""),
+            ("timestamp", "DateTime64(3)", ""),
+            ("text", "String", ""),
+            ("matched_at", "DateTime", ""),
+        ],
+        sorting_key: "search_id, timestamp",
+    },
+    ExpectedTable {
+        name: "user_display_name",
+        columns: &[
+            ("user_id", "String", ""),
+            ("display_name", "String", ""),
+            ("first_seen", "SimpleAggregateFunction(min, DateTime)", ""),
+            ("last_seen", "SimpleAggregateFunction(max, DateTime)", ""),
+        ],
+        sorting_key: "user_id, display_name",
+    },
+    ExpectedTable {
+        name: "channel_login_alias",
+        columns: &[
+            ("channel_login", "String", ""),
+            ("channel_id", "String", ""),
+            ("last_seen", "SimpleAggregateFunction(max, DateTime)", ""),
+        ],
+        sorting_key: "channel_login, channel_id",
+    },
+    ExpectedTable {
+        name: "missing_user",
+        columns: &[
+            ("user_id", "String", ""),
+            ("first_missing_at", "DateTime", ""),
+            ("last_checked_at", "DateTime", ""),
+            ("handled", "UInt8", ""),
+        ],
+        sorting_key: "user_id",
+    },
+    ExpectedTable {
+        name: "user_consent",
+        columns: &[
+            ("user_id", "String", ""),
+            ("consented", "UInt8", ""),
+            ("updated_at", "DateTime", ""),
+        ],
+        sorting_key: "user_id",
+    },
+    ExpectedTable {
+        name: "user_opt_out",
+        columns: &[
+            ("user_id", "String", ""),
+            ("opted_out", "UInt8", ""),
+            ("purged", "UInt8", ""),
+            ("updated_at", "DateTime", ""),
+        ],
+        sorting_key: "user_id",
+    },
+    ExpectedTable {
+        name: "audit_log",
+        columns: &[
+            ("timestamp", "DateTime", ""),
+            ("actor", "String", ""),
+            ("action", "LowCardinality(String)", ""),
+            ("target", "String", ""),
+            ("details", "String", ""),
+        ],
+        sorting_key: "timestamp",
+    },
+];
+
+#[derive(Row, Deserialize)]
+struct ColumnRow {
+    table: String,
+    name: String,
+    column_type: String,
+    compression_codec: String,
+}
+
+#[derive(Row, Deserialize)]
+struct TableRow {
+    name: String,
+    sorting_key: String,
+}
+
+/// Columns by name with their type and codec, by table
+type LiveTables = HashMap<String, (String, HashMap<String, (String, String)>)>;
+
+/// Differences between the live tables and the expected schema, empty if they match
+pub async fn read_schema_drift(db: &Client) -> Result<Vec<SchemaDifference>> {
+    let table_names: Vec<_> = EXPECTED_TABLES.iter().map(|table| table.name).collect();
+
+    let tables = db
+        .query(
+            "SELECT name, sorting_key FROM system.tables
+            WHERE database = currentDatabase() AND has(?, name)",
+        )
+        .bind(&table_names)
+        .fetch_all::<TableRow>()
+        .await?;
+    let columns = db
+        .query(
+            "SELECT table, name, type AS column_type, compression_codec FROM system.columns
+            WHERE database = currentDatabase() AND has(?, table)",
+        )
+        .bind(&table_names)
+        .fetch_all::<ColumnRow>()
+        .await?;
+
+    let mut live: LiveTables = tables
+        .into_iter()
+        .map(|table| (table.name, (table.sorting_key, HashMap::new())))
+        .collect();
+    for column in columns {
+        if let Some((_, table_columns)) = live.get_mut(&column.table) {
+            table_columns.insert(column.name, (column.column_type, column.compression_codec));
+        }
+    }
+
+    Ok(compare_tables(EXPECTED_TABLES, &live))
+}
+
+fn compare_tables(expected_tables: &[ExpectedTable], live: &LiveTables) -> Vec<SchemaDifference> {
+    let mut differences = Vec::new();
+    let mut difference = |table: &str, column: Option<&str>, kind, expected: &str, actual: &str| {
+        differences.push(SchemaDifference {
+            table: table.to_owned(),
+            column: column.map(str::to_owned),
+            kind,
+            expected: expected.to_owned(),
+            actual: actual.to_owned(),
+        });
+    };
+
+    for expected in expected_tables {
+        let Some((sorting_key, columns)) = live.get(expected.name) else {
+            difference(
+                expected.name,
+                None,
+                SchemaDifferenceKind::MissingTable,
+                "",
+                "",
+            );
+            continue;
+        };
+
+        if normalize(sorting_key) != normalize(expected.sorting_key) {
+            difference(
+                expected.name,
+                None,
+                SchemaDifferenceKind::SortingKey,
+                expected.sorting_key,
+                sorting_key,
+            );
+        }
+
+        for &(name, column_type, codec) in expected.columns {
+            let Some((live_type, live_codec)) = columns.get(name) else {
+                difference(
+                    expected.name,
+                    Some(name),
+                    SchemaDifferenceKind::MissingColumn,
+                    column_type,
+                    "",
+                );
+                continue;
+            };
+
+            if normalize(live_type) != normalize(column_type) {
+                difference(
+                    expected.name,
+                    Some(name),
+                    SchemaDifferenceKind::Type,
+                    column_type,
+                    live_type,
+                );
+            }
+            let live_codec = live_codec
+                .strip_prefix("CODEC(")
+                .and_then(|codec| codec.strip_suffix(')'))
+                .unwrap_or(live_codec.as_str());
+            if normalize(live_codec) != normalize(codec) {
+                difference(
+                    expected.name,
+                    Some(name),
+                    SchemaDifferenceKind::Codec,
+                    codec,
+                    live_codec,
+                );
+            }
+        }
+
+        let mut unexpected: Vec<_> = columns
+            .iter()
+            .filter(|(name, _)| {
+                !expected
+                    .columns
+                    .iter()
+                    .any(|column| column.0 == name.as_str())
+            })
+            .collect();
+        unexpected.sort_by_key(|(name, _)| *name);
+        for (name, (column_type, _)) in unexpected {
+            difference(
+                expected.name,
+                Some(name),
+                SchemaDifferenceKind::UnexpectedColumn,
+                "",
+                column_type,
+            );
+        }
+    }
+
+    differences
+}
+
+/// ClickHouse formats types, codecs and keys with varying whitespace
+fn normalize(definition: &str) -> String {
+    definition.split_whitespace().collect()
+}
+
+/// Run after the migrations, as tables they have not changed yet would show up as drift
+pub async fn check_schema_drift(db: &Client, action: SchemaDriftAction) -> anyhow::Result<()> {
+    if action == SchemaDriftAction::Ignore {
+        return Ok(());
+    }
+
+    let differences = read_schema_drift(db).await?;
+    if differences.is_empty() {
+        return Ok(());
+    }
+    for difference in &differences {
+        warn!("Schema drift: {difference}");
+    }
+
+    if action == SchemaDriftAction::Refuse {
+        bail!(
+            "The database schema differs from the expected schema in {} places, fix the tables or set schemaDrift to \"warn\" to start anyway",
+            differences.len()
+        );
+    }
+    warn!(
+        "The database schema has been changed manually in {} places, this can lead to data being written incorrectly. See /admin/schema-drift",
+        differences.len()
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_tables, ExpectedTable, LiveTables};
+    use crate::web::schema::{SchemaDifference, SchemaDifferenceKind};
+    use pretty_assertions::assert_eq;
+    use std::collections::HashMap;
+
+    const EXPECTED: &[ExpectedTable] = &[
+        ExpectedTable {
+            name: "links",
+            columns: &[
+                ("channel_id", "LowCardinality(String)", ""),
+                ("timestamp", "DateTime64(3)", "T64, ZSTD(5)"),
+                ("url", "String", "ZSTD(8)"),
+            ],
+            sorting_key: "channel_id, timestamp",
+        },
+        ExpectedTable {
+            name: "audit_log",
+            columns: &[("timestamp", "DateTime", "")],
+            sorting_key: "timestamp",
+        },
+    ];
+
+    fn live_table(
+        sorting_key: &str,
+        columns: &[(&str, &str, &str)],
+    ) -> (String, HashMap<String, (String, String)>) {
+        let columns = columns
+            .iter()
+            .map(|(name, column_type, codec)| {
+                (
+                    name.to_string(),
+                    (column_type.to_string(), codec.to_string()),
+                )
+            })
+            .collect();
+        (sorting_key.to_owned(), columns)
+    }
+
+    fn difference(
+        column: Option<&str>,
+        kind: SchemaDifferenceKind,
+        expected: &str,
+        actual: &str,
+    ) -> SchemaDifference {
+        SchemaDifference {
+            table: "links".to_owned(),
+            column: column.map(str::to_owned),
+            kind,
+            expected: expected.to_owned(),
+            actual: actual.to_owned(),
+        }
+    }
+
+    #[test]
+    fn matching_tables_have_no_differences() {
+        let live: LiveTables = HashMap::from([
+            (
+                "links".to_owned(),
+                live_table(
+                    "channel_id, timestamp",
+                    &[
+                        ("channel_id", "LowCardinality(String)", ""),
+                        ("timestamp", "DateTime64(3)", "CODEC(T64, ZSTD(5))"),
+                        ("url", "String", "CODEC(ZSTD(8))"),
+                    ],
+                ),
+            ),
+            (
+                "audit_log".to_owned(),
+                live_table("timestamp", &[("timestamp", "DateTime", "")]),
+            ),
+        ]);
+
+        assert_eq!(compare_tables(EXPECTED, &live), vec![]);
+    }
+
+    #[test]
+    fn reports_changed_tables() {
+        let live: LiveTables = HashMap::from([(
+            "links".to_owned(),
+            live_table(
+                "channel_id",
+                &[
+                    ("channel_id", "String", ""),
+                    ("timestamp", "DateTime64(3)", "CODEC(ZSTD(1))"),
+                    ("referrer", "String", ""),
+                ],
+            ),
+        )]);
+
+        assert_eq!(
+            compare_tables(EXPECTED, &live),
+            vec![
+                difference(
+                    None,
+                    SchemaDifferenceKind::SortingKey,
+                    "channel_id, timestamp",
+                    "channel_id"
+                ),
+                difference(
+                    Some("channel_id"),
+                    SchemaDifferenceKind::Type,
+                    "LowCardinality(String)",
+                    "String"
+                ),
+                difference(
+                    Some("timestamp"),
+                    SchemaDifferenceKind::Codec,
+                    "T64, ZSTD(5)",
+                    "ZSTD(1)"
+                ),
+                difference(
+                    Some("url"),
+                    SchemaDifferenceKind::MissingColumn,
+                    "String",
+                    ""
+                ),
+                difference(
+                    Some("referrer"),
+                    SchemaDifferenceKind::UnexpectedColumn,
+                    "",
+                    "String"
+                ),
+                SchemaDifference {
+                    table: "audit_log".to_owned(),
+                    column: None,
+                    kind: SchemaDifferenceKind::MissingTable,
+                    expected: String::new(),
+                    actual: String::new(),
+                },
+            ]
+        );
+    }
+}
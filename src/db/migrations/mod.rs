@@ -1,3 +1,4 @@
+mod drift;
 mod migratable;
 mod structured;
 
@@ -7,6 +8,10 @@ use structured::StructuredMigration;
 use tracing::{debug, info};
 
 use self::migratable::Migratable;
+pub use drift::{check_schema_drift, read_schema_drift};
+
+/// Number of the newest migration, has to be raised along with every new migration
+pub const LATEST_MIGRATION: u32 = 39;
 
 pub async fn run(db: &Client, db_name: &str) -> Result<()> {
     create_migrations_table(db).await?;
@@ -71,6 +76,475 @@ String CODEC(ZSTD(10))
 
     run_migration(db, "6_structured_message", StructuredMigration { db_name }).await?;
 
+    run_migration(
+        db,
+        "7_add_user_channel_message_counts_projection",
+        "
+ALTER TABLE message_structured
+ADD PROJECTION user_channel_message_counts
+(SELECT user_id, channel_id, toDateTime(toStartOfDay(timestamp)) as date, count() GROUP BY user_id, channel_id, date)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "8_materialize_user_channel_message_counts_projection",
+        "
+ALTER TABLE message_structured
+MATERIALIZE PROJECTION user_channel_message_counts",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "9_create_reports",
+        "
+CREATE TABLE IF NOT EXISTS reports
+(
+    channel_id LowCardinality(String),
+    period LowCardinality(String),
+    period_start DateTime,
+    generated_at DateTime,
+    data String CODEC(ZSTD(5))
+)
+ENGINE = ReplacingMergeTree(generated_at)
+ORDER BY (channel_id, period, period_start)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "10_create_report_deliveries",
+        "
+CREATE TABLE IF NOT EXISTS report_deliveries
+(
+    channel_id LowCardinality(String),
+    period LowCardinality(String),
+    period_start DateTime,
+    url String,
+    attempts UInt8,
+    success Bool,
+    error String,
+    delivered_at DateTime
+)
+ENGINE = MergeTree
+ORDER BY (channel_id, delivered_at)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "11_create_emote_sets",
+        "
+CREATE TABLE IF NOT EXISTS emote_sets
+(
+    channel_id LowCardinality(String),
+    provider LowCardinality(String),
+    emote_id String,
+    name String,
+    updated_at DateTime
+)
+ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY (channel_id, provider, emote_id)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "12_create_links",
+        "
+CREATE TABLE IF NOT EXISTS links
+(
+    channel_id LowCardinality(String),
+    user_id String CODEC(ZSTD(8)),
+    user_login String CODEC(ZSTD(8)),
+    timestamp DateTime64(3) CODEC(T64, ZSTD(5)),
+    domain LowCardinality(String),
+    url String CODEC(ZSTD(8))
+)
+ENGINE = MergeTree
+PARTITION BY toYYYYMM(timestamp)
+ORDER BY (channel_id, timestamp)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "13_create_links_view",
+        "
+CREATE MATERIALIZED VIEW IF NOT EXISTS links_mv TO links AS
+SELECT channel_id, user_id, user_login, timestamp, domain(url) AS domain, url
+FROM message_structured
+ARRAY JOIN extractAll(text, '(?i)https?://[^ ]+') AS url
+WHERE message_type = 1",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "14_create_api_usage",
+        "
+CREATE TABLE IF NOT EXISTS api_usage
+(
+    timestamp DateTime,
+    route LowCardinality(String),
+    api_key LowCardinality(String),
+    ip String,
+    status UInt16,
+    rows UInt64,
+    bytes UInt64
+)
+ENGINE = MergeTree
+PARTITION BY toYYYYMMDD(timestamp)
+ORDER BY timestamp
+TTL timestamp + INTERVAL 30 DAY",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "15_create_message_unparsed",
+        "
+CREATE TABLE IF NOT EXISTS message_unparsed
+(
+    id UUID,
+    channel_id LowCardinality(String),
+    user_id String,
+    timestamp DateTime64(3),
+    raw String,
+    error String
+)
+ENGINE = MergeTree
+ORDER BY (channel_id, timestamp)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "16_add_raw_invalid_column",
+        "
+ALTER TABLE message_structured
+ADD COLUMN IF NOT EXISTS raw_invalid Array(UInt8) CODEC(ZSTD(8))",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "17_add_text_normalized_column",
+        "
+ALTER TABLE message_structured
+ADD COLUMN IF NOT EXISTS text_normalized String CODEC(ZSTD(8))",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "18_create_stream",
+        "
+CREATE TABLE IF NOT EXISTS stream
+(
+    stream_id String,
+    channel_id LowCardinality(String),
+    started_at DateTime,
+    ended_at DateTime,
+    title String,
+    game_name LowCardinality(String),
+    updated_at DateTime
+)
+ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY (channel_id, stream_id)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "19_create_backup",
+        "
+CREATE TABLE IF NOT EXISTS backup
+(
+    name String,
+    url String,
+    started_at DateTime,
+    finished_at DateTime,
+    success Bool,
+    error String
+)
+ENGINE = MergeTree
+ORDER BY started_at",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "20_create_channel_user",
+        "
+CREATE TABLE IF NOT EXISTS channel_user
+(
+    channel_id LowCardinality(String),
+    user_login LowCardinality(String),
+    user_id String,
+    last_seen SimpleAggregateFunction(max, DateTime)
+)
+ENGINE = AggregatingMergeTree
+ORDER BY (channel_id, user_login, user_id)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "21_create_channel_user_view",
+        "
+CREATE MATERIALIZED VIEW IF NOT EXISTS channel_user_mv TO channel_user AS
+SELECT channel_id, user_login, user_id, max(toDateTime(timestamp)) AS last_seen
+FROM message_structured
+WHERE user_login != ''
+GROUP BY channel_id, user_login, user_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "22_fill_channel_user",
+        "
+INSERT INTO channel_user
+SELECT channel_id, user_login, user_id, max(toDateTime(timestamp)) AS last_seen
+FROM message_structured
+WHERE user_login != ''
+GROUP BY channel_id, user_login, user_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "23_create_saved_search",
+        "
+CREATE TABLE IF NOT EXISTS saved_search
+(
+    id UUID,
+    owner LowCardinality(String),
+    channel_id String,
+    user_id String,
+    query String,
+    webhook_url String,
+    created_at DateTime,
+    updated_at DateTime64(3),
+    deleted UInt8
+)
+ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY (owner, id)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "24_create_alert_match",
+        "
+CREATE TABLE IF NOT EXISTS alert_match
+(
+    search_id UUID,
+    channel_id LowCardinality(String),
+    user_id String,
+    user_login String,
+    timestamp DateTime64(3),
+    text String,
+    matched_at DateTime
+)
+ENGINE = MergeTree
+ORDER BY (search_id, timestamp)
+TTL matched_at + INTERVAL 30 DAY",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "25_create_user_display_name",
+        "
+CREATE TABLE IF NOT EXISTS user_display_name
+(
+    user_id String,
+    display_name String,
+    first_seen SimpleAggregateFunction(min, DateTime),
+    last_seen SimpleAggregateFunction(max, DateTime)
+)
+ENGINE = AggregatingMergeTree
+ORDER BY (user_id, display_name)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "26_create_user_display_name_view",
+        "
+CREATE MATERIALIZED VIEW IF NOT EXISTS user_display_name_mv TO user_display_name AS
+SELECT user_id, display_name, min(toDateTime(timestamp)) AS first_seen, max(toDateTime(timestamp)) AS last_seen
+FROM message_structured
+WHERE user_id != '' AND display_name != ''
+GROUP BY user_id, display_name",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "27_fill_user_display_name",
+        "
+INSERT INTO user_display_name
+SELECT user_id, display_name, min(toDateTime(timestamp)) AS first_seen, max(toDateTime(timestamp)) AS last_seen
+FROM message_structured
+WHERE user_id != '' AND display_name != ''
+GROUP BY user_id, display_name",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "28_create_channel_login_alias",
+        "
+CREATE TABLE IF NOT EXISTS channel_login_alias
+(
+    channel_login String,
+    channel_id String,
+    last_seen SimpleAggregateFunction(max, DateTime)
+)
+ENGINE = AggregatingMergeTree
+ORDER BY (channel_login, channel_id)",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "29_create_channel_login_alias_view",
+        "
+CREATE MATERIALIZED VIEW IF NOT EXISTS channel_login_alias_mv TO channel_login_alias AS
+SELECT channel_login, channel_id, max(toDateTime(timestamp)) AS last_seen
+FROM message_structured
+WHERE channel_login != ''
+GROUP BY channel_login, channel_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "30_fill_channel_login_alias",
+        "
+INSERT INTO channel_login_alias
+SELECT channel_login, channel_id, max(toDateTime(timestamp)) AS last_seen
+FROM message_structured
+WHERE channel_login != ''
+GROUP BY channel_login, channel_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "31_create_missing_user",
+        "
+CREATE TABLE IF NOT EXISTS missing_user
+(
+    user_id String,
+    first_missing_at DateTime,
+    last_checked_at DateTime,
+    handled UInt8
+)
+ENGINE = ReplacingMergeTree(last_checked_at)
+ORDER BY user_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "32_create_user_consent",
+        "
+CREATE TABLE IF NOT EXISTS user_consent
+(
+    user_id String,
+    consented UInt8,
+    updated_at DateTime
+)
+ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY user_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "33_create_user_opt_out",
+        "
+CREATE TABLE IF NOT EXISTS user_opt_out
+(
+    user_id String,
+    opted_out UInt8,
+    purged UInt8,
+    updated_at DateTime
+)
+ENGINE = ReplacingMergeTree(updated_at)
+ORDER BY user_id",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "34_add_stream_video_id",
+        "
+ALTER TABLE stream
+ADD COLUMN IF NOT EXISTS video_id String",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "35_add_stream_ended",
+        "
+ALTER TABLE stream
+ADD COLUMN IF NOT EXISTS ended Bool DEFAULT false",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "36_create_audit_log",
+        "
+CREATE TABLE IF NOT EXISTS audit_log
+(
+    timestamp DateTime,
+    actor String,
+    action LowCardinality(String),
+    target String,
+    details String
+)
+ENGINE = MergeTree
+ORDER BY timestamp",
+    )
+    .await?;
+
+    // Lightweight deletes are rejected on tables with projections unless they are rebuilt
+    run_migration(
+        db,
+        "37_allow_lightweight_message_deletes",
+        "
+ALTER TABLE message_structured
+MODIFY SETTING lightweight_mutation_projection_mode = 'rebuild'",
+    )
+    .await?;
+
+    // Messages are looked up by their id for permalinks and deletions, which otherwise scans the whole channel
+    run_migration(
+        db,
+        "38_add_message_id_index",
+        "
+ALTER TABLE message_structured
+ADD INDEX IF NOT EXISTS id_bloom_filter id TYPE bloom_filter(0.01) GRANULARITY 1",
+    )
+    .await?;
+
+    run_migration(
+        db,
+        "39_materialize_message_id_index",
+        "
+ALTER TABLE message_structured
+MATERIALIZE INDEX id_bloom_filter",
+    )
+    .await?;
+
     Ok(())
 }
 
@@ -100,6 +574,25 @@ async fn run_migration<'a, T: Migratable<'a>>(
     Ok(())
 }
 
+/// Number of the newest migration which has been run on the database, 0 if none have been run
+pub async fn read_schema_version(db: &Client) -> Result<u32> {
+    let names: Vec<String> = db
+        .query("SELECT name FROM __rustlog_migrations")
+        .fetch_all()
+        .await?;
+
+    Ok(names
+        .iter()
+        .filter_map(|name| migration_number(name))
+        .max()
+        .unwrap_or(0))
+}
+
+/// Migrations are named like `36_create_audit_log`
+fn migration_number(name: &str) -> Option<u32> {
+    name.split_once('_')?.0.parse().ok()
+}
+
 async fn create_migrations_table(db: &Client) -> Result<()> {
     db.query(
         "
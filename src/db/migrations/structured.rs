@@ -42,6 +42,8 @@ CREATE TABLE message_structured
     `text` String CODEC(ZSTD(8)),
     `message_flags` UInt16 CODEC(ZSTD(8)),
     `extra_tags` Map(LowCardinality(String), String) CODEC(ZSTD(8)),
+    `raw_invalid` Array(UInt8) CODEC(ZSTD(8)),
+    `text_normalized` String CODEC(ZSTD(8)),
     PROJECTION channel_log_dates
     (
         SELECT
@@ -1,53 +1,113 @@
-use std::collections::HashMap;
+use std::cmp::Reverse;
+use std::collections::{HashMap, HashSet};
 use axum::extract::State;
 
-use chrono::{Datelike, DateTime, Duration, Utc};
-use clickhouse::{Client, query::RowCursor};
+use chrono::{Datelike, DateTime, Duration, NaiveDate, Utc};
+use chrono_tz::Tz;
+use clickhouse::{Client, query::{Query, RowCursor}};
+use lazy_static::lazy_static;
+use prometheus::{register_histogram_vec, HistogramTimer, HistogramVec};
 use rand::{seq::IteratorRandom, thread_rng};
 use tracing::debug;
+use uuid::Uuid;
 
-pub use migrations::run as setup_db;
+pub use migrations::{
+    check_schema_drift, read_schema_drift, read_schema_version, run as setup_db, LATEST_MIGRATION,
+};
+use pool::QueryClass;
 use writer::FlushBuffer;
-use schema::StructuredMessage;
+use schema::{MessageFlags, ScoredMessage, StructuredMessage};
 
 use crate::{
     error::Error,
+    ids::{ChannelId, UserId},
     logs::{
         schema::LogRangeParams,
         stream::{FlushBufferResponse, LogsStream},
     },
     Result,
-    web::schema::{AvailableLogDate, LogsParams, UserHasLogs},
+    web::schema::{
+        AvailableLogCount, AvailableLogDate, LogsParams, MessageSort, SampleRate, UserHasLogs,
+    },
 };
 use crate::app::App;
-use crate::web::schema::{UserLogins, UserParam};
+use crate::logs::normalize::normalize_text;
+use crate::web::schema::{DisplayNameEntry, UserLogins, UserParam};
 
+pub mod alerts;
+pub mod aliases;
+pub mod announcements;
+pub mod audit;
+pub mod backups;
+pub mod channel_users;
+pub mod consent;
+pub mod context;
+pub mod deleted_users;
+pub mod display_names;
+pub mod duplicates;
+pub mod emotes;
+pub mod gaps;
+pub mod latency;
+pub mod links;
+pub mod logs_query;
 mod migrations;
+pub mod nonces;
+pub mod opt_outs;
+pub mod pool;
+pub mod processes;
+#[cfg(test)]
+mod pruning;
+pub mod purge;
+pub mod reports;
+pub mod retention;
 pub mod schema;
+pub mod stats;
+pub mod storage;
+pub mod streams;
+pub mod unparsed;
+pub mod usage;
 pub mod writer;
 
 const CHANNEL_MULTI_QUERY_SIZE_DAYS: i64 = 14;
+/// Age after which the relevance of a search result is halved
+const RELEVANCE_HALF_LIFE_DAYS: u64 = 30;
+lazy_static! {
+    static ref QUERY_DURATION_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "rustlog_db_query_duration_seconds",
+        "How long database reads took, for streamed logs until the first rows were received",
+        &["query"]
+    )
+    .unwrap();
+}
+
+/// How far back the latest channel messages are looked up, so the query does not scan the whole channel
+const RECENT_CHANNEL_LOOKBACK_DAYS: i64 = 7;
+/// Messages which are not sorted by timestamp have to be sorted by ClickHouse as a whole before they can be returned
+const DEFAULT_SORTED_LIMIT: u64 = 100;
+const MAX_SORTED_LIMIT: u64 = 1000;
+/// Every skipped message has to be sorted as well
+const MAX_SORTED_OFFSET: u64 = 10_000;
+/// Months of [`read_available_user_logs`], bound with the timezone, channel and user
+const AVAILABLE_USER_LOGS_QUERY: &str = "SELECT toDateTime(toStartOfMonth(timestamp, ?)) AS date FROM message_structured WHERE channel_id = ? AND user_id = ? GROUP BY date ORDER BY date DESC";
 
 pub async fn read_channel(
     db: &Client,
-    channel_id: &str,
+    channel_id: &ChannelId,
     params: LogRangeParams,
+    excluded_users: &[String],
     flush_buffer: &FlushBuffer,
 ) -> Result<LogsStream> {
-    let suffix = if params.logs_params.reverse {
-        "DESC"
-    } else {
-        "ASC"
-    };
+    if params.logs_params.sort != MessageSort::Timestamp {
+        return read_sorted(db, channel_id, None, None, params, excluded_users).await;
+    }
 
-    let mut query = format!("SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? ORDER BY timestamp {suffix}");
+    let _timer = query_timer("read_channel");
+    let mut query = channel_logs_query(params.logs_params, excluded_users);
 
-    let flush_params = FlushBufferResponse {
-        buffer: Some(flush_buffer.clone()),
-        channel_id: channel_id.to_owned(),
-        user_id: None,
-        params,
-    };
+    let buffer = buffer_to_merge(flush_buffer, channel_id, params.from, params.to).await;
+    let merges_buffer = buffer.is_some();
+    let flush_params = FlushBufferResponse::new(buffer, channel_id.to_string(), None, params)
+        .with_excluded_users(excluded_users);
 
     let interval = Duration::days(CHANNEL_MULTI_QUERY_SIZE_DAYS);
     if params.to - params.from > interval {
@@ -67,14 +127,28 @@ pub async fn read_channel(
         let mut current_to = current_from + interval;
 
         loop {
-            let cursor = next_cursor(db, &query, channel_id, current_from, current_to)?;
+            let cursor = next_cursor(
+                db,
+                &query,
+                channel_id,
+                current_from,
+                current_to,
+                excluded_users,
+            )?;
             streams.push(cursor);
 
             current_from += interval;
             current_to += interval;
 
             if current_to > params.to {
-                let cursor = next_cursor(db, &query, channel_id, current_from, params.to)?;
+                let cursor = next_cursor(
+                    db,
+                    &query,
+                    channel_id,
+                    current_from,
+                    params.to,
+                    excluded_users,
+                )?;
                 streams.push(cursor);
                 break;
             }
@@ -86,96 +160,229 @@ pub async fn read_channel(
 
         debug!("Using {} queries for multi-query stream", streams.len());
 
-        LogsStream::new_multi_query(streams, flush_params)
+        let stream = LogsStream::new_multi_query(streams, flush_params)?;
+        Ok(stream.windowed(params.logs_params.offset, params.logs_params.limit))
     } else {
-        apply_limit_offset(
+        let offset = apply_window_limit(
             &mut query,
             params.logs_params.limit,
             params.logs_params.offset,
+            merges_buffer,
         );
 
-        let cursor = db
-            .query(&query)
-            .bind(channel_id)
-            .bind(params.from.timestamp_millis() as f64 / 1000.0)
-            .bind(params.to.timestamp_millis() as f64 / 1000.0)
-            .fetch()?;
-        LogsStream::new_cursor(cursor, flush_params).await
+        let cursor = next_cursor(
+            db,
+            &query,
+            channel_id,
+            params.from,
+            params.to,
+            excluded_users,
+        )?;
+        let stream = LogsStream::new_cursor(cursor, flush_params).await?;
+        Ok(stream.windowed(offset, params.logs_params.limit))
+    }
+}
+
+/// Query of [`read_channel`] for a single range, without the limit. The parameters are bound with [`bind_range`]
+fn channel_logs_query(params: LogsParams, excluded_users: &[String]) -> String {
+    let suffix = if params.reverse { "DESC" } else { "ASC" };
+    let filter_conditions = filter_conditions(params);
+    let excluded_users_condition = excluded_users_condition(excluded_users);
+    format!("SELECT ?fields FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? {filter_conditions} {excluded_users_condition} ORDER BY timestamp {suffix}")
+}
+
+/// Query of [`read_user`], without the limit. The parameters are bound with [`bind_range`]
+fn user_logs_query(params: LogsParams, excluded_users: &[String]) -> String {
+    let suffix = if params.reverse { "DESC" } else { "ASC" };
+    let filter_conditions = filter_conditions(params);
+    let excluded_users_condition = excluded_users_condition(excluded_users);
+    format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND timestamp >= ? AND timestamp < ? {filter_conditions} {excluded_users_condition} ORDER BY timestamp {suffix}")
+}
+
+fn bind_range(
+    mut query: Query,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    excluded_users: &[String],
+) -> Query {
+    query = query.bind(channel_id);
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    query = query
+        .bind(from.timestamp_millis() as f64 / 1000.0)
+        .bind(to.timestamp_millis() as f64 / 1000.0);
+    if !excluded_users.is_empty() {
+        query = query.bind(excluded_users);
+    }
+    query
+}
+
+/// Restricts a log query to the requested message types and flags, and to messages carrying AutoMod flags if requested.
+/// The filters are numbers, so they are written into the query instead of being bound
+fn filter_conditions(params: LogsParams) -> String {
+    let mut conditions = Vec::new();
+    if params.flagged {
+        conditions.push("AND automod_flags != ''".to_owned());
+    }
+    if !params.filter.message_type.is_empty() {
+        conditions.push(format!(
+            "AND bitTest({}, message_type)",
+            params.filter.message_type.bits()
+        ));
+    }
+    if !params.filter.flags.is_empty() {
+        let flags = params.filter.flags.bits();
+        conditions.push(format!("AND bitAnd(message_flags, {flags}) = {flags}"));
+    }
+    if let Some(sample) = params.sample {
+        // Hashed instead of random, so every page and every chunk of long ranges samples the same messages
+        conditions.push(format!(
+            "AND sipHash64(user_id, timestamp) % {} < {}",
+            SampleRate::SCALE,
+            sample.threshold()
+        ));
+    }
+    conditions.join(" ")
+}
+
+/// Skips messages of the excluded users, the users are bound after the other parameters
+fn excluded_users_condition(excluded_users: &[String]) -> &'static str {
+    if excluded_users.is_empty() {
+        ""
+    } else {
+        "AND NOT has(?, user_id)"
     }
 }
 
 fn next_cursor(
     db: &Client,
     query: &str,
-    channel_id: &str,
+    channel_id: &ChannelId,
     from: DateTime<Utc>,
     to: DateTime<Utc>,
+    excluded_users: &[String],
 ) -> Result<RowCursor<StructuredMessage<'static>>> {
-    let cursor = db
-        .query(query)
-        .bind(channel_id)
-        .bind(from.timestamp_millis() as f64 / 1000.0)
-        .bind(to.timestamp_millis() as f64 / 1000.0)
-        .fetch()?;
-    Ok(cursor)
+    let query = bind_range(db.query(query), channel_id, None, from, to, excluded_users);
+    Ok(query.fetch()?)
 }
 
 pub async fn read_user(
     db: &Client,
-    channel_id: &str,
-    user_id: &str,
+    channel_id: &ChannelId,
+    user_id: &UserId,
     params: LogRangeParams,
+    excluded_users: &[String],
     flush_buffer: &FlushBuffer,
 ) -> Result<LogsStream> {
+    if params.logs_params.sort != MessageSort::Timestamp {
+        return read_sorted(db, channel_id, Some(user_id), None, params, excluded_users).await;
+    }
+
+    let _timer = query_timer("read_user");
+    let mut query = user_logs_query(params.logs_params, excluded_users);
+    let buffer = buffer_to_merge(flush_buffer, channel_id, params.from, params.to).await;
+    let offset = apply_window_limit(
+        &mut query,
+        params.logs_params.limit,
+        params.logs_params.offset,
+        buffer.is_some(),
+    );
+
+    let flush_params = FlushBufferResponse::new(
+        buffer,
+        channel_id.to_string(),
+        Some(user_id.to_string()),
+        params,
+    )
+    .with_excluded_users(excluded_users);
+
+    let cursor = bind_range(
+        db.query(&query),
+        channel_id,
+        Some(user_id),
+        params.from,
+        params.to,
+        excluded_users,
+    )
+    .fetch()?;
+    let stream = LogsStream::new_cursor(cursor, flush_params).await?;
+    Ok(stream.windowed(offset, params.logs_params.limit))
+}
+
+pub async fn read_first_time_chatters(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: LogRangeParams,
+) -> Result<LogsStream> {
+    let _timer = query_timer("read_first_time_chatters");
     let suffix = if params.logs_params.reverse {
         "DESC"
     } else {
         "ASC"
     };
-    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND timestamp >= ? AND timestamp < ? ORDER BY timestamp {suffix}");
+    let filter_conditions = filter_conditions(params.logs_params);
+    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND bitAnd(message_flags, ?) != 0 {filter_conditions} ORDER BY timestamp {suffix}");
     apply_limit_offset(
         &mut query,
         params.logs_params.limit,
         params.logs_params.offset,
     );
 
-    let flush_params = FlushBufferResponse {
-        buffer: Some(flush_buffer.clone()),
-        channel_id: channel_id.to_owned(),
-        user_id: Some(user_id.to_owned()),
-        params,
-    };
-
     let cursor = db
         .query(&query)
         .bind(channel_id)
-        .bind(user_id)
         .bind(params.from.timestamp_millis() as f64 / 1000.0)
         .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageFlags::FIRST_MSG.bits())
         .fetch()?;
+
+    let flush_params = FlushBufferResponse::new(None, channel_id.to_string(), None, params);
     LogsStream::new_cursor(cursor, flush_params).await
 }
 
+/// IDs of all channels which have logs
+pub async fn read_logged_channel_ids(db: &Client) -> Result<Vec<String>> {
+    let channel_ids = db
+        .query("SELECT DISTINCT channel_id FROM message_structured")
+        .fetch_all()
+        .await?;
+
+    Ok(channel_ids)
+}
+
 pub async fn read_available_channel_logs(
     db: &Client,
-    channel_id: &str,
+    channel_id: &ChannelId,
+    timezone: Tz,
 ) -> Result<Vec<AvailableLogDate>> {
-    let timestamps: Vec<i32> = db
-        .query(
+    let _timer = query_timer("read_available_channel_logs");
+    // Days in UTC can be read from the channel_log_dates projection
+    let query = if timezone == Tz::UTC {
+        db.query(
             "SELECT toDateTime(toStartOfDay(timestamp)) AS date FROM message_structured WHERE channel_id = ? GROUP BY date ORDER BY date DESC",
         )
         .bind(channel_id)
-        .fetch_all().await?;
+    } else {
+        db.query(
+            "SELECT toDateTime(toStartOfDay(timestamp, ?)) AS date FROM message_structured WHERE channel_id = ? GROUP BY date ORDER BY date DESC",
+        )
+        .bind(timezone.name())
+        .bind(channel_id)
+    };
+    let timestamps: Vec<i32> = query.fetch_all().await?;
 
     let dates = timestamps
         .into_iter()
         .map(|timestamp| {
-            let naive = DateTime::from_timestamp(timestamp.into(), 0).expect("Invalid DateTime");
+            let date = local_date(timestamp, timezone);
 
             AvailableLogDate {
-                year: naive.year().to_string(),
-                month: naive.month().to_string(),
-                day: Some(naive.day().to_string()),
+                year: date.year().to_string(),
+                month: date.month().to_string(),
+                day: Some(date.day().to_string()),
             }
         })
         .collect();
@@ -185,23 +392,27 @@ pub async fn read_available_channel_logs(
 
 pub async fn read_available_user_logs(
     db: &Client,
-    channel_id: &str,
-    user_id: &str,
+    channel_id: &ChannelId,
+    user_id: &UserId,
+    timezone: Tz,
 ) -> Result<Vec<AvailableLogDate>> {
+    let _timer = query_timer("read_available_user_logs");
     let timestamps: Vec<i32> = db
-        .query("SELECT toDateTime(toStartOfMonth(timestamp)) AS date FROM message_structured WHERE channel_id = ? AND user_id = ? GROUP BY date ORDER BY date DESC")
+        .query(AVAILABLE_USER_LOGS_QUERY)
+        .bind(timezone.name())
         .bind(channel_id)
         .bind(user_id)
-        .fetch_all().await?;
+        .fetch_all()
+        .await?;
 
     let dates = timestamps
         .into_iter()
         .map(|timestamp| {
-            let naive = DateTime::from_timestamp(timestamp.into(), 0).expect("Invalid DateTime");
+            let date = local_date(timestamp, timezone);
 
             AvailableLogDate {
-                year: naive.year().to_string(),
-                month: naive.month().to_string(),
+                year: date.year().to_string(),
+                month: date.month().to_string(),
                 day: None,
             }
         })
@@ -210,11 +421,228 @@ pub async fn read_available_user_logs(
     Ok(dates)
 }
 
+/// Like [`read_available_channel_logs`], with the amount of messages of each day
+pub async fn read_channel_day_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    timezone: Tz,
+) -> Result<Vec<AvailableLogCount>> {
+    let _timer = query_timer("read_channel_day_counts");
+    let rows: Vec<(i32, u64)> = db
+        .query("SELECT toDateTime(toStartOfDay(timestamp, ?)) AS date, count() FROM message_structured WHERE channel_id = ? GROUP BY date ORDER BY date DESC")
+        .bind(timezone.name())
+        .bind(channel_id)
+        .fetch_all()
+        .await?;
+
+    let counts = rows
+        .into_iter()
+        .map(|(timestamp, messages)| {
+            let date = local_date(timestamp, timezone);
+            AvailableLogCount {
+                date: AvailableLogDate {
+                    year: date.year().to_string(),
+                    month: date.month().to_string(),
+                    day: Some(date.day().to_string()),
+                },
+                messages,
+            }
+        })
+        .collect();
+
+    Ok(counts)
+}
+
+/// Like [`read_available_user_logs`], with the amount of messages of each month
+pub async fn read_user_month_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: &UserId,
+    timezone: Tz,
+) -> Result<Vec<AvailableLogCount>> {
+    let _timer = query_timer("read_user_month_counts");
+    let rows: Vec<(i32, u64)> = db
+        .query("SELECT toDateTime(toStartOfMonth(timestamp, ?)) AS date, count() FROM message_structured WHERE channel_id = ? AND user_id = ? GROUP BY date ORDER BY date DESC")
+        .bind(timezone.name())
+        .bind(channel_id)
+        .bind(user_id)
+        .fetch_all()
+        .await?;
+
+    let counts = rows
+        .into_iter()
+        .map(|(timestamp, messages)| {
+            let date = local_date(timestamp, timezone);
+            AvailableLogCount {
+                date: AvailableLogDate {
+                    year: date.year().to_string(),
+                    month: date.month().to_string(),
+                    day: None,
+                },
+                messages,
+            }
+        })
+        .collect();
+
+    Ok(counts)
+}
+
+/// The date of a local midnight returned by ClickHouse as a unix timestamp
+fn local_date(timestamp: i32, timezone: Tz) -> NaiveDate {
+    DateTime::from_timestamp(timestamp.into(), 0)
+        .expect("Invalid DateTime")
+        .with_timezone(&timezone)
+        .date_naive()
+}
+
+/// The latest `limit` messages of a channel or user before the cursor, newest first. Messages are ordered by timestamp and id,
+/// so with `before_id` the other messages sent in the same millisecond are not skipped. Unwritten messages are read from the flush buffer
+pub async fn read_recent_messages(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    before: DateTime<Utc>,
+    before_id: Option<Uuid>,
+    limit: u64,
+    flush_buffer: &FlushBuffer,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    let _timer = query_timer("read_recent_messages");
+    let before_seconds = before.timestamp_millis() as f64 / 1000.0;
+    let before_id = before_id.map(|id| id.to_string());
+    let stored = match user_id {
+        Some(_) => {
+            read_recent_stored(
+                db,
+                channel_id,
+                user_id,
+                before_seconds,
+                before_id.as_deref(),
+                None,
+                limit,
+            )
+            .await?
+        }
+        None => {
+            // Most channels fill a page within the lookback, which keeps the query to a few partitions
+            let from = (before - Duration::days(RECENT_CHANNEL_LOOKBACK_DAYS)).timestamp_millis()
+                as f64
+                / 1000.0;
+            let mut stored = read_recent_stored(
+                db,
+                channel_id,
+                None,
+                before_seconds,
+                before_id.as_deref(),
+                Some(from),
+                limit,
+            )
+            .await?;
+            if (stored.len() as u64) < limit {
+                // Older history after a gap of more than the lookback
+                let remaining = limit - stored.len() as u64;
+                stored.extend(
+                    read_recent_stored(db, channel_id, None, from, None, None, remaining).await?,
+                );
+            }
+            stored
+        }
+    };
+
+    let before_millis = before.timestamp_millis().max(0) as u64;
+    let time_range = 0..before_millis + 1;
+    let mut buffered = Vec::new();
+    let mut position = None;
+    while (buffered.len() as u64) < limit {
+        let page = flush_buffer
+            .messages_page(
+                time_range.clone(),
+                channel_id.as_str(),
+                user_id.map(UserId::as_str),
+                None,
+                true,
+                &mut position,
+            )
+            .await;
+        if page.is_empty() {
+            break;
+        }
+        buffered.extend(page.into_iter().filter(|msg| match &before_id {
+            Some(before_id) => {
+                (
+                    msg.timestamp,
+                    msg.uuid().unwrap_or_default().to_string().as_str(),
+                ) < (before_millis, before_id.as_str())
+            }
+            None => msg.timestamp < before_millis,
+        }));
+    }
+
+    // Messages can be flushed between both reads, so they may be returned twice
+    let mut seen = HashSet::new();
+    let mut messages: Vec<_> = buffered
+        .into_iter()
+        .chain(stored)
+        .filter(|msg| seen.insert((msg.timestamp, msg.id(), msg.user_id.to_string())))
+        .collect();
+    messages.sort_by_cached_key(|msg| {
+        Reverse((msg.timestamp, msg.uuid().unwrap_or_default().to_string()))
+    });
+    messages.truncate(limit as usize);
+
+    Ok(messages)
+}
+
+/// Stored messages of a channel or user before the cursor and optionally since `from`, newest first
+async fn read_recent_stored(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    before: f64,
+    before_id: Option<&str>,
+    from: Option<f64>,
+    limit: u64,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    let mut conditions = String::new();
+    if user_id.is_some() {
+        conditions.push_str(" AND user_id = ?");
+    }
+    if from.is_some() {
+        conditions.push_str(" AND timestamp >= ?");
+    }
+    if before_id.is_some() {
+        conditions.push_str(
+            " AND timestamp <= ? AND (timestamp, toString(id)) < (toDateTime64(?, 3), ?)",
+        );
+    } else {
+        conditions.push_str(" AND timestamp < ?");
+    }
+
+    let mut query = db
+        .query(&format!("SELECT ?fields FROM message_structured WHERE channel_id = ?{conditions} ORDER BY timestamp DESC, toString(id) DESC LIMIT ?"))
+        .bind(channel_id);
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    if let Some(from) = from {
+        query = query.bind(from);
+    }
+    query = query.bind(before);
+    if let Some(before_id) = before_id {
+        query = query.bind(before).bind(before_id);
+    }
+
+    Ok(query
+        .bind(limit)
+        .fetch_all::<StructuredMessage<'static>>()
+        .await?)
+}
+
 pub async fn read_random_user_line(
     db: &Client,
-    channel_id: &str,
-    user_id: &str,
+    channel_id: &ChannelId,
+    user_id: &UserId,
 ) -> Result<StructuredMessage<'static>> {
+    let _timer = query_timer("read_random_user_line");
     let total_count = db
         .query("SELECT count(*) FROM message_structured WHERE channel_id = ? AND user_id = ? ")
         .bind(channel_id)
@@ -250,15 +678,32 @@ pub async fn read_random_user_line(
     Ok(msg)
 }
 
+/// Skips the messages of `excluded_users` and, if `included_users` is set, of everyone else who is not in it.
+/// Messages without a user are always included
 pub async fn read_random_channel_line(
     db: &Client,
-    channel_id: &str,
+    channel_id: &ChannelId,
+    excluded_users: &[String],
+    included_users: Option<&[String]>,
 ) -> Result<StructuredMessage<'static>> {
-    let total_count = db
-        .query("SELECT count(*) FROM message_structured WHERE channel_id = ? ")
-        .bind(channel_id)
-        .fetch_one::<u64>()
-        .await?;
+    let _timer = query_timer("read_random_channel_line");
+    let mut conditions = String::from("channel_id = ? AND NOT has(?, user_id)");
+    if included_users.is_some() {
+        conditions.push_str(" AND (user_id = '' OR has(?, user_id))");
+    }
+    let bind_conditions = |mut query: Query| {
+        query = query.bind(channel_id).bind(excluded_users);
+        if let Some(included_users) = included_users {
+            query = query.bind(included_users);
+        }
+        query
+    };
+
+    let total_count = bind_conditions(db.query(&format!(
+        "SELECT count(*) FROM message_structured WHERE {conditions}"
+    )))
+    .fetch_one::<u64>()
+    .await?;
 
     if total_count == 0 {
         return Err(Error::NotFound);
@@ -269,16 +714,15 @@ pub async fn read_random_channel_line(
         (0..total_count).choose(&mut rng).ok_or(Error::NotFound)
     }?;
 
-    let msg = db
-        .query(
-            "WITH
-            (SELECT timestamp FROM message_structured WHERE channel_id = ? LIMIT 1 OFFSET ?)
-            AS random_timestamp
-            SELECT * FROM message_structured WHERE channel_id = ? AND timestamp = random_timestamp",
-        )
-        .bind(channel_id)
-        .bind(offset)
-        .bind(channel_id)
+    let query = bind_conditions(db.query(&format!(
+        "WITH
+        (SELECT timestamp FROM message_structured WHERE {conditions} LIMIT 1 OFFSET ?)
+        AS random_timestamp
+        SELECT * FROM message_structured WHERE {conditions} AND timestamp = random_timestamp
+        LIMIT 1"
+    )))
+    .bind(offset);
+    let msg = bind_conditions(query)
         .fetch_optional::<StructuredMessage>()
         .await?
         .ok_or(Error::NotFound)?;
@@ -286,7 +730,7 @@ pub async fn read_random_channel_line(
     Ok(msg)
 }
 
-pub async fn check_users_exist(db: &Client, channel_id: &str, user_ids: &[String]) -> Result<Vec<UserHasLogs>> {
+pub async fn check_users_exist(db: &Client, channel_id: &ChannelId, user_ids: &[String]) -> Result<Vec<UserHasLogs>> {
     if user_ids.is_empty() {
         return Ok(Vec::new());
     }
@@ -316,8 +760,8 @@ pub async fn check_users_exist(db: &Client, channel_id: &str, user_ids: &[String
 }
 
 pub async fn search_user_logins(app: &State<App>, param: &UserParam) -> Result<UserLogins> {
-    let db = &app.db;
-    let id = match param {
+    let db = &app.db.profile(QueryClass::Logs);
+    let id = UserId::from(match param {
         UserParam::UserId(id) => id.to_string(),
         UserParam::User(login) => {
             // try to fetch the user ID from the database
@@ -333,48 +777,291 @@ pub async fn search_user_logins(app: &State<App>, param: &UserParam) -> Result<U
                 app.get_user_id_by_name(login).await?
             }
         }
-    };
+    });
 
-    if id.is_empty() {
+    if id.as_str().is_empty() {
         return Err(Error::NotFound);
     }
 
-    let query = db.query("SELECT user_login FROM message_structured WHERE user_id = ? GROUP BY user_login").bind(id);
+    let query = db.query("SELECT user_login FROM message_structured WHERE user_id = ? GROUP BY user_login").bind(&id);
 
     let logins = query.fetch_all::<String>().await?;
-    Ok(UserLogins { logins })
+    let display_names = display_names::read_display_names(db, &id)
+        .await?
+        .into_iter()
+        .map(|row| DisplayNameEntry {
+            display_name: row.display_name,
+            first_seen: DateTime::from_timestamp(row.first_seen.into(), 0).unwrap_or_default(),
+            last_seen: DateTime::from_timestamp(row.last_seen.into(), 0).unwrap_or_default(),
+        })
+        .collect();
+    Ok(UserLogins {
+        logins,
+        display_names,
+    })
 }
 
 pub async fn search_user_logs(
     db: &Client,
-    channel_id: &str,
-    user_id: &str,
+    channel_id: &ChannelId,
+    user_id: &UserId,
     search: &str,
+    since: Option<DateTime<Utc>>,
     params: LogsParams,
+    flush_buffer: &FlushBuffer,
 ) -> Result<LogsStream> {
-    let suffix = if params.reverse { "DESC" } else { "ASC" };
+    search_logs(
+        db,
+        channel_id,
+        Some(user_id),
+        search,
+        since,
+        params,
+        flush_buffer,
+    )
+    .await
+}
 
-    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? AND user_id = ? AND positionCaseInsensitive(text, ?) != 0 ORDER BY timestamp {suffix}");
-    apply_limit_offset(&mut query, params.limit, params.offset);
+pub async fn search_channel_logs(
+    db: &Client,
+    channel_id: &ChannelId,
+    search: &str,
+    since: Option<DateTime<Utc>>,
+    params: LogsParams,
+    flush_buffer: &FlushBuffer,
+) -> Result<LogsStream> {
+    search_logs(db, channel_id, None, search, since, params, flush_buffer).await
+}
 
-    let cursor = db
-        .query(&query)
-        .bind(channel_id)
-        .bind(user_id)
-        .bind(search)
-        .fetch()?;
+async fn search_logs(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    search: &str,
+    since: Option<DateTime<Utc>>,
+    params: LogsParams,
+    flush_buffer: &FlushBuffer,
+) -> Result<LogsStream> {
+    let since = since.unwrap_or(DateTime::UNIX_EPOCH);
+    if params.sort != MessageSort::Timestamp {
+        let range = LogRangeParams {
+            from: since,
+            to: DateTime::<Utc>::MAX_UTC,
+            logs_params: params,
+        };
+        return read_sorted(db, channel_id, user_id, Some(search), range, &[]).await;
+    }
+
+    let _timer = query_timer("search_logs");
+    let mut query = search_query(user_id.is_some(), params);
+    let buffer = buffer_to_merge(flush_buffer, channel_id, since, DateTime::<Utc>::MAX_UTC).await;
+    let offset = apply_window_limit(&mut query, params.limit, params.offset, buffer.is_some());
+
+    let cursor = bind_search(db.query(&query), channel_id, user_id, since, search).fetch()?;
 
-    let flush_params = FlushBufferResponse {
-        buffer: None,
-        channel_id: String::new(),
-        user_id: None,
-        params: LogRangeParams {
-            from: DateTime::UNIX_EPOCH,
-            to: DateTime::UNIX_EPOCH,
+    let flush_params = FlushBufferResponse::new(
+        buffer,
+        channel_id.to_string(),
+        user_id.map(UserId::to_string),
+        LogRangeParams {
+            from: since,
+            to: DateTime::<Utc>::MAX_UTC,
             logs_params: params,
         },
+    )
+    .with_search(search.to_owned());
+
+    // Recent matches may only be in the flush buffer
+    let stream = match LogsStream::new_cursor(cursor, flush_params.clone()).await {
+        Err(Error::NotFound) => LogsStream::new_buffered(flush_params).await?,
+        result => result?,
     };
-    LogsStream::new_cursor(cursor, flush_params).await
+    Ok(stream.windowed(offset, params.limit))
+}
+
+/// Query of [`search_logs`], without the limit. The parameters are bound with [`bind_search`]
+fn search_query(has_user: bool, params: LogsParams) -> String {
+    let suffix = if params.reverse { "DESC" } else { "ASC" };
+    let user_condition = if has_user { "AND user_id = ?" } else { "" };
+    let filter_conditions = filter_conditions(params);
+
+    // Messages are also matched by their normalized text, so evasion attempts with invisible characters or homoglyphs are found
+    format!("SELECT * FROM message_structured WHERE channel_id = ? {user_condition} AND timestamp >= ? AND (positionCaseInsensitive(text, ?) != 0 OR positionCaseInsensitive(text_normalized, ?) != 0) {filter_conditions} ORDER BY timestamp {suffix}")
+}
+
+fn bind_search(
+    mut query: Query,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    since: DateTime<Utc>,
+    search: &str,
+) -> Query {
+    query = query.bind(channel_id);
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    query
+        .bind(since.timestamp_millis() as f64 / 1000.0)
+        .bind(search)
+        .bind(normalize_text(search))
+}
+
+/// Messages in the order of [`LogsParams::sort`], optionally only of a user or containing the search.
+/// Messages in the flush buffer are not included, as they would have to be sorted into the rows
+async fn read_sorted(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    search: Option<&str>,
+    params: LogRangeParams,
+    excluded_users: &[String],
+) -> Result<LogsStream> {
+    let _timer = query_timer("read_sorted");
+    let logs_params = params.logs_params;
+    if logs_params.offset.unwrap_or(0) > MAX_SORTED_OFFSET {
+        return Err(Error::InvalidParam(format!(
+            "offset can be at most {MAX_SORTED_OFFSET} when messages are not sorted by timestamp"
+        )));
+    }
+    let direction = |descending: bool| {
+        if descending != logs_params.reverse {
+            "DESC"
+        } else {
+            "ASC"
+        }
+    };
+    let order_by = match logs_params.sort {
+        MessageSort::Timestamp => format!("timestamp {}", direction(false)),
+        MessageSort::Length => format!("lengthUTF8(text) {}, timestamp ASC", direction(true)),
+        MessageSort::User => format!("user_login {}, timestamp ASC", direction(false)),
+        MessageSort::Random => "rand()".to_owned(),
+    };
+
+    let user_condition = if user_id.is_some() {
+        "AND user_id = ?"
+    } else {
+        ""
+    };
+    // Searches are not limited to a range
+    let to_condition = if params.to != DateTime::<Utc>::MAX_UTC {
+        "AND timestamp < ?"
+    } else {
+        ""
+    };
+    let search_condition = if search.is_some() {
+        "AND (positionCaseInsensitive(text, ?) != 0 OR positionCaseInsensitive(text_normalized, ?) != 0)"
+    } else {
+        ""
+    };
+    let filter_conditions = filter_conditions(logs_params);
+    let excluded_users_condition = excluded_users_condition(excluded_users);
+    let mut query = format!("SELECT * FROM message_structured WHERE channel_id = ? {user_condition} AND timestamp >= ? {to_condition} {search_condition} {filter_conditions} {excluded_users_condition} ORDER BY {order_by}");
+    let limit = logs_params
+        .limit
+        .unwrap_or(DEFAULT_SORTED_LIMIT)
+        .min(MAX_SORTED_LIMIT);
+    apply_limit_offset(&mut query, Some(limit), logs_params.offset);
+
+    let mut query = db.query(&query).bind(channel_id);
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    query = query.bind(params.from.timestamp_millis() as f64 / 1000.0);
+    if !to_condition.is_empty() {
+        query = query.bind(params.to.timestamp_millis() as f64 / 1000.0);
+    }
+    if let Some(search) = search {
+        query = query.bind(search).bind(normalize_text(search));
+    }
+    if !excluded_users.is_empty() {
+        query = query.bind(excluded_users);
+    }
+
+    let flush_params = FlushBufferResponse::new(
+        None,
+        channel_id.to_string(),
+        user_id.map(UserId::to_string),
+        params,
+    );
+    LogsStream::new_cursor(query.fetch()?, flush_params).await
+}
+
+/// Channel messages matching the search ranked by how often they contain it, with older messages scoring lower.
+/// Only written messages are ranked
+pub async fn search_channel_logs_by_relevance(
+    db: &Client,
+    channel_id: &ChannelId,
+    search: &str,
+    since: Option<DateTime<Utc>>,
+    limit: u64,
+    offset: u64,
+) -> Result<Vec<ScoredMessage>> {
+    let _timer = query_timer("search_channel_logs_by_relevance");
+    let since = since.unwrap_or(DateTime::UNIX_EPOCH);
+    let normalized = normalize_text(search);
+    let messages = db
+        .query(
+            "SELECT
+                log(1 + greatest(countSubstringsCaseInsensitive(text, ?), countSubstringsCaseInsensitive(text_normalized, ?)))
+                    * pow(0.5, (toUnixTimestamp(now()) - toUnixTimestamp(timestamp)) / ?) AS score,
+                *
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND (positionCaseInsensitive(text, ?) != 0 OR positionCaseInsensitive(text_normalized, ?) != 0)
+            ORDER BY score DESC, timestamp DESC
+            LIMIT ? OFFSET ?",
+        )
+        .bind(search)
+        .bind(&normalized)
+        .bind(RELEVANCE_HALF_LIFE_DAYS * 86400)
+        .bind(channel_id)
+        .bind(since.timestamp_millis() as f64 / 1000.0)
+        .bind(search)
+        .bind(&normalized)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all::<ScoredMessage>()
+        .await?;
+
+    if messages.is_empty() {
+        return Err(Error::NotFound);
+    }
+    Ok(messages)
+}
+
+/// For streams which include buffered messages, the offset is applied by [`LogsStream::windowed`]
+/// because it spans both stored and buffered messages. The query only needs to return enough rows to fill the window.
+/// Otherwise the offset is part of the query, so the server skips the rows of deep pages.
+/// Returns the offset which is left to be applied to the stream
+fn apply_window_limit(
+    query: &mut String,
+    limit: Option<u64>,
+    offset: Option<u64>,
+    merges_buffer: bool,
+) -> Option<u64> {
+    if !merges_buffer {
+        apply_limit_offset(query, limit, offset);
+        return None;
+    }
+
+    if let Some(limit) = limit {
+        let rows = limit.saturating_add(offset.unwrap_or(0));
+        *query = format!("{query} LIMIT {rows}");
+    }
+    offset
+}
+
+/// Buffered messages of the channel in the range, which a log stream has to merge with the stored ones
+async fn buffer_to_merge(
+    flush_buffer: &FlushBuffer,
+    channel_id: &ChannelId,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+) -> Option<FlushBuffer> {
+    let time_range = (from.timestamp_millis() as u64)..(to.timestamp_millis() as u64);
+    flush_buffer
+        .has_messages(channel_id.as_str(), time_range)
+        .await
+        .then(|| flush_buffer.clone())
 }
 
 fn apply_limit_offset(query: &mut String, limit: Option<u64>, offset: Option<u64>) {
@@ -385,3 +1072,10 @@ fn apply_limit_offset(query: &mut String, limit: Option<u64>, offset: Option<u64
         *query = format!("{query} OFFSET {offset}");
     }
 }
+
+/// Records the duration of the query once it is dropped
+fn query_timer(query: &str) -> HistogramTimer {
+    QUERY_DURATION_HISTOGRAM
+        .with_label_values(&[query])
+        .start_timer()
+}
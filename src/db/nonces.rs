@@ -0,0 +1,67 @@
+use super::{schema::StructuredMessage, writer::FlushBuffer};
+use crate::{ids::ChannelId, Result};
+use chrono::{DateTime, Utc};
+use clickhouse::Client;
+use std::collections::HashSet;
+
+/// Messages of the channel sent with the client nonce, oldest first.
+/// Messages which have not been written to the database yet are included
+pub async fn read_messages_by_client_nonce(
+    db: &Client,
+    channel_id: &ChannelId,
+    client_nonce: &str,
+    from: DateTime<Utc>,
+    to: DateTime<Utc>,
+    limit: u64,
+    flush_buffer: &FlushBuffer,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    let stored = db
+        .query(
+            "SELECT ?fields FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND client_nonce = ?
+            ORDER BY timestamp ASC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(from.timestamp_millis() as f64 / 1000.0)
+        .bind(to.timestamp_millis() as f64 / 1000.0)
+        .bind(client_nonce)
+        .bind(limit)
+        .fetch_all::<StructuredMessage<'static>>()
+        .await?;
+
+    let time_range = (from.timestamp_millis().max(0) as u64)..(to.timestamp_millis().max(0) as u64);
+    let mut buffered = Vec::new();
+    let mut position = None;
+    loop {
+        let page = flush_buffer
+            .messages_page(
+                time_range.clone(),
+                channel_id.as_str(),
+                None,
+                None,
+                false,
+                &mut position,
+            )
+            .await;
+        if page.is_empty() {
+            break;
+        }
+        buffered.extend(
+            page.into_iter()
+                .filter(|msg| msg.client_nonce == client_nonce),
+        );
+    }
+
+    // Messages can be flushed between both reads, so they may be returned twice
+    let mut seen = HashSet::new();
+    let mut messages: Vec<_> = stored
+        .into_iter()
+        .chain(buffered)
+        .filter(|msg| seen.insert((msg.timestamp, msg.id(), msg.user_id.to_string())))
+        .collect();
+    messages.sort_by_key(|msg| msg.timestamp);
+    messages.truncate(limit as usize);
+
+    Ok(messages)
+}
@@ -0,0 +1,48 @@
+use crate::Result;
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+
+const USER_OPT_OUT_TABLE: &str = "user_opt_out";
+
+/// An opt-out made through the self-service flow
+#[derive(Row, Serialize, Deserialize)]
+pub struct UserOptOutRow {
+    pub user_id: String,
+    pub opted_out: u8,
+    /// Whether the user's existing logs were deleted
+    pub purged: u8,
+    pub updated_at: u32,
+}
+
+/// Users who are currently opted out through the self-service flow
+pub async fn read_opt_outs(db: &Client) -> Result<Vec<UserOptOutRow>> {
+    let rows = db
+        .query(
+            "SELECT ?fields FROM user_opt_out FINAL WHERE opted_out = 1 ORDER BY updated_at DESC",
+        )
+        .fetch_all::<UserOptOutRow>()
+        .await?;
+
+    Ok(rows)
+}
+
+/// Records an opt-out, or its revocation if `opted_out` is false
+pub async fn write_opt_out(
+    db: &Client,
+    user_id: &str,
+    opted_out: bool,
+    purged: bool,
+) -> Result<()> {
+    let mut insert = db.insert(USER_OPT_OUT_TABLE)?;
+    insert
+        .write(&UserOptOutRow {
+            user_id: user_id.to_owned(),
+            opted_out: opted_out.into(),
+            purged: purged.into(),
+            updated_at: chrono::Utc::now().timestamp() as u32,
+        })
+        .await?;
+    insert.end().await?;
+
+    Ok(())
+}
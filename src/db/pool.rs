@@ -0,0 +1,269 @@
+use crate::{
+    config::{url_without_userinfo, QueryProfilesConfig},
+    error::Error,
+    web::schema::{DatabaseNode, NodeHealth},
+    Result, ShutdownRx,
+};
+use chrono::Utc;
+use clickhouse::Client;
+use serde::{Deserialize, Serialize};
+use std::{
+    borrow::Cow,
+    collections::HashMap,
+    future::Future,
+    ops::Deref,
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::{sleep, timeout};
+use tracing::{debug, info, warn};
+
+const HEALTH_CHECK_INTERVAL_SECONDS: u64 = 10;
+const HEALTH_CHECK_TIMEOUT_SECONDS: u64 = 5;
+
+tokio::task_local! {
+    static LOG_COMMENT: String;
+}
+
+/// Classes of endpoints which can be assigned their own ClickHouse settings profile
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "camelCase")]
+pub enum QueryClass {
+    Logs,
+    Search,
+    Stats,
+    Admin,
+}
+
+const QUERY_CLASSES: [QueryClass; 4] = [
+    QueryClass::Logs,
+    QueryClass::Search,
+    QueryClass::Stats,
+    QueryClass::Admin,
+];
+
+/// ClickHouse nodes which can serve reads, in order of preference. The first node is the primary
+pub struct DbPool {
+    nodes: Vec<Node>,
+    cooldown_seconds: i64,
+}
+
+struct Node {
+    /// Without credentials, as it is logged and shown to admins
+    url: String,
+    client: Client,
+    /// Clients with the settings of the profile assigned to each class
+    profiles: HashMap<QueryClass, Client>,
+    /// Unix timestamp until which the node is skipped, 0 if it is healthy
+    unhealthy_until: AtomicI64,
+}
+
+impl DbPool {
+    pub fn new(
+        nodes: Vec<(String, Client)>,
+        cooldown_seconds: u64,
+        query_profiles: &QueryProfilesConfig,
+    ) -> Self {
+        assert!(!nodes.is_empty(), "At least one database node is required");
+
+        let nodes = nodes
+            .into_iter()
+            .map(|(url, client)| {
+                let profiles = QUERY_CLASSES
+                    .into_iter()
+                    .filter_map(|class| {
+                        let settings = query_profiles.settings(class)?;
+                        let client = settings
+                            .iter()
+                            .fold(client.clone(), |client, (name, value)| {
+                                client.with_option(name, value)
+                            });
+                        Some((class, client))
+                    })
+                    .collect();
+
+                Node {
+                    url: url_without_userinfo(&url),
+                    client,
+                    profiles,
+                    unhealthy_until: AtomicI64::new(0),
+                }
+            })
+            .collect();
+
+        Self {
+            nodes,
+            cooldown_seconds: cooldown_seconds as i64,
+        }
+    }
+
+    pub fn primary(&self) -> Cow<'_, Client> {
+        with_current_log_comment(&self.nodes[0].client)
+    }
+
+    /// Client of the first healthy node with the settings profile of the class
+    pub fn profile(&self, class: QueryClass) -> Cow<'_, Client> {
+        let client = self
+            .available_nodes()
+            .next()
+            .expect("Pool has at least one node")
+            .client_for(class);
+        with_current_log_comment(client)
+    }
+
+    /// Runs a read against the first healthy node, retrying on the next ones if a node is unreachable
+    pub async fn read_with_failover<T, F, Fut>(&self, class: QueryClass, read: F) -> Result<T>
+    where
+        F: Fn(Client) -> Fut,
+        Fut: Future<Output = Result<T>>,
+    {
+        let mut last_err = None;
+
+        for node in self.available_nodes() {
+            match read(with_current_log_comment(node.client_for(class)).into_owned()).await {
+                Err(Error::Clickhouse(err)) if is_unreachable(&err) => {
+                    warn!("Database node {} is unreachable: {err}", node.url);
+                    self.mark_unhealthy(node);
+                    last_err = Some(err);
+                }
+                result => return result,
+            }
+        }
+
+        Err(last_err.map(Error::Clickhouse).unwrap_or(Error::Internal))
+    }
+
+    /// Nodes are only identified by their position, as the health is public
+    pub fn health(&self) -> Vec<NodeHealth> {
+        let now = Utc::now().timestamp();
+        self.nodes
+            .iter()
+            .enumerate()
+            .map(|(index, node)| NodeHealth {
+                index,
+                healthy: node.is_healthy(now),
+            })
+            .collect()
+    }
+
+    pub fn nodes(&self) -> Vec<DatabaseNode> {
+        let now = Utc::now().timestamp();
+        self.nodes
+            .iter()
+            .map(|node| DatabaseNode {
+                url: node.url.clone(),
+                healthy: node.is_healthy(now),
+            })
+            .collect()
+    }
+
+    pub fn is_ready(&self) -> bool {
+        let now = Utc::now().timestamp();
+        self.nodes.iter().any(|node| node.is_healthy(now))
+    }
+
+    /// Healthy nodes first, so reads still go somewhere if every node is in cooldown
+    fn available_nodes(&self) -> impl Iterator<Item = &Node> {
+        let now = Utc::now().timestamp();
+        let healthy = self.nodes.iter().filter(move |node| node.is_healthy(now));
+        let unhealthy = self.nodes.iter().filter(move |node| !node.is_healthy(now));
+        healthy.chain(unhealthy)
+    }
+
+    fn mark_unhealthy(&self, node: &Node) {
+        let until = Utc::now().timestamp() + self.cooldown_seconds;
+        node.unhealthy_until.store(until, Ordering::Relaxed);
+    }
+}
+
+impl Node {
+    fn is_healthy(&self, now: i64) -> bool {
+        self.unhealthy_until.load(Ordering::Relaxed) <= now
+    }
+
+    fn client_for(&self, class: QueryClass) -> &Client {
+        self.profiles.get(&class).unwrap_or(&self.client)
+    }
+}
+
+/// Queries which do not opt into failover through [`DbPool::profile`] or [`DbPool::read_with_failover`] go to
+/// the primary, so writes and mutations never land on a read node. They are not tagged with the `log_comment`,
+/// so reads during requests use [`DbPool::primary`] instead
+impl Deref for DbPool {
+    type Target = Client;
+
+    fn deref(&self) -> &Client {
+        &self.nodes[0].client
+    }
+}
+
+/// Queries made through [`DbPool::profile`], [`DbPool::primary`] and [`DbPool::read_with_failover`] in the future
+/// are tagged with the `log_comment`, so they can be told apart from other queries in the query log
+pub async fn with_log_comment<F: Future>(comment: String, future: F) -> F::Output {
+    LOG_COMMENT.scope(comment, future).await
+}
+
+/// Like [`with_log_comment`] for a single poll, e.g. of a streamed response body
+pub fn sync_with_log_comment<R>(comment: String, f: impl FnOnce() -> R) -> R {
+    LOG_COMMENT.sync_scope(comment, f)
+}
+
+fn with_current_log_comment(client: &Client) -> Cow<'_, Client> {
+    LOG_COMMENT
+        .try_with(|comment| Cow::Owned(client.clone().with_option("log_comment", comment)))
+        .unwrap_or(Cow::Borrowed(client))
+}
+
+/// Periodically checks every node, so nodes leave the cooldown early once they recover
+/// and failing nodes are detected before a read runs into them
+pub async fn run_health_checks(pool: Arc<DbPool>, mut shutdown_rx: ShutdownRx) {
+    loop {
+        for node in &pool.nodes {
+            let check = timeout(
+                Duration::from_secs(HEALTH_CHECK_TIMEOUT_SECONDS),
+                node.client.query("SELECT 1").execute(),
+            )
+            .await;
+            let was_healthy = node.is_healthy(Utc::now().timestamp());
+
+            match check {
+                Ok(Ok(())) => {
+                    if !was_healthy {
+                        info!("Database node {} is healthy again", node.url);
+                    }
+                    node.unhealthy_until.store(0, Ordering::Relaxed);
+                }
+                Ok(Err(err)) => {
+                    if was_healthy {
+                        warn!("Database node {} failed health check: {err}", node.url);
+                    }
+                    pool.mark_unhealthy(node);
+                }
+                Err(_) => {
+                    if was_healthy {
+                        warn!("Database node {} health check timed out", node.url);
+                    }
+                    pool.mark_unhealthy(node);
+                }
+            }
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(HEALTH_CHECK_INTERVAL_SECONDS)) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down database health checks");
+                break;
+            }
+        }
+    }
+}
+
+fn is_unreachable(err: &clickhouse::error::Error) -> bool {
+    matches!(
+        err,
+        clickhouse::error::Error::Network(_) | clickhouse::error::Error::TimedOut
+    )
+}
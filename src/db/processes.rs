@@ -0,0 +1,73 @@
+use crate::{error::Error, web::schema::RunningQuery, Result};
+use chrono::{DateTime, Utc};
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+use tracing::warn;
+
+/// Totals of the queries of a request
+#[derive(Row, Deserialize, Debug)]
+pub struct QueryStats {
+    pub query_count: u64,
+    pub read_rows: u64,
+    pub read_bytes: u64,
+    pub elapsed_ms: u64,
+}
+
+/// Queries currently executed by rustlog's database user, excluding this one
+pub async fn read_running_queries(db: &Client) -> Result<Vec<RunningQuery>> {
+    let queries = db
+        .query(
+            "SELECT query_id, elapsed, read_rows, read_bytes, memory_usage, query FROM system.processes
+            WHERE user = currentUser() AND query_id != queryID()
+            ORDER BY elapsed DESC",
+        )
+        .fetch_all()
+        .await?;
+
+    Ok(queries)
+}
+
+pub async fn kill_query(db: &Client, query_id: &str) -> Result<()> {
+    let exists = db
+        .query("SELECT count() FROM system.processes WHERE user = currentUser() AND query_id = ?")
+        .bind(query_id)
+        .fetch_one::<u64>()
+        .await?;
+    if exists == 0 {
+        return Err(Error::NotFound);
+    }
+
+    db.query("KILL QUERY WHERE user = currentUser() AND query_id = ? ASYNC")
+        .bind(query_id)
+        .execute()
+        .await?;
+
+    Ok(())
+}
+
+/// Finished read queries which were tagged with the `log_comment` (see [`super::pool::with_log_comment`])
+/// and started after `from`
+pub async fn read_query_stats(
+    db: &Client,
+    log_comment: &str,
+    from: DateTime<Utc>,
+) -> Result<QueryStats> {
+    // The query log is written periodically, flushing requires the SYSTEM FLUSH LOGS grant
+    if let Err(err) = db.query("SYSTEM FLUSH LOGS").execute().await {
+        warn!("Could not flush query log, statistics may be incomplete: {err}");
+    }
+
+    let stats = db
+        .query(
+            "SELECT count(), sum(read_rows), sum(read_bytes), sum(query_duration_ms) FROM system.query_log
+            WHERE user = currentUser() AND type = 'QueryFinish' AND query_kind = 'Select' AND is_initial_query
+                AND event_date >= toDate(?) AND query_start_time_microseconds >= ? AND log_comment = ?",
+        )
+        .bind(from.timestamp())
+        .bind(from.timestamp_micros() as f64 / 1_000_000.0)
+        .bind(log_comment)
+        .fetch_one()
+        .await?;
+
+    Ok(stats)
+}
@@ -0,0 +1,291 @@
+//! Checks that the main read queries only read the parts and granules they need. The queries are built like in
+//! [`super::read_channel`], [`super::read_user`], [`super::search_logs`] and [`super::read_available_user_logs`].
+//! These tests start a ClickHouse container and are ignored by default, run them with
+//! `cargo test pruning -- --ignored`
+
+use super::{
+    bind_range, bind_search, channel_logs_query, search_query, setup_db, user_logs_query,
+    AVAILABLE_USER_LOGS_QUERY,
+};
+use crate::{
+    ids::{ChannelId, UserId},
+    web::schema::LogsParams,
+};
+use chrono::{DateTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clickhouse::{query::Query, Client};
+use testcontainers_modules::{
+    clickhouse::ClickHouse,
+    testcontainers::{runners::AsyncRunner, ContainerAsync},
+};
+
+const CHANNEL_ID: &str = "11111111";
+const OTHER_CHANNEL_ID: &str = "22222222";
+const USER_ID: &str = "33333333";
+/// Rows per channel and month, enough for the primary key to span several granules
+const ROWS_PER_MONTH: u64 = 50_000;
+
+/// Selected and total amount of an index step in the `EXPLAIN indexes = 1` output
+#[derive(Debug, PartialEq)]
+struct IndexUsage {
+    index: String,
+    selected_parts: u64,
+    total_parts: u64,
+    selected_granules: u64,
+    total_granules: u64,
+}
+
+async fn start_db() -> (ContainerAsync<ClickHouse>, Client) {
+    let container = ClickHouse::default()
+        .start()
+        .await
+        .expect("Could not start ClickHouse container");
+    let port = container
+        .get_host_port_ipv4(8123)
+        .await
+        .expect("ClickHouse HTTP port is not exposed");
+    let db = Client::default().with_url(format!("http://127.0.0.1:{port}"));
+
+    setup_db(&db, "default").await.unwrap();
+    insert_messages(&db).await;
+
+    (container, db)
+}
+
+/// Two channels with messages in January and February, so there are multiple partitions
+async fn insert_messages(db: &Client) {
+    for channel_id in [CHANNEL_ID, OTHER_CHANNEL_ID] {
+        for month in [1, 2] {
+            let start = Utc.with_ymd_and_hms(2024, month, 1, 0, 0, 0).unwrap();
+            db.query(
+                "INSERT INTO message_structured (channel_id, channel_login, timestamp, id, message_type, user_id, user_login, text)
+                SELECT ?, 'channel', fromUnixTimestamp64Milli(toInt64(? + number * 1000)), generateUUIDv4(), 1,
+                    if(number % 100 = 0, ?, toString(number % 5000)), 'user', concat('message ', toString(number))
+                FROM numbers(?)",
+            )
+            .bind(channel_id)
+            .bind(start.timestamp_millis())
+            .bind(USER_ID)
+            .bind(ROWS_PER_MONTH)
+            .execute()
+            .await
+            .unwrap();
+        }
+    }
+
+    db.query("OPTIMIZE TABLE message_structured FINAL")
+        .execute()
+        .await
+        .unwrap();
+}
+
+/// The explained rows are strings, so the fields of the messages are selected as `*`
+fn explain(db: &Client, query: &str) -> Query {
+    db.query(&format!(
+        "EXPLAIN indexes = 1 {}",
+        query.replace("?fields", "*")
+    ))
+}
+
+async fn explain_indexes(explain: Query) -> Vec<IndexUsage> {
+    let lines = explain.fetch_all::<String>().await.unwrap();
+    parse_index_usage(&lines)
+}
+
+fn parse_index_usage(lines: &[String]) -> Vec<IndexUsage> {
+    let mut usages = Vec::new();
+    let mut index = None;
+    let mut parts = None;
+
+    for line in lines.iter().map(|line| line.trim()) {
+        if let Some(counts) = line.strip_prefix("Parts: ") {
+            parts = parse_ratio(counts);
+        } else if let Some(counts) = line.strip_prefix("Granules: ") {
+            if let (Some(index), Some((selected_parts, total_parts)), Some(granules)) =
+                (index.take(), parts.take(), parse_ratio(counts))
+            {
+                usages.push(IndexUsage {
+                    index,
+                    selected_parts,
+                    total_parts,
+                    selected_granules: granules.0,
+                    total_granules: granules.1,
+                });
+            }
+        } else if matches!(line, "MinMax" | "Partition" | "PrimaryKey" | "Skip") {
+            index = Some(line.to_owned());
+        }
+    }
+
+    usages
+}
+
+fn parse_ratio(value: &str) -> Option<(u64, u64)> {
+    let (selected, total) = value.split_once('/')?;
+    Some((selected.parse().ok()?, total.parse().ok()?))
+}
+
+fn find_usage<'a>(usages: &'a [IndexUsage], index: &str) -> &'a IndexUsage {
+    usages
+        .iter()
+        .find(|usage| usage.index == index)
+        .unwrap_or_else(|| panic!("No {index} index was used: {usages:?}"))
+}
+
+fn assert_partitions_pruned(usages: &[IndexUsage]) {
+    let partition = find_usage(usages, "Partition");
+    assert!(
+        partition.selected_parts < partition.total_parts,
+        "Partitions were not pruned: {partition:?}"
+    );
+}
+
+fn assert_granules_pruned(usages: &[IndexUsage]) {
+    let primary_key = find_usage(usages, "PrimaryKey");
+    assert!(
+        primary_key.selected_granules < primary_key.total_granules,
+        "Primary key did not skip any granules: {primary_key:?}"
+    );
+}
+
+fn january() -> (DateTime<Utc>, DateTime<Utc>) {
+    let from = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+    let to = Utc.with_ymd_and_hms(2024, 1, 2, 0, 0, 0).unwrap();
+    (from, to)
+}
+
+#[tokio::test]
+#[ignore = "requires docker"]
+async fn channel_logs_are_pruned() {
+    let (_container, db) = start_db().await;
+    let (from, to) = january();
+
+    let query = channel_logs_query(LogsParams::default(), &[]);
+    let usages = explain_indexes(bind_range(
+        explain(&db, &query),
+        &ChannelId::from(CHANNEL_ID),
+        None,
+        from,
+        to,
+        &[],
+    ))
+    .await;
+
+    assert_partitions_pruned(&usages);
+    assert_granules_pruned(&usages);
+}
+
+#[tokio::test]
+#[ignore = "requires docker"]
+async fn user_logs_are_pruned() {
+    let (_container, db) = start_db().await;
+    let (from, to) = january();
+
+    let query = user_logs_query(LogsParams::default(), &[]);
+    let usages = explain_indexes(bind_range(
+        explain(&db, &query),
+        &ChannelId::from(CHANNEL_ID),
+        Some(&UserId::from(USER_ID)),
+        from,
+        to,
+        &[],
+    ))
+    .await;
+
+    assert_partitions_pruned(&usages);
+    assert_granules_pruned(&usages);
+}
+
+#[tokio::test]
+#[ignore = "requires docker"]
+async fn channel_search_is_pruned() {
+    let (_container, db) = start_db().await;
+    // Searches have no upper bound, so only the January partition can be skipped
+    let since = Utc.with_ymd_and_hms(2024, 2, 1, 0, 0, 0).unwrap();
+
+    let query = search_query(false, LogsParams::default());
+    let usages = explain_indexes(bind_search(
+        explain(&db, &query),
+        &ChannelId::from(CHANNEL_ID),
+        None,
+        since,
+        "message 1",
+    ))
+    .await;
+
+    assert_partitions_pruned(&usages);
+    assert_granules_pruned(&usages);
+}
+
+#[tokio::test]
+#[ignore = "requires docker"]
+async fn available_user_logs_are_pruned() {
+    let (_container, db) = start_db().await;
+
+    let usages = explain_indexes(
+        explain(&db, AVAILABLE_USER_LOGS_QUERY)
+            .bind(Tz::UTC.name())
+            .bind(CHANNEL_ID)
+            .bind(USER_ID),
+    )
+    .await;
+
+    assert_granules_pruned(&usages);
+}
+
+#[test]
+fn parses_explain_output() {
+    let lines: Vec<String> = [
+        "Expression ((Projection + Before ORDER BY))",
+        "  ReadFromMergeTree (default.message_structured)",
+        "  Indexes:",
+        "    MinMax",
+        "      Keys:",
+        "        timestamp",
+        "      Parts: 1/4",
+        "      Granules: 2/28",
+        "    Partition",
+        "      Keys:",
+        "        toYYYYMM(timestamp)",
+        "      Parts: 1/4",
+        "      Granules: 2/28",
+        "    PrimaryKey",
+        "      Keys:",
+        "        channel_id",
+        "      Condition: (channel_id in ['11111111', '11111111'])",
+        "      Parts: 1/1",
+        "      Granules: 1/2",
+    ]
+    .into_iter()
+    .map(str::to_owned)
+    .collect();
+
+    let usages = parse_index_usage(&lines);
+
+    pretty_assertions::assert_eq!(
+        usages,
+        vec![
+            IndexUsage {
+                index: "MinMax".to_owned(),
+                selected_parts: 1,
+                total_parts: 4,
+                selected_granules: 2,
+                total_granules: 28,
+            },
+            IndexUsage {
+                index: "Partition".to_owned(),
+                selected_parts: 1,
+                total_parts: 4,
+                selected_granules: 2,
+                total_granules: 28,
+            },
+            IndexUsage {
+                index: "PrimaryKey".to_owned(),
+                selected_parts: 1,
+                total_parts: 1,
+                selected_granules: 1,
+                total_granules: 2,
+            },
+        ]
+    );
+}
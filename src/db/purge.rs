@@ -0,0 +1,326 @@
+use super::schema::{StructuredMessage, MESSAGES_STRUCTURED_TABLE};
+use crate::{error::Error, ids::ChannelId, web::schema::PurgeStatus, Result};
+use chrono::{DateTime, Utc};
+use clickhouse::{query::Query, Client, Row};
+use serde::Deserialize;
+use std::time::Duration;
+use tokio::time::sleep;
+use uuid::Uuid;
+
+const MUTATION_POLL_INTERVAL_SECONDS: u64 = 5;
+/// Tables which hold copies of messages, with the channel, user and timestamp of the message
+pub const MESSAGE_COPY_TABLES: [&str; 3] = ["links", "message_unparsed", "alert_match"];
+
+/// Which messages of a user are deleted by [`purge_user_messages`]
+#[derive(Clone)]
+pub struct PurgeScope {
+    pub user_id: String,
+    pub channel_id: Option<String>,
+    pub from: Option<DateTime<Utc>>,
+    pub to: Option<DateTime<Utc>>,
+    /// Channels under legal hold, whose messages are kept
+    pub held_channels: Vec<String>,
+}
+
+impl PurgeScope {
+    pub fn matches(&self, msg: &StructuredMessage) -> bool {
+        msg.user_id == self.user_id
+            && self
+                .channel_id
+                .as_ref()
+                .map_or(true, |channel_id| msg.channel_id == *channel_id)
+            && self
+                .from
+                .map_or(true, |from| msg.timestamp >= from.timestamp_millis() as u64)
+            && self
+                .to
+                .map_or(true, |to| msg.timestamp < to.timestamp_millis() as u64)
+            && !self
+                .held_channels
+                .iter()
+                .any(|channel_id| msg.channel_id == *channel_id)
+    }
+
+    fn is_whole_history(&self) -> bool {
+        self.from.is_none() && self.to.is_none()
+    }
+
+    /// Tables without a timestamp leave out the range, see [`PurgeScope::is_whole_history`]
+    fn conditions(&self, with_range: bool) -> String {
+        let mut conditions = String::from("user_id = ?");
+        if self.channel_id.is_some() {
+            conditions.push_str(" AND channel_id = ?");
+        }
+        if with_range && self.from.is_some() {
+            conditions.push_str(" AND timestamp >= ?");
+        }
+        if with_range && self.to.is_some() {
+            conditions.push_str(" AND timestamp < ?");
+        }
+        if !self.held_channels.is_empty() {
+            conditions.push_str(" AND NOT has(?, channel_id)");
+        }
+        conditions
+    }
+
+    fn bind(&self, mut query: Query, with_range: bool) -> Query {
+        query = query.bind(&self.user_id);
+        if let Some(channel_id) = &self.channel_id {
+            query = query.bind(channel_id);
+        }
+        if let Some(from) = self.from.filter(|_| with_range) {
+            query = query.bind(from.timestamp_millis() as f64 / 1000.0);
+        }
+        if let Some(to) = self.to.filter(|_| with_range) {
+            query = query.bind(to.timestamp_millis() as f64 / 1000.0);
+        }
+        if !self.held_channels.is_empty() {
+            query = query.bind(&self.held_channels);
+        }
+        query
+    }
+}
+
+/// Channel of the messages of a purge or opt-out and when the first and last of them were sent, in milliseconds
+#[derive(Row, Deserialize)]
+pub struct ChannelMessageRange {
+    pub channel_id: String,
+    pub first: u64,
+    pub last: u64,
+}
+
+/// Starts a mutation deleting the user's messages in the scope and returns its ID.
+/// Only rows which exist when the mutation is created are deleted
+pub async fn purge_user_messages(db: &Client, scope: &PurgeScope) -> Result<String> {
+    // The mutation ID is not returned by the ALTER query. The command is tagged with a unique value instead,
+    // which is part of the stored command, so it can't be confused with other purges of the same user
+    let tag = Uuid::new_v4().to_string();
+    let query = db.query(&format!(
+        "ALTER TABLE {MESSAGES_STRUCTURED_TABLE} DELETE WHERE {} AND ? != ''",
+        scope.conditions(true)
+    ));
+    scope.bind(query, true).bind(&tag).execute().await?;
+
+    let mutation_id = db
+        .query(
+            "SELECT mutation_id FROM system.mutations
+            WHERE database = currentDatabase() AND table = ? AND position(command, ?) > 0",
+        )
+        .bind(MESSAGES_STRUCTURED_TABLE)
+        .bind(&tag)
+        .fetch_optional::<String>()
+        .await?;
+
+    mutation_id.ok_or(Error::Internal)
+}
+
+/// Deletes the copies of the user's messages in the scope from the other tables. The channels of the user
+/// and their display names are only forgotten when the whole history is purged
+pub async fn purge_user_copies(db: &Client, scope: &PurgeScope) -> Result<()> {
+    for table in MESSAGE_COPY_TABLES {
+        let query = db.query(&format!(
+            "DELETE FROM {table} WHERE {}",
+            scope.conditions(true)
+        ));
+        scope.bind(query, true).execute().await?;
+    }
+
+    if scope.is_whole_history() {
+        let query = db.query(&format!(
+            "DELETE FROM channel_user WHERE {}",
+            scope.conditions(false)
+        ));
+        scope.bind(query, false).execute().await?;
+
+        if scope.channel_id.is_none() {
+            db.query("DELETE FROM user_display_name WHERE user_id = ?")
+                .bind(&scope.user_id)
+                .execute()
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Who sent a stored message and when, in milliseconds
+#[derive(Row, Deserialize)]
+pub struct MessageAuthor {
+    pub user_id: String,
+    pub timestamp: u64,
+}
+
+pub async fn read_message_author(
+    db: &Client,
+    channel_id: &ChannelId,
+    id: Uuid,
+) -> Result<Option<MessageAuthor>> {
+    let author = db
+        .query(&format!(
+            "SELECT user_id, toUnixTimestamp64Milli(timestamp) AS timestamp
+            FROM {MESSAGES_STRUCTURED_TABLE}
+            WHERE channel_id = ? AND id = ?
+            LIMIT 1"
+        ))
+        .bind(channel_id)
+        .bind(id.to_string())
+        .fetch_optional()
+        .await?;
+
+    Ok(author)
+}
+
+/// Lightweight delete of a single message, which hides it from queries right away instead of rewriting
+/// whole parts like [`purge_user_messages`]. The rows are removed by the next merge.
+/// The copies have no message id, they are matched by their author and timestamp in milliseconds
+pub async fn delete_message(
+    db: &Client,
+    channel_id: &ChannelId,
+    id: Uuid,
+    author: &MessageAuthor,
+) -> Result<()> {
+    db.query(&format!(
+        "DELETE FROM {MESSAGES_STRUCTURED_TABLE} WHERE channel_id = ? AND id = ?"
+    ))
+    .bind(channel_id)
+    .bind(id.to_string())
+    .execute()
+    .await?;
+
+    for table in MESSAGE_COPY_TABLES {
+        db.query(&format!(
+            "DELETE FROM {table}
+            WHERE channel_id = ? AND user_id = ? AND timestamp = fromUnixTimestamp64Milli(toInt64(?))"
+        ))
+        .bind(channel_id)
+        .bind(&author.user_id)
+        .bind(author.timestamp)
+        .execute()
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Has to be read before the messages are deleted
+pub async fn read_purge_ranges(
+    db: &Client,
+    scope: &PurgeScope,
+) -> Result<Vec<ChannelMessageRange>> {
+    let query = db.query(&format!(
+        "SELECT channel_id, toUnixTimestamp64Milli(min(timestamp)) AS first,
+            toUnixTimestamp64Milli(max(timestamp)) AS last
+        FROM {MESSAGES_STRUCTURED_TABLE}
+        WHERE {}
+        GROUP BY channel_id",
+        scope.conditions(true)
+    ));
+    let ranges = scope.bind(query, true).fetch_all().await?;
+
+    Ok(ranges)
+}
+
+/// Like [`read_purge_ranges`], for all messages of the users
+pub async fn read_users_message_ranges(
+    db: &Client,
+    user_ids: &[String],
+) -> Result<Vec<ChannelMessageRange>> {
+    if user_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let ranges = db
+        .query(&format!(
+            "SELECT channel_id, toUnixTimestamp64Milli(min(timestamp)) AS first,
+                toUnixTimestamp64Milli(max(timestamp)) AS last
+            FROM {MESSAGES_STRUCTURED_TABLE}
+            WHERE has(?, user_id)
+            GROUP BY channel_id"
+        ))
+        .bind(user_ids)
+        .fetch_all()
+        .await?;
+
+    Ok(ranges)
+}
+
+/// Waits until no mutation of the messages table is running anymore
+pub async fn wait_for_mutations(db: &Client) -> Result<()> {
+    loop {
+        let running: u64 = db
+            .query(
+                "SELECT count() FROM system.mutations
+                WHERE database = currentDatabase() AND table = ? AND NOT is_done",
+            )
+            .bind(MESSAGES_STRUCTURED_TABLE)
+            .fetch_one()
+            .await?;
+        if running == 0 {
+            return Ok(());
+        }
+
+        sleep(Duration::from_secs(MUTATION_POLL_INTERVAL_SECONDS)).await;
+    }
+}
+
+pub async fn read_purge_status(db: &Client, mutation_id: &str) -> Result<PurgeStatus> {
+    db.query(
+        "SELECT mutation_id, is_done, parts_to_do, latest_fail_reason FROM system.mutations
+        WHERE database = currentDatabase() AND table = ? AND mutation_id = ?",
+    )
+    .bind(MESSAGES_STRUCTURED_TABLE)
+    .bind(mutation_id)
+    .fetch_optional()
+    .await?
+    .ok_or(Error::NotFound)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PurgeScope;
+    use crate::db::schema::{StructuredMessage, UnstructuredMessage};
+    use chrono::{TimeZone, Utc};
+
+    fn message(channel_id: &str, user_id: &str, timestamp: u64) -> StructuredMessage<'static> {
+        let unstructured = UnstructuredMessage {
+            channel_id,
+            user_id,
+            timestamp,
+            raw: ":user!user@user.tmi.twitch.tv PRIVMSG #channel :hi",
+        };
+        StructuredMessage::from_unstructured(&unstructured)
+            .unwrap()
+            .into_owned()
+    }
+
+    #[test]
+    fn scope_matches_channel_and_range() {
+        let scope = PurgeScope {
+            user_id: "1".to_owned(),
+            channel_id: Some("2".to_owned()),
+            from: Some(Utc.timestamp_millis_opt(1000).unwrap()),
+            to: Some(Utc.timestamp_millis_opt(2000).unwrap()),
+            held_channels: Vec::new(),
+        };
+
+        assert!(scope.matches(&message("2", "1", 1000)));
+        assert!(!scope.matches(&message("2", "1", 2000)));
+        assert!(!scope.matches(&message("3", "1", 1500)));
+        assert!(!scope.matches(&message("2", "4", 1500)));
+
+        let unscoped = PurgeScope {
+            channel_id: None,
+            from: None,
+            to: None,
+            ..scope
+        };
+        assert!(unscoped.matches(&message("3", "1", 0)));
+
+        let held = PurgeScope {
+            held_channels: vec!["3".to_owned()],
+            ..unscoped
+        };
+        assert!(!held.matches(&message("3", "1", 0)));
+        assert!(held.matches(&message("2", "1", 0)));
+    }
+}
@@ -0,0 +1,101 @@
+use anyhow::Context;
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    ids::ChannelId,
+    web::schema::{ChannelReport, ReportPeriod},
+    Result,
+};
+
+pub const REPORTS_TABLE: &str = "reports";
+pub const REPORT_DELIVERIES_TABLE: &str = "report_deliveries";
+
+#[derive(Row, Serialize, Deserialize)]
+struct ReportRow {
+    channel_id: String,
+    period: String,
+    period_start: u32,
+    generated_at: u32,
+    data: String,
+}
+
+pub async fn report_exists(
+    db: &Client,
+    channel_id: &ChannelId,
+    period: ReportPeriod,
+    period_start: u32,
+) -> Result<bool> {
+    let count = db
+        .query(
+            "SELECT count() FROM reports WHERE channel_id = ? AND period = ? AND period_start = ?",
+        )
+        .bind(channel_id)
+        .bind(period.to_string())
+        .bind(period_start)
+        .fetch_one::<u64>()
+        .await?;
+
+    Ok(count > 0)
+}
+
+pub async fn write_report(
+    db: &Client,
+    channel_id: &ChannelId,
+    report: &ChannelReport,
+) -> Result<()> {
+    let row = ReportRow {
+        channel_id: channel_id.to_string(),
+        period: report.period.to_string(),
+        period_start: report.from.timestamp() as u32,
+        generated_at: report.generated_at.timestamp() as u32,
+        data: serde_json::to_string(report).context("Could not serialize report")?,
+    };
+
+    let mut insert = db.insert(REPORTS_TABLE)?;
+    insert.write(&row).await?;
+    insert.end().await?;
+
+    Ok(())
+}
+
+pub async fn read_latest_report(
+    db: &Client,
+    channel_id: &ChannelId,
+    period: ReportPeriod,
+) -> Result<Option<ChannelReport>> {
+    let row = db
+        .query("SELECT ?fields FROM reports FINAL WHERE channel_id = ? AND period = ? ORDER BY period_start DESC LIMIT 1")
+        .bind(channel_id)
+        .bind(period.to_string())
+        .fetch_optional::<ReportRow>()
+        .await?;
+
+    match row {
+        Some(row) => {
+            let report = serde_json::from_str(&row.data).context("Could not deserialize report")?;
+            Ok(Some(report))
+        }
+        None => Ok(None),
+    }
+}
+
+#[derive(Row, Serialize)]
+pub struct ReportDelivery {
+    pub channel_id: String,
+    pub period: String,
+    pub period_start: u32,
+    pub url: String,
+    pub attempts: u8,
+    pub success: bool,
+    pub error: String,
+    pub delivered_at: u32,
+}
+
+pub async fn write_report_delivery(db: &Client, delivery: &ReportDelivery) -> Result<()> {
+    let mut insert = db.insert(REPORT_DELIVERIES_TABLE)?;
+    insert.write(delivery).await?;
+    insert.end().await?;
+
+    Ok(())
+}
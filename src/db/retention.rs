@@ -0,0 +1,135 @@
+use super::{
+    purge::{ChannelMessageRange, MESSAGE_COPY_TABLES},
+    schema::MESSAGES_STRUCTURED_TABLE,
+};
+use crate::Result;
+use chrono::{DateTime, Utc};
+use clickhouse::Client;
+
+/// Channels a retention window applies to
+#[derive(Clone, Copy)]
+pub enum RetentionScope<'a> {
+    Channels(&'a [String]),
+    /// Every channel except the ones with their own retention
+    AllExcept(&'a [String]),
+}
+
+impl RetentionScope<'_> {
+    fn condition(&self) -> &'static str {
+        match self {
+            RetentionScope::Channels(_) => "has(?, channel_id)",
+            RetentionScope::AllExcept(_) => "NOT has(?, channel_id)",
+        }
+    }
+
+    fn channel_ids(&self) -> &[String] {
+        match self {
+            RetentionScope::Channels(channel_ids) | RetentionScope::AllExcept(channel_ids) => {
+                channel_ids
+            }
+        }
+    }
+}
+
+/// Starts a mutation deleting the messages in the scope older than `before`, except the ones of `held_users`,
+/// and deletes their copies in the other tables.
+/// Returns false without starting a mutation if there are no such messages
+pub async fn delete_messages_before(
+    db: &Client,
+    scope: RetentionScope<'_>,
+    held_users: &[String],
+    before: DateTime<Utc>,
+) -> Result<bool> {
+    let condition = format!(
+        "{} AND NOT has(?, user_id) AND timestamp < ?",
+        scope.condition()
+    );
+    let before = before.timestamp_millis() as f64 / 1000.0;
+
+    // Unparsed messages are never in the messages table, so the copies are checked regardless
+    for table in MESSAGE_COPY_TABLES {
+        db.query(&format!("DELETE FROM {table} WHERE {condition}"))
+            .bind(scope.channel_ids())
+            .bind(held_users)
+            .bind(before)
+            .execute()
+            .await?;
+    }
+
+    // Every mutation rewrites the affected parts, so none are started when there is nothing to delete
+    let expired = db
+        .query(&format!(
+            "SELECT count() FROM (SELECT 1 FROM {MESSAGES_STRUCTURED_TABLE} WHERE {condition} LIMIT 1)"
+        ))
+        .bind(scope.channel_ids())
+        .bind(held_users)
+        .bind(before)
+        .fetch_one::<u64>()
+        .await?;
+    if expired == 0 {
+        return Ok(false);
+    }
+
+    db.query(&format!(
+        "ALTER TABLE {MESSAGES_STRUCTURED_TABLE} DELETE WHERE {condition}"
+    ))
+    .bind(scope.channel_ids())
+    .bind(held_users)
+    .bind(before)
+    .execute()
+    .await?;
+
+    Ok(true)
+}
+
+/// Channels of the messages which [`delete_messages_before`] would delete, and when the first and last of them
+/// were sent. Has to be read before they are deleted
+pub async fn read_expired_ranges(
+    db: &Client,
+    scope: RetentionScope<'_>,
+    held_users: &[String],
+    before: DateTime<Utc>,
+) -> Result<Vec<ChannelMessageRange>> {
+    let ranges = db
+        .query(&format!(
+            "SELECT channel_id, toUnixTimestamp64Milli(min(timestamp)) AS first,
+                toUnixTimestamp64Milli(max(timestamp)) AS last
+            FROM {MESSAGES_STRUCTURED_TABLE}
+            WHERE {} AND NOT has(?, user_id) AND timestamp < ?
+            GROUP BY channel_id",
+            scope.condition()
+        ))
+        .bind(scope.channel_ids())
+        .bind(held_users)
+        .bind(before.timestamp_millis() as f64 / 1000.0)
+        .fetch_all()
+        .await?;
+
+    Ok(ranges)
+}
+
+/// Drops the monthly partitions which end before `before` and returns their IDs.
+/// Only valid if no channel keeps its messages longer than that
+pub async fn drop_partitions_before(db: &Client, before: DateTime<Utc>) -> Result<Vec<String>> {
+    let partitions = db
+        .query(
+            "SELECT DISTINCT partition_id FROM system.parts
+            WHERE database = currentDatabase() AND table = ? AND active AND partition_id < ?
+            ORDER BY partition_id",
+        )
+        .bind(MESSAGES_STRUCTURED_TABLE)
+        .bind(before.format("%Y%m").to_string())
+        .fetch_all::<String>()
+        .await?;
+
+    for partition in &partitions {
+        db.query(&format!(
+            "ALTER TABLE {MESSAGES_STRUCTURED_TABLE} DROP PARTITION ID ?"
+        ))
+        .bind(partition)
+        .execute()
+        .await?;
+    }
+
+    Ok(partitions)
+}
@@ -1,10 +1,11 @@
+use crate::logs::normalize::normalize_text;
 use anyhow::Context;
 use bitflags::bitflags;
 use clickhouse::Row;
 use serde::{Deserialize, Serialize};
 use serde_repr::{Deserialize_repr, Serialize_repr};
 use std::fmt::Write;
-use std::{borrow::Cow, fmt::Debug};
+use std::{borrow::Cow, fmt::Debug, str::FromStr};
 use strum::{Display, EnumString};
 use tmi::{IrcMessageRef, Tag};
 use uuid::Uuid;
@@ -25,6 +26,8 @@ bitflags! {
         const R9K               = 128;
         const SUBS_ONLY         = 256;
         const SLOW_MODE         = 512;
+        /// The raw message contained invalid UTF-8 or null characters which were replaced
+        const SANITIZED         = 1024;
     }
 }
 
@@ -92,6 +95,17 @@ pub struct StructuredMessage<'a> {
     text: Cow<'a, str>,
     pub message_flags: MessageFlags,
     pub extra_tags: Vec<(Cow<'a, str>, Cow<'a, str>)>,
+    /// Original bytes of sanitized messages, empty otherwise
+    pub raw_invalid: Cow<'a, [u8]>,
+    /// Text with invisible characters and homoglyphs normalized, empty if it's identical to the text
+    pub text_normalized: Cow<'a, str>,
+}
+
+/// A search result with its relevance, selected as the score followed by all message columns
+#[derive(Row, Deserialize, Debug)]
+pub struct ScoredMessage {
+    pub score: f64,
+    pub message: StructuredMessage<'static>,
 }
 
 #[derive(Row, Serialize, Deserialize, Debug)]
@@ -222,9 +236,64 @@ impl<'a> StructuredMessage<'a> {
             emotes,
             text,
             extra_tags,
+            raw_invalid: Cow::default(),
+            text_normalized: Cow::default(),
         })
     }
 
+    /// Flags the message as sanitized and keeps the bytes it was originally received as
+    pub fn set_sanitized_original(&mut self, original: &'a [u8]) {
+        self.message_flags.insert(MessageFlags::SANITIZED);
+        self.raw_invalid = Cow::Borrowed(original);
+    }
+
+    pub fn normalize_text(&mut self) {
+        if let Cow::Owned(normalized) = normalize_text(&self.text) {
+            self.text_normalized = Cow::Owned(normalized);
+        }
+    }
+
+    /// Approximate amount of heap and inline memory used by the message
+    pub fn estimated_size(&self) -> usize {
+        let strings = [
+            &self.channel_id,
+            &self.channel_login,
+            &self.user_id,
+            &self.user_login,
+            &self.display_name,
+            &self.user_type,
+            &self.badge_info,
+            &self.client_nonce,
+            &self.emotes,
+            &self.automod_flags,
+            &self.text,
+            &self.text_normalized,
+        ];
+        let tags: usize = self
+            .extra_tags
+            .iter()
+            .map(|(tag, value)| tag.len() + value.len())
+            .sum();
+        let badges: usize = self.badges.iter().map(|badge| badge.len()).sum();
+
+        std::mem::size_of::<Self>()
+            + strings.iter().map(|s| s.len()).sum::<usize>()
+            + tags
+            + badges
+            + self.raw_invalid.len()
+    }
+
+    /// Case insensitive substring match on the text, like the database search.
+    /// Both search terms have to be lowercase, `normalized_search` is matched against the normalized text
+    pub fn matches_search(&self, search: &str, normalized_search: &str) -> bool {
+        self.text.to_ascii_lowercase().contains(search)
+            || (!self.text_normalized.is_empty()
+                && self
+                    .text_normalized
+                    .to_ascii_lowercase()
+                    .contains(normalized_search))
+    }
+
     pub fn user_friendly_text(&self) -> Cow<'_, str> {
         match self.message_type {
             MessageType::PrivMsg => Cow::Borrowed(extract_message_text(&self.text)),
@@ -278,6 +347,11 @@ impl<'a> StructuredMessage<'a> {
         }
     }
 
+    /// Id of the message, if Twitch sent one
+    pub fn uuid(&self) -> Option<Uuid> {
+        Some(self.id).filter(|id| !id.is_nil())
+    }
+
     pub fn display_name(&self) -> &str {
         if !self.display_name.is_empty() {
             &self.display_name
@@ -460,6 +534,8 @@ impl<'a> StructuredMessage<'a> {
                 .into_iter()
                 .map(|(k, v)| (Cow::Owned(k.into_owned()), Cow::Owned(v.into_owned())))
                 .collect(),
+            raw_invalid: Cow::Owned(self.raw_invalid.into_owned()),
+            text_normalized: Cow::Owned(self.text_normalized.into_owned()),
         }
     }
 }
@@ -487,7 +563,9 @@ fn escape_tag(value: &str) -> Cow<'_, str> {
     }
 }
 
-#[derive(Serialize_repr, Deserialize_repr, EnumString, Debug, PartialEq, Display, Clone, Copy)]
+#[derive(
+    Serialize_repr, Deserialize_repr, EnumString, Debug, PartialEq, Eq, Hash, Display, Clone, Copy,
+)]
 #[repr(u8)]
 #[strum(serialize_all = "UPPERCASE")]
 pub enum MessageType {
@@ -532,6 +610,48 @@ impl MessageType {
     }
 }
 
+/// A set of message types, e.g. to filter logs by. Bit `n` is set for the message type `n`
+#[derive(Debug, PartialEq, Eq, Default, Clone, Copy)]
+pub struct MessageTypes(u16);
+
+impl MessageTypes {
+    pub fn insert(&mut self, message_type: MessageType) {
+        self.0 |= 1 << message_type as u8;
+    }
+
+    pub fn contains(self, message_type: MessageType) -> bool {
+        self.0 & (1 << message_type as u8) != 0
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    /// Usable with `bitTest(bits, message_type)` in queries
+    pub fn bits(self) -> u16 {
+        self.0
+    }
+}
+
+/// Parses a comma separated list of case insensitive names, e.g. `privmsg,clearchat`
+impl FromStr for MessageTypes {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut types = Self::default();
+        for name in value
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+        {
+            let message_type = MessageType::from_str(&name.to_uppercase())
+                .map_err(|_| format!("Unknown message type {name}"))?;
+            types.insert(message_type);
+        }
+        Ok(types)
+    }
+}
+
 fn extract_message_text(mut message_text: &str) -> &str {
     let is_action =
         message_text.starts_with("\u{0001}ACTION ") && message_text.ends_with('\u{0001}');
@@ -545,7 +665,7 @@ fn extract_message_text(mut message_text: &str) -> &str {
 
 #[cfg(test)]
 mod tests {
-    use super::{MessageType, StructuredMessage, UnstructuredMessage};
+    use super::{MessageType, MessageTypes, StructuredMessage, UnstructuredMessage};
     use crate::db::schema::MessageFlags;
     use pretty_assertions::assert_eq;
     use tmi::{IrcMessageRef, Tag};
@@ -582,6 +702,8 @@ mod tests {
             automod_flags: "".into(),
             text: "+join 󠀀".into(),
             extra_tags: vec![],
+            raw_invalid: Cow::default(),
+            text_normalized: Cow::default(),
         };
 
         assert_eq!(expected_message, message);
@@ -673,4 +795,16 @@ mod tests {
         };
         assert_roundtrip(unstructured);
     }
+
+    #[test]
+    fn parse_message_types() {
+        let types: MessageTypes = "privmsg, CLEARCHAT".parse().unwrap();
+
+        assert!(types.contains(MessageType::PrivMsg));
+        assert!(types.contains(MessageType::ClearChat));
+        assert!(!types.contains(MessageType::UserNotice));
+        assert_eq!(types.bits(), 0b110);
+        assert!("".parse::<MessageTypes>().unwrap().is_empty());
+        assert!("privmsg,unknown".parse::<MessageTypes>().is_err());
+    }
 }
@@ -0,0 +1,974 @@
+use chrono_tz::Tz;
+use clickhouse::{Client, Row};
+use serde::Deserialize;
+use std::collections::{HashMap, HashSet};
+use uuid::Uuid;
+
+use crate::{
+    emotes::GLOBAL_CHANNEL_ID,
+    ids::{ChannelId, UserId},
+    web::schema::{
+        ActivityBreakdown, ActivityStreak, AutomodCounts, ChannelSummary, ChatOverlap,
+        ChatQualityStats, DailyAutomodCounts, DailyMessageCount, DailyModerationCounts,
+        DailySubCounts, EmoteCount, ModerationCounts, NewAndReturningChatters, OverlapChatter,
+        RangeParams, ReplyPair, SubCounts, TopChatter, TopGifter, TwitchEmoteCount,
+        UserChannelMessageCount,
+    },
+    Result,
+};
+
+use super::{schema::MessageType, writer::FlushBuffer};
+
+pub async fn read_activity_breakdown(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    flush_buffer: &FlushBuffer,
+) -> Result<ActivityBreakdown> {
+    let mut breakdown = db
+        .query(
+            "SELECT
+                countIf(message_count = 1),
+                countIf(message_count >= 2 AND message_count <= 10),
+                countIf(message_count >= 11 AND message_count <= 100),
+                countIf(message_count > 100)
+            FROM (
+                SELECT user_id, count() AS message_count FROM message_structured
+                WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND user_id != '' AND NOT has(?, user_id)
+                GROUP BY user_id
+            )",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .fetch_one::<ActivityBreakdown>()
+        .await?;
+
+    // Move chatters with unwritten messages into the bucket of their combined count
+    let buffered =
+        read_buffered_counts(db, flush_buffer, channel_id, params, excluded_user_ids).await?;
+    if !buffered.is_empty() {
+        let stored = read_stored_counts(db, channel_id, params, &buffered).await?;
+        for chatter in &buffered {
+            let stored_count = stored.get(&chatter.user_id).copied().unwrap_or(0);
+            if let Some(bucket) = breakdown.bucket_mut(stored_count) {
+                *bucket = bucket.saturating_sub(1);
+            }
+            if let Some(bucket) = breakdown.bucket_mut(stored_count + chatter.message_count) {
+                *bucket += 1;
+            }
+        }
+    }
+
+    Ok(breakdown)
+}
+
+/// Uses the `user_channel_message_counts` projection, so the range is aligned to whole days
+pub async fn read_user_channel_message_counts(
+    db: &Client,
+    user_id: &UserId,
+    params: RangeParams,
+) -> Result<Vec<UserChannelMessageCount>> {
+    let counts = db
+        .query(
+            "SELECT channel_id, count() AS message_count FROM message_structured
+            WHERE user_id = ? AND toDateTime(toStartOfDay(timestamp)) >= toStartOfDay(toDateTime(?)) AND toDateTime(toStartOfDay(timestamp)) < ?
+            GROUP BY channel_id
+            ORDER BY message_count DESC",
+        )
+        .bind(user_id)
+        .bind(params.from.timestamp())
+        .bind(params.to.timestamp())
+        .fetch_all::<UserChannelMessageCount>()
+        .await?;
+
+    Ok(counts)
+}
+
+#[derive(Row, Deserialize)]
+pub struct UserColorRow {
+    /// Never null, messages without a color are left out
+    pub color: Option<u32>,
+    pub first_seen: u64,
+    pub last_seen: u64,
+    pub message_count: u64,
+}
+
+/// Distinct chat colors the user sent messages with in the given channels, in the order they were first used
+pub async fn read_user_colors(
+    db: &Client,
+    user_id: &UserId,
+    channel_ids: &[String],
+    params: RangeParams,
+) -> Result<Vec<UserColorRow>> {
+    if channel_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let colors = db
+        .query(
+            "SELECT color, toUnixTimestamp64Milli(min(timestamp)) AS first_seen,
+                toUnixTimestamp64Milli(max(timestamp)) AS last_seen, count() AS message_count
+            FROM message_structured
+            WHERE user_id = ? AND has(?, channel_id) AND color IS NOT NULL
+                AND toDateTime(toStartOfDay(timestamp)) >= toStartOfDay(toDateTime(?)) AND toDateTime(toStartOfDay(timestamp)) < ?
+            GROUP BY color
+            ORDER BY first_seen ASC",
+        )
+        .bind(user_id)
+        .bind(channel_ids)
+        .bind(params.from.timestamp())
+        .bind(params.to.timestamp())
+        .fetch_all()
+        .await?;
+
+    Ok(colors)
+}
+
+/// Unique chatters are estimated with `uniqCombined` unless `exact` is set, which is a lot faster on large channels
+pub async fn read_channel_summary(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    exact: bool,
+    flush_buffer: &FlushBuffer,
+) -> Result<ChannelSummary> {
+    let buffered =
+        read_buffered_counts(db, flush_buffer, channel_id, params, excluded_user_ids).await?;
+    let buffered_user_ids: Vec<&str> = buffered.iter().map(|c| c.user_id.as_str()).collect();
+
+    let uniq = if exact { "uniqExact" } else { "uniqCombined" };
+    let query = format!(
+        "SELECT count(), {uniq}(user_id), {uniq}If(user_id, has(?, user_id)) FROM message_structured
+        WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)"
+    );
+
+    let (message_count, unique_chatters, already_counted) = db
+        .query(&query)
+        .bind(&buffered_user_ids)
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .fetch_one::<(u64, u64, u64)>()
+        .await?;
+
+    Ok(ChannelSummary {
+        message_count: message_count + buffered.iter().map(|c| c.message_count).sum::<u64>(),
+        unique_chatters: (unique_chatters + buffered.len() as u64).saturating_sub(already_counted),
+        approximate: !exact,
+    })
+}
+
+/// Summaries of several ranges, in the given order. The messages are read in a single pass,
+/// with every message counted towards each range it falls into
+pub async fn read_range_summaries(
+    db: &Client,
+    channel_id: &ChannelId,
+    ranges: &[RangeParams],
+    excluded_user_ids: &[String],
+    exact: bool,
+    flush_buffer: &FlushBuffer,
+) -> Result<Vec<ChannelSummary>> {
+    let (Some(from), Some(to)) = (
+        ranges.iter().map(|range| range.from).min(),
+        ranges.iter().map(|range| range.to).max(),
+    ) else {
+        return Ok(Vec::new());
+    };
+
+    let mut buffered = Vec::with_capacity(ranges.len());
+    for range in ranges {
+        buffered.push(
+            read_buffered_counts(db, flush_buffer, channel_id, *range, excluded_user_ids).await?,
+        );
+    }
+    let buffered_user_ids: Vec<Vec<&str>> = buffered
+        .iter()
+        .map(|chatters| chatters.iter().map(|c| c.user_id.as_str()).collect())
+        .collect();
+    let froms: Vec<i64> = ranges
+        .iter()
+        .map(|range| range.from.timestamp_millis())
+        .collect();
+    let tos: Vec<i64> = ranges
+        .iter()
+        .map(|range| range.to.timestamp_millis())
+        .collect();
+
+    let uniq = if exact { "uniqExact" } else { "uniqCombined" };
+    let query = format!(
+        "SELECT toUInt64(range_index), count(), {uniq}(user_id), {uniq}If(user_id, has(arrayElement(?, range_index), user_id))
+        FROM message_structured
+        ARRAY JOIN arrayFilter(
+            i -> toUnixTimestamp64Milli(timestamp) >= arrayElement(?, i) AND toUnixTimestamp64Milli(timestamp) < arrayElement(?, i),
+            range(1, ?)
+        ) AS range_index
+        WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)
+        GROUP BY range_index"
+    );
+
+    let rows = db
+        .query(&query)
+        .bind(&buffered_user_ids)
+        .bind(&froms)
+        .bind(&tos)
+        .bind(ranges.len() as u64 + 1)
+        .bind(channel_id)
+        .bind(from.timestamp_millis() as f64 / 1000.0)
+        .bind(to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .fetch_all::<(u64, u64, u64, u64)>()
+        .await?;
+    let stored: HashMap<u64, (u64, u64, u64)> = rows
+        .into_iter()
+        .map(|(index, messages, chatters, counted)| (index, (messages, chatters, counted)))
+        .collect();
+
+    let summaries = buffered
+        .iter()
+        .enumerate()
+        .map(|(i, buffered)| {
+            let (message_count, unique_chatters, already_counted) =
+                stored.get(&(i as u64 + 1)).copied().unwrap_or_default();
+            ChannelSummary {
+                message_count: message_count
+                    + buffered.iter().map(|c| c.message_count).sum::<u64>(),
+                unique_chatters: (unique_chatters + buffered.len() as u64)
+                    .saturating_sub(already_counted),
+                approximate: !exact,
+            }
+        })
+        .collect();
+
+    Ok(summaries)
+}
+
+pub async fn read_top_chatters(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+    offset: u64,
+    flush_buffer: &FlushBuffer,
+) -> Result<Vec<TopChatter>> {
+    let chatters = db
+        .query(
+            "SELECT user_id, any(user_login), count() AS message_count FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND user_id != '' AND NOT has(?, user_id)
+            GROUP BY user_id
+            ORDER BY message_count DESC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .bind(limit.saturating_add(offset))
+        .fetch_all::<TopChatter>()
+        .await?;
+
+    let buffered =
+        read_buffered_counts(db, flush_buffer, channel_id, params, excluded_user_ids).await?;
+    if buffered.is_empty() {
+        return Ok(chatters.into_iter().skip(offset as usize).collect());
+    }
+
+    // Chatters outside of the stored top list can move up with their unwritten messages,
+    // so their stored counts are needed as well
+    let stored = read_stored_counts(db, channel_id, params, &buffered).await?;
+    let mut merged: HashMap<String, TopChatter> = chatters
+        .into_iter()
+        .map(|chatter| (chatter.user_id.clone(), chatter))
+        .collect();
+    for mut chatter in buffered {
+        chatter.message_count += stored.get(&chatter.user_id).copied().unwrap_or(0);
+        merged.insert(chatter.user_id.clone(), chatter);
+    }
+
+    let mut chatters: Vec<TopChatter> = merged.into_values().collect();
+    chatters.sort_unstable_by(|a, b| b.message_count.cmp(&a.message_count));
+
+    Ok(chatters
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .collect())
+}
+
+/// Chat message counts per user of messages which have not been written to the database yet.
+/// Messages which the writer has inserted but not removed from the buffer yet are left out
+async fn read_buffered_counts(
+    db: &Client,
+    flush_buffer: &FlushBuffer,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+) -> Result<Vec<TopChatter>> {
+    let time_range = (params.from.timestamp_millis() as u64)..(params.to.timestamp_millis() as u64);
+    let buffered_ids = flush_buffer
+        .message_ids(
+            time_range.clone(),
+            channel_id.as_str(),
+            MessageType::PrivMsg,
+        )
+        .await;
+    let written_ids = read_written_ids(db, channel_id, params, &buffered_ids).await?;
+
+    Ok(flush_buffer
+        .user_message_counts(
+            time_range,
+            channel_id.as_str(),
+            MessageType::PrivMsg,
+            excluded_user_ids,
+            &written_ids,
+        )
+        .await)
+}
+
+#[derive(Row, Deserialize)]
+struct WrittenId {
+    #[serde(with = "clickhouse::serde::uuid")]
+    id: Uuid,
+}
+
+/// The given buffered message IDs which are already stored
+async fn read_written_ids(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    ids: &[Uuid],
+) -> Result<HashSet<Uuid>> {
+    if ids.is_empty() {
+        return Ok(HashSet::new());
+    }
+    let ids: Vec<String> = ids.iter().map(Uuid::to_string).collect();
+
+    let written = db
+        .query(
+            "SELECT id FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND has(?, toString(id))",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(&ids)
+        .fetch_all::<WrittenId>()
+        .await?;
+
+    Ok(written.into_iter().map(|row| row.id).collect())
+}
+
+/// Stored chat message counts of the given chatters, keyed by user id
+async fn read_stored_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    chatters: &[TopChatter],
+) -> Result<HashMap<String, u64>> {
+    let user_ids: Vec<&str> = chatters.iter().map(|c| c.user_id.as_str()).collect();
+
+    let counts = db
+        .query(
+            "SELECT user_id, count() FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND has(?, user_id)
+            GROUP BY user_id",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(&user_ids)
+        .fetch_all::<(String, u64)>()
+        .await?;
+
+    Ok(counts.into_iter().collect())
+}
+
+/// Counts the third-party emotes in the channel's messages, or only in the messages of the user
+pub async fn read_third_party_emote_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<EmoteCount>> {
+    let user_condition = if user_id.is_some() {
+        " AND user_id = ?"
+    } else {
+        ""
+    };
+
+    let mut query = db
+        .query(&format!(
+            "SELECT tokens.token, emotes.provider, count() AS usage_count
+            FROM (
+                SELECT arrayJoin(splitByWhitespace(text)) AS token FROM message_structured
+                WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id){user_condition}
+            ) AS tokens
+            INNER JOIN (
+                SELECT name, any(provider) AS provider FROM emote_sets
+                WHERE channel_id IN (?, ?)
+                GROUP BY name
+            ) AS emotes ON tokens.token = emotes.name
+            GROUP BY tokens.token, emotes.provider
+            ORDER BY usage_count DESC
+            LIMIT ?",
+        ))
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids);
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    let counts = query
+        .bind(channel_id)
+        .bind(GLOBAL_CHANNEL_ID)
+        .bind(limit)
+        .fetch_all::<EmoteCount>()
+        .await?;
+
+    Ok(counts)
+}
+
+/// Counts the Twitch emotes from the `emotes` tag. The tag only has the IDs and positions of the emotes,
+/// so the name is taken from the text at the first position
+pub async fn read_twitch_emote_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<TwitchEmoteCount>> {
+    let user_condition = if user_id.is_some() {
+        " AND user_id = ?"
+    } else {
+        ""
+    };
+
+    // The tag looks like `25:0-4,12-16/1902:6-10`, the positions are characters of the text without the `/me` wrapper
+    let mut query = db
+        .query(&format!(
+            "SELECT emote_id, any(name), sum(uses) AS usage_count
+            FROM (
+                SELECT
+                    splitByChar(':', arrayJoin(splitByChar('/', emotes))) AS parts,
+                    parts[1] AS emote_id,
+                    splitByChar(',', parts[2]) AS positions,
+                    length(positions) AS uses,
+                    splitByChar('-', positions[1]) AS first_position,
+                    if(startsWith(text, '\\x01ACTION '), substringUTF8(text, 9), text) AS message_text,
+                    substringUTF8(
+                        message_text,
+                        toUInt64OrZero(first_position[1]) + 1,
+                        toUInt64OrZero(first_position[2]) - toUInt64OrZero(first_position[1]) + 1
+                    ) AS name
+                FROM message_structured
+                WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND emotes != '' AND NOT has(?, user_id){user_condition}
+            )
+            GROUP BY emote_id
+            ORDER BY usage_count DESC
+            LIMIT ?",
+        ))
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids);
+    if let Some(user_id) = user_id {
+        query = query.bind(user_id);
+    }
+    let counts = query.bind(limit).fetch_all::<TwitchEmoteCount>().await?;
+
+    Ok(counts)
+}
+
+/// Chatters in the range whose first message in the channel is inside the range are counted as new
+pub async fn read_new_and_returning_chatters(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+) -> Result<NewAndReturningChatters> {
+    let from = params.from.timestamp_millis() as f64 / 1000.0;
+
+    let chatters = db
+        .query(
+            "SELECT countIf(first_seen >= ?), countIf(first_seen < ?) FROM (
+                SELECT user_id, min(timestamp) AS first_seen FROM message_structured
+                WHERE channel_id = ? AND timestamp < ? AND message_type = ? AND user_id != '' AND NOT has(?, user_id)
+                GROUP BY user_id
+                HAVING max(timestamp) >= ?
+            )",
+        )
+        .bind(from)
+        .bind(from)
+        .bind(channel_id)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .bind(from)
+        .fetch_one::<NewAndReturningChatters>()
+        .await?;
+
+    Ok(chatters)
+}
+
+/// Gifts are counted by their individual `subgift` notices, `submysterygift` is only a summary of those
+pub async fn read_sub_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+) -> Result<SubCounts> {
+    let counts = db
+        .query(
+            "SELECT
+                countIf(extra_tags['msg-id'] = 'sub'),
+                countIf(extra_tags['msg-id'] = 'resub'),
+                countIf(extra_tags['msg-id'] = 'subgift')
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::UserNotice as u8)
+        .bind(excluded_user_ids)
+        .fetch_one::<SubCounts>()
+        .await?;
+
+    Ok(counts)
+}
+
+pub async fn read_daily_message_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+) -> Result<Vec<DailyMessageCount>> {
+    let counts = db
+        .query(
+            "SELECT toString(toDate(timestamp)) AS date, count(), uniqCombined(user_id)
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)
+            GROUP BY date
+            ORDER BY date ASC",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .fetch_all::<DailyMessageCount>()
+        .await?;
+
+    Ok(counts)
+}
+
+pub async fn read_daily_sub_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+) -> Result<Vec<DailySubCounts>> {
+    let counts = db
+        .query(
+            "SELECT
+                toString(toDate(timestamp)) AS date,
+                countIf(extra_tags['msg-id'] = 'sub'),
+                countIf(extra_tags['msg-id'] = 'resub'),
+                countIf(extra_tags['msg-id'] = 'subgift')
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)
+                AND extra_tags['msg-id'] IN ('sub', 'resub', 'subgift')
+            GROUP BY date
+            ORDER BY date ASC",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::UserNotice as u8)
+        .bind(excluded_user_ids)
+        .fetch_all::<DailySubCounts>()
+        .await?;
+
+    Ok(counts)
+}
+
+/// Duplicates are messages whose text was already sent in the range.
+/// Emote positions in the `emotes` tag look like `25:0-4,12-16/1902:6-10` and count characters like `lengthUTF8`
+pub async fn read_chat_quality(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+) -> Result<ChatQualityStats> {
+    let (message_count, average_length, median_length, unique_texts, emote_chars, total_chars) = db
+        .query(
+            "SELECT
+                count(),
+                ifNotFinite(avg(lengthUTF8(text)), 0),
+                ifNotFinite(median(lengthUTF8(text)), 0),
+                uniqExact(text),
+                toUInt64(sum(arraySum(arrayMap(
+                    range -> greatest(toInt64OrZero(splitByChar('-', range)[2]) - toInt64OrZero(splitByChar('-', range)[1]) + 1, 0),
+                    arrayFilter(range -> range != '', splitByRegexp('[,/]', replaceRegexpAll(emotes, '[^:/,]+:', '')))
+                )))),
+                sum(lengthUTF8(text))
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .fetch_one::<(u64, f64, f64, u64, u64, u64)>()
+        .await?;
+
+    let share = |part: u64, total: u64| {
+        if total == 0 {
+            0.0
+        } else {
+            part as f64 / total as f64
+        }
+    };
+
+    Ok(ChatQualityStats {
+        message_count,
+        average_length,
+        median_length,
+        duplicate_share: share(message_count - unique_texts, message_count),
+        emote_share: share(emote_chars.min(total_chars), total_chars),
+    })
+}
+
+/// Runs of consecutive days on which the user chatted, with days in the channel's timezone.
+/// Subtracting the row number from consecutive days gives the same value, which groups each run
+pub async fn read_user_activity_streaks(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: &UserId,
+    timezone: Tz,
+) -> Result<Vec<ActivityStreak>> {
+    let streaks = db
+        .query(
+            "SELECT count(), toString(min(day)), toString(max(day)) FROM (
+                SELECT day, toInt64(toUInt32(day)) - toInt64(row_number() OVER (ORDER BY day)) AS streak
+                FROM (
+                    SELECT DISTINCT toDate(timestamp, ?) AS day FROM message_structured
+                    WHERE channel_id = ? AND user_id = ? AND message_type = ?
+                )
+            )
+            GROUP BY streak
+            ORDER BY streak ASC",
+        )
+        .bind(timezone.name())
+        .bind(channel_id)
+        .bind(user_id)
+        .bind(MessageType::PrivMsg as u8)
+        .fetch_all::<ActivityStreak>()
+        .await?;
+
+    Ok(streaks)
+}
+
+/// Distinct active buckets of the user per stream, keyed by the index of the stream window.
+/// Windows are given as unix timestamps and a message counts for the first window it falls into
+pub async fn read_user_active_buckets(
+    db: &Client,
+    channel_id: &ChannelId,
+    user_id: &UserId,
+    windows: &[(u32, u32)],
+    bucket_seconds: u64,
+) -> Result<HashMap<usize, u64>> {
+    let Some(from) = windows.iter().map(|(start, _)| *start).min() else {
+        return Ok(HashMap::new());
+    };
+    let to = windows.iter().map(|(_, end)| *end).max().unwrap_or(from);
+    let (starts, ends): (Vec<u32>, Vec<u32>) = windows.iter().copied().unzip();
+
+    let rows = db
+        .query(
+            "SELECT
+                arrayFirstIndex((start, end) -> toUnixTimestamp(timestamp) >= start AND toUnixTimestamp(timestamp) < end, ?, ?) AS window,
+                uniqExact(intDiv(toUnixTimestamp(timestamp), ?))
+            FROM message_structured
+            WHERE channel_id = ? AND user_id = ? AND message_type = ? AND timestamp >= ? AND timestamp < ?
+            GROUP BY window
+            HAVING window > 0",
+        )
+        .bind(starts)
+        .bind(ends)
+        .bind(bucket_seconds)
+        .bind(channel_id)
+        .bind(user_id)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(from)
+        .bind(to)
+        .fetch_all::<(u32, u64)>()
+        .await?;
+
+    // arrayFirstIndex is 1-based
+    Ok(rows
+        .into_iter()
+        .map(|(window, buckets)| (window as usize - 1, buckets))
+        .collect())
+}
+
+/// Chatters of both channels, intersected by grouping their messages per user
+pub async fn read_chat_overlap(
+    db: &Client,
+    first_channel_id: &ChannelId,
+    second_channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    sample: u64,
+) -> Result<ChatOverlap> {
+    let (shared_chatters, first_chatters, second_chatters, sample) = db
+        .query(
+            "SELECT
+                countIf(in_first AND in_second),
+                countIf(in_first),
+                countIf(in_second),
+                groupArraySampleIf(?)((user_id, user_login), in_first AND in_second)
+            FROM (
+                SELECT user_id, any(user_login) AS user_login, max(channel_id = ?) AS in_first, max(channel_id = ?) AS in_second
+                FROM message_structured
+                WHERE channel_id IN (?, ?) AND timestamp >= ? AND timestamp < ? AND message_type = ? AND user_id != '' AND NOT has(?, user_id)
+                GROUP BY user_id
+            )",
+        )
+        .bind(sample)
+        .bind(first_channel_id)
+        .bind(second_channel_id)
+        .bind(first_channel_id)
+        .bind(second_channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .fetch_one::<(u64, u64, u64, Vec<OverlapChatter>)>()
+        .await?;
+
+    let either = first_chatters + second_chatters - shared_chatters;
+    let jaccard_index = if either == 0 {
+        0.0
+    } else {
+        shared_chatters as f64 / either as f64
+    };
+
+    Ok(ChatOverlap {
+        shared_chatters,
+        first_chatters,
+        second_chatters,
+        jaccard_index,
+        sample,
+    })
+}
+
+/// Text of randomly sampled chat messages in the range
+pub async fn read_message_text_sample(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<String>> {
+    let texts = db
+        .query(
+            "SELECT text FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)
+            ORDER BY rand()
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .bind(limit)
+        .fetch_all::<String>()
+        .await?;
+
+    Ok(texts)
+}
+
+/// The `flags` tag lists flagged ranges of the text like `0-4:A.6/P.6,10-15:S.3`, with the category letter before each level
+const AUTOMOD_CATEGORY_COUNTS: &str = "count(),
+    countIf(match(automod_flags, '[:/]A[.]')),
+    countIf(match(automod_flags, '[:/]I[.]')),
+    countIf(match(automod_flags, '[:/]P[.]')),
+    countIf(match(automod_flags, '[:/]S[.]'))";
+
+pub async fn read_automod_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<AutomodCounts> {
+    let counts = db
+        .query(&format!(
+            "SELECT {AUTOMOD_CATEGORY_COUNTS}
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND automod_flags != ''"
+        ))
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .fetch_one::<AutomodCounts>()
+        .await?;
+
+    Ok(counts)
+}
+
+pub async fn read_daily_automod_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<Vec<DailyAutomodCounts>> {
+    let counts = db
+        .query(&format!(
+            "SELECT toString(toDate(timestamp)) AS date, {AUTOMOD_CATEGORY_COUNTS}
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND automod_flags != ''
+            GROUP BY date
+            ORDER BY date ASC"
+        ))
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .fetch_all::<DailyAutomodCounts>()
+        .await?;
+
+    Ok(counts)
+}
+
+/// Bans and timeouts are `CLEARCHAT` messages with a target user, timeouts carry a `ban-duration`
+pub async fn read_moderation_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<ModerationCounts> {
+    let counts = db
+        .query(
+            "SELECT
+                countIf(NOT mapContains(extra_tags, 'ban-duration')),
+                countIf(mapContains(extra_tags, 'ban-duration')),
+                ifNotFinite(avgIf(toUInt32OrZero(extra_tags['ban-duration']), mapContains(extra_tags, 'ban-duration')), 0)
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND text != ''",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::ClearChat as u8)
+        .fetch_one::<ModerationCounts>()
+        .await?;
+
+    Ok(counts)
+}
+
+pub async fn read_daily_moderation_counts(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<Vec<DailyModerationCounts>> {
+    let counts = db
+        .query(
+            "SELECT
+                toString(toDate(timestamp)) AS date,
+                countIf(NOT mapContains(extra_tags, 'ban-duration')),
+                countIf(mapContains(extra_tags, 'ban-duration')),
+                ifNotFinite(avgIf(toUInt32OrZero(extra_tags['ban-duration']), mapContains(extra_tags, 'ban-duration')), 0)
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND text != ''
+            GROUP BY date
+            ORDER BY date ASC",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::ClearChat as u8)
+        .fetch_all::<DailyModerationCounts>()
+        .await?;
+
+    Ok(counts)
+}
+
+/// Replies keep the parent message's author in their tags, replies to oneself are left out
+pub async fn read_top_reply_pairs(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<ReplyPair>> {
+    let pairs = db
+        .query(
+            "SELECT
+                user_id,
+                any(user_login),
+                extra_tags['reply-parent-user-id'] AS parent_user_id,
+                any(extra_tags['reply-parent-user-login']),
+                count() AS reply_count
+            FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ?
+                AND mapContains(extra_tags, 'reply-parent-user-id')
+                AND NOT has(?, user_id) AND NOT has(?, parent_user_id) AND parent_user_id != user_id
+            GROUP BY user_id, parent_user_id
+            ORDER BY reply_count DESC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::PrivMsg as u8)
+        .bind(excluded_user_ids)
+        .bind(excluded_user_ids)
+        .bind(limit)
+        .fetch_all::<ReplyPair>()
+        .await?;
+
+    Ok(pairs)
+}
+
+pub async fn read_top_gifters(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+    excluded_user_ids: &[String],
+    limit: u64,
+) -> Result<Vec<TopGifter>> {
+    let gifters = db
+        .query(
+            "SELECT user_id, any(user_login), count() AS gift_count FROM message_structured
+            WHERE channel_id = ? AND timestamp >= ? AND timestamp < ? AND message_type = ? AND NOT has(?, user_id)
+                AND extra_tags['msg-id'] = 'subgift'
+            GROUP BY user_id
+            ORDER BY gift_count DESC
+            LIMIT ?",
+        )
+        .bind(channel_id)
+        .bind(params.from.timestamp_millis() as f64 / 1000.0)
+        .bind(params.to.timestamp_millis() as f64 / 1000.0)
+        .bind(MessageType::UserNotice as u8)
+        .bind(excluded_user_ids)
+        .bind(limit)
+        .fetch_all::<TopGifter>()
+        .await?;
+
+    Ok(gifters)
+}
@@ -0,0 +1,65 @@
+use crate::{config::StorageTiering, web::schema::TableDiskUsage, Result};
+use clickhouse::Client;
+use tracing::info;
+
+/// Table whose old partitions are moved to the cold volume
+const TIERED_TABLE: &str = "message_structured";
+
+/// Applies the configured storage policy and move TTL if they differ from the table's current settings
+pub async fn apply_storage_tiering(db: &Client, tiering: &StorageTiering) -> Result<()> {
+    let (current_policy, engine) = db
+        .query("SELECT storage_policy, engine_full FROM system.tables WHERE database = currentDatabase() AND name = ?")
+        .bind(TIERED_TABLE)
+        .fetch_one::<(String, String)>()
+        .await?;
+
+    if current_policy != tiering.policy {
+        info!(
+            "Changing storage policy of {TIERED_TABLE} from {current_policy} to {}",
+            tiering.policy
+        );
+        db.query(&format!(
+            "ALTER TABLE {TIERED_TABLE} MODIFY SETTING storage_policy = ?"
+        ))
+        .bind(&tiering.policy)
+        .execute()
+        .await?;
+    }
+
+    // Modifying the TTL recalculates it for all parts, so it's only done when it changed
+    let expected_ttl = format!(
+        "toIntervalDay({}) TO VOLUME '{}'",
+        tiering.move_after_days, tiering.volume
+    );
+    if !engine.contains(&expected_ttl) {
+        info!(
+            "Moving partitions of {TIERED_TABLE} older than {} days to volume {}",
+            tiering.move_after_days, tiering.volume
+        );
+        db.query(&format!(
+            "ALTER TABLE {TIERED_TABLE} MODIFY TTL toDateTime(timestamp) + INTERVAL ? DAY TO VOLUME ?"
+        ))
+        .bind(tiering.move_after_days)
+        .bind(&tiering.volume)
+        .execute()
+        .await?;
+    }
+
+    Ok(())
+}
+
+/// Active parts of rustlog's tables grouped by the disk they are stored on
+pub async fn read_disk_usage(db: &Client) -> Result<Vec<TableDiskUsage>> {
+    let usage = db
+        .query(
+            "SELECT table, disk_name, count(), uniqExact(partition), min(partition), max(partition), sum(rows), sum(bytes_on_disk)
+            FROM system.parts
+            WHERE database = currentDatabase() AND active
+            GROUP BY table, disk_name
+            ORDER BY table, disk_name",
+        )
+        .fetch_all()
+        .await?;
+
+    Ok(usage)
+}
@@ -0,0 +1,125 @@
+use chrono::DateTime;
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+use crate::{error::Error, ids::ChannelId, web::schema::RangeParams, Result};
+
+pub const STREAMS_TABLE: &str = "stream";
+
+/// Live streams are written on every poll, `ended_at` is the last time the stream was seen live.
+/// Once a stream is missing from a poll, it is written a last time with `ended` set
+#[derive(Row, Serialize, Deserialize, Debug, Clone)]
+pub struct StreamRow {
+    pub stream_id: String,
+    pub channel_id: String,
+    pub started_at: u32,
+    pub ended_at: u32,
+    pub title: String,
+    pub game_name: String,
+    /// ID of the stream's VOD, empty if it is not known
+    pub video_id: String,
+    pub ended: bool,
+    pub updated_at: u32,
+}
+
+pub async fn write_streams(db: &Client, streams: &[StreamRow]) -> Result<()> {
+    if streams.is_empty() {
+        return Ok(());
+    }
+
+    let mut insert = db.insert(STREAMS_TABLE)?;
+    for stream in streams {
+        insert.write(stream).await?;
+    }
+    insert.end().await?;
+
+    Ok(())
+}
+
+/// Streams which were live at the last poll, so streams which ended while rustlog was not running are detected
+pub async fn read_unended_streams(db: &Client) -> Result<Vec<StreamRow>> {
+    let streams = db
+        .query("SELECT ?fields FROM stream FINAL WHERE NOT ended")
+        .fetch_all()
+        .await?;
+
+    Ok(streams)
+}
+
+pub async fn read_stream_ids(db: &Client, channel_id: &ChannelId) -> Result<HashSet<String>> {
+    let stream_ids = db
+        .query("SELECT DISTINCT stream_id FROM stream WHERE channel_id = ?")
+        .bind(channel_id)
+        .fetch_all()
+        .await?;
+
+    Ok(stream_ids.into_iter().collect())
+}
+
+pub async fn read_stream(
+    db: &Client,
+    channel_id: &ChannelId,
+    stream_id: &str,
+) -> Result<Option<StreamRow>> {
+    let stream = db
+        .query("SELECT ?fields FROM stream FINAL WHERE channel_id = ? AND stream_id = ?")
+        .bind(channel_id)
+        .bind(stream_id)
+        .fetch_optional()
+        .await?;
+
+    Ok(stream)
+}
+
+pub async fn read_streams(
+    db: &Client,
+    channel_id: &ChannelId,
+    params: RangeParams,
+) -> Result<Vec<StreamRow>> {
+    let streams = db
+        .query(
+            "SELECT ?fields FROM stream FINAL
+            WHERE channel_id = ? AND started_at < ? AND ended_at >= ?
+            ORDER BY started_at ASC",
+        )
+        .bind(channel_id)
+        .bind(params.to.timestamp())
+        .bind(params.from.timestamp())
+        .fetch_all()
+        .await?;
+
+    Ok(streams)
+}
+
+/// Time range of the chat during the stream. As the stream may have ended any time until the next poll,
+/// the range ends `end_grace` seconds after it was last seen live, but at the latest when the next stream started
+pub async fn read_stream_range(
+    db: &Client,
+    channel_id: &ChannelId,
+    stream_id: &str,
+    end_grace: u32,
+) -> Result<Option<RangeParams>> {
+    let Some(stream) = read_stream(db, channel_id, stream_id).await? else {
+        return Ok(None);
+    };
+
+    let next_started_at: Option<u32> = db
+        .query(
+            "SELECT minOrNull(started_at) FROM stream FINAL
+            WHERE channel_id = ? AND started_at > ?",
+        )
+        .bind(channel_id)
+        .bind(stream.started_at)
+        .fetch_one()
+        .await?;
+
+    let mut ended_at = stream.ended_at.saturating_add(end_grace);
+    if let Some(next_started_at) = next_started_at {
+        ended_at = ended_at.min(next_started_at);
+    }
+
+    let from = DateTime::from_timestamp(stream.started_at.into(), 0).ok_or(Error::Internal)?;
+    let to = DateTime::from_timestamp(ended_at.into(), 0).ok_or(Error::Internal)?;
+    Ok(Some(RangeParams { from, to }))
+}
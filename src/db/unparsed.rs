@@ -0,0 +1,193 @@
+use super::{
+    schema::{StructuredMessage, UnstructuredMessage, MESSAGES_STRUCTURED_TABLE},
+    writer::BatchRow,
+};
+use crate::{web::schema::UnparsedRetryResult, Result};
+use clickhouse::{Client, Row};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+use tracing::debug;
+use uuid::Uuid;
+
+pub const MESSAGES_UNPARSED_TABLE: &str = "message_unparsed";
+const DEFAULT_UNPARSED_LIMIT: u64 = 100;
+/// Unparsed messages retried at once, so the table is never loaded completely
+const RETRY_BATCH_SIZE: u64 = 10_000;
+
+/// A message which could not be converted into a [`StructuredMessage`]
+#[derive(Row, Serialize, Deserialize, Debug, Clone)]
+pub struct UnparsedMessage {
+    #[serde(with = "clickhouse::serde::uuid")]
+    pub id: Uuid,
+    pub channel_id: String,
+    pub user_id: String,
+    pub timestamp: u64,
+    pub raw: String,
+    pub error: String,
+}
+
+impl BatchRow for UnparsedMessage {
+    const TABLE: &'static str = MESSAGES_UNPARSED_TABLE;
+}
+
+impl UnparsedMessage {
+    pub fn new(message: &UnstructuredMessage, error: &anyhow::Error) -> Self {
+        Self {
+            id: Uuid::new_v4(),
+            channel_id: message.channel_id.to_owned(),
+            user_id: message.user_id.to_owned(),
+            timestamp: message.timestamp,
+            raw: message.raw.to_owned(),
+            error: format!("{error:#}"),
+        }
+    }
+}
+
+pub async fn read_unparsed_messages(
+    db: &Client,
+    limit: Option<u64>,
+) -> Result<Vec<UnparsedMessage>> {
+    let messages = db
+        .query("SELECT ?fields FROM message_unparsed ORDER BY timestamp DESC LIMIT ?")
+        .bind(limit.unwrap_or(DEFAULT_UNPARSED_LIMIT))
+        .fetch_all()
+        .await?;
+
+    Ok(messages)
+}
+
+/// Parses all stored messages again and moves the ones which succeed into the main table, in batches following
+/// the table's sorting key
+pub async fn retry_unparsed_messages(db: &Client) -> Result<UnparsedRetryResult> {
+    let mut result = UnparsedRetryResult {
+        parsed: 0,
+        failed: 0,
+    };
+    let mut last_key: Option<(String, u64, Uuid)> = None;
+
+    loop {
+        let query = match &last_key {
+            Some((channel_id, timestamp, id)) => db
+                .query(
+                    "SELECT ?fields FROM message_unparsed
+                    WHERE (channel_id, timestamp, id) > (?, toDateTime64(?, 3), toUUID(?))
+                    ORDER BY channel_id, timestamp, id LIMIT ?",
+                )
+                .bind(channel_id)
+                .bind(*timestamp as f64 / 1000.0)
+                .bind(id.to_string()),
+            None => db.query(
+                "SELECT ?fields FROM message_unparsed ORDER BY channel_id, timestamp, id LIMIT ?",
+            ),
+        };
+        let messages = query
+            .bind(RETRY_BATCH_SIZE)
+            .fetch_all::<UnparsedMessage>()
+            .await?;
+        let Some(last) = messages.last() else {
+            break;
+        };
+        last_key = Some((last.channel_id.clone(), last.timestamp, last.id));
+
+        let parsed = retry_batch(db, &messages).await?;
+        result.parsed += parsed;
+        result.failed += messages.len() as u64 - parsed;
+    }
+
+    debug!(
+        "Parsed {} of {} previously unparsed messages",
+        result.parsed,
+        result.parsed + result.failed
+    );
+
+    Ok(result)
+}
+
+/// Returns how many messages of the batch were parsed and moved
+async fn retry_batch(db: &Client, messages: &[UnparsedMessage]) -> Result<u64> {
+    let mut parsed = Vec::new();
+    let mut parsed_ids = Vec::new();
+
+    for message in messages {
+        let unstructured = UnstructuredMessage {
+            channel_id: &message.channel_id,
+            user_id: &message.user_id,
+            timestamp: message.timestamp,
+            raw: &message.raw,
+        };
+
+        if let Ok(structured) = StructuredMessage::from_unstructured(&unstructured) {
+            parsed.push(structured.into_owned());
+            parsed_ids.push(message.id.to_string());
+        }
+    }
+
+    if parsed.is_empty() {
+        return Ok(0);
+    }
+
+    // A retry which was interrupted between the insert and the delete has already stored some of the messages
+    let stored = read_stored_keys(db, &parsed).await?;
+    let mut insert = db.insert(MESSAGES_STRUCTURED_TABLE)?;
+    for message in parsed
+        .iter()
+        .filter(|message| !stored.contains(&message_key(message)))
+    {
+        insert.write(message).await?;
+    }
+    insert.end().await?;
+
+    db.query("DELETE FROM message_unparsed WHERE has(?, toString(id))")
+        .bind(&parsed_ids)
+        .execute()
+        .await?;
+
+    Ok(parsed.len() as u64)
+}
+
+#[derive(Row, Deserialize, PartialEq, Eq, Hash)]
+struct StoredKey {
+    channel_id: String,
+    timestamp: u64,
+    #[serde(with = "clickhouse::serde::uuid")]
+    id: Uuid,
+}
+
+fn message_key(message: &StructuredMessage) -> StoredKey {
+    StoredKey {
+        channel_id: message.channel_id.to_string(),
+        timestamp: message.timestamp,
+        id: message.uuid().unwrap_or_default(),
+    }
+}
+
+async fn read_stored_keys(
+    db: &Client,
+    messages: &[StructuredMessage<'_>],
+) -> Result<HashSet<StoredKey>> {
+    let channel_ids: HashSet<&str> = messages
+        .iter()
+        .map(|message| message.channel_id.as_ref())
+        .collect();
+    let channel_ids: Vec<&str> = channel_ids.into_iter().collect();
+    let ids: Vec<String> = messages
+        .iter()
+        .map(|message| message.uuid().unwrap_or_default().to_string())
+        .collect();
+    let from = messages.iter().map(|message| message.timestamp).min();
+    let to = messages.iter().map(|message| message.timestamp).max();
+
+    let keys = db
+        .query(
+            "SELECT channel_id, timestamp, id FROM message_structured
+            WHERE has(?, channel_id) AND timestamp >= ? AND timestamp <= ? AND has(?, toString(id))",
+        )
+        .bind(&channel_ids)
+        .bind(from.unwrap_or_default() as f64 / 1000.0)
+        .bind(to.unwrap_or_default() as f64 / 1000.0)
+        .bind(&ids)
+        .fetch_all::<StoredKey>()
+        .await?;
+
+    Ok(keys.into_iter().collect())
+}
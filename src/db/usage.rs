@@ -0,0 +1,51 @@
+use super::writer::BatchRow;
+use crate::{
+    web::schema::{UsageConsumer, UsageGroup, UsageParams},
+    Result,
+};
+use clickhouse::{Client, Row};
+use serde::Serialize;
+
+pub const API_USAGE_TABLE: &str = "api_usage";
+const DEFAULT_USAGE_LIMIT: u64 = 100;
+
+#[derive(Row, Serialize)]
+pub struct UsageRecord {
+    pub timestamp: u32,
+    pub route: String,
+    pub api_key: String,
+    pub ip: String,
+    pub status: u16,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+impl BatchRow for UsageRecord {
+    const TABLE: &'static str = API_USAGE_TABLE;
+}
+
+pub async fn read_top_consumers(db: &Client, params: &UsageParams) -> Result<Vec<UsageConsumer>> {
+    let column = match params.group_by {
+        UsageGroup::Ip => "ip",
+        UsageGroup::Key => "api_key",
+        UsageGroup::Route => "route",
+    };
+
+    let query = format!(
+        "SELECT {column} AS consumer, count() AS requests, sum(rows) AS total_rows, sum(bytes) AS total_bytes FROM api_usage
+        WHERE timestamp >= ? AND timestamp < ?
+        GROUP BY consumer
+        ORDER BY requests DESC
+        LIMIT ?"
+    );
+
+    let consumers = db
+        .query(&query)
+        .bind(params.range.from.timestamp())
+        .bind(params.range.to.timestamp())
+        .bind(params.limit.unwrap_or(DEFAULT_USAGE_LIMIT))
+        .fetch_all::<UsageConsumer>()
+        .await?;
+
+    Ok(consumers)
+}
@@ -1,85 +1,386 @@
-use super::schema::StructuredMessage;
-use crate::{db::schema::MESSAGES_STRUCTURED_TABLE, ShutdownRx};
+use super::{
+    latency::INGESTION_LATENCY,
+    schema::{MessageType, StructuredMessage},
+};
+use crate::{
+    db::schema::MESSAGES_STRUCTURED_TABLE, logs::normalize::normalize_text, watchdog::Heartbeat,
+    web::schema::TopChatter, ShutdownRx,
+};
 use anyhow::{anyhow, Context};
-use clickhouse::Client;
+use clickhouse::{Client, Row};
 use lazy_static::lazy_static;
-use prometheus::{register_int_gauge, IntGauge};
-use std::{ops::Range, sync::Arc, time::Duration};
+use prometheus::{
+    register_histogram_vec, register_int_counter_vec, register_int_gauge_vec, HistogramVec,
+    IntCounterVec, IntGaugeVec,
+};
+use serde::Serialize;
+use std::{
+    collections::{HashMap, HashSet},
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, Mutex,
+    },
+    time::Duration,
+};
 use tokio::{
     sync::{
         mpsc::{channel, Sender},
-        RwLock,
+        oneshot, Notify, RwLock,
     },
     task::JoinHandle,
     time::{sleep, Instant},
 };
-use tracing::{debug, error, info, trace};
+use tracing::{debug, error, info, trace, warn};
+use uuid::Uuid;
 
-const RETRY_COUNT: usize = 20;
-const RETRY_INTERVAL_SECONDS: u64 = 5;
+const CHANNEL_SIZE: usize = 1000;
+const RETRY_COUNT: u32 = 10;
+/// Attempts for each half after a batch has been split
+const SPLIT_RETRY_COUNT: u32 = 2;
+const INITIAL_BACKOFF_MS: u64 = 500;
+const MAX_BACKOFF_SECONDS: u64 = 60;
+const DEFAULT_PAGE_BYTES: usize = 4 * 1024 * 1024;
+/// ClickHouse error codes caused by the inserted rows, e.g. values which cannot be parsed or are too large
+const DATA_ERROR_CODES: &[u32] = &[
+    6,   // CANNOT_PARSE_TEXT
+    26,  // CANNOT_PARSE_QUOTED_STRING
+    27,  // CANNOT_PARSE_INPUT_ASSERTION_FAILED
+    33,  // CANNOT_READ_ALL_DATA
+    38,  // CANNOT_PARSE_DATE
+    41,  // CANNOT_PARSE_DATETIME
+    53,  // TYPE_MISMATCH
+    69,  // ARGUMENT_OUT_OF_BOUND
+    70,  // CANNOT_CONVERT_TYPE
+    72,  // CANNOT_PARSE_NUMBER
+    117, // INCORRECT_DATA
+    131, // TOO_LARGE_STRING_SIZE
+    321, // VALUE_IS_OUT_OF_RANGE_OF_DATA_TYPE
+    349, // CANNOT_INSERT_NULL_IN_ORDINARY_COLUMN
+];
 
 lazy_static! {
-    static ref BATCH_MSG_COUNT_GAGUE: IntGauge = register_int_gauge!(
+    static ref BATCH_MSG_COUNT_GAGUE: IntGaugeVec = register_int_gauge_vec!(
         "rustlog_messages_written_per_batch",
-        "How many messages are written to the database per batch"
+        "How many rows are written to the database per batch",
+        &["table"]
+    )
+    .unwrap();
+    static ref RETRIED_ROWS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "rustlog_writer_retried_rows_total",
+        "How many rows had to be inserted again after a failed insert",
+        &["table"]
     )
     .unwrap();
+    static ref DROPPED_ROWS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        "rustlog_writer_dropped_rows_total",
+        "How many rows were dropped because the database rejected them",
+        &["table"]
+    )
+    .unwrap();
+    static ref BUFFERED_ROWS_GAUGE: IntGaugeVec = register_int_gauge_vec!(
+        "rustlog_writer_buffered_rows",
+        "How many rows are waiting in the flush buffer to be written",
+        &["table"]
+    )
+    .unwrap();
+    static ref FLUSH_DURATION_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        "rustlog_writer_flush_duration_seconds",
+        "How long writing the flush buffer to the database took, including retries",
+        &["table"]
+    )
+    .unwrap();
+}
+
+/// A row which is inserted in batches by a writer task created with [`create_writer`]
+pub trait BatchRow: Row + Serialize + Send + Sync + 'static {
+    const TABLE: &'static str;
+
+    /// Channel and sent timestamp in milliseconds, for rows whose ingestion latency is tracked
+    fn sent_at(&self) -> Option<(&str, u64)> {
+        None
+    }
+}
+
+impl BatchRow for StructuredMessage<'static> {
+    const TABLE: &'static str = MESSAGES_STRUCTURED_TABLE;
+
+    fn sent_at(&self) -> Option<(&str, u64)> {
+        Some((&self.channel_id, self.timestamp))
+    }
+}
+
+/// Rows matching the predicate are removed from the buffer, the amount is sent back once they are gone
+type Eviction<T> = (
+    Box<dyn Fn(&T) -> bool + Send + Sync>,
+    oneshot::Sender<usize>,
+);
+
+/// Rows which have been received but not written to the database yet
+#[derive(Clone)]
+pub struct FlushBuffer<T = StructuredMessage<'static>> {
+    messages: Arc<RwLock<Vec<T>>>,
+    overloaded: Arc<AtomicBool>,
+    /// Evictions are applied by the writer task, since it relies on the buffer not changing while it writes
+    evictions: Arc<Mutex<Vec<Eviction<T>>>>,
+    evictions_requested: Arc<Notify>,
+    /// Approximate amount of bytes a log response reads from the buffer at once
+    page_bytes: usize,
+    /// Set by every iteration of the writer loop, which runs at least once per flush interval
+    heartbeat: Heartbeat,
+}
+
+impl<T> Default for FlushBuffer<T> {
+    fn default() -> Self {
+        Self {
+            messages: Arc::default(),
+            overloaded: Arc::default(),
+            evictions: Arc::default(),
+            evictions_requested: Arc::default(),
+            page_bytes: DEFAULT_PAGE_BYTES,
+            heartbeat: Heartbeat::default(),
+        }
+    }
+}
+
+impl<T> FlushBuffer<T> {
+    pub fn with_page_bytes(mut self, page_bytes: usize) -> Self {
+        self.page_bytes = page_bytes;
+        self
+    }
+
+    pub fn heartbeat(&self) -> Heartbeat {
+        self.heartbeat.clone()
+    }
+
+    /// Whether the writer is falling behind and producers should shed load
+    pub fn is_overloaded(&self) -> bool {
+        self.overloaded.load(Ordering::Relaxed)
+    }
+
+    /// Removes the buffered rows matching the predicate, so they are never written.
+    /// Resolves with the amount of removed rows once the writer task has removed them,
+    /// at which point every other row received before has been written.
+    /// Waits forever if there is no writer task
+    pub async fn evict(&self, predicate: impl Fn(&T) -> bool + Send + Sync + 'static) -> usize {
+        let (done_tx, done_rx) = oneshot::channel();
+        self.evictions
+            .lock()
+            .unwrap()
+            .push((Box::new(predicate), done_tx));
+        self.evictions_requested.notify_one();
+
+        // The sender is only dropped together with the buffer
+        done_rx.await.unwrap_or_default()
+    }
+
+    async fn apply_evictions(&self) {
+        let evictions = std::mem::take(&mut *self.evictions.lock().unwrap());
+        if evictions.is_empty() {
+            return;
+        }
+
+        let mut messages = self.messages.write().await;
+        for (predicate, done_tx) in evictions {
+            let len = messages.len();
+            messages.retain(|msg| !predicate(msg));
+            done_tx.send(len - messages.len()).ok();
+        }
+    }
+
+    fn set_overloaded(&self, overloaded: bool) {
+        if self.overloaded.swap(overloaded, Ordering::Relaxed) != overloaded {
+            if overloaded {
+                warn!("Database writer is falling behind, signaling backpressure");
+            } else {
+                info!("Database writer has caught up");
+            }
+        }
+    }
 }
 
-#[derive(Default, Clone)]
-pub struct FlushBuffer {
-    messages: Arc<RwLock<Vec<StructuredMessage<'static>>>>,
+/// Where a paged read of the flush buffer left off
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct BufferPosition {
+    /// Timestamp of the last returned message
+    timestamp: u64,
+    /// How many messages with that timestamp have been returned
+    count: usize,
 }
 
-impl FlushBuffer {
-    pub async fn messages_by_channel(
+impl FlushBuffer<StructuredMessage<'static>> {
+    /// Returns the next matching messages after `position`, up to roughly the configured page size in bytes.
+    /// The position is based on timestamps instead of indexes, so it stays valid when the buffer is flushed in between pages.
+    /// Returns an empty page once all messages have been read
+    pub async fn messages_page(
         &self,
         time_range: Range<u64>,
         channel_id: &str,
+        user_id: Option<&str>,
+        search: Option<&str>,
+        reverse: bool,
+        position: &mut Option<BufferPosition>,
     ) -> Vec<StructuredMessage<'static>> {
-        let msgs = self
-            .messages
+        let search = search.map(|search| {
+            let search = search.to_ascii_lowercase();
+            let normalized = normalize_text(&search).into_owned();
+            (search, normalized)
+        });
+
+        let messages = self.messages.read().await;
+        let matching = |msg: &&StructuredMessage<'static>| {
+            time_range.contains(&msg.timestamp)
+                && msg.channel_id == channel_id
+                && user_id.map_or(true, |user_id| msg.user_id == user_id)
+                && search.as_ref().map_or(true, |(search, normalized)| {
+                    msg.matches_search(search, normalized)
+                })
+        };
+        let page = if reverse {
+            next_page(
+                messages.iter().rev().filter(matching),
+                position,
+                reverse,
+                self.page_bytes,
+            )
+        } else {
+            next_page(
+                messages.iter().filter(matching),
+                position,
+                reverse,
+                self.page_bytes,
+            )
+        };
+
+        trace!("Read {} messages from flush buffer", page.len());
+        page
+    }
+
+    /// Counts the buffered messages of the given type per user, like a `GROUP BY user_id` over the messages table
+    /// Whether log streams of the range have to merge buffered messages of the channel
+    pub async fn has_messages(&self, channel_id: &str, time_range: Range<u64>) -> bool {
+        self.messages
             .read()
             .await
             .iter()
-            .filter(|msg| time_range.contains(&msg.timestamp))
-            .filter(|msg| msg.channel_id == channel_id)
-            .cloned()
-            .collect::<Vec<_>>();
-        trace!("Read {} messages from flush buffer", msgs.len());
-        msgs
+            .any(|msg| msg.channel_id == channel_id && time_range.contains(&msg.timestamp))
     }
 
-    pub async fn messages_by_channel_and_user(
+    /// IDs of the buffered messages, used to find the ones which have been written but not drained yet
+    pub async fn message_ids(
         &self,
         time_range: Range<u64>,
         channel_id: &str,
-        user_id: &str,
-    ) -> Vec<StructuredMessage<'static>> {
-        let msgs = self
-            .messages
+        message_type: MessageType,
+    ) -> Vec<Uuid> {
+        self.messages
             .read()
             .await
             .iter()
-            .filter(|msg| time_range.contains(&msg.timestamp))
-            .filter(|msg| msg.channel_id == channel_id && msg.user_id == user_id)
-            .cloned()
-            .collect::<Vec<_>>();
-        trace!("Read {} messages from flush buffer", msgs.len());
-        msgs
+            .filter(|msg| {
+                time_range.contains(&msg.timestamp)
+                    && msg.channel_id == channel_id
+                    && msg.message_type == message_type
+            })
+            .filter_map(|msg| msg.uuid())
+            .collect()
+    }
+
+    /// Messages in `written_ids` are skipped, since they are already counted from the database
+    pub async fn user_message_counts(
+        &self,
+        time_range: Range<u64>,
+        channel_id: &str,
+        message_type: MessageType,
+        excluded_user_ids: &[String],
+        written_ids: &HashSet<Uuid>,
+    ) -> Vec<TopChatter> {
+        let messages = self.messages.read().await;
+
+        let mut counts: HashMap<&str, TopChatter> = HashMap::new();
+        for msg in messages.iter().filter(|msg| {
+            time_range.contains(&msg.timestamp)
+                && msg.channel_id == channel_id
+                && msg.message_type == message_type
+                && !msg.user_id.is_empty()
+                && !excluded_user_ids.iter().any(|id| *id == msg.user_id)
+                && !msg.uuid().is_some_and(|id| written_ids.contains(&id))
+        }) {
+            counts
+                .entry(&msg.user_id)
+                .or_insert_with(|| TopChatter {
+                    user_id: msg.user_id.to_string(),
+                    user_login: msg.user_login.to_string(),
+                    message_count: 0,
+                })
+                .message_count += 1;
+        }
+
+        counts.into_values().collect()
+    }
+}
+
+fn next_page<'a>(
+    messages: impl Iterator<Item = &'a StructuredMessage<'static>>,
+    position: &mut Option<BufferPosition>,
+    reverse: bool,
+    page_bytes: usize,
+) -> Vec<StructuredMessage<'static>> {
+    let mut page = Vec::new();
+    let mut bytes = 0;
+    let mut skipped_at_position = 0;
+
+    for msg in messages {
+        if let Some(position) = position {
+            let already_read = if reverse {
+                msg.timestamp > position.timestamp
+            } else {
+                msg.timestamp < position.timestamp
+            };
+            if already_read {
+                continue;
+            }
+            if msg.timestamp == position.timestamp && skipped_at_position < position.count {
+                skipped_at_position += 1;
+                continue;
+            }
+        }
+
+        // Always return at least one message, so the read makes progress
+        if bytes >= page_bytes && !page.is_empty() {
+            break;
+        }
+        bytes += msg.estimated_size();
+        page.push(msg.clone());
+    }
+
+    if let Some(last) = page.last() {
+        let mut count = page
+            .iter()
+            .rev()
+            .take_while(|msg| msg.timestamp == last.timestamp)
+            .count();
+        if let Some(position) = position {
+            if position.timestamp == last.timestamp {
+                count += position.count;
+            }
+        }
+        *position = Some(BufferPosition {
+            timestamp: last.timestamp,
+            count,
+        });
     }
+
+    page
 }
 
-pub async fn create_writer(
+pub async fn create_writer<T: BatchRow>(
     db: Client,
     mut shutdown_rx: ShutdownRx,
     flush_interval: u64,
-) -> anyhow::Result<(
-    Sender<StructuredMessage<'static>>,
-    FlushBuffer,
-    JoinHandle<()>,
-)> {
-    let (tx, mut rx) = channel(1000);
+    backlog_limit: usize,
+) -> anyhow::Result<(Sender<T>, FlushBuffer<T>, JoinHandle<()>)> {
+    let (tx, mut rx) = channel(CHANNEL_SIZE);
 
     let flush_buffer = FlushBuffer::default();
     let flush_buffer_clone = flush_buffer.clone();
@@ -89,21 +390,42 @@ pub async fn create_writer(
         tokio::pin!(timeout);
 
         loop {
+            flush_buffer.heartbeat.beat();
             tokio::select! {
                 _ = &mut timeout => {
                     timeout.as_mut().reset(Instant::now() + Duration::from_secs(flush_interval));
-                    if let Err(err) = write_chunk_with_retry(&db, &flush_buffer).await {
-                        error!("Could not write messages: {err}");
+                    match write_chunk_with_retry(&db, &flush_buffer).await {
+                        Ok(()) => {
+                            // Only stop signaling once the backlog is well below the limit again
+                            let len = flush_buffer.messages.read().await.len();
+                            if len < backlog_limit / 2 {
+                                flush_buffer.set_overloaded(false);
+                            }
+                        }
+                        Err(err) => {
+                            error!("Could not write rows to {}: {err:#}", T::TABLE);
+                            flush_buffer.set_overloaded(true);
+                        }
                     }
                 }
+                _ = flush_buffer.evictions_requested.notified() => {
+                    flush_buffer.apply_evictions().await;
+                }
                 Some(msg) = rx.recv() => {
-                    flush_buffer.messages.write().await.push(msg);
+                    let mut messages = flush_buffer.messages.write().await;
+                    messages.push(msg);
+                    BUFFERED_ROWS_GAUGE
+                        .with_label_values(&[T::TABLE])
+                        .set(messages.len() as i64);
+                    if messages.len() >= backlog_limit {
+                        flush_buffer.set_overloaded(true);
+                    }
                 }
                 Ok(()) = shutdown_rx.changed() => {
-                    info!("Flushing database write buffer");
+                    info!("Flushing database write buffer for {}", T::TABLE);
 
                     if let Err(err) = write_chunk_with_retry(&db, &flush_buffer).await {
-                        error!("Could not flush messages: {err}");
+                        error!("Could not flush rows to {}: {err:#}", T::TABLE);
                     }
 
                     break;
@@ -115,47 +437,230 @@ pub async fn create_writer(
     Ok((tx, flush_buffer_clone, handle))
 }
 
-async fn write_chunk_with_retry(db: &Client, buffer: &FlushBuffer) -> anyhow::Result<()> {
-    for attempt in 1..=RETRY_COUNT {
-        match write_chunk(db, buffer).await {
+/// Samples the oldest written row of each channel, which waited the longest for this flush
+fn record_latency<T: BatchRow>(rows: &[T]) {
+    let mut oldest: HashMap<&str, u64> = HashMap::new();
+    for (channel_id, sent_at) in rows.iter().filter_map(|row| row.sent_at()) {
+        oldest.entry(channel_id).or_insert(sent_at);
+    }
+    INGESTION_LATENCY.record(oldest);
+}
+
+async fn write_chunk_with_retry<T: BatchRow>(
+    db: &Client,
+    buffer: &FlushBuffer<T>,
+) -> anyhow::Result<()> {
+    // Only this task modifies the buffer, so it stays unchanged while the chunk is written
+    let len = buffer.messages.read().await.len();
+    if len == 0 {
+        return Ok(());
+    }
+
+    let started_at = Instant::now();
+    let mut dropped = 0;
+    let mut pending = vec![(0..len, RETRY_COUNT)];
+
+    while let Some((range, attempts)) = pending.pop() {
+        match insert_with_backoff(db, buffer, range.clone(), attempts).await {
+            Ok(()) => (),
+            // The database is down or failing, keep the remaining rows until the next flush.
+            // Batches are processed in order, so everything before this range has been handled
+            Err(err) if !is_data_error(&err) => {
+                let mut messages = buffer.messages.write().await;
+                messages.drain(..range.start);
+                BUFFERED_ROWS_GAUGE
+                    .with_label_values(&[T::TABLE])
+                    .set(messages.len() as i64);
+                return Err(err)
+                    .with_context(|| format!("Inserting failed even after {attempts} attempts"));
+            }
+            Err(err) if range.len() > 1 => {
+                warn!(
+                    "Database rejected batch of {} rows: {err}, splitting it",
+                    range.len()
+                );
+                let middle = range.start + range.len() / 2;
+                pending.push((middle..range.end, SPLIT_RETRY_COUNT));
+                pending.push((range.start..middle, SPLIT_RETRY_COUNT));
+            }
+            Err(err) => {
+                error!(
+                    "Dropping row which could not be inserted into {}: {err}",
+                    T::TABLE
+                );
+                DROPPED_ROWS_COUNTER.with_label_values(&[T::TABLE]).inc();
+                dropped += 1;
+            }
+        }
+    }
+
+    record_latency(&buffer.messages.read().await[..len]);
+    let mut messages = buffer.messages.write().await;
+    messages.drain(..len);
+    BUFFERED_ROWS_GAUGE
+        .with_label_values(&[T::TABLE])
+        .set(messages.len() as i64);
+    drop(messages);
+    FLUSH_DURATION_HISTOGRAM
+        .with_label_values(&[T::TABLE])
+        .observe(started_at.elapsed().as_secs_f64());
+
+    debug!(
+        "{} rows have been inserted into {} (took {}ms)",
+        len - dropped,
+        T::TABLE,
+        started_at.elapsed().as_millis()
+    );
+    BATCH_MSG_COUNT_GAGUE
+        .with_label_values(&[T::TABLE])
+        .set(len.try_into().unwrap());
+
+    if dropped > 0 {
+        return Err(anyhow!("{dropped} rows were dropped"));
+    }
+
+    Ok(())
+}
+
+async fn insert_with_backoff<T: BatchRow>(
+    db: &Client,
+    buffer: &FlushBuffer<T>,
+    range: Range<usize>,
+    attempts: u32,
+) -> Result<(), clickhouse::error::Error> {
+    let mut backoff = Duration::from_millis(INITIAL_BACKOFF_MS);
+    let mut attempt = 1;
+
+    loop {
+        match write_chunk(db, buffer, range.clone()).await {
             Ok(()) => {
                 if attempt > 1 {
                     debug!("Insert succeeded on attempt {attempt}");
                 }
                 return Ok(());
             }
+            // Retrying does not help if the rows themselves are rejected, the batch is split instead
+            Err(err) if attempt >= attempts || is_data_error(&err) => return Err(err),
             Err(err) => {
-                error!("Could not insert chunk: {err:#} (attempt {attempt}/{RETRY_COUNT}, retrying in {RETRY_INTERVAL_SECONDS} seconds)");
-                sleep(Duration::from_secs(RETRY_INTERVAL_SECONDS)).await;
+                error!(
+                    "Could not insert chunk: {err} (attempt {attempt}/{attempts}, retrying in {}ms)",
+                    backoff.as_millis()
+                );
+                buffer.set_overloaded(true);
+                RETRIED_ROWS_COUNTER
+                    .with_label_values(&[T::TABLE])
+                    .inc_by(range.len().try_into().unwrap());
+                sleep(backoff).await;
+                backoff = (backoff * 2).min(Duration::from_secs(MAX_BACKOFF_SECONDS));
+                attempt += 1;
             }
         }
     }
-    Err(anyhow!(
-        "Inserting failed even after {RETRY_COUNT} attempts"
-    ))
 }
 
-async fn write_chunk(db: &Client, buffer: &FlushBuffer) -> anyhow::Result<()> {
-    let messages_read_guard = buffer.messages.read().await;
+/// Errors caused by the inserted rows, which are split to find and drop the offending ones.
+/// Everything else, e.g. network errors, timeouts or an overloaded server, is retried without dropping rows
+fn is_data_error(err: &clickhouse::error::Error) -> bool {
+    use clickhouse::error::Error;
 
-    let started_at = Instant::now();
+    match err {
+        Error::BadResponse(response) => {
+            server_error_code(response).is_some_and(|code| DATA_ERROR_CODES.contains(&code))
+        }
+        // The rows could not be serialized
+        Error::Custom(_) | Error::SequenceMustHaveLength | Error::InvalidUtf8Encoding(_) => true,
+        _ => false,
+    }
+}
+
+/// The code of a server error like `Code: 27. DB::Exception: Cannot parse input`
+fn server_error_code(response: &str) -> Option<u32> {
+    let code = &response[response.find("Code: ")? + "Code: ".len()..];
+    let end = code
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(code.len());
+    code[..end].parse().ok()
+}
+
+async fn write_chunk<T: BatchRow>(
+    db: &Client,
+    buffer: &FlushBuffer<T>,
+    range: Range<usize>,
+) -> Result<(), clickhouse::error::Error> {
+    let messages = buffer.messages.read().await;
+
+    let mut insert = db.insert(T::TABLE)?;
+    for message in &messages[range] {
+        insert.write(message).await?;
+    }
+    drop(messages);
+
+    insert.end().await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{next_page, server_error_code, BufferPosition};
+    use crate::db::schema::{StructuredMessage, UnstructuredMessage};
+    use pretty_assertions::assert_eq;
 
-    let mut insert = db.insert(MESSAGES_STRUCTURED_TABLE)?;
-    for message in messages_read_guard.iter() {
-        insert.write(message).await.context("Could not write row")?;
+    fn messages(timestamps: &[u64]) -> Vec<StructuredMessage<'static>> {
+        timestamps
+            .iter()
+            .enumerate()
+            .map(|(i, timestamp)| {
+                let raw = format!(":user!user@user.tmi.twitch.tv PRIVMSG #channel :{i}");
+                let unstructured = UnstructuredMessage {
+                    channel_id: "1",
+                    user_id: "2",
+                    timestamp: *timestamp,
+                    raw: &raw,
+                };
+                StructuredMessage::from_unstructured(&unstructured)
+                    .unwrap()
+                    .into_owned()
+            })
+            .collect()
+    }
+
+    fn read_all(messages: &[StructuredMessage<'static>], reverse: bool) -> Vec<String> {
+        let mut position: Option<BufferPosition> = None;
+        let mut texts = Vec::new();
+        loop {
+            let page = if reverse {
+                next_page(messages.iter().rev(), &mut position, reverse, 1)
+            } else {
+                next_page(messages.iter(), &mut position, reverse, 1)
+            };
+            if page.is_empty() {
+                break;
+            }
+            assert_eq!(1, page.len());
+            texts.extend(page.iter().map(|msg| msg.user_friendly_text().into_owned()));
+        }
+        texts
     }
-    drop(messages_read_guard);
 
-    let mut messages_write_guard = buffer.messages.write().await;
-    insert.end().await.context("Could not end insert")?;
+    #[test]
+    fn pages_with_equal_timestamps() {
+        let messages = messages(&[1, 2, 2, 2, 3]);
 
-    debug!(
-        "{} messages have been inserted (took {}ms)",
-        messages_write_guard.len(),
-        started_at.elapsed().as_millis()
-    );
-    BATCH_MSG_COUNT_GAGUE.set(messages_write_guard.len().try_into().unwrap());
-    messages_write_guard.clear();
+        assert_eq!(vec!["0", "1", "2", "3", "4"], read_all(&messages, false));
+        assert_eq!(vec!["4", "3", "2", "1", "0"], read_all(&messages, true));
+    }
 
-    Ok(())
+    #[test]
+    fn parses_server_error_codes() {
+        assert_eq!(
+            Some(27),
+            server_error_code(
+                "Code: 27. DB::Exception: Cannot parse input: expected '\\t' before: 'abc'"
+            )
+        );
+        assert_eq!(
+            Some(241),
+            server_error_code("bad response: Code: 241. DB::Exception: Memory limit exceeded")
+        );
+        assert_eq!(None, server_error_code("502 Bad Gateway"));
+    }
 }
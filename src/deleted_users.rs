@@ -0,0 +1,154 @@
+use crate::{
+    app::{
+        cache_invalidation::{invalidate_cached_logs, read_cached_user_ranges},
+        helix_queue::HelixPriority,
+        App,
+    },
+    config::{DeletedUserAction, DeletedUsersConfig},
+    db::deleted_users::{
+        delete_missing_users, pseudonymize_users, purge_users, read_missing_users,
+        read_user_ids_partition, write_missing_users, MissingUserRow,
+    },
+    ShutdownRx,
+};
+use chrono::Utc;
+use std::{collections::HashMap, time::Duration};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+const CHECK_INTERVAL_SECONDS: u64 = 3600;
+/// Every logged user is checked once in this many intervals, i.e. once a week
+const CHECK_PARTITIONS: u64 = 24 * 7;
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let Some(config) = &app.config.deleted_users else {
+        debug!("Deleted users check is disabled");
+        shutdown_rx.changed().await.ok();
+        return;
+    };
+
+    loop {
+        let partition = (Utc::now().timestamp() as u64 / CHECK_INTERVAL_SECONDS) % CHECK_PARTITIONS;
+        let check = check_users(&app, config, partition);
+        if let Err(err) = HelixPriority::Background.scope(check).await {
+            error!("Could not check for deleted users: {err:#}");
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(CHECK_INTERVAL_SECONDS)) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down deleted users check");
+                break;
+            }
+        }
+    }
+}
+
+/// Looks up one partition of the logged users and handles the ones which have been missing
+/// from the Twitch API for longer than the grace period
+async fn check_users(app: &App, config: &DeletedUsersConfig, partition: u64) -> anyhow::Result<()> {
+    let user_ids = read_user_ids_partition(&app.db, partition, CHECK_PARTITIONS).await?;
+    if user_ids.is_empty() {
+        return Ok(());
+    }
+
+    let known_missing: HashMap<String, MissingUserRow> = read_missing_users(&app.db, &user_ids)
+        .await?
+        .into_iter()
+        .map(|user| (user.user_id.clone(), user))
+        .collect();
+
+    // Users not returned by the API are deleted, suspended or banned
+    let found = app.get_users(user_ids.clone(), vec![], true).await?;
+    let (found_ids, missing_ids): (Vec<String>, Vec<String>) = user_ids
+        .into_iter()
+        .partition(|user_id| found.contains_key(user_id));
+
+    let reappeared: Vec<String> = found_ids
+        .into_iter()
+        .filter(|user_id| known_missing.contains_key(user_id))
+        .collect();
+    delete_missing_users(&app.db, &reappeared).await?;
+
+    let now = Utc::now().timestamp() as u32;
+    let grace_seconds = u64::from(config.grace_days) * 24 * 3600;
+
+    let mut rows = Vec::with_capacity(missing_ids.len());
+    let mut expired = Vec::new();
+    for user_id in missing_ids {
+        let first_missing_at = known_missing
+            .get(&user_id)
+            .map_or(now, |user| user.first_missing_at);
+        let handled = known_missing
+            .get(&user_id)
+            .is_some_and(|user| user.handled == 1);
+
+        if !handled && u64::from(now.saturating_sub(first_missing_at)) >= grace_seconds {
+            expired.push(user_id.clone());
+        }
+
+        rows.push(MissingUserRow {
+            user_id,
+            first_missing_at,
+            last_checked_at: now,
+            handled: handled as u8,
+        });
+    }
+
+    if !expired.is_empty() {
+        handle_deleted_users(app, config.action, &expired).await?;
+        for row in &mut rows {
+            if expired.contains(&row.user_id) {
+                row.handled = 1;
+            }
+        }
+    }
+    write_missing_users(&app.db, &rows).await?;
+
+    debug!(
+        "Checked partition {partition} of logged users, {} missing, {} handled",
+        rows.len(),
+        expired.len()
+    );
+
+    Ok(())
+}
+
+async fn handle_deleted_users(
+    app: &App,
+    action: DeletedUserAction,
+    user_ids: &[String],
+) -> anyhow::Result<()> {
+    let cached_ranges = read_cached_user_ranges(app, user_ids).await?;
+    let legal_holds = &app.config.legal_holds;
+    // Messages of held users are kept, they are not deleted later either
+    let deletable_ids: Vec<String> = user_ids
+        .iter()
+        .filter(|user_id| !legal_holds.users.contains_key(*user_id))
+        .cloned()
+        .collect();
+    let held_channels = legal_holds.channel_ids();
+
+    match action {
+        DeletedUserAction::OptOut => {
+            for user_id in user_ids {
+                app.config.opt_out.insert(user_id.clone(), true);
+            }
+            app.config.save()?;
+        }
+        DeletedUserAction::Purge => purge_users(&app.db, &deletable_ids, &held_channels).await?,
+        DeletedUserAction::Pseudonymize => {
+            pseudonymize_users(&app.db, &deletable_ids, &held_channels).await?
+        }
+    }
+    // Opted-out users are only hidden, the other actions delete the messages with a mutation
+    let after_mutations = !matches!(action, DeletedUserAction::OptOut);
+    invalidate_cached_logs(app, cached_ranges, after_mutations);
+
+    info!(
+        "Handled {} deleted users with action {action:?}",
+        user_ids.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,147 @@
+//! `rustlog doctor`, which checks the setup without starting the logger and prints a report.
+//! It runs before the migrations, so the schema is checked as it is
+
+use crate::{
+    config::Config,
+    db::{read_schema_drift, read_schema_version, LATEST_MIGRATION},
+};
+use anyhow::{bail, ensure, Context};
+use clickhouse::Client;
+use std::{cmp::Ordering, future::Future, time::Duration};
+use tokio::{net::TcpStream, time::timeout};
+use twitch_api::{
+    twitch_oauth2::{AppAccessToken, TwitchToken},
+    HelixClient,
+};
+
+const CHECK_TIMEOUT_SECONDS: u64 = 15;
+/// The TLS port the bot connects to
+const IRC_ADDRESS: &str = "irc.chat.twitch.tv:6697";
+const PROBE_TABLE: &str = "__rustlog_doctor_probe";
+
+pub async fn run(config: &Config, db: &Client) -> anyhow::Result<()> {
+    let checks = [
+        ("ClickHouse schema", check(check_schema(db)).await),
+        ("ClickHouse writes", check(check_writes(db)).await),
+        ("Twitch credentials", check(check_credentials(config)).await),
+        ("IRC connection", check(check_irc()).await),
+    ];
+
+    let mut failed = 0;
+    for (name, result) in &checks {
+        match result {
+            Ok(details) => println!("[ok]   {name}: {details}"),
+            Err(err) => {
+                failed += 1;
+                println!("[fail] {name}: {err:#}");
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {} checks failed", checks.len());
+    }
+    println!("All checks passed");
+    Ok(())
+}
+
+/// Unreachable hosts would otherwise only fail once the OS gives up on the connection
+async fn check(future: impl Future<Output = anyhow::Result<String>>) -> anyhow::Result<String> {
+    timeout(Duration::from_secs(CHECK_TIMEOUT_SECONDS), future)
+        .await
+        .with_context(|| format!("Timed out after {CHECK_TIMEOUT_SECONDS}s"))?
+}
+
+async fn check_schema(db: &Client) -> anyhow::Result<String> {
+    let version: String = db
+        .query("SELECT version()")
+        .fetch_one()
+        .await
+        .context("Could not connect to ClickHouse")?;
+    let schema_version = read_schema_version(db).await.context(
+        "Could not read the migrations, the database has not been set up by rustlog yet",
+    )?;
+
+    match schema_version.cmp(&LATEST_MIGRATION) {
+        Ordering::Equal => {
+            let differences = read_schema_drift(db).await?;
+            if let Some(difference) = differences.first() {
+                bail!(
+                    "The tables differ from the expected schema in {} places, e.g. {difference}",
+                    differences.len()
+                );
+            }
+            Ok(format!(
+                "ClickHouse {version}, all {LATEST_MIGRATION} migrations have been run and the tables match the schema"
+            ))
+        }
+        Ordering::Less => Ok(format!(
+            "ClickHouse {version}, {} migrations will be run on the next start",
+            LATEST_MIGRATION - schema_version
+        )),
+        Ordering::Greater => bail!(
+            "The database has been migrated to version {schema_version} by a newer rustlog, this version only knows {LATEST_MIGRATION} migrations"
+        ),
+    }
+}
+
+/// Inserts a row into a throwaway table and reads it back
+async fn check_writes(db: &Client) -> anyhow::Result<String> {
+    db.query(&format!(
+        "CREATE TABLE IF NOT EXISTS {PROBE_TABLE} (probe UInt8) ENGINE = Memory"
+    ))
+    .execute()
+    .await
+    .context("Could not create a table")?;
+
+    let result = async {
+        db.query(&format!("INSERT INTO {PROBE_TABLE} VALUES (1)"))
+            .execute()
+            .await
+            .context("Could not insert a row")?;
+        let count: u64 = db
+            .query(&format!("SELECT count() FROM {PROBE_TABLE}"))
+            .fetch_one()
+            .await?;
+        ensure!(count > 0, "The inserted row could not be read back");
+        Ok(())
+    }
+    .await;
+
+    // Dropped even if the insert failed
+    db.query(&format!("DROP TABLE IF EXISTS {PROBE_TABLE}"))
+        .execute()
+        .await
+        .context("Could not drop the probe table")?;
+    result?;
+
+    Ok("A probe row was inserted and read back".to_owned())
+}
+
+async fn check_credentials(config: &Config) -> anyhow::Result<String> {
+    let helix_client: HelixClient<reqwest::Client> = HelixClient::default();
+    let token = AppAccessToken::get_app_access_token(
+        &helix_client,
+        config.client_id.clone().into(),
+        config.client_secret.expose().clone().into(),
+        vec![],
+    )
+    .await
+    .context("Could not generate an app access token, check clientID and clientSecret")?;
+
+    Ok(format!(
+        "Generated an app access token which expires in {}s",
+        token.expires_in().as_secs()
+    ))
+}
+
+async fn check_irc() -> anyhow::Result<String> {
+    let stream = TcpStream::connect(IRC_ADDRESS)
+        .await
+        .with_context(|| format!("Could not connect to {IRC_ADDRESS}"))?;
+
+    Ok(format!(
+        "Connected to {IRC_ADDRESS} ({})",
+        stream.peer_addr()?
+    ))
+}
@@ -0,0 +1,113 @@
+mod providers;
+
+use self::providers::fetch_emotes;
+use crate::{
+    app::App,
+    db::emotes::{replace_emote_set, ThirdPartyEmote},
+    ShutdownRx,
+};
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::{fmt::Display, time::Duration};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+/// Emotes which are available in every channel are stored with an empty channel id
+pub const GLOBAL_CHANNEL_ID: &str = "";
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum EmoteProvider {
+    #[serde(rename = "7tv")]
+    SevenTv,
+    Bttv,
+    Ffz,
+}
+
+impl Display for EmoteProvider {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            EmoteProvider::SevenTv => "7tv",
+            EmoteProvider::Bttv => "bttv",
+            EmoteProvider::Ffz => "ffz",
+        };
+        f.write_str(s)
+    }
+}
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    if app.config.emotes.providers.is_empty() {
+        debug!("No emote providers configured, emote syncer is idle");
+        shutdown_rx.changed().await.ok();
+        return;
+    }
+
+    let interval = Duration::from_secs(app.config.emotes.interval);
+    let http_client = reqwest::Client::new();
+
+    loop {
+        sync_emotes(&app, &http_client).await;
+
+        tokio::select! {
+            _ = sleep(interval) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down emote syncer");
+                break;
+            }
+        }
+    }
+}
+
+async fn sync_emotes(app: &App, http_client: &reqwest::Client) {
+    let mut channel_ids: Vec<String> = app
+        .config
+        .channels
+        .read()
+        .unwrap()
+        .iter()
+        .cloned()
+        .collect();
+    channel_ids.push(GLOBAL_CHANNEL_ID.to_owned());
+
+    for provider in &app.config.emotes.providers {
+        for channel_id in &channel_ids {
+            if let Err(err) = sync_channel_emotes(app, http_client, *provider, channel_id).await {
+                error!("Could not sync {provider} emotes for channel {channel_id:?}: {err:#}");
+            }
+        }
+    }
+
+    info!(
+        "Synced third-party emotes for {} channels",
+        channel_ids.len() - 1
+    );
+}
+
+async fn sync_channel_emotes(
+    app: &App,
+    http_client: &reqwest::Client,
+    provider: EmoteProvider,
+    channel_id: &str,
+) -> anyhow::Result<()> {
+    let updated_at = Utc::now().timestamp() as u32;
+
+    let emotes: Vec<ThirdPartyEmote> = fetch_emotes(http_client, provider, channel_id)
+        .await?
+        .into_iter()
+        .map(|(emote_id, name)| ThirdPartyEmote {
+            channel_id: channel_id.to_owned(),
+            provider: provider.to_string(),
+            emote_id,
+            name,
+            updated_at,
+        })
+        .collect();
+
+    debug!(
+        "Fetched {} {provider} emotes for channel {channel_id:?}",
+        emotes.len()
+    );
+    replace_emote_set(&app.db, channel_id, provider, &emotes).await?;
+
+    Ok(())
+}
@@ -0,0 +1,153 @@
+use super::{EmoteProvider, GLOBAL_CHANNEL_ID};
+use anyhow::{anyhow, Context};
+use reqwest::StatusCode;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::collections::HashMap;
+
+/// Returns a list of `(id, name)` pairs
+pub async fn fetch_emotes(
+    http_client: &reqwest::Client,
+    provider: EmoteProvider,
+    channel_id: &str,
+) -> anyhow::Result<Vec<(String, String)>> {
+    let is_global = channel_id == GLOBAL_CHANNEL_ID;
+
+    let emotes = match provider {
+        EmoteProvider::SevenTv => {
+            let set = if is_global {
+                get_json::<SevenTvEmoteSet>(http_client, "https://7tv.io/v3/emote-sets/global")
+                    .await?
+            } else {
+                let url = format!("https://7tv.io/v3/users/twitch/{channel_id}");
+                get_json::<SevenTvUser>(http_client, &url)
+                    .await?
+                    .and_then(|user| user.emote_set)
+            };
+
+            set.map(|set| set.emotes)
+                .unwrap_or_default()
+                .into_iter()
+                .map(|emote| (emote.id, emote.name))
+                .collect()
+        }
+        EmoteProvider::Bttv => {
+            if is_global {
+                get_json::<Vec<BttvEmote>>(
+                    http_client,
+                    "https://api.betterttv.net/3/cached/emotes/global",
+                )
+                .await?
+                .unwrap_or_default()
+                .into_iter()
+                .map(|emote| (emote.id, emote.code))
+                .collect()
+            } else {
+                let url = format!("https://api.betterttv.net/3/cached/users/twitch/{channel_id}");
+                get_json::<BttvUser>(http_client, &url)
+                    .await?
+                    .map(|user| {
+                        user.channel_emotes
+                            .into_iter()
+                            .chain(user.shared_emotes)
+                            .map(|emote| (emote.id, emote.code))
+                            .collect()
+                    })
+                    .unwrap_or_default()
+            }
+        }
+        EmoteProvider::Ffz => {
+            let url = if is_global {
+                "https://api.frankerfacez.com/v1/set/global".to_owned()
+            } else {
+                format!("https://api.frankerfacez.com/v1/room/id/{channel_id}")
+            };
+
+            get_json::<FfzSets>(http_client, &url)
+                .await?
+                .map(|response| {
+                    response
+                        .sets
+                        .into_values()
+                        .flat_map(|set| set.emoticons)
+                        .map(|emote| (emote.id.to_string(), emote.name))
+                        .collect()
+                })
+                .unwrap_or_default()
+        }
+    };
+
+    Ok(emotes)
+}
+
+/// Returns `None` if the provider does not know the channel
+async fn get_json<T: DeserializeOwned>(
+    http_client: &reqwest::Client,
+    url: &str,
+) -> anyhow::Result<Option<T>> {
+    let response = http_client
+        .get(url)
+        .send()
+        .await
+        .context("Could not send request")?;
+
+    let status = response.status();
+    if status == StatusCode::NOT_FOUND {
+        return Ok(None);
+    }
+    if !status.is_success() {
+        return Err(anyhow!("{url} responded with status {status}"));
+    }
+
+    let body = response.bytes().await?;
+    let value = serde_json::from_slice(&body).context("Could not deserialize response")?;
+    Ok(Some(value))
+}
+
+#[derive(Deserialize)]
+struct SevenTvUser {
+    emote_set: Option<SevenTvEmoteSet>,
+}
+
+#[derive(Deserialize)]
+struct SevenTvEmoteSet {
+    #[serde(default)]
+    emotes: Vec<SevenTvEmote>,
+}
+
+#[derive(Deserialize)]
+struct SevenTvEmote {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BttvUser {
+    #[serde(default)]
+    channel_emotes: Vec<BttvEmote>,
+    #[serde(default)]
+    shared_emotes: Vec<BttvEmote>,
+}
+
+#[derive(Deserialize)]
+struct BttvEmote {
+    id: String,
+    code: String,
+}
+
+#[derive(Deserialize)]
+struct FfzSets {
+    sets: HashMap<String, FfzSet>,
+}
+
+#[derive(Deserialize)]
+struct FfzSet {
+    #[serde(default)]
+    emoticons: Vec<FfzEmote>,
+}
+
+#[derive(Deserialize)]
+struct FfzEmote {
+    id: u64,
+    name: String,
+}
@@ -1,11 +1,17 @@
-use aide::{openapi::MediaType, OperationOutput};
+use aide::{
+    openapi::{Example, MediaType, ReferenceOr, SchemaObject},
+    OperationOutput,
+};
 use axum::response::{IntoResponse, Response};
+use indexmap::IndexMap;
 use reqwest::StatusCode;
 use std::num::ParseIntError;
 use thiserror::Error;
 use tracing::error;
 use twitch_api::helix::ClientRequestError;
 
+const ERROR_CODE_HEADER: &str = "x-rustlog-error";
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Twitch API error: {0}")]
@@ -24,24 +30,132 @@ pub enum Error {
     ChannelOptedOut,
     #[error("The requested user has opted out of being logged")]
     UserOptedOut,
+    #[error("User logs are disabled in the requested channel")]
+    UserLogsDisabled,
+    #[error("The requested user has not consented to their logs being served")]
+    UserNotConsented,
+    #[error("The message was sent by another user")]
+    NotMessageAuthor,
     #[error("Not found")]
     NotFound,
+    #[error("The requested channel is not logged on this instance")]
+    ChannelNotTracked,
+    #[error("There are no messages in the requested range")]
+    NoMessagesInRange,
+    #[error("This endpoint is disabled on this instance")]
+    EndpointDisabled,
+    #[error("A backup is already running")]
+    BackupRunning,
+    #[error("Too many requests are pending, try again later")]
+    Busy,
+    #[error("The messages are under legal hold")]
+    LegalHold,
+}
+
+impl Error {
+    fn status_code(&self) -> StatusCode {
+        match self {
+            Error::Helix(_) | Error::Io(_) | Error::Internal | Error::Clickhouse(_) => {
+                StatusCode::INTERNAL_SERVER_ERROR
+            }
+            Error::ParseInt(_) | Error::InvalidParam(_) => StatusCode::BAD_REQUEST,
+            Error::ChannelOptedOut
+            | Error::UserOptedOut
+            | Error::UserLogsDisabled
+            | Error::UserNotConsented
+            | Error::NotMessageAuthor => StatusCode::FORBIDDEN,
+            Error::NotFound
+            | Error::ChannelNotTracked
+            | Error::NoMessagesInRange
+            | Error::EndpointDisabled => StatusCode::NOT_FOUND,
+            Error::BackupRunning => StatusCode::CONFLICT,
+            Error::Busy => StatusCode::SERVICE_UNAVAILABLE,
+            Error::LegalHold => StatusCode::CONFLICT,
+        }
+    }
+
+    /// Sent in the error code header, so clients can tell errors with the same status apart
+    fn code(&self) -> &'static str {
+        match self {
+            Error::Helix(_) | Error::Io(_) | Error::Internal | Error::Clickhouse(_) => "internal",
+            Error::ParseInt(_) | Error::InvalidParam(_) => "invalid_param",
+            Error::ChannelOptedOut => "channel_opted_out",
+            Error::UserOptedOut => "user_opted_out",
+            Error::UserLogsDisabled => "user_logs_disabled",
+            Error::UserNotConsented => "user_not_consented",
+            Error::NotMessageAuthor => "not_message_author",
+            Error::NotFound => "not_found",
+            Error::ChannelNotTracked => "channel_not_tracked",
+            Error::NoMessagesInRange => "no_messages_in_range",
+            Error::EndpointDisabled => "endpoint_disabled",
+            Error::BackupRunning => "backup_running",
+            Error::Busy => "busy",
+            Error::LegalHold => "legal_hold",
+        }
+    }
+
+    /// Errors shown as examples in the API docs
+    fn documented() -> Vec<(&'static str, Self)> {
+        vec![
+            (
+                "invalidParam",
+                Error::InvalidParam("Invalid date".to_owned()),
+            ),
+            ("parseInt", Error::ParseInt("a".parse::<u8>().unwrap_err())),
+            ("channelOptedOut", Error::ChannelOptedOut),
+            ("userOptedOut", Error::UserOptedOut),
+            ("userLogsDisabled", Error::UserLogsDisabled),
+            ("userNotConsented", Error::UserNotConsented),
+            ("notMessageAuthor", Error::NotMessageAuthor),
+            ("notFound", Error::NotFound),
+            ("channelNotTracked", Error::ChannelNotTracked),
+            ("noMessagesInRange", Error::NoMessagesInRange),
+            ("endpointDisabled", Error::EndpointDisabled),
+            ("backupRunning", Error::BackupRunning),
+            ("busy", Error::Busy),
+            ("legalHold", Error::LegalHold),
+            ("internal", Error::Internal),
+        ]
+    }
 }
 
 impl IntoResponse for Error {
     fn into_response(self) -> Response {
-        let status_code = match &self {
-            Error::Helix(_) | Error::Io(_) | Error::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        if let Error::Clickhouse(error) = &self {
+            error!("DB error: {error}");
+        }
+
+        (
+            self.status_code(),
+            [(ERROR_CODE_HEADER, self.code())],
+            self.to_string(),
+        )
+            .into_response()
+    }
+}
+
+impl From<Error> for tonic::Status {
+    fn from(err: Error) -> Self {
+        match &err {
+            Error::Helix(_) | Error::Io(_) | Error::Internal => Self::internal(err.to_string()),
             Error::Clickhouse(error) => {
                 error!("DB error: {error}");
-                StatusCode::INTERNAL_SERVER_ERROR
+                Self::internal(err.to_string())
             }
-            Error::ParseInt(_) | Error::InvalidParam(_) => StatusCode::BAD_REQUEST,
-            Error::ChannelOptedOut | Error::UserOptedOut => StatusCode::FORBIDDEN,
-            Error::NotFound => StatusCode::NOT_FOUND,
-        };
-
-        (status_code, self.to_string()).into_response()
+            Error::ParseInt(_) | Error::InvalidParam(_) => Self::invalid_argument(err.to_string()),
+            Error::ChannelOptedOut
+            | Error::UserOptedOut
+            | Error::UserLogsDisabled
+            | Error::UserNotConsented
+            | Error::NotMessageAuthor => Self::permission_denied(err.to_string()),
+            Error::NotFound | Error::ChannelNotTracked | Error::NoMessagesInRange => {
+                Self::not_found(err.to_string())
+            }
+            Error::EndpointDisabled => Self::unimplemented(err.to_string()),
+            Error::BackupRunning => Self::failed_precondition(err.to_string()),
+            Error::Busy => Self::unavailable(err.to_string()),
+            Error::LegalHold => Self::failed_precondition(err.to_string()),
+        }
     }
 }
 
@@ -56,14 +170,24 @@ impl OperationOutput for Error {
     type Inner = Self;
 
     fn operation_response(
-        _: &mut aide::gen::GenContext,
+        ctx: &mut aide::gen::GenContext,
         _: &mut aide::openapi::Operation,
     ) -> Option<aide::openapi::Response> {
         Some(aide::openapi::Response {
             description: "Error response".into(),
-            content: [("text/plain".into(), MediaType::default())]
-                .into_iter()
-                .collect(),
+            content: [(
+                "text/plain; charset=utf-8".into(),
+                MediaType {
+                    schema: Some(SchemaObject {
+                        json_schema: ctx.schema.subschema_for::<String>(),
+                        external_docs: None,
+                        example: None,
+                    }),
+                    ..Default::default()
+                },
+            )]
+            .into_iter()
+            .collect(),
             ..Default::default()
         })
     }
@@ -72,39 +196,60 @@ impl OperationOutput for Error {
         ctx: &mut aide::gen::GenContext,
         operation: &mut aide::openapi::Operation,
     ) -> Vec<(Option<u16>, aide::openapi::Response)> {
-        if let Some(res) = Self::operation_response(ctx, operation) {
-            vec![
-                (
-                    Some(400),
-                    aide::openapi::Response {
-                        description: "The request is invalid".to_owned(),
-                        ..res.clone()
-                    },
-                ),
-                (
-                    Some(403),
-                    aide::openapi::Response {
-                        description: "Channel or user has opted out".to_owned(),
-                        ..res.clone()
-                    },
-                ),
-                (
-                    Some(404),
-                    aide::openapi::Response {
-                        description: "The requested data was not found".to_owned(),
-                        ..res.clone()
-                    },
-                ),
-                (
-                    Some(500),
-                    aide::openapi::Response {
-                        description: "An internal server error occured".to_owned(),
-                        ..res
-                    },
-                ),
-            ]
-        } else {
-            Vec::new()
-        }
+        let Some(res) = Self::operation_response(ctx, operation) else {
+            return Vec::new();
+        };
+
+        let statuses = [
+            (StatusCode::BAD_REQUEST, "The request is invalid"),
+            (
+                StatusCode::FORBIDDEN,
+                "Channel or user has opted out, or user logs are disabled in the channel",
+            ),
+            (
+                StatusCode::NOT_FOUND,
+                "The requested data was not found. The `x-rustlog-error` header is `channel_not_tracked` if the channel is not logged on this instance and `no_messages_in_range` if it has no messages in the requested range",
+            ),
+            (
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "An internal server error occured",
+            ),
+        ];
+        let errors = Self::documented();
+
+        statuses
+            .into_iter()
+            .map(|(status, description)| {
+                let examples: IndexMap<String, ReferenceOr<Example>> = errors
+                    .iter()
+                    .filter(|(_, err)| err.status_code() == status)
+                    .map(|(name, err)| {
+                        let example = Example {
+                            value: Some(err.to_string().into()),
+                            ..Default::default()
+                        };
+                        (name.to_string(), ReferenceOr::Item(example))
+                    })
+                    .collect();
+                let content = res
+                    .content
+                    .iter()
+                    .map(|(content_type, media_type)| {
+                        let media_type = MediaType {
+                            examples: examples.clone(),
+                            ..media_type.clone()
+                        };
+                        (content_type.clone(), media_type)
+                    })
+                    .collect();
+
+                let response = aide::openapi::Response {
+                    description: description.to_owned(),
+                    content,
+                    ..res.clone()
+                };
+                (Some(status.as_u16()), response)
+            })
+            .collect()
     }
 }
@@ -0,0 +1,350 @@
+use crate::{
+    config::Config,
+    db::{
+        consent::read_consented_users, opt_outs::read_opt_outs, read_available_channel_logs,
+        read_channel, schema::StructuredMessage, writer::FlushBuffer,
+    },
+    error::Error,
+    ids::ChannelId,
+    logs::schema::{message::BasicMessage, LogRangeParams},
+    web::schema::{AvailableLogDate, LogsParams},
+};
+use anyhow::{bail, Context};
+use chrono_tz::Tz;
+use clickhouse::Client;
+use futures::TryStreamExt;
+use object_store::{path::Path, ObjectStore};
+use serde::{Deserialize, Serialize};
+use std::{collections::HashSet, fmt::Write};
+use tracing::{info, warn};
+use url::Url;
+
+const TIMESTAMP_FORMAT: &str = "%H:%M:%S";
+/// Lists the days which have been written, so an interrupted export can be resumed
+const CHECKPOINT_FILE: &str = "checkpoint.json";
+
+#[derive(Serialize, Deserialize)]
+struct IndexEntry {
+    date: String,
+    messages: usize,
+}
+
+/// Opt-outs and consent when the export starts, applied to the archive like the API applies them
+pub struct Privacy {
+    opted_out: HashSet<String>,
+    /// Only set if `requireUserConsent` is enabled
+    consented: Option<HashSet<String>>,
+}
+
+impl Privacy {
+    pub async fn read(db: &Client, config: &Config) -> anyhow::Result<Self> {
+        let opted_out = config
+            .opt_out
+            .iter()
+            .map(|entry| entry.key().clone())
+            .chain(read_opt_outs(db).await?.into_iter().map(|row| row.user_id))
+            .collect();
+        let consented = if config.require_user_consent {
+            Some(read_consented_users(db).await?.into_iter().collect())
+        } else {
+            None
+        };
+
+        Ok(Self {
+            opted_out,
+            consented,
+        })
+    }
+
+    /// Messages without a user, such as room notices, are always included
+    fn includes(&self, msg: &StructuredMessage) -> bool {
+        let user_id = &*msg.user_id;
+        user_id.is_empty()
+            || (!self.opted_out.contains(user_id)
+                && self
+                    .consented
+                    .as_ref()
+                    .map_or(true, |consented| consented.contains(user_id)))
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+struct Checkpoint {
+    channel_login: Option<String>,
+    days: Vec<IndexEntry>,
+}
+
+/// Renders the whole history of a channel into a static archive of daily HTML and NDJSON files
+/// with an index page, written to a local directory (`file://`) or object storage (e.g. `s3://`).
+/// With `resume`, days listed in the checkpoint of a previous export are not read again.
+/// Opted out channels are not exported, and messages of opted out or, in consent mode, non-consenting users are left out
+pub async fn export_channel(
+    db: &Client,
+    privacy: &Privacy,
+    channel_id: &ChannelId,
+    output: &str,
+    options: Vec<(String, String)>,
+    resume: bool,
+    timezone: Tz,
+) -> anyhow::Result<()> {
+    if privacy.opted_out.contains(channel_id.as_str()) {
+        bail!("Channel {channel_id} has opted out");
+    }
+
+    let url = Url::parse(output).context("Invalid output url")?;
+    let (store, prefix) =
+        object_store::parse_url_opts(&url, options).context("Could not create output store")?;
+
+    let mut days = read_available_channel_logs(db, channel_id, timezone)
+        .await
+        .context("Could not read available logs")?;
+    days.reverse();
+
+    let mut checkpoint = if resume {
+        read_checkpoint(&*store, &prefix).await?
+    } else {
+        Checkpoint::default()
+    };
+    let mut channel_login = checkpoint
+        .channel_login
+        .clone()
+        .unwrap_or_else(|| channel_id.to_string());
+    info!(
+        "Exporting {} days of channel {channel_id}, {} of them have already been exported",
+        days.len(),
+        checkpoint.days.len()
+    );
+
+    for day in &days {
+        let date = day.to_string();
+        if checkpoint.days.iter().any(|entry| entry.date == date) {
+            continue;
+        }
+
+        let mut messages = read_day(db, channel_id, day, timezone).await?;
+        messages.retain(|msg| privacy.includes(msg));
+        let Some(first) = messages.first() else {
+            continue;
+        };
+        channel_login = first.channel_login.to_string();
+
+        let html = render_day(&channel_login, &date, &messages, timezone);
+        put(&*store, &prefix, &format!("{date}.html"), html.into_bytes()).await?;
+        put(
+            &*store,
+            &prefix,
+            &format!("{date}.ndjson"),
+            render_ndjson(&messages),
+        )
+        .await?;
+
+        checkpoint.channel_login = Some(channel_login.clone());
+        checkpoint.days.push(IndexEntry {
+            date,
+            messages: messages.len(),
+        });
+        put(
+            &*store,
+            &prefix,
+            CHECKPOINT_FILE,
+            serde_json::to_vec(&checkpoint)?,
+        )
+        .await?;
+    }
+    let index = checkpoint.days;
+
+    put(
+        &*store,
+        &prefix,
+        "index.html",
+        render_index(&channel_login, &index).into_bytes(),
+    )
+    .await?;
+    put(&*store, &prefix, "index.json", serde_json::to_vec(&index)?).await?;
+    // The export is complete, so a later one starts from scratch
+    store
+        .delete(&file_path(&prefix, CHECKPOINT_FILE))
+        .await
+        .context("Could not remove checkpoint")?;
+
+    info!(
+        "Exported {} days of #{channel_login} to {output}",
+        index.len()
+    );
+    Ok(())
+}
+
+async fn read_day(
+    db: &Client,
+    channel_id: &ChannelId,
+    day: &AvailableLogDate,
+    timezone: Tz,
+) -> anyhow::Result<Vec<StructuredMessage<'static>>> {
+    let (from, to) = day.range(timezone).context("Invalid log date")?;
+    let params = LogRangeParams {
+        from,
+        to,
+        logs_params: LogsParams::default(),
+    };
+
+    // Nothing is buffered for past days, so an empty buffer is enough
+    match read_channel(db, channel_id, params, &[], &FlushBuffer::default()).await {
+        Ok(stream) => {
+            let chunks: Vec<_> = stream.try_collect().await?;
+            Ok(chunks.into_iter().flatten().collect())
+        }
+        Err(Error::NotFound) => {
+            warn!("No messages found for {day}");
+            Ok(Vec::new())
+        }
+        Err(err) => Err(err.into()),
+    }
+}
+
+async fn read_checkpoint(store: &dyn ObjectStore, prefix: &Path) -> anyhow::Result<Checkpoint> {
+    let path = file_path(prefix, CHECKPOINT_FILE);
+    match store.get(&path).await {
+        Ok(result) => {
+            let content = result.bytes().await?;
+            serde_json::from_slice(&content).context("Invalid checkpoint")
+        }
+        Err(object_store::Error::NotFound { .. }) => {
+            warn!("No checkpoint found, starting from the beginning");
+            Ok(Checkpoint::default())
+        }
+        Err(err) => Err(err).context("Could not read checkpoint"),
+    }
+}
+
+fn file_path(prefix: &Path, name: &str) -> Path {
+    name.split('/')
+        .fold(prefix.clone(), |path, part| path.child(part))
+}
+
+async fn put(
+    store: &dyn ObjectStore,
+    prefix: &Path,
+    name: &str,
+    content: Vec<u8>,
+) -> anyhow::Result<()> {
+    let path = file_path(prefix, name);
+    store
+        .put(&path, content.into())
+        .await
+        .with_context(|| format!("Could not write {path}"))?;
+    Ok(())
+}
+
+/// Times are shown in the channel's timezone, like the day boundaries
+fn render_day(
+    channel_login: &str,
+    date: &str,
+    messages: &[StructuredMessage],
+    timezone: Tz,
+) -> String {
+    let mut html = page_header(&format!("#{channel_login} {date}"));
+    // Day pages are nested as year/month/day
+    html.push_str("<p><a href=\"../../index.html\">All days</a></p>\n<pre>\n");
+
+    for msg in messages {
+        let time = chrono::DateTime::from_timestamp_millis(msg.timestamp as i64)
+            .unwrap_or_default()
+            .with_timezone(&timezone)
+            .format(TIMESTAMP_FORMAT);
+        let text = escape_html(&msg.user_friendly_text());
+
+        if msg.user_login.is_empty() {
+            let _ = writeln!(html, "[{time}] {text}");
+        } else {
+            let user = escape_html(&msg.user_login);
+            let _ = writeln!(html, "[{time}] <b>{user}</b>: {text}");
+        }
+    }
+
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+fn render_ndjson(messages: &[StructuredMessage]) -> Vec<u8> {
+    let mut buf = Vec::new();
+    for msg in messages {
+        match BasicMessage::from_structured(msg) {
+            Ok(message) => {
+                serde_json::to_writer(&mut buf, &message).unwrap();
+                buf.push(b'\n');
+            }
+            Err(err) => warn!("Skipping message {msg:?} which could not be parsed: {err}"),
+        }
+    }
+    buf
+}
+
+fn render_index(channel_login: &str, days: &[IndexEntry]) -> String {
+    let mut html = page_header(&format!("#{channel_login}"));
+    html.push_str("<ul>\n");
+    for day in days.iter().rev() {
+        let _ = writeln!(
+            html,
+            "<li><a href=\"{date}.html\">{date}</a> ({messages} messages, <a href=\"{date}.ndjson\">ndjson</a>)</li>",
+            date = day.date,
+            messages = day.messages,
+        );
+    }
+    html.push_str("</ul>\n</body>\n</html>\n");
+    html
+}
+
+fn page_header(title: &str) -> String {
+    let title = escape_html(title);
+    format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n"
+    )
+}
+
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_html, render_index, IndexEntry};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn escapes_html() {
+        assert_eq!(
+            "&lt;script&gt;alert(&quot;xss&quot; &amp; &#39;more&#39;)&lt;/script&gt;",
+            escape_html("<script>alert(\"xss\" & 'more')</script>")
+        );
+    }
+
+    #[test]
+    fn index_links_newest_day_first() {
+        let days = [
+            IndexEntry {
+                date: "2024/3/1".to_owned(),
+                messages: 10,
+            },
+            IndexEntry {
+                date: "2024/3/2".to_owned(),
+                messages: 5,
+            },
+        ];
+        let html = render_index("forsen", &days);
+
+        let newest = html.find("2024/3/2.html").unwrap();
+        let oldest = html.find("2024/3/1.html").unwrap();
+        assert!(newest < oldest);
+    }
+}
@@ -0,0 +1,242 @@
+mod proto {
+    tonic::include_proto!("rustlog");
+}
+
+use self::proto::{
+    logs_server::{Logs, LogsServer},
+    ChannelLogsRequest, Message, MessageBatch, SearchLogsRequest, UserLogsRequest,
+};
+use crate::{
+    app::App,
+    config::EndpointGroup,
+    db::{self, pool::QueryClass, read_channel, read_user, schema::StructuredMessage},
+    ids::{ChannelId, UserId},
+    logs::{schema::LogRangeParams, stream::LogsStream},
+    web::{
+        export_quotas::{QuotaUsage, QUOTA_EXCEEDED_MESSAGE},
+        parse_listen_addr,
+        schema::{FormatOptions, LogsParams, MessageFilter, MessageSort},
+    },
+    ShutdownRx,
+};
+use chrono::{DateTime, Utc};
+use futures::{Stream, StreamExt, TryStreamExt};
+use prost::Message as _;
+use std::pin::Pin;
+use tonic::{transport::Server, Request, Response, Status};
+use tracing::{debug, info};
+
+type MessageStream = Pin<Box<dyn Stream<Item = Result<MessageBatch, Status>> + Send>>;
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let Some(listen_address) = app.config.grpc_listen_address.clone() else {
+        debug!("No gRPC listen address configured, gRPC server is disabled");
+        shutdown_rx.changed().await.ok();
+        return;
+    };
+    let listen_address = parse_listen_addr(&listen_address).expect("Invalid gRPC listen address");
+
+    info!("gRPC server listening on {listen_address}");
+
+    Server::builder()
+        .add_service(LogsServer::new(LogsService { app }))
+        .serve_with_shutdown(listen_address, async move {
+            shutdown_rx.changed().await.ok();
+            debug!("Shutting down gRPC task");
+        })
+        .await
+        .expect("Could not run gRPC server");
+}
+
+struct LogsService {
+    app: App,
+}
+
+impl LogsService {
+    /// Downloads count against the same daily quotas as over HTTP. Clients are told apart by their address,
+    /// as there are no API keys
+    fn acquire_quota<T>(
+        &self,
+        request: &Request<T>,
+        channel_id: &ChannelId,
+    ) -> Result<Option<QuotaUsage>, Status> {
+        let Some(limit) = self.app.config.export_quotas.daily_limit(None) else {
+            return Ok(None);
+        };
+        let client = format!(
+            "ip:{}",
+            request
+                .remote_addr()
+                .map(|addr| addr.ip().to_string())
+                .unwrap_or_default()
+        );
+
+        self.app
+            .export_quotas
+            .acquire_channels(&client, &[channel_id], limit)
+            .map(Some)
+            .map_err(|_| Status::resource_exhausted(QUOTA_EXCEEDED_MESSAGE))
+    }
+}
+
+#[tonic::async_trait]
+impl Logs for LogsService {
+    type GetChannelLogsStream = MessageStream;
+    type GetUserLogsStream = MessageStream;
+    type SearchLogsStream = MessageStream;
+
+    async fn get_channel_logs(
+        &self,
+        request: Request<ChannelLogsRequest>,
+    ) -> Result<Response<MessageStream>, Status> {
+        let channel_id = ChannelId::from(request.get_ref().channel_id.as_str());
+        self.app.check_opted_out(&channel_id, None)?;
+        let quota_usage = self.acquire_quota(&request, &channel_id)?;
+        let request = request.into_inner();
+
+        let params = LogRangeParams {
+            from: parse_timestamp(request.from)?,
+            to: parse_timestamp(request.to)?,
+            logs_params: logs_params(request.reverse, request.limit, request.offset),
+        };
+        let stream = read_channel(
+            &self.app.db.profile(QueryClass::Logs),
+            &channel_id,
+            params,
+            &[],
+            &self.app.flush_buffer,
+        )
+        .await?;
+
+        Ok(Response::new(into_batches(stream, quota_usage)))
+    }
+
+    async fn get_user_logs(
+        &self,
+        request: Request<UserLogsRequest>,
+    ) -> Result<Response<MessageStream>, Status> {
+        let channel_id = ChannelId::from(request.get_ref().channel_id.as_str());
+        let user_id = UserId::from(request.get_ref().user_id.as_str());
+        self.app.check_opted_out(&channel_id, Some(&user_id))?;
+        let quota_usage = self.acquire_quota(&request, &channel_id)?;
+        let request = request.into_inner();
+
+        let params = LogRangeParams {
+            from: parse_timestamp(request.from)?,
+            to: parse_timestamp(request.to)?,
+            logs_params: logs_params(request.reverse, request.limit, request.offset),
+        };
+        let stream = read_user(
+            &self.app.db.profile(QueryClass::Logs),
+            &channel_id,
+            &user_id,
+            params,
+            &[],
+            &self.app.flush_buffer,
+        )
+        .await?;
+
+        Ok(Response::new(into_batches(stream, quota_usage)))
+    }
+
+    async fn search_logs(
+        &self,
+        request: Request<SearchLogsRequest>,
+    ) -> Result<Response<MessageStream>, Status> {
+        let channel_id = ChannelId::from(request.get_ref().channel_id.as_str());
+        let user_id = UserId::from(request.get_ref().user_id.as_str());
+        self.app.check_endpoint_group(EndpointGroup::Search)?;
+        self.app.check_opted_out(&channel_id, Some(&user_id))?;
+        let quota_usage = self.acquire_quota(&request, &channel_id)?;
+        let request = request.into_inner();
+
+        let stream = db::search_user_logs(
+            &self.app.db.profile(QueryClass::Search),
+            &channel_id,
+            &user_id,
+            &request.query,
+            None,
+            logs_params(request.reverse, request.limit, request.offset),
+            &self.app.flush_buffer,
+        )
+        .await?;
+
+        Ok(Response::new(into_batches(stream, quota_usage)))
+    }
+}
+
+fn parse_timestamp(millis: i64) -> Result<DateTime<Utc>, Status> {
+    DateTime::from_timestamp_millis(millis)
+        .ok_or_else(|| Status::invalid_argument("Timestamp out of range"))
+}
+
+fn logs_params(reverse: bool, limit: Option<u64>, offset: Option<u64>) -> LogsParams {
+    LogsParams {
+        json: false,
+        json_basic: false,
+        raw: false,
+        reverse,
+        envelope: false,
+        ndjson: false,
+        arrow: false,
+        csv: false,
+        parquet: false,
+        vods: false,
+        sort: MessageSort::default(),
+        sample: None,
+        flagged: false,
+        format: FormatOptions::default(),
+        filter: MessageFilter::default(),
+        limit,
+        offset,
+    }
+}
+
+/// The stream ends with an error once the download quota has been used up
+fn into_batches(stream: LogsStream, quota_usage: Option<QuotaUsage>) -> MessageStream {
+    let stream = stream
+        .map_ok(|chunk| MessageBatch {
+            messages: chunk.iter().map(Message::from).collect(),
+        })
+        .map_err(Status::from)
+        .map(move |batch| {
+            let batch = batch?;
+            if let Some(usage) = &quota_usage {
+                usage
+                    .consume(batch.encoded_len() as u64)
+                    .map_err(|_| Status::resource_exhausted(QUOTA_EXCEEDED_MESSAGE))?;
+            }
+            Ok::<_, Status>(batch)
+        });
+    Box::pin(stream)
+}
+
+impl From<&StructuredMessage<'_>> for Message {
+    fn from(msg: &StructuredMessage<'_>) -> Self {
+        Self {
+            channel_id: msg.channel_id.to_string(),
+            channel_login: msg.channel_login.to_string(),
+            timestamp: msg.timestamp,
+            id: msg.id().unwrap_or_default(),
+            message_type: msg.message_type.to_string(),
+            user_id: msg.user_id.to_string(),
+            user_login: msg.user_login.to_string(),
+            display_name: msg.display_name().to_owned(),
+            color: msg.color,
+            user_type: msg.user_type.to_string(),
+            badges: msg.badges.iter().map(|badge| badge.to_string()).collect(),
+            badge_info: msg.badge_info.to_string(),
+            client_nonce: msg.client_nonce.to_string(),
+            emotes: msg.emotes.to_string(),
+            automod_flags: msg.automod_flags.to_string(),
+            text: msg.user_friendly_text().into_owned(),
+            message_flags: msg.message_flags.bits().into(),
+            extra_tags: msg
+                .extra_tags
+                .iter()
+                .map(|(tag, value)| (tag.to_string(), value.to_string()))
+                .collect(),
+            raw: msg.to_raw_irc(),
+        }
+    }
+}
@@ -0,0 +1,86 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use std::{borrow::Borrow, fmt::Display};
+
+/// Defines a Twitch ID newtype, so a login can't be passed where an ID is expected.
+/// It does not deref to `str`, converting it back is spelled out with [`as_str`](ChannelId::as_str)
+macro_rules! twitch_id {
+    ($(#[$meta:meta])* $name:ident) => {
+        $(#[$meta])*
+        #[derive(
+            Serialize, Deserialize, JsonSchema, Clone, Debug, PartialEq, Eq, Hash, PartialOrd, Ord,
+        )]
+        #[serde(transparent)]
+        pub struct $name(String);
+
+        impl $name {
+            pub fn as_str(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl From<String> for $name {
+            fn from(id: String) -> Self {
+                Self(id)
+            }
+        }
+
+        impl From<&str> for $name {
+            fn from(id: &str) -> Self {
+                Self(id.to_owned())
+            }
+        }
+
+        impl From<$name> for String {
+            fn from(id: $name) -> Self {
+                id.0
+            }
+        }
+
+        impl AsRef<str> for $name {
+            fn as_ref(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Borrow<str> for $name {
+            fn borrow(&self) -> &str {
+                &self.0
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                f.write_str(&self.0)
+            }
+        }
+    };
+}
+
+twitch_id!(
+    /// Twitch user ID of a channel
+    ChannelId
+);
+
+twitch_id!(
+    /// Twitch user ID of a chatter
+    UserId
+);
+
+#[cfg(test)]
+mod tests {
+    use super::{ChannelId, UserId};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn serializes_as_string() {
+        let id = ChannelId::from("22484632".to_owned());
+
+        assert_eq!(serde_json::to_string(&id).unwrap(), r#""22484632""#);
+        assert_eq!(
+            serde_json::from_str::<UserId>(r#""68136884""#).unwrap(),
+            UserId::from("68136884".to_owned())
+        );
+        assert_eq!(id.as_str(), "22484632");
+    }
+}
@@ -0,0 +1,29 @@
+pub mod alerts;
+pub mod app;
+pub mod backup;
+pub mod bot;
+pub mod config;
+pub mod db;
+pub mod deleted_users;
+pub mod doctor;
+pub mod emotes;
+pub mod error;
+pub mod export;
+pub mod grpc;
+pub mod ids;
+pub mod logs;
+pub mod migrator;
+pub mod mirror;
+pub mod public_stats;
+pub mod publish;
+pub mod reports;
+pub mod retention;
+pub mod streams;
+pub mod transport;
+pub mod watchdog;
+pub mod web;
+
+pub type Result<T> = std::result::Result<T, error::Error>;
+pub type ShutdownRx = watch::Receiver<()>;
+
+use tokio::sync::watch;
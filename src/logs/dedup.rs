@@ -0,0 +1,65 @@
+use std::collections::{HashSet, VecDeque};
+use uuid::Uuid;
+
+/// Ids of the most recently received messages, so messages Twitch sends again
+/// (e.g. on multiple connections during a reconnect) are only logged once
+pub struct RecentIds {
+    ids: HashSet<Uuid>,
+    /// Oldest first, used to forget ids once the capacity is reached
+    order: VecDeque<Uuid>,
+    capacity: usize,
+}
+
+impl RecentIds {
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            ids: HashSet::with_capacity(capacity),
+            order: VecDeque::with_capacity(capacity),
+            capacity,
+        }
+    }
+
+    /// Returns `false` if the id has already been seen recently
+    pub fn insert(&mut self, id: Uuid) -> bool {
+        if !self.ids.insert(id) {
+            return false;
+        }
+
+        self.order.push_back(id);
+        if self.order.len() > self.capacity {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::RecentIds;
+    use uuid::Uuid;
+
+    #[test]
+    fn rejects_recent_duplicates() {
+        let mut recent = RecentIds::new(10);
+        let id = Uuid::new_v4();
+
+        assert!(recent.insert(id));
+        assert!(!recent.insert(id));
+        assert!(recent.insert(Uuid::new_v4()));
+    }
+
+    #[test]
+    fn forgets_oldest_ids() {
+        let mut recent = RecentIds::new(2);
+        let ids: Vec<Uuid> = (0..3).map(|_| Uuid::new_v4()).collect();
+
+        for id in &ids {
+            recent.insert(*id);
+        }
+
+        assert!(recent.insert(ids[0]));
+        assert!(!recent.insert(ids[2]));
+    }
+}
@@ -0,0 +1,56 @@
+use std::collections::HashMap;
+use whatlang::Lang;
+
+/// Detected languages of a set of messages
+#[derive(Debug, PartialEq)]
+pub struct LanguageCounts {
+    /// Most used first
+    pub languages: Vec<(Lang, u64)>,
+    /// Messages whose language could not be detected reliably, e.g. because they only contain emotes
+    pub undetected: u64,
+}
+
+pub fn count_languages<'a>(texts: impl IntoIterator<Item = &'a str>) -> LanguageCounts {
+    let mut counts: HashMap<Lang, u64> = HashMap::new();
+    let mut undetected = 0;
+
+    for text in texts {
+        match whatlang::detect(text).filter(|info| info.is_reliable()) {
+            Some(info) => *counts.entry(info.lang()).or_default() += 1,
+            None => undetected += 1,
+        }
+    }
+
+    let mut languages: Vec<(Lang, u64)> = counts.into_iter().collect();
+    languages.sort_unstable_by(|a, b| b.1.cmp(&a.1).then_with(|| a.0.code().cmp(b.0.code())));
+
+    LanguageCounts {
+        languages,
+        undetected,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{count_languages, LanguageCounts};
+    use pretty_assertions::assert_eq;
+    use whatlang::Lang;
+
+    #[test]
+    fn counts_reliable_detections() {
+        let texts = [
+            "This is a rather long English sentence about the stream today",
+            "I think the streamer is going to win this game tonight",
+            "Das ist ein ziemlich langer deutscher Satz über den Stream heute",
+            "KEKW",
+        ];
+
+        assert_eq!(
+            count_languages(texts),
+            LanguageCounts {
+                languages: vec![(Lang::Eng, 2), (Lang::Deu, 1)],
+                undetected: 1,
+            }
+        );
+    }
+}
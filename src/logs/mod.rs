@@ -1,3 +1,7 @@
+pub mod dedup;
 pub mod extract;
+pub mod language;
+pub mod normalize;
+pub mod sanitize;
 pub mod schema;
 pub mod stream;
@@ -0,0 +1,100 @@
+use std::borrow::Cow;
+
+/// Strips invisible characters (e.g. the suffix Chatterino appends to bypass the duplicate message check)
+/// and replaces common homoglyphs with their ASCII counterparts
+pub fn normalize_text(text: &str) -> Cow<'_, str> {
+    if text.chars().all(|c| normalize_char(c) == Some(c)) {
+        return Cow::Borrowed(text);
+    }
+
+    Cow::Owned(text.chars().filter_map(normalize_char).collect())
+}
+
+fn normalize_char(c: char) -> Option<char> {
+    let normalized = match c {
+        // Zero-width and formatting characters
+        '\u{00AD}'
+        | '\u{034F}'
+        | '\u{180E}'
+        | '\u{200B}'..='\u{200F}'
+        | '\u{2060}'..='\u{2064}'
+        | '\u{FEFF}'
+        | '\u{E0000}'..='\u{E007F}' => return None,
+        // Fullwidth ASCII
+        '\u{FF01}'..='\u{FF5E}' => char::from_u32(c as u32 - 0xFEE0).unwrap_or(c),
+        // Cyrillic
+        'а' => 'a',
+        'в' => 'b',
+        'е' => 'e',
+        'к' => 'k',
+        'м' => 'm',
+        'н' => 'h',
+        'о' => 'o',
+        'р' => 'p',
+        'с' => 'c',
+        'т' => 't',
+        'у' => 'y',
+        'х' => 'x',
+        'і' => 'i',
+        'ј' => 'j',
+        'ѕ' => 's',
+        'А' => 'A',
+        'В' => 'B',
+        'Е' => 'E',
+        'К' => 'K',
+        'М' => 'M',
+        'Н' => 'H',
+        'О' => 'O',
+        'Р' => 'P',
+        'С' => 'C',
+        'Т' => 'T',
+        'Х' => 'X',
+        'І' => 'I',
+        'Ј' => 'J',
+        'Ѕ' => 'S',
+        // Greek
+        'ο' => 'o',
+        'ν' => 'v',
+        'Α' => 'A',
+        'Β' => 'B',
+        'Ε' => 'E',
+        'Ζ' => 'Z',
+        'Η' => 'H',
+        'Ι' => 'I',
+        'Κ' => 'K',
+        'Μ' => 'M',
+        'Ν' => 'N',
+        'Ο' => 'O',
+        'Ρ' => 'P',
+        'Τ' => 'T',
+        'Υ' => 'Y',
+        'Χ' => 'X',
+        _ => c,
+    };
+    Some(normalized)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::normalize_text;
+    use pretty_assertions::assert_eq;
+    use std::borrow::Cow;
+
+    #[test]
+    fn strips_invisible_suffix() {
+        assert_eq!("+join ", normalize_text("+join \u{E0000}"));
+        assert_eq!("forsen", normalize_text("for\u{200B}sen"));
+    }
+
+    #[test]
+    fn replaces_homoglyphs() {
+        // Cyrillic а, е and о
+        assert_eq!("spam here too", normalize_text("spаm hеre tоo"));
+        assert_eq!("ABC", normalize_text("ＡＢＣ"));
+    }
+
+    #[test]
+    fn borrows_unchanged_text() {
+        assert!(matches!(normalize_text("forsenE"), Cow::Borrowed(_)));
+    }
+}
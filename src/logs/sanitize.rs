@@ -0,0 +1,46 @@
+use std::borrow::Cow;
+
+pub struct SanitizedLine<'a> {
+    pub text: Cow<'a, str>,
+    /// The original bytes, only set if the line had to be modified
+    pub original: Option<&'a [u8]>,
+}
+
+/// Replaces invalid UTF-8 sequences and null characters with `U+FFFD`
+pub fn sanitize_line(bytes: &[u8]) -> SanitizedLine<'_> {
+    let mut text = String::from_utf8_lossy(bytes);
+    let mut modified = matches!(text, Cow::Owned(_));
+
+    if text.contains('\0') {
+        text = Cow::Owned(text.replace('\0', "\u{FFFD}"));
+        modified = true;
+    }
+
+    SanitizedLine {
+        text,
+        original: modified.then_some(bytes),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sanitize_line;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn keeps_valid_line() {
+        let sanitized = sanitize_line("PRIVMSG #forsen :forsenE 󠀀".as_bytes());
+
+        assert_eq!("PRIVMSG #forsen :forsenE 󠀀", sanitized.text);
+        assert_eq!(None, sanitized.original);
+    }
+
+    #[test]
+    fn replaces_invalid_bytes() {
+        let raw = b"PRIVMSG #forsen :a\xFFb\0c";
+        let sanitized = sanitize_line(raw);
+
+        assert_eq!("PRIVMSG #forsen :a\u{FFFD}b\u{FFFD}c", sanitized.text);
+        assert_eq!(Some(&raw[..]), sanitized.original);
+    }
+}
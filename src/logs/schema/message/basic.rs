@@ -6,7 +6,7 @@ use std::{borrow::Cow, collections::HashMap};
 
 use crate::db::schema::StructuredMessage;
 
-use super::ResponseMessage;
+use super::{MessageHighlight, ResponseMessage, SearchHighlighter};
 
 #[derive(Serialize, JsonSchema, Debug, PartialEq)]
 #[serde(rename_all = "camelCase")]
@@ -17,6 +17,9 @@ pub struct BasicMessage<'a> {
     pub timestamp: DateTime<Utc>,
     pub id: Cow<'a, str>,
     pub tags: HashMap<&'a str, Cow<'a, str>>,
+    /// Set with `highlight` on searches
+    #[serde(flatten)]
+    pub highlight: Option<MessageHighlight>,
 }
 
 impl<'a> ResponseMessage<'a> for BasicMessage<'a> {
@@ -32,8 +35,13 @@ impl<'a> ResponseMessage<'a> for BasicMessage<'a> {
                 .into_iter()
                 .map(|(tag, value)| (tag.as_str(), value))
                 .collect(),
+            highlight: None,
         })
     }
+
+    fn set_highlight(&mut self, highlighter: &SearchHighlighter) {
+        self.highlight = Some(highlighter.highlight(&self.text));
+    }
 }
 
 #[cfg(test)]
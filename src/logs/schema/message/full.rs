@@ -1,4 +1,4 @@
-use super::{BasicMessage, ResponseMessage};
+use super::{BasicMessage, ResponseMessage, SearchHighlighter, VodLink};
 use crate::db::schema::{MessageType, StructuredMessage};
 use schemars::JsonSchema;
 use serde::Serialize;
@@ -13,6 +13,9 @@ pub struct FullMessage<'a> {
     pub raw: String,
     #[schemars(with = "i8")]
     pub r#type: MessageType,
+    /// Set with `vods` if the message was sent during a stream whose VOD is known
+    #[serde(flatten)]
+    pub vod: Option<VodLink>,
 }
 
 impl<'a> ResponseMessage<'a> for FullMessage<'a> {
@@ -24,8 +27,17 @@ impl<'a> ResponseMessage<'a> for FullMessage<'a> {
             channel: &msg.channel_login,
             raw: msg.to_raw_irc(),
             r#type: msg.message_type,
+            vod: None,
         })
     }
+
+    fn set_vod(&mut self, vod: VodLink) {
+        self.vod = Some(vod);
+    }
+
+    fn set_highlight(&mut self, highlighter: &SearchHighlighter) {
+        self.basic.set_highlight(highlighter);
+    }
 }
 
 #[cfg(test)]
@@ -74,11 +86,13 @@ mod tests {
                 .into_iter()
                 .map(|(k, v)| (k, Cow::Borrowed(v)))
                 .collect(),
+                highlight: None,
             },
             raw: "@tmi-sent-ts=1489263601000;room-id=22484632;user-id=62541963;display-name=Snusbot;badges=;badge-info=;flags=;user-type=;emotes= :snusbot!snusbot@snusbot.tmi.twitch.tv PRIVMSG #forsen :prasoc won 10 points in roulette and now has 2838 points! forsenPls".to_owned(),
             r#type: MessageType::PrivMsg,
             username: "snusbot",
             channel: "forsen",
+            vod: None,
         };
 
         let mut expected_tags = expected_message.basic.tags.iter().collect::<Vec<_>>();
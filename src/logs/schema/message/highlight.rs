@@ -0,0 +1,137 @@
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Where the search text was found in the text of a message
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageHighlight {
+    /// Matches in the order they appear in the text. Empty if the message only matched through its normalized text
+    pub matches: Vec<TextMatch>,
+    /// The text with every match wrapped in the requested markers
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub highlighted_text: Option<String>,
+}
+
+/// Offsets in Unicode characters, the end is exclusive
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Clone, Copy)]
+pub struct TextMatch {
+    pub start: usize,
+    pub end: usize,
+}
+
+struct Highlighter {
+    search: String,
+    markers: Option<(String, String)>,
+}
+
+/// Finds the search text in messages the same way the search query does, so clients don't have to
+#[derive(Clone)]
+pub struct SearchHighlighter(Arc<Highlighter>);
+
+impl SearchHighlighter {
+    pub fn new(search: &str, markers: Option<(String, String)>) -> Self {
+        Self(Arc::new(Highlighter {
+            search: search.to_owned(),
+            markers,
+        }))
+    }
+
+    pub fn highlight(&self, text: &str) -> MessageHighlight {
+        let byte_matches = find_matches(text, &self.0.search);
+
+        let highlighted_text = self.0.markers.as_ref().map(|(pre, post)| {
+            let mut highlighted = String::with_capacity(text.len());
+            let mut last_end = 0;
+            for (start, end) in &byte_matches {
+                highlighted.push_str(&text[last_end..*start]);
+                highlighted.push_str(pre);
+                highlighted.push_str(&text[*start..*end]);
+                highlighted.push_str(post);
+                last_end = *end;
+            }
+            highlighted.push_str(&text[last_end..]);
+            highlighted
+        });
+
+        // Byte offsets are converted in one pass, the matches are ordered
+        let mut matches = Vec::with_capacity(byte_matches.len());
+        let mut chars = 0;
+        let mut last_byte = 0;
+        for (start, end) in byte_matches {
+            chars += text[last_byte..start].chars().count();
+            let match_start = chars;
+            chars += text[start..end].chars().count();
+            matches.push(TextMatch {
+                start: match_start,
+                end: chars,
+            });
+            last_byte = end;
+        }
+
+        MessageHighlight {
+            matches,
+            highlighted_text,
+        }
+    }
+}
+
+/// Byte ranges of the non-overlapping matches. Like ClickHouse's `positionCaseInsensitive`, only ASCII letters
+/// are compared case insensitively. The ranges are always on character boundaries, as the search text is valid UTF-8
+fn find_matches(text: &str, search: &str) -> Vec<(usize, usize)> {
+    let (text, search) = (text.as_bytes(), search.as_bytes());
+    let mut matches = Vec::new();
+    if search.is_empty() {
+        return matches;
+    }
+
+    let mut i = 0;
+    while i + search.len() <= text.len() {
+        if text[i..i + search.len()].eq_ignore_ascii_case(search) {
+            matches.push((i, i + search.len()));
+            i += search.len();
+        } else {
+            i += 1;
+        }
+    }
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{MessageHighlight, SearchHighlighter, TextMatch};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn highlights_case_insensitive_matches() {
+        let highlighter =
+            SearchHighlighter::new("forsen", Some(("<mark>".to_owned(), "</mark>".to_owned())));
+
+        assert_eq!(
+            highlighter.highlight("ÄÄ FORSEN forsenE forsen"),
+            MessageHighlight {
+                matches: vec![
+                    TextMatch { start: 3, end: 9 },
+                    TextMatch { start: 10, end: 16 },
+                    TextMatch { start: 18, end: 24 },
+                ],
+                highlighted_text: Some(
+                    "ÄÄ <mark>FORSEN</mark> <mark>forsen</mark>E <mark>forsen</mark>".to_owned()
+                ),
+            }
+        );
+    }
+
+    #[test]
+    fn only_ascii_is_case_insensitive() {
+        let highlighter = SearchHighlighter::new("äa", None);
+
+        assert_eq!(
+            highlighter.highlight("ÄA äA aaa"),
+            MessageHighlight {
+                matches: vec![TextMatch { start: 3, end: 5 }],
+                highlighted_text: None,
+            }
+        );
+    }
+}
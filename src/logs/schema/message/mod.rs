@@ -1,8 +1,12 @@
 mod basic;
 mod full;
+mod highlight;
+mod vod;
 
 pub use basic::BasicMessage;
 pub use full::FullMessage;
+pub use highlight::{MessageHighlight, SearchHighlighter, TextMatch};
+pub use vod::{StreamVods, VodLink};
 
 use serde::Serialize;
 
@@ -10,4 +14,9 @@ use crate::db::schema::StructuredMessage;
 
 pub trait ResponseMessage<'a>: Sized + Send + Serialize + Unpin {
     fn from_structured(msg: &'a StructuredMessage<'a>) -> anyhow::Result<Self>;
+
+    /// Only full messages include VOD links
+    fn set_vod(&mut self, _vod: VodLink) {}
+
+    fn set_highlight(&mut self, highlighter: &SearchHighlighter);
 }
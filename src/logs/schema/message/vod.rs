@@ -0,0 +1,106 @@
+use crate::db::streams::StreamRow;
+use schemars::JsonSchema;
+use serde::Serialize;
+use std::sync::Arc;
+
+const VIDEO_URL: &str = "https://www.twitch.tv/videos";
+
+/// Where a message can be found in the VOD of the stream it was sent during
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct VodLink {
+    /// Seconds since the start of the VOD
+    pub vod_offset: u64,
+    /// VOD URL starting at the message
+    pub vod_url: String,
+}
+
+struct StreamVod {
+    video_id: String,
+    /// Milliseconds since the Unix epoch, like message timestamps
+    started_at: u64,
+    ended_at: u64,
+}
+
+/// Streams with a known VOD, used to link messages to their VOD moment
+#[derive(Clone)]
+pub struct StreamVods(Arc<[StreamVod]>);
+
+impl StreamVods {
+    /// Chat is still linked to the VOD for `end_grace` seconds after the stream was last seen live
+    pub fn new(streams: Vec<StreamRow>, end_grace: u32) -> Self {
+        let vods = streams
+            .into_iter()
+            .filter(|stream| !stream.video_id.is_empty())
+            .map(|stream| StreamVod {
+                video_id: stream.video_id,
+                started_at: u64::from(stream.started_at) * 1000,
+                ended_at: u64::from(stream.ended_at.saturating_add(end_grace)) * 1000,
+            })
+            .collect();
+        Self(vods)
+    }
+
+    pub fn link(&self, timestamp: u64) -> Option<VodLink> {
+        let vod = self
+            .0
+            .iter()
+            .find(|vod| (vod.started_at..vod.ended_at).contains(&timestamp))?;
+        let offset = (timestamp - vod.started_at) / 1000;
+
+        Some(VodLink {
+            vod_offset: offset,
+            vod_url: format!(
+                "{VIDEO_URL}/{}?t={}h{}m{}s",
+                vod.video_id,
+                offset / 3600,
+                offset % 3600 / 60,
+                offset % 60
+            ),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{StreamVods, VodLink};
+    use crate::db::streams::StreamRow;
+    use pretty_assertions::assert_eq;
+
+    fn stream(video_id: &str, started_at: u32, ended_at: u32) -> StreamRow {
+        StreamRow {
+            stream_id: "1".to_owned(),
+            channel_id: "22484632".to_owned(),
+            started_at,
+            ended_at,
+            title: String::new(),
+            game_name: String::new(),
+            video_id: video_id.to_owned(),
+            ended: true,
+            updated_at: ended_at,
+        }
+    }
+
+    #[test]
+    fn links_messages_during_streams() {
+        let vods = StreamVods::new(
+            vec![stream("2070000000", 1000, 10000), stream("", 20000, 30000)],
+            60,
+        );
+
+        assert_eq!(
+            vods.link(4_723_500),
+            Some(VodLink {
+                vod_offset: 3723,
+                vod_url: "https://www.twitch.tv/videos/2070000000?t=1h2m3s".to_owned(),
+            })
+        );
+        assert_eq!(
+            vods.link(10_059_000).map(|link| link.vod_offset),
+            Some(9059)
+        );
+        assert_eq!(vods.link(999_000), None);
+        assert_eq!(vods.link(10_060_000), None);
+        assert_eq!(vods.link(25_000_000), None);
+    }
+}
@@ -1,23 +1,47 @@
 pub mod message;
 
 use chrono::{DateTime, Utc};
-use schemars::JsonSchema;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::Deserialize;
 
-use crate::web::schema::LogsParams;
+use crate::web::schema::{LogsParams, RangeParams};
 
-#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[derive(Deserialize, Clone, Copy)]
+#[serde(from = "LogRangeQuery")]
 pub struct LogRangeParams {
-    #[schemars(with = "String")]
-    /// RFC 3339 start date
     pub from: DateTime<Utc>,
-    #[schemars(with = "String")]
-    /// RFC 3339 end date
     pub to: DateTime<Utc>,
-    #[serde(flatten)]
     pub logs_params: LogsParams,
 }
 
+impl JsonSchema for LogRangeParams {
+    fn schema_name() -> String {
+        LogRangeQuery::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        LogRangeQuery::json_schema(gen)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LogRangeQuery {
+    #[serde(flatten)]
+    range: RangeParams,
+    #[serde(flatten)]
+    logs_params: LogsParams,
+}
+
+impl From<LogRangeQuery> for LogRangeParams {
+    fn from(query: LogRangeQuery) -> Self {
+        Self {
+            from: query.range.from,
+            to: query.range.to,
+            logs_params: query.logs_params,
+        }
+    }
+}
+
 #[derive(Deserialize)]
 #[serde(rename_all = "lowercase")]
 pub enum UserIdentifier<'a> {
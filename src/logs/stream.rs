@@ -1,60 +1,112 @@
 use crate::{
-    db::{schema::StructuredMessage, writer::FlushBuffer},
+    db::{
+        schema::StructuredMessage,
+        writer::{BufferPosition, FlushBuffer},
+    },
     error::Error,
     Result,
 };
+use chrono::{DateTime, Utc};
 use clickhouse::query::RowCursor;
 use futures::{Future, Stream};
 use std::{
     ops::{DerefMut, Range},
     pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
     task::{Context, Poll},
 };
 use tokio::pin;
 
 use super::schema::LogRangeParams;
 
+/// Messages from the flush buffer which are appended to a log response.
+/// They are read in pages, so a response for a busy channel does not copy the whole buffer at once
+#[derive(Clone)]
 pub struct FlushBufferResponse {
-    pub buffer: Option<FlushBuffer>,
-    pub channel_id: String,
-    pub user_id: Option<String>,
+    buffer: Option<FlushBuffer>,
+    channel_id: String,
+    user_id: Option<String>,
+    /// Only messages containing this text are returned
+    search: Option<String>,
+    /// Messages of these users are skipped, e.g. bots
+    excluded_users: Vec<String>,
     pub params: LogRangeParams,
+    position: Option<BufferPosition>,
 }
 
 impl FlushBufferResponse {
+    pub fn new(
+        buffer: Option<FlushBuffer>,
+        channel_id: String,
+        user_id: Option<String>,
+        params: LogRangeParams,
+    ) -> Self {
+        Self {
+            buffer,
+            channel_id,
+            user_id,
+            search: None,
+            excluded_users: Vec::new(),
+            params,
+            position: None,
+        }
+    }
+
+    pub fn with_search(mut self, search: String) -> Self {
+        self.search = Some(search);
+        self
+    }
+
+    pub fn with_excluded_users(mut self, user_ids: &[String]) -> Self {
+        self.excluded_users = user_ids.to_vec();
+        self
+    }
+
+    /// The filters of the query which are not applied when reading the buffer
+    fn matches(&self, msg: &StructuredMessage) -> bool {
+        let logs_params = &self.params.logs_params;
+        // Buffered messages can not be hashed like the query does, so they are left out of samples
+        logs_params.sample.is_none()
+            && (!logs_params.flagged || !msg.automod_flags.is_empty())
+            && logs_params.filter.matches(msg)
+            && !self
+                .excluded_users
+                .iter()
+                .any(|user_id| *user_id == msg.user_id)
+    }
+
     fn timestamp_range(&self) -> Range<u64> {
         (self.params.from.timestamp_millis() as u64)..(self.params.to.timestamp_millis() as u64)
     }
 
+    /// Returns the next page of buffered messages, or `None` once the buffer has been read completely
     async fn take_messages(&mut self) -> Option<Vec<StructuredMessage<'static>>> {
-        if self.params.logs_params.limit.is_some() || self.params.logs_params.offset.is_some() {
-            return None;
-        }
-
-        match &self.buffer {
-            Some(buffer) => {
-                let mut messages = if let Some(user_id) = &self.user_id {
-                    buffer
-                        .messages_by_channel_and_user(
-                            self.timestamp_range(),
-                            &self.channel_id,
-                            user_id,
-                        )
-                        .await
-                } else {
-                    buffer
-                        .messages_by_channel(self.timestamp_range(), &self.channel_id)
-                        .await
-                };
-
-                if self.params.logs_params.reverse {
-                    messages.reverse();
-                }
+        loop {
+            let buffer = self.buffer.as_ref()?;
+            let mut messages = buffer
+                .messages_page(
+                    self.timestamp_range(),
+                    &self.channel_id,
+                    self.user_id.as_deref(),
+                    self.search.as_deref(),
+                    self.params.logs_params.reverse,
+                    &mut self.position,
+                )
+                .await;
 
+            if messages.is_empty() {
                 self.buffer = None;
-                Some(messages)
+                return None;
+            }
+
+            // A page without matching messages is skipped instead of ending the stream
+            messages.retain(|msg| self.matches(msg));
+            if !messages.is_empty() {
+                return Some(messages);
             }
-            None => None,
         }
     }
 }
@@ -63,6 +115,8 @@ pub enum LogsStream {
     Cursor {
         cursor: RowCursor<StructuredMessage<'static>>,
         first_item: Option<StructuredMessage<'static>>,
+        /// Set once the cursor is exhausted and only buffered messages are left
+        finished: bool,
         flush_params: FlushBufferResponse,
     },
     MultiQuery {
@@ -71,6 +125,47 @@ pub enum LogsStream {
         flush_params: FlushBufferResponse,
     },
     Provided(Option<Vec<StructuredMessage<'static>>>),
+    Counted {
+        inner: Box<LogsStream>,
+        rows: Arc<AtomicU64>,
+    },
+    Windowed {
+        inner: Box<LogsStream>,
+        window: Window,
+    },
+}
+
+/// Offset and limit applied over the whole response, so they count stored and buffered messages the same way.
+/// Buffered messages come after the stored ones, or before them in reverse order
+#[derive(Debug, Clone, Copy)]
+pub struct Window {
+    skip: u64,
+    remaining: Option<u64>,
+}
+
+impl Window {
+    pub fn new(offset: Option<u64>, limit: Option<u64>) -> Self {
+        Self {
+            skip: offset.unwrap_or(0),
+            remaining: limit,
+        }
+    }
+
+    fn is_exhausted(&self) -> bool {
+        self.remaining == Some(0)
+    }
+
+    fn apply<T>(&mut self, mut messages: Vec<T>) -> Vec<T> {
+        let skipped = self.skip.min(messages.len() as u64);
+        messages.drain(..skipped as usize);
+        self.skip -= skipped;
+
+        if let Some(remaining) = &mut self.remaining {
+            messages.truncate((*remaining).min(messages.len() as u64) as usize);
+            *remaining -= messages.len() as u64;
+        }
+        messages
+    }
 }
 
 impl LogsStream {
@@ -83,10 +178,19 @@ impl LogsStream {
         Ok(Self::Cursor {
             cursor,
             first_item: Some(first_item),
+            finished: false,
             flush_params,
         })
     }
 
+    /// A stream of only buffered messages, for when the database has no matching rows yet
+    pub async fn new_buffered(flush_params: FlushBufferResponse) -> Result<Self> {
+        if flush_params.clone().take_messages().await.is_none() {
+            return Err(Error::NotFound);
+        }
+        Self::new_multi_query(vec![], flush_params)
+    }
+
     pub fn new_provided(messages: Vec<StructuredMessage<'static>>) -> Result<Self> {
         if messages.is_empty() {
             Err(Error::NotFound)
@@ -95,6 +199,36 @@ impl LogsStream {
         }
     }
 
+    /// Adds the amount of returned messages to `rows`
+    pub fn counted(self, rows: Arc<AtomicU64>) -> Self {
+        Self::Counted {
+            inner: Box::new(self),
+            rows,
+        }
+    }
+
+    /// Applies the offset and limit across all messages of the stream
+    pub fn windowed(self, offset: Option<u64>, limit: Option<u64>) -> Self {
+        if offset.is_none() && limit.is_none() {
+            return self;
+        }
+        Self::Windowed {
+            inner: Box::new(self),
+            window: Window::new(offset, limit),
+        }
+    }
+
+    /// Range the logs were requested for
+    pub fn range(&self) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        match self {
+            Self::Cursor { flush_params, .. } | Self::MultiQuery { flush_params, .. } => {
+                Some((flush_params.params.from, flush_params.params.to))
+            }
+            Self::Counted { inner, .. } | Self::Windowed { inner, .. } => inner.range(),
+            Self::Provided(_) => None,
+        }
+    }
+
     pub fn new_multi_query(
         cursors: Vec<RowCursor<StructuredMessage<'static>>>,
         flush_params: FlushBufferResponse,
@@ -119,17 +253,14 @@ impl Stream for LogsStream {
             LogsStream::Cursor {
                 cursor,
                 first_item,
+                finished,
                 flush_params,
             } => {
                 if flush_params.params.logs_params.reverse {
                     let fut = flush_params.take_messages();
                     pin!(fut);
                     match fut.poll(cx) {
-                        Poll::Ready(Some(messages)) => {
-                            if !messages.is_empty() {
-                                return Poll::Ready(Some(Ok(messages)));
-                            }
-                        }
+                        Poll::Ready(Some(messages)) => return Poll::Ready(Some(Ok(messages))),
                         Poll::Ready(None) => (),
                         Poll::Pending => return Poll::Pending,
                     }
@@ -137,6 +268,10 @@ impl Stream for LogsStream {
 
                 if let Some(item) = first_item.take() {
                     Poll::Ready(Some(Ok(vec![item])))
+                } else if *finished {
+                    let fut = flush_params.take_messages();
+                    pin!(fut);
+                    fut.poll(cx).map(|option| option.map(Ok))
                 } else {
                     let fut = cursor.next();
                     pin!(fut);
@@ -146,16 +281,37 @@ impl Stream for LogsStream {
                         Poll::Ready(Err(err)) => Poll::Ready(Some(Err(err.into()))),
                         Poll::Pending => Poll::Pending,
                         Poll::Ready(Ok(None)) => {
+                            *finished = true;
                             let fut = flush_params.take_messages();
                             pin!(fut);
-                            fut.poll(cx).map(|option| {
-                                Ok(option.filter(|messages| !messages.is_empty())).transpose()
-                            })
+                            fut.poll(cx).map(|option| option.map(Ok))
                         }
                     }
                 }
             }
             LogsStream::Provided(msgs) => Poll::Ready(msgs.take().map(Ok)),
+            LogsStream::Windowed { inner, window } => loop {
+                if window.is_exhausted() {
+                    return Poll::Ready(None);
+                }
+                match Pin::new(inner.as_mut()).poll_next(cx) {
+                    Poll::Ready(Some(Ok(messages))) => {
+                        let messages = window.apply(messages);
+                        // Chunks which were skipped entirely are not returned as empty chunks
+                        if !messages.is_empty() {
+                            return Poll::Ready(Some(Ok(messages)));
+                        }
+                    }
+                    poll => return poll,
+                }
+            },
+            LogsStream::Counted { inner, rows } => {
+                let poll = Pin::new(inner.as_mut()).poll_next(cx);
+                if let Poll::Ready(Some(Ok(messages))) = &poll {
+                    rows.fetch_add(messages.len() as u64, Ordering::Relaxed);
+                }
+                poll
+            }
             LogsStream::MultiQuery {
                 cursors,
                 current,
@@ -165,11 +321,7 @@ impl Stream for LogsStream {
                     let fut = flush_params.take_messages();
                     pin!(fut);
                     match fut.poll(cx) {
-                        Poll::Ready(Some(messages)) => {
-                            if !messages.is_empty() {
-                                return Poll::Ready(Some(Ok(messages)));
-                            }
-                        }
+                        Poll::Ready(Some(messages)) => return Poll::Ready(Some(Ok(messages))),
                         Poll::Ready(None) => (),
                         Poll::Pending => return Poll::Pending,
                     }
@@ -198,12 +350,65 @@ impl Stream for LogsStream {
                     None => {
                         let fut = flush_params.take_messages();
                         pin!(fut);
-                        fut.poll(cx).map(|option| {
-                            Ok(option.filter(|messages| !messages.is_empty())).transpose()
-                        })
+                        fut.poll(cx).map(|option| option.map(Ok))
                     }
                 }
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Window;
+    use pretty_assertions::assert_eq;
+    use proptest::prelude::*;
+
+    fn apply_chunked(window: &mut Window, total: u64, chunk_sizes: &[usize]) -> Vec<u64> {
+        let mut items = 0..total;
+        let mut result = Vec::new();
+        for size in chunk_sizes.iter().cycle() {
+            let chunk: Vec<u64> = items.by_ref().take(*size).collect();
+            if chunk.is_empty() {
+                break;
+            }
+            result.extend(window.apply(chunk));
+        }
+        result
+    }
+
+    proptest! {
+        #[test]
+        fn window_matches_skip_and_take(
+            total in 0u64..200,
+            offset in proptest::option::of(0u64..250),
+            limit in proptest::option::of(0u64..250),
+            chunk_sizes in proptest::collection::vec(1usize..50, 1..10),
+        ) {
+            let mut window = Window::new(offset, limit);
+            let result = apply_chunked(&mut window, total, &chunk_sizes);
+
+            let expected: Vec<u64> = (0..total)
+                .skip(offset.unwrap_or(0) as usize)
+                .take(limit.map_or(usize::MAX, |limit| limit as usize))
+                .collect();
+            prop_assert_eq!(expected, result);
+        }
+    }
+
+    #[test]
+    fn window_boundaries() {
+        // Offset ends exactly at a chunk boundary
+        let mut window = Window::new(Some(3), Some(2));
+        assert_eq!(vec![3, 4], apply_chunked(&mut window, 10, &[3]));
+
+        // Limit of zero returns nothing
+        let mut window = Window::new(None, Some(0));
+        assert!(apply_chunked(&mut window, 10, &[4]).is_empty());
+        assert!(window.is_exhausted());
+
+        // Offset past the end
+        let mut window = Window::new(Some(20), Some(5));
+        assert!(apply_chunked(&mut window, 10, &[4]).is_empty());
+    }
+}
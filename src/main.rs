@@ -1,25 +1,24 @@
-mod app;
 mod args;
-mod bot;
-mod config;
-mod db;
-mod error;
-mod logs;
-mod migrator;
-mod web;
-
-pub type Result<T> = std::result::Result<T, error::Error>;
-pub type ShutdownRx = watch::Receiver<()>;
 
 use anyhow::{anyhow, Context};
 use app::App;
-use args::{Args, Command};
+use args::{Args, Command, ImportFormat};
 use clap::Parser;
 use config::Config;
-use db::{setup_db, writer::create_writer};
+use db::{
+    check_schema_drift,
+    pool::{run_health_checks, DbPool},
+    setup_db,
+    storage::apply_storage_tiering,
+    writer::create_writer,
+};
 use futures::{future::try_join_all, stream::FuturesUnordered, StreamExt};
 use migrator::Migrator;
 use mimalloc::MiMalloc;
+use rustlog::{
+    alerts, app, backup, bot, config, db, deleted_users, doctor, emotes, export, grpc, migrator,
+    mirror, public_stats, publish, reports, retention, streams, watchdog, web,
+};
 use std::{
     env,
     sync::Arc,
@@ -27,7 +26,7 @@ use std::{
 };
 use tokio::{
     signal::unix::{signal, SignalKind},
-    sync::{mpsc, watch},
+    sync::{broadcast, mpsc, watch},
     time::timeout,
 };
 use tracing::{debug, info};
@@ -37,10 +36,16 @@ use twitch_api::{
     HelixClient,
 };
 use twitch_irc::login::StaticLoginCredentials;
+use watchdog::{Heartbeat, Liveness};
 
-use crate::app::cache::UsersCache;
+use crate::app::{
+    cache::UsersCache, channel_index, helix_queue::HelixHttpClient, logs_cache::LogsCache,
+    user_warmup,
+};
 
 const SHUTDOWN_TIMEOUT_SECONDS: u64 = 8;
+/// How many messages live subscribers can fall behind before skipping messages
+const LIVE_MESSAGES_CAPACITY: usize = 4096;
 
 #[global_allocator]
 static GLOBAL: MiMalloc = MiMalloc;
@@ -59,25 +64,37 @@ async fn main() -> anyhow::Result<()> {
         .init();
 
     let config = Config::load()?;
-    let mut db = clickhouse::Client::default()
-        .with_url(&config.clickhouse_url)
-        .with_database(&config.clickhouse_db)
-        .with_compression(clickhouse::Compression::None);
+    let db = create_db_client(&config, &config.clickhouse_url);
 
-    if let Some(user) = &config.clickhouse_username {
-        db = db.with_user(user);
-    }
+    let args = Args::parse();
 
-    if let Some(password) = &config.clickhouse_password {
-        db = db.with_password(password);
+    // Migrations would create the tables the backup is restored into
+    if let Some(Command::Restore { name }) = &args.subcommand {
+        let backup_config = config
+            .backup
+            .as_ref()
+            .context("No backup destination configured")?;
+        return backup::restore(&db, &config.clickhouse_db, backup_config, name).await;
+    }
+    // The schema is checked before the migrations change it
+    if let Some(Command::Doctor) = &args.subcommand {
+        return doctor::run(&config, &db).await;
     }
-
-    let args = Args::parse();
 
     setup_db(&db, &config.clickhouse_db)
         .await
         .context("Could not run DB migrations")?;
 
+    if let Some(tiering) = &config.storage_tiering {
+        apply_storage_tiering(&db, tiering)
+            .await
+            .context("Could not apply storage tiering")?;
+    }
+
+    check_schema_drift(&db, config.schema_drift)
+        .await
+        .context("Could not check the database schema")?;
+
     match args.subcommand {
         None => run(config, db).await,
         Some(Command::Migrate {
@@ -85,30 +102,122 @@ async fn main() -> anyhow::Result<()> {
             channel_id,
             jobs,
         }) => migrate(db, source_dir, channel_id, jobs).await,
+        Some(Command::Import {
+            format: ImportFormat::Justlog,
+            dir,
+            channel_id,
+            jobs,
+        }) => {
+            let migrator = Migrator::new(db, dir, channel_id)
+                .await?
+                .with_deduplication();
+            migrator.run(jobs).await
+        }
+        Some(Command::Export {
+            channel_id,
+            output,
+            options,
+            resume,
+        }) => {
+            let timezone = config.channel_timezone(&channel_id);
+            let privacy = export::Privacy::read(&db, &config).await?;
+            export::export_channel(
+                &db,
+                &privacy,
+                &channel_id.into(),
+                &output,
+                options,
+                resume,
+                timezone,
+            )
+            .await
+        }
+        Some(Command::Restore { .. } | Command::Doctor) => unreachable!(),
     }
 }
 
 async fn run(config: Config, db: clickhouse::Client) -> anyhow::Result<()> {
     let mut shutdown_rx = listen_shutdown().await;
 
-    let helix_client: HelixClient<reqwest::Client> = HelixClient::default();
+    let helix_client = HelixClient::with_client(HelixHttpClient::default());
     let token = generate_token(&config).await?;
 
     let (writer_tx, flush_buffer, mut writer_handle) = create_writer(
+        db.clone()
+            .with_compression(config.clickhouse_insert_compression.into()),
+        shutdown_rx.clone(),
+        config.clickhouse_flush_interval,
+        config.writer_backlog_limit,
+    )
+    .await?;
+    let flush_buffer = flush_buffer.with_page_bytes(config.buffer_page_bytes);
+    let (unparsed_tx, unparsed_flush_buffer, mut unparsed_writer_handle) = create_writer(
         db.clone(),
         shutdown_rx.clone(),
         config.clickhouse_flush_interval,
+        config.writer_backlog_limit,
     )
     .await?;
 
+    let mut db_nodes = vec![(config.clickhouse_url.clone(), db.clone())];
+    db_nodes.extend(
+        config
+            .clickhouse_fallback_urls
+            .iter()
+            .map(|url| (url.clone(), create_db_client(&config, url))),
+    );
+    let db_pool = Arc::new(DbPool::new(
+        db_nodes,
+        config.clickhouse_node_cooldown,
+        &config.query_profiles,
+    ));
+    let mut health_check_handle =
+        tokio::spawn(run_health_checks(db_pool.clone(), shutdown_rx.clone()));
+
+    let logs_cache = config
+        .logs_cache
+        .as_ref()
+        .map(LogsCache::new)
+        .transpose()?
+        .map(Arc::new);
+
+    let consented_users = db::consent::read_consented_users(&db)
+        .await?
+        .into_iter()
+        .collect();
+    let opted_out_users = db::opt_outs::read_opt_outs(&db)
+        .await?
+        .into_iter()
+        .map(|row| row.user_id)
+        .collect();
+
+    let bot_heartbeat = Heartbeat::default();
+    let liveness = Liveness::new(
+        vec![
+            ("bot", bot_heartbeat.clone()),
+            ("writer", flush_buffer.heartbeat()),
+            ("unparsed_writer", unparsed_flush_buffer.heartbeat()),
+        ],
+        config.liveness_timeout,
+    );
+
     let app = App {
         helix_client,
         token: Arc::new(token),
         users: UsersCache::default(),
         config: Arc::new(config),
-        db: Arc::new(db),
+        db: db_pool,
         optout_codes: Arc::default(),
+        opted_out_users: Arc::new(opted_out_users),
+        consent_codes: Arc::default(),
+        consented_users: Arc::new(consented_users),
         flush_buffer,
+        live_tx: broadcast::channel(LIVE_MESSAGES_CAPACITY).0,
+        logs_cache,
+        channel_index: Arc::default(),
+        live_channels: Arc::default(),
+        liveness: liveness.clone(),
+        export_quotas: web::export_quotas::ExportQuotas::default(),
     };
 
     let (bot_tx, bot_rx) = mpsc::channel(1);
@@ -118,18 +227,57 @@ async fn run(config: Config, db: clickhouse::Client) -> anyhow::Result<()> {
         login_credentials,
         app.clone(),
         writer_tx,
+        unparsed_tx,
         shutdown_rx.clone(),
         bot_rx,
+        bot_heartbeat,
     ));
+    let mut reports_handle = tokio::spawn(reports::run(app.clone(), shutdown_rx.clone()));
+    let mut emotes_handle = tokio::spawn(emotes::run(app.clone(), shutdown_rx.clone()));
+    let mut streams_handle = tokio::spawn(streams::run(app.clone(), shutdown_rx.clone()));
+    let mut alerts_handle = tokio::spawn(alerts::run(app.clone(), shutdown_rx.clone()));
+    let mut publish_handle = tokio::spawn(publish::run(app.clone(), shutdown_rx.clone()));
+    let mut backup_handle = tokio::spawn(backup::run(app.clone(), shutdown_rx.clone()));
+    let mut channel_index_handle =
+        tokio::spawn(channel_index::run(app.clone(), shutdown_rx.clone()));
+    let mut user_warmup_handle = tokio::spawn(user_warmup::run(app.clone(), shutdown_rx.clone()));
+    let mut deleted_users_handle =
+        tokio::spawn(deleted_users::run(app.clone(), shutdown_rx.clone()));
+    let mut retention_handle = tokio::spawn(retention::run(app.clone(), shutdown_rx.clone()));
+    let mut public_stats_handle = tokio::spawn(public_stats::run(app.clone(), shutdown_rx.clone()));
+    let mut mirror_handle = tokio::spawn(mirror::run(app.clone(), shutdown_rx.clone()));
+    let mut grpc_handle = tokio::spawn(grpc::run(app.clone(), shutdown_rx.clone()));
     let mut web_handle = tokio::spawn(web::run(app, shutdown_rx.clone(), bot_tx));
 
+    watchdog::notify_ready();
+
     tokio::select! {
         _ = shutdown_rx.changed() => {
+            watchdog::notify_stopping();
             debug!("Waiting for tasks to shut down");
 
             let started_at = Instant::now();
 
-            let shutdown_future = try_join_all([bot_handle, web_handle, writer_handle]);
+            let shutdown_future = try_join_all([
+                bot_handle,
+                web_handle,
+                writer_handle,
+                unparsed_writer_handle,
+                reports_handle,
+                emotes_handle,
+                streams_handle,
+                alerts_handle,
+                grpc_handle,
+                publish_handle,
+                mirror_handle,
+                backup_handle,
+                channel_index_handle,
+                user_warmup_handle,
+                deleted_users_handle,
+                retention_handle,
+                public_stats_handle,
+                health_check_handle,
+            ]);
             match timeout(Duration::from_secs(SHUTDOWN_TIMEOUT_SECONDS), shutdown_future).await {
                 Ok(Ok(_)) => {
                     debug!("Cleanup finished in {}ms", started_at.elapsed().as_millis());
@@ -151,7 +299,72 @@ async fn run(config: Config, db: clickhouse::Client) -> anyhow::Result<()> {
         _ = &mut writer_handle => {
             Err(anyhow!("Writer task exited unexpectedly"))
         }
+        _ = &mut unparsed_writer_handle => {
+            Err(anyhow!("Unparsed message writer task exited unexpectedly"))
+        }
+        _ = &mut reports_handle => {
+            Err(anyhow!("Reports task exited unexpectedly"))
+        }
+        _ = &mut emotes_handle => {
+            Err(anyhow!("Emotes task exited unexpectedly"))
+        }
+        _ = &mut streams_handle => {
+            Err(anyhow!("Streams task exited unexpectedly"))
+        }
+        _ = &mut alerts_handle => {
+            Err(anyhow!("Alerts task exited unexpectedly"))
+        }
+        _ = &mut grpc_handle => {
+            Err(anyhow!("gRPC task exited unexpectedly"))
+        }
+        _ = &mut publish_handle => {
+            Err(anyhow!("Publish task exited unexpectedly"))
+        }
+        _ = &mut mirror_handle => {
+            Err(anyhow!("Mirror task exited unexpectedly"))
+        }
+        _ = &mut backup_handle => {
+            Err(anyhow!("Backup task exited unexpectedly"))
+        }
+        _ = &mut channel_index_handle => {
+            Err(anyhow!("Channel index task exited unexpectedly"))
+        }
+        _ = &mut user_warmup_handle => {
+            Err(anyhow!("Users cache warmup task exited unexpectedly"))
+        }
+        _ = &mut deleted_users_handle => {
+            Err(anyhow!("Deleted users task exited unexpectedly"))
+        }
+        _ = &mut retention_handle => {
+            Err(anyhow!("Retention task exited unexpectedly"))
+        }
+        _ = &mut public_stats_handle => {
+            Err(anyhow!("Public stats task exited unexpectedly"))
+        }
+        _ = &mut health_check_handle => {
+            Err(anyhow!("Database health check task exited unexpectedly"))
+        }
+        _ = watchdog::run(liveness) => {
+            Err(anyhow!("Watchdog exited unexpectedly"))
+        }
+    }
+}
+
+fn create_db_client(config: &Config, url: &str) -> clickhouse::Client {
+    let mut db = clickhouse::Client::default()
+        .with_url(url)
+        .with_database(&config.clickhouse_db)
+        .with_compression(clickhouse::Compression::None);
+
+    if let Some(user) = &config.clickhouse_username {
+        db = db.with_user(user);
     }
+
+    if let Some(password) = &config.clickhouse_password {
+        db = db.with_password(password.expose());
+    }
+
+    db
 }
 
 async fn migrate(
@@ -169,7 +382,7 @@ async fn generate_token(config: &Config) -> anyhow::Result<AppAccessToken> {
     let token = AppAccessToken::get_app_access_token(
         &helix_client,
         config.client_id.clone().into(),
-        config.client_secret.clone().into(),
+        config.client_secret.expose().clone().into(),
         Scope::all(),
     )
     .await?;
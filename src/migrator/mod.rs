@@ -2,8 +2,14 @@ mod reader;
 
 use self::reader::{LogsReader, COMPRESSED_CHANNEL_FILE, UNCOMPRESSED_CHANNEL_FILE};
 use crate::{
-    db::schema::{StructuredMessage, UnstructuredMessage, MESSAGES_STRUCTURED_TABLE},
-    logs::extract::{extract_raw_timestamp, extract_user_id},
+    db::{
+        duplicates::read_message_keys,
+        schema::{StructuredMessage, UnstructuredMessage, MESSAGES_STRUCTURED_TABLE},
+    },
+    logs::{
+        extract::{extract_raw_timestamp, extract_user_id},
+        sanitize::sanitize_line,
+    },
     migrator::reader::ChannelLogDateMap,
 };
 use anyhow::{anyhow, Context};
@@ -12,6 +18,7 @@ use clickhouse::inserter::Inserter;
 use flate2::bufread::GzDecoder;
 use indexmap::IndexMap;
 use std::{
+    collections::HashSet,
     convert::TryInto,
     fs::File,
     io::{BufRead, BufReader},
@@ -25,6 +32,7 @@ use std::{
 use tmi::Command;
 use tokio::sync::Semaphore;
 use tracing::{debug, error, info, warn};
+use uuid::Uuid;
 
 const INSERT_BATCH_SIZE: u64 = 10_000_000;
 
@@ -33,6 +41,8 @@ pub struct Migrator {
     db: clickhouse::Client,
     source_logs_path: String,
     channel_ids: Arc<Vec<String>>,
+    /// Skip messages which are already stored, so logs can be imported again or overlap with logged ones
+    deduplicate: bool,
 }
 
 impl Migrator {
@@ -45,9 +55,15 @@ impl Migrator {
             db,
             source_logs_path,
             channel_ids: Arc::new(channel_ids),
+            deduplicate: false,
         })
     }
 
+    pub fn with_deduplication(mut self) -> Self {
+        self.deduplicate = true;
+        self
+    }
+
     pub async fn run(self, parallel_count: usize) -> anyhow::Result<()> {
         let source_logs = LogsReader::new(&self.source_logs_path)?;
 
@@ -185,6 +201,14 @@ impl Migrator {
     ) -> anyhow::Result<usize> {
         let day_path = get_day_path(root_path, channel_id, date);
 
+        let existing = if self.deduplicate {
+            read_message_keys(&self.db, channel_id, date, date + chrono::Duration::days(1))
+                .await
+                .context("Could not read stored messages")?
+        } else {
+            HashSet::new()
+        };
+
         let compressed_file_path = day_path.join(COMPRESSED_CHANNEL_FILE);
         let uncompressed_file_path = day_path.join(UNCOMPRESSED_CHANNEL_FILE);
 
@@ -193,12 +217,13 @@ impl Migrator {
             let file_reader = BufReader::new(File::open(&compressed_file_path)?);
             let gz = BufReader::new(GzDecoder::new(file_reader));
 
-            self.migrate_reader(gz, date, channel_id, inserter).await
+            self.migrate_reader(gz, date, channel_id, &existing, inserter)
+                .await
         } else if uncompressed_file_path.exists() {
             debug!("Reading uncompressed log {uncompressed_file_path:?}");
             let file_reader = BufReader::new(File::open(&uncompressed_file_path)?);
 
-            self.migrate_reader(file_reader, date, channel_id, inserter)
+            self.migrate_reader(file_reader, date, channel_id, &existing, inserter)
                 .await
         } else {
             Err(anyhow!("File does not exist"))
@@ -210,16 +235,29 @@ impl Migrator {
         reader: R,
         datetime: DateTime<Utc>,
         channel_id: &'a str,
+        existing: &HashSet<(u64, Uuid)>,
         inserter: &mut Inserter<StructuredMessage<'a>>,
     ) -> anyhow::Result<usize> {
         let mut read_bytes = 0;
+        let mut skipped = 0;
 
-        for (i, line) in reader.lines().enumerate() {
-            let line = line.with_context(|| format!("Could not read line {i} from input"))?;
+        // Lines are read as bytes, since old logs can contain invalid UTF-8
+        for (i, line) in reader.split(b'\n').enumerate() {
+            let mut line = line.with_context(|| format!("Could not read line {i} from input"))?;
             read_bytes += line.len() + 1; // Add 1 byte for newline symbol
-            write_line(channel_id, line, inserter, datetime)
+            if line.last() == Some(&b'\r') {
+                line.pop();
+            }
+            let written = write_line(channel_id, line, existing, inserter, datetime)
                 .await
                 .with_context(|| format!("Could not write line {i} to inserter"))?;
+            if !written {
+                skipped += 1;
+            }
+        }
+
+        if skipped > 0 {
+            debug!("Skipped {skipped} lines of channel {channel_id} date {datetime}");
         }
 
         let stats = inserter.commit().await?;
@@ -234,13 +272,17 @@ impl Migrator {
     }
 }
 
+/// Returns whether the line was written, lines which are invalid or already stored are skipped
 async fn write_line<'a>(
     channel_id: &'a str,
-    raw: String,
+    raw: Vec<u8>,
+    existing: &HashSet<(u64, Uuid)>,
     inserter: &mut Inserter<StructuredMessage<'_>>,
     datetime: DateTime<Utc>,
-) -> anyhow::Result<()> {
-    match tmi::IrcMessageRef::parse(&raw) {
+) -> anyhow::Result<bool> {
+    let sanitized = sanitize_line(&raw);
+
+    match tmi::IrcMessageRef::parse(&sanitized.text) {
         Some(irc_message) => {
             let timestamp = extract_raw_timestamp(&irc_message)
                 .unwrap_or_else(|| datetime.timestamp_millis() as u64);
@@ -261,23 +303,30 @@ async fn write_line<'a>(
                 raw: irc_message.raw(),
             };
             match StructuredMessage::from_unstructured(&unstructured) {
-                Ok(msg) => {
+                Ok(mut msg) => {
+                    if existing.contains(&(msg.timestamp, msg.uuid().unwrap_or_default())) {
+                        return Ok(false);
+                    }
+                    if let Some(original) = sanitized.original {
+                        msg.set_sanitized_original(original);
+                    }
                     // This is safe because despite the function signature,
                     // `inserter.write` only uses the value for serialization at the time of the method call, and not later
                     let msg: StructuredMessage<'static> = unsafe { std::mem::transmute(msg) };
                     inserter.write(&msg).await?;
+                    Ok(true)
                 }
                 Err(err) => {
                     error!("Could not convert message {unstructured:?}: {err}");
+                    Ok(false)
                 }
             }
         }
         None => {
-            warn!("Could not parse message `{raw}`");
+            warn!("Could not parse message `{}`", sanitized.text);
+            Ok(false)
         }
     }
-
-    Ok(())
 }
 
 fn get_day_path(root_path: &Path, channel_id: &str, date: DateTime<Utc>) -> PathBuf {
@@ -0,0 +1,269 @@
+use crate::{
+    app::App,
+    config::DiscordMirror,
+    db::schema::{MessageType, StructuredMessage},
+    reports::webhook::escape_markdown,
+    ShutdownRx,
+};
+use anyhow::{anyhow, Context};
+use futures::future::join_all;
+use reqwest::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::time::Duration;
+use tokio::{
+    sync::{broadcast::error::RecvError, mpsc},
+    time::sleep,
+};
+use tracing::{debug, error, warn};
+
+/// Discord rejects messages longer than 2000 characters
+const MAX_CONTENT_LENGTH: usize = 2000;
+/// How long to collect messages before posting them, so busy chats don't hit the rate limit
+const BATCH_DELAY: Duration = Duration::from_secs(2);
+/// Lines which are waiting to be posted to a single webhook
+const MIRROR_BACKLOG: usize = 1000;
+const DELIVERY_ATTEMPTS: u8 = 5;
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let mirrors = &app.config.discord_mirrors;
+    if mirrors.is_empty() {
+        debug!("No Discord mirrors configured, mirror is idle");
+        shutdown_rx.changed().await.ok();
+        return;
+    }
+
+    let mut live_rx = app.live_tx.subscribe();
+    let http_client = reqwest::Client::new();
+
+    let (senders, handles): (Vec<_>, Vec<_>) = mirrors
+        .iter()
+        .map(|mirror| {
+            let (tx, rx) = mpsc::channel(MIRROR_BACKLOG);
+            let handle = tokio::spawn(deliver(
+                http_client.clone(),
+                mirror.url.expose().clone(),
+                rx,
+            ));
+            (tx, handle)
+        })
+        .unzip();
+
+    loop {
+        tokio::select! {
+            result = live_rx.recv() => match result {
+                Ok(msg) => {
+                    for (mirror, tx) in mirrors.iter().zip(&senders) {
+                        if mirror_matches(mirror, &msg) && tx.try_send(format_line(&msg)).is_err() {
+                            warn!("Discord mirror for channel {} is falling behind, dropping message", mirror.channel_id);
+                        }
+                    }
+                }
+                Err(RecvError::Lagged(count)) => {
+                    warn!("Discord mirror is falling behind, skipped {count} messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down Discord mirror");
+                break;
+            }
+        }
+    }
+
+    // Closing the channels makes the delivery tasks post what is left and exit
+    drop(senders);
+    join_all(handles).await;
+}
+
+fn mirror_matches(mirror: &DiscordMirror, msg: &StructuredMessage) -> bool {
+    if msg.message_type != MessageType::PrivMsg || msg.channel_id != mirror.channel_id {
+        return false;
+    }
+
+    if !mirror.users.is_empty()
+        && !mirror
+            .users
+            .iter()
+            .any(|user| *user == *msg.user_id || user.eq_ignore_ascii_case(&msg.user_login))
+    {
+        return false;
+    }
+
+    if !mirror.keywords.is_empty() {
+        let text = msg.user_friendly_text().to_lowercase();
+        if !mirror
+            .keywords
+            .iter()
+            .any(|keyword| text.contains(&keyword.to_lowercase()))
+        {
+            return false;
+        }
+    }
+
+    true
+}
+
+fn format_line(msg: &StructuredMessage) -> String {
+    let time = chrono::DateTime::from_timestamp_millis(msg.timestamp as i64)
+        .map(|timestamp| timestamp.format("%H:%M:%S").to_string())
+        .unwrap_or_default();
+
+    format!(
+        "`[{time}]` **{}**: {}",
+        escape_markdown(msg.display_name()),
+        escape_markdown(&msg.user_friendly_text())
+    )
+}
+
+async fn deliver(http_client: reqwest::Client, url: String, mut rx: mpsc::Receiver<String>) {
+    let mut lines = Vec::new();
+
+    while let Some(line) = rx.recv().await {
+        lines.push(line);
+
+        sleep(BATCH_DELAY).await;
+        while let Ok(line) = rx.try_recv() {
+            lines.push(line);
+        }
+
+        for content in split_content(&lines) {
+            if let Err(err) = post_with_retry(&http_client, &url, &content).await {
+                error!("Could not post to Discord mirror: {err:#}");
+            }
+        }
+        lines.clear();
+    }
+}
+
+/// Joins lines into as few messages as possible, truncating lines which don't fit into a message on their own
+fn split_content(lines: &[String]) -> Vec<String> {
+    let mut contents = Vec::new();
+    let mut current = String::new();
+    let mut current_len = 0;
+
+    for line in lines {
+        let line: String = line.chars().take(MAX_CONTENT_LENGTH).collect();
+        let line_len = line.chars().count();
+
+        if current_len > 0 && current_len + 1 + line_len > MAX_CONTENT_LENGTH {
+            contents.push(std::mem::take(&mut current));
+            current_len = 0;
+        }
+        if current_len > 0 {
+            current.push('\n');
+            current_len += 1;
+        }
+        current.push_str(&line);
+        current_len += line_len;
+    }
+
+    if !current.is_empty() {
+        contents.push(current);
+    }
+    contents
+}
+
+#[derive(Deserialize)]
+struct RateLimitResponse {
+    retry_after: f64,
+}
+
+async fn post_with_retry(
+    http_client: &reqwest::Client,
+    url: &str,
+    content: &str,
+) -> anyhow::Result<()> {
+    let body = serde_json::to_vec(&json!({
+        "content": content,
+        // Never ping anyone mentioned in chat
+        "allowed_mentions": { "parse": [] },
+    }))?;
+
+    for _ in 0..DELIVERY_ATTEMPTS {
+        let response = http_client
+            .post(url)
+            .header(CONTENT_TYPE, "application/json")
+            .body(body.clone())
+            .send()
+            .await
+            .context("Could not send request")?;
+        let status = response.status();
+
+        if status == StatusCode::TOO_MANY_REQUESTS {
+            let header_delay = response
+                .headers()
+                .get(RETRY_AFTER)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<f64>().ok());
+            let retry_after = match header_delay {
+                Some(delay) => delay,
+                None => {
+                    let body = response.bytes().await?;
+                    serde_json::from_slice::<RateLimitResponse>(&body)
+                        .context("Could not deserialize rate limit response")?
+                        .retry_after
+                }
+            };
+
+            warn!("Discord mirror is rate limited, retrying in {retry_after} seconds");
+            sleep(Duration::from_secs_f64(retry_after)).await;
+            continue;
+        }
+        if !status.is_success() {
+            return Err(anyhow!("Webhook responded with status {status}"));
+        }
+
+        // Wait for the bucket to reset instead of running into the rate limit
+        let remaining = response
+            .headers()
+            .get("X-RateLimit-Remaining")
+            .and_then(|value| value.to_str().ok());
+        if remaining == Some("0") {
+            let reset_after = response
+                .headers()
+                .get("X-RateLimit-Reset-After")
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.parse::<f64>().ok())
+                .unwrap_or_default();
+            sleep(Duration::from_secs_f64(reset_after)).await;
+        }
+
+        return Ok(());
+    }
+
+    Err(anyhow!(
+        "Still rate limited after {DELIVERY_ATTEMPTS} attempts"
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{split_content, MAX_CONTENT_LENGTH};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn split_content_by_length() {
+        let lines = vec!["a".repeat(1500), "b".repeat(400), "c".repeat(200)];
+        let contents = split_content(&lines);
+
+        assert_eq!(
+            vec![
+                format!("{}\n{}", "a".repeat(1500), "b".repeat(400)),
+                "c".repeat(200)
+            ],
+            contents
+        );
+    }
+
+    #[test]
+    fn truncate_long_line() {
+        let lines = vec!["a".repeat(2500)];
+        let contents = split_content(&lines);
+
+        assert_eq!(vec!["a".repeat(MAX_CONTENT_LENGTH)], contents);
+    }
+}
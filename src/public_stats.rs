@@ -0,0 +1,112 @@
+//! Periodic export of channel aggregates to JSON files, which are served to public dashboards
+//! without querying the database on every request
+
+use crate::{
+    app::App,
+    config::PublicStatsConfig,
+    db::{
+        pool::QueryClass,
+        stats::{read_daily_message_counts, read_third_party_emote_counts},
+    },
+    ids::ChannelId,
+    web::schema::{PublicChannelStats, RangeParams},
+    ShutdownRx,
+};
+use anyhow::Context;
+use chrono::{Days, Utc};
+use std::{path::Path, time::Duration};
+use tokio::{fs, time::sleep};
+use tracing::{debug, error, info};
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let Some(config) = &app.config.public_stats else {
+        debug!("Public stats export is disabled");
+        shutdown_rx.changed().await.ok();
+        return;
+    };
+    let interval = Duration::from_secs(config.interval);
+
+    loop {
+        if let Err(err) = export_all(&app, config).await {
+            error!("Could not export public stats: {err:#}");
+        }
+
+        tokio::select! {
+            _ = sleep(interval) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down public stats export");
+                break;
+            }
+        }
+    }
+}
+
+async fn export_all(app: &App, config: &PublicStatsConfig) -> anyhow::Result<()> {
+    fs::create_dir_all(&config.dir)
+        .await
+        .with_context(|| format!("Could not create directory {}", config.dir))?;
+
+    let channel_ids: Vec<ChannelId> = app
+        .config
+        .channels
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|channel_id| !app.has_opted_out(channel_id))
+        .map(|channel_id| ChannelId::from(channel_id.as_str()))
+        .collect();
+
+    for channel_id in &channel_ids {
+        // One failing channel should not stop the export of the others
+        if let Err(err) = export_channel(app, config, channel_id).await {
+            error!("Could not export public stats of channel {channel_id}: {err:#}");
+        }
+    }
+    info!("Exported public stats of {} channels", channel_ids.len());
+
+    Ok(())
+}
+
+async fn export_channel(
+    app: &App,
+    config: &PublicStatsConfig,
+    channel_id: &ChannelId,
+) -> anyhow::Result<()> {
+    let now = Utc::now();
+    let params = RangeParams {
+        from: now - Days::new(config.days.into()),
+        to: now,
+    };
+    let excluded = app.stats_excluded_users(false);
+
+    let stats = PublicChannelStats {
+        channel_id: channel_id.to_string(),
+        generated_at: now,
+        days: read_daily_message_counts(
+            &app.db.profile(QueryClass::Stats),
+            channel_id,
+            params,
+            excluded,
+        )
+        .await?,
+        top_emotes: read_third_party_emote_counts(
+            &app.db.profile(QueryClass::Stats),
+            channel_id,
+            None,
+            params,
+            excluded,
+            config.top_emotes,
+        )
+        .await?,
+    };
+    let body = serde_json::to_vec(&stats)?;
+
+    // Written to a temporary file first, so a file is never served while it is partially written
+    let dir = Path::new(&config.dir);
+    let path = dir.join(format!("{channel_id}.json"));
+    let tmp_path = dir.join(format!("{channel_id}.json.tmp"));
+    fs::write(&tmp_path, body).await?;
+    fs::rename(&tmp_path, &path).await?;
+
+    Ok(())
+}
@@ -0,0 +1,124 @@
+use crate::{
+    app::App,
+    config::{url_without_userinfo, PublishConfig, PublishTarget},
+    db::schema::StructuredMessage,
+    logs::schema::message::{FullMessage, ResponseMessage},
+    ShutdownRx,
+};
+use anyhow::Context;
+use rumqttc::{AsyncClient, MqttOptions, QoS};
+use std::{sync::Arc, time::Duration};
+use tokio::sync::broadcast::error::RecvError;
+use tracing::{debug, error, info, warn};
+
+/// Maximum amount of MQTT requests waiting to be sent by the event loop
+const MQTT_CAPACITY: usize = 1000;
+
+enum Publisher {
+    Mqtt(AsyncClient),
+    Nats(async_nats::Client),
+}
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    let Some(target) = &app.config.publish.target else {
+        debug!("No publish target configured, publisher is idle");
+        shutdown_rx.changed().await.ok();
+        return;
+    };
+
+    // Subscribe before connecting, so no messages are missed while connecting
+    let mut live_rx = app.live_tx.subscribe();
+
+    let publisher = match connect(target).await {
+        Ok(publisher) => publisher,
+        Err(err) => {
+            error!("Could not connect publisher: {err:#}");
+            shutdown_rx.changed().await.ok();
+            return;
+        }
+    };
+    info!(
+        "Publishing logged messages to {}",
+        url_without_userinfo(target.url())
+    );
+
+    loop {
+        tokio::select! {
+            result = live_rx.recv() => match result {
+                Ok(msg) => {
+                    if let Err(err) = publish(&app.config.publish, &publisher, &msg).await {
+                        error!("Could not publish message: {err:#}");
+                    }
+                }
+                Err(RecvError::Lagged(count)) => {
+                    warn!("Publisher is falling behind, skipped {count} messages");
+                }
+                Err(RecvError::Closed) => break,
+            },
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down publisher");
+                break;
+            }
+        }
+    }
+}
+
+async fn connect(target: &PublishTarget) -> anyhow::Result<Publisher> {
+    match target {
+        PublishTarget::Mqtt { url } => {
+            let mut options = MqttOptions::parse_url(url.expose()).context("Invalid MQTT url")?;
+            options.set_keep_alive(Duration::from_secs(30));
+
+            let (client, mut event_loop) = AsyncClient::new(options, MQTT_CAPACITY);
+            // The event loop has to be polled to send messages, it reconnects on its own when polled again after an error
+            tokio::spawn(async move {
+                loop {
+                    if let Err(err) = event_loop.poll().await {
+                        warn!("MQTT connection error: {err}");
+                        tokio::time::sleep(Duration::from_secs(5)).await;
+                    }
+                }
+            });
+
+            Ok(Publisher::Mqtt(client))
+        }
+        PublishTarget::Nats { url } => {
+            let client = async_nats::connect(url.expose())
+                .await
+                .context("Could not connect to NATS")?;
+            Ok(Publisher::Nats(client))
+        }
+    }
+}
+
+async fn publish(
+    config: &PublishConfig,
+    publisher: &Publisher,
+    msg: &Arc<StructuredMessage<'static>>,
+) -> anyhow::Result<()> {
+    if !config.channels.is_empty() && !config.channels.contains(&*msg.channel_id) {
+        return Ok(());
+    }
+    if let Some(types) = &config.message_types {
+        if !types.contains(&msg.message_type) {
+            return Ok(());
+        }
+    }
+
+    let payload = serde_json::to_vec(&FullMessage::from_structured(msg)?)?;
+
+    match publisher {
+        Publisher::Mqtt(client) => {
+            let topic = format!("{}/{}", config.prefix, msg.channel_login);
+            client
+                .publish(topic, QoS::AtMostOnce, false, payload)
+                .await?;
+        }
+        Publisher::Nats(client) => {
+            let subject = format!("{}.{}", config.prefix, msg.channel_login);
+            client.publish(subject, payload.into()).await?;
+        }
+    }
+
+    Ok(())
+}
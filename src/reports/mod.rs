@@ -0,0 +1,187 @@
+pub mod webhook;
+
+use self::webhook::deliver_report;
+use crate::{
+    app::App,
+    db::{
+        reports::{report_exists, write_report},
+        stats::{read_channel_summary, read_top_chatters},
+    },
+    ids::ChannelId,
+    web::schema::{ChannelReport, RangeParams, ReportPeriod},
+    ShutdownRx,
+};
+use anyhow::Context;
+use chrono::{DateTime, Datelike, Days, Months, NaiveDate, NaiveTime, Utc};
+use std::time::Duration;
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    if app.config.reports.periods.is_empty() {
+        debug!("No reports configured, report scheduler is idle");
+        shutdown_rx.changed().await.ok();
+        return;
+    }
+
+    let interval = Duration::from_secs(app.config.reports.interval);
+    let http_client = reqwest::Client::new();
+
+    loop {
+        if let Err(err) = generate_reports(&app, &http_client).await {
+            error!("Could not generate reports: {err:#}");
+        }
+
+        tokio::select! {
+            _ = sleep(interval) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down report scheduler");
+                break;
+            }
+        }
+    }
+}
+
+async fn generate_reports(app: &App, http_client: &reqwest::Client) -> anyhow::Result<()> {
+    let channel_ids = app.config.channels.read().unwrap().clone();
+    let now = Utc::now();
+
+    for period in &app.config.reports.periods {
+        let range = last_completed_range(*period, now).context("Invalid report range")?;
+
+        for channel_id in &channel_ids {
+            if app.has_opted_out(channel_id) {
+                continue;
+            }
+            let channel_id = ChannelId::from(channel_id.as_str());
+
+            // A failing channel does not hold back the reports of the others
+            if let Err(err) =
+                generate_channel_report(app, http_client, &channel_id, *period, range).await
+            {
+                error!("Could not generate {period} report for channel {channel_id}: {err:#}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+async fn generate_channel_report(
+    app: &App,
+    http_client: &reqwest::Client,
+    channel_id: &ChannelId,
+    period: ReportPeriod,
+    range: RangeParams,
+) -> anyhow::Result<()> {
+    if report_exists(&app.db, channel_id, period, range.from.timestamp() as u32).await? {
+        return Ok(());
+    }
+
+    let report = generate_report(app, channel_id, period, range).await?;
+    write_report(&app.db, channel_id, &report).await?;
+    info!("Generated {period} report for channel {channel_id}");
+
+    if let Err(err) = deliver_report(app, http_client, channel_id.as_str(), &report).await {
+        error!("Could not deliver {period} report for channel {channel_id}: {err:#}");
+    }
+
+    Ok(())
+}
+
+async fn generate_report(
+    app: &App,
+    channel_id: &ChannelId,
+    period: ReportPeriod,
+    range: RangeParams,
+) -> anyhow::Result<ChannelReport> {
+    let excluded_users = app.stats_excluded_users(false);
+    let summary = read_channel_summary(
+        &app.db,
+        channel_id,
+        range,
+        excluded_users,
+        true,
+        &app.flush_buffer,
+    )
+    .await?;
+    let top_chatters = read_top_chatters(
+        &app.db,
+        channel_id,
+        range,
+        excluded_users,
+        app.config.reports.top_chatters,
+        0,
+        &app.flush_buffer,
+    )
+    .await?;
+
+    Ok(ChannelReport {
+        period,
+        from: range.from,
+        to: range.to,
+        generated_at: Utc::now(),
+        summary,
+        top_chatters,
+    })
+}
+
+/// The most recent period which has fully ended before `now`
+fn last_completed_range(period: ReportPeriod, now: DateTime<Utc>) -> Option<RangeParams> {
+    let today = now.date_naive();
+
+    let (from, to) = match period {
+        ReportPeriod::Weekly => {
+            let to =
+                today.checked_sub_days(Days::new(today.weekday().num_days_from_monday().into()))?;
+            let from = to.checked_sub_days(Days::new(7))?;
+            (from, to)
+        }
+        ReportPeriod::Monthly => {
+            let to = NaiveDate::from_ymd_opt(today.year(), today.month(), 1)?;
+            let from = to.checked_sub_months(Months::new(1))?;
+            (from, to)
+        }
+    };
+
+    Some(RangeParams {
+        from: from.and_time(NaiveTime::default()).and_utc(),
+        to: to.and_time(NaiveTime::default()).and_utc(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::last_completed_range;
+    use crate::web::schema::ReportPeriod;
+    use chrono::{TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn weekly_range_ends_on_monday() {
+        // Thursday
+        let now = Utc.with_ymd_and_hms(2024, 5, 16, 12, 30, 0).unwrap();
+        let range = last_completed_range(ReportPeriod::Weekly, now).unwrap();
+
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 5, 6, 0, 0, 0).unwrap(),
+            range.from
+        );
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 5, 13, 0, 0, 0).unwrap(),
+            range.to
+        );
+    }
+
+    #[test]
+    fn monthly_range_crosses_year() {
+        let now = Utc.with_ymd_and_hms(2024, 1, 3, 0, 0, 0).unwrap();
+        let range = last_completed_range(ReportPeriod::Monthly, now).unwrap();
+
+        assert_eq!(
+            Utc.with_ymd_and_hms(2023, 12, 1, 0, 0, 0).unwrap(),
+            range.from
+        );
+        assert_eq!(Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap(), range.to);
+    }
+}
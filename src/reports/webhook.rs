@@ -0,0 +1,176 @@
+use crate::{
+    app::App,
+    config::{ReportWebhook, ReportWebhookFormat},
+    db::reports::{write_report_delivery, ReportDelivery},
+    web::schema::{ChannelReport, ReportPeriod},
+};
+use anyhow::{anyhow, Context};
+use chrono::Utc;
+use reqwest::header::CONTENT_TYPE;
+use serde_json::json;
+use std::{fmt::Write, time::Duration};
+use tokio::time::sleep;
+use tracing::{debug, error, warn};
+
+const DELIVERY_ATTEMPTS: u8 = 5;
+const INITIAL_BACKOFF_SECONDS: u64 = 2;
+const DATE_FORMAT: &str = "%Y-%m-%d";
+/// Discord rejects messages longer than 2000 characters
+const DISCORD_TOP_CHATTERS: usize = 10;
+
+pub async fn deliver_report(
+    app: &App,
+    http_client: &reqwest::Client,
+    channel_id: &str,
+    report: &ChannelReport,
+) -> anyhow::Result<()> {
+    let Some(webhooks) = app.config.reports.webhooks.get(channel_id) else {
+        return Ok(());
+    };
+
+    let channel_login = app
+        .get_users(vec![channel_id.to_owned()], vec![], false)
+        .await?
+        .remove(channel_id)
+        .unwrap_or_else(|| channel_id.to_owned());
+
+    for webhook in webhooks {
+        let body = render_body(webhook.format, &channel_login, report)?;
+        let (attempts, result) = send_with_retry(http_client, webhook, body).await;
+
+        match &result {
+            Ok(()) => debug!("Delivered {} report for {channel_login}", report.period),
+            Err(err) => error!(
+                "Could not deliver {} report for {channel_login}: {err:#}",
+                report.period
+            ),
+        }
+
+        let delivery = ReportDelivery {
+            channel_id: channel_id.to_owned(),
+            period: report.period.to_string(),
+            period_start: report.from.timestamp() as u32,
+            url: webhook.url.expose().clone(),
+            attempts,
+            success: result.is_ok(),
+            error: result
+                .err()
+                .map(|err| format!("{err:#}"))
+                .unwrap_or_default(),
+            delivered_at: Utc::now().timestamp() as u32,
+        };
+        write_report_delivery(&app.db, &delivery).await?;
+    }
+
+    Ok(())
+}
+
+async fn send_with_retry(
+    http_client: &reqwest::Client,
+    webhook: &ReportWebhook,
+    body: Vec<u8>,
+) -> (u8, anyhow::Result<()>) {
+    let mut backoff = Duration::from_secs(INITIAL_BACKOFF_SECONDS);
+    let mut attempt = 1;
+
+    loop {
+        match send(http_client, webhook, body.clone()).await {
+            Ok(()) => return (attempt, Ok(())),
+            Err(err) if attempt >= DELIVERY_ATTEMPTS => return (attempt, Err(err)),
+            Err(err) => {
+                warn!(
+                    "Webhook delivery failed: {err:#} (attempt {attempt}/{DELIVERY_ATTEMPTS}, retrying in {} seconds)",
+                    backoff.as_secs()
+                );
+                sleep(backoff).await;
+                backoff *= 2;
+                attempt += 1;
+            }
+        }
+    }
+}
+
+async fn send(
+    http_client: &reqwest::Client,
+    webhook: &ReportWebhook,
+    body: Vec<u8>,
+) -> anyhow::Result<()> {
+    let response = http_client
+        .post(webhook.url.expose())
+        .header(CONTENT_TYPE, "application/json")
+        .body(body)
+        .send()
+        .await
+        .context("Could not send request")?;
+
+    let status = response.status();
+    if status.is_success() {
+        Ok(())
+    } else {
+        Err(anyhow!("Webhook responded with status {status}"))
+    }
+}
+
+fn render_body(
+    format: ReportWebhookFormat,
+    channel_login: &str,
+    report: &ChannelReport,
+) -> anyhow::Result<Vec<u8>> {
+    let body = match format {
+        ReportWebhookFormat::Json => serde_json::to_vec(report)?,
+        ReportWebhookFormat::Discord => {
+            let content = render_discord_message(channel_login, report);
+            serde_json::to_vec(&json!({ "content": content }))?
+        }
+    };
+    Ok(body)
+}
+
+fn render_discord_message(channel_login: &str, report: &ChannelReport) -> String {
+    let mut out = String::new();
+
+    let period = match report.period {
+        ReportPeriod::Weekly => "Weekly",
+        ReportPeriod::Monthly => "Monthly",
+    };
+    let _ = writeln!(
+        out,
+        "**{period} report for #{}** ({} to {})",
+        escape_markdown(channel_login),
+        report.from.format(DATE_FORMAT),
+        report.to.format(DATE_FORMAT),
+    );
+    let _ = writeln!(out, "Messages: {}", report.summary.message_count);
+    let _ = writeln!(out, "Unique chatters: {}", report.summary.unique_chatters);
+
+    if !report.top_chatters.is_empty() {
+        let _ = writeln!(out, "Top chatters:");
+        for (i, chatter) in report
+            .top_chatters
+            .iter()
+            .take(DISCORD_TOP_CHATTERS)
+            .enumerate()
+        {
+            let _ = writeln!(
+                out,
+                "{}. {} ({})",
+                i + 1,
+                escape_markdown(&chatter.user_login),
+                chatter.message_count
+            );
+        }
+    }
+
+    out
+}
+
+pub fn escape_markdown(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for char in value.chars() {
+        if matches!(char, '_' | '*' | '~' | '`' | '|' | '\\') {
+            out.push('\\');
+        }
+        out.push(char);
+    }
+    out
+}
@@ -0,0 +1,118 @@
+use crate::{
+    app::{
+        cache_invalidation::{invalidate_cached_logs, read_cached_expired_ranges},
+        App,
+    },
+    db::retention::{delete_messages_before, drop_partitions_before, RetentionScope},
+    ShutdownRx,
+};
+use chrono::{DateTime, Days, Utc};
+use std::{collections::HashMap, time::Duration};
+use tokio::time::sleep;
+use tracing::{debug, error, info};
+
+/// Deletions are mutations which rewrite whole parts, so they are only started once a day
+const CLEANUP_INTERVAL_SECONDS: u64 = 24 * 3600;
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    loop {
+        // Channel retentions can be set through the admin API, so it's checked every time
+        if app.config.retention.is_enabled() {
+            if let Err(err) = apply_retention(&app).await {
+                error!("Could not delete expired messages: {err:#}");
+            }
+        }
+
+        tokio::select! {
+            _ = sleep(Duration::from_secs(CLEANUP_INTERVAL_SECONDS)) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down retention task");
+                break;
+            }
+        }
+    }
+}
+
+async fn apply_retention(app: &App) -> anyhow::Result<()> {
+    let retention = &app.config.retention;
+    let legal_holds = &app.config.legal_holds;
+    let db = &app.db.primary();
+    let now = Utc::now();
+
+    // Channels under legal hold and archived channels are treated like channels whose messages are kept forever,
+    // no partitions are dropped while anything is held or archived
+    let archived = app.config.archived_channels.read().unwrap().clone();
+    let held_users = legal_holds.user_ids();
+    let mut overridden = legal_holds.channel_ids();
+    overridden.extend(archived.iter().cloned());
+    let mut channels_by_days: HashMap<u32, Vec<String>> = HashMap::new();
+    let mut kept_forever = !legal_holds.is_empty() || !archived.is_empty();
+    for entry in retention.channels.iter() {
+        if legal_holds.channels.contains_key(entry.key()) || archived.contains(entry.key()) {
+            continue;
+        }
+        overridden.push(entry.key().clone());
+        match *entry.value() {
+            Some(days) => channels_by_days
+                .entry(days)
+                .or_default()
+                .push(entry.key().clone()),
+            None => kept_forever = true,
+        }
+    }
+
+    let mut expirations = Vec::new();
+    if let Some(default_days) = retention.default_days {
+        if let Some(before) = cutoff(now, default_days) {
+            expirations.push((RetentionScope::AllExcept(&overridden), before, default_days));
+        }
+    }
+    for (days, channel_ids) in &channels_by_days {
+        if let Some(before) = cutoff(now, *days) {
+            expirations.push((RetentionScope::Channels(channel_ids), before, *days));
+        }
+    }
+
+    // Cached days of the expired messages are invalidated once they are deleted,
+    // which has to be read before any of them are dropped with their partitions
+    let mut cached_ranges = Vec::new();
+    for (scope, before, _) in &expirations {
+        cached_ranges.extend(read_cached_expired_ranges(app, *scope, &held_users, *before).await?);
+    }
+
+    if let Some(default_days) = retention.default_days {
+        // Whole partitions can be dropped, which is much cheaper than a mutation,
+        // once they are older than the longest retention of any channel
+        if !kept_forever {
+            let max_days = channels_by_days
+                .keys()
+                .copied()
+                .fold(default_days, u32::max);
+            if let Some(before) = cutoff(now, max_days) {
+                let dropped = drop_partitions_before(db, before).await?;
+                if !dropped.is_empty() {
+                    info!("Dropped expired partitions {dropped:?}");
+                }
+            }
+        }
+    }
+
+    for (scope, before, days) in expirations {
+        if delete_messages_before(db, scope, &held_users, before).await? {
+            match scope {
+                RetentionScope::AllExcept(_) => info!("Deleting messages older than {days} days"),
+                RetentionScope::Channels(channel_ids) => {
+                    info!("Deleting messages older than {days} days in channels {channel_ids:?}")
+                }
+            }
+        }
+    }
+    invalidate_cached_logs(app, cached_ranges, true);
+
+    Ok(())
+}
+
+/// No messages are old enough to expire if the retention reaches before the earliest date
+fn cutoff(now: DateTime<Utc>, days: u32) -> Option<DateTime<Utc>> {
+    now.checked_sub_days(Days::new(days.into()))
+}
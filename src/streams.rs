@@ -0,0 +1,272 @@
+use crate::{
+    app::{helix_queue::HelixPriority, record_helix_request, App},
+    db::streams::{read_stream_ids, read_unended_streams, write_streams, StreamRow},
+    ids::ChannelId,
+    web::schema::StreamBackfill,
+    Result, ShutdownRx,
+};
+use anyhow::Context;
+use chrono::{DateTime, Utc};
+use std::{collections::HashMap, time::Duration};
+use tokio::time::sleep;
+use tracing::{debug, error};
+use twitch_api::helix::{
+    streams::GetStreamsRequest,
+    videos::{GetVideosRequest, VideoTypeFilter},
+};
+
+/// Maximum page size of the Helix streams endpoint
+const STREAMS_PER_REQUEST: usize = 100;
+/// The VOD of a live stream is the channel's newest video, unless a highlight was created since
+const VIDEOS_PER_REQUEST: usize = 5;
+/// Maximum page size of the Helix videos endpoint
+const BACKFILL_VIDEOS_PER_REQUEST: usize = 100;
+
+pub async fn run(app: App, mut shutdown_rx: ShutdownRx) {
+    if !app.config.streams.enabled {
+        debug!("Stream polling is disabled");
+        shutdown_rx.changed().await.ok();
+        return;
+    }
+
+    let interval = Duration::from_secs(app.config.streams.interval);
+    // Streams of the last poll by id, to detect when they end and to only look up their VODs once
+    let mut live_streams = match read_unended_streams(&app.db).await {
+        Ok(streams) => streams
+            .into_iter()
+            .map(|stream| (stream.stream_id.clone(), stream))
+            .collect(),
+        Err(err) => {
+            error!("Could not read unended streams: {err}");
+            HashMap::new()
+        }
+    };
+
+    loop {
+        let poll = poll_streams(&app, &mut live_streams);
+        if let Err(err) = HelixPriority::Background.scope(poll).await {
+            error!("Could not poll streams: {err:#}");
+        }
+
+        tokio::select! {
+            _ = sleep(interval) => (),
+            _ = shutdown_rx.changed() => {
+                debug!("Shutting down stream poller");
+                break;
+            }
+        }
+    }
+}
+
+/// Only the joined channels are polled, so the Helix usage grows with the amount of channels instead of
+/// walking every live stream on Twitch. Opted out channels are skipped, their logs are not served anyway
+async fn poll_streams(
+    app: &App,
+    live_streams: &mut HashMap<String, StreamRow>,
+) -> anyhow::Result<()> {
+    let channel_ids: Vec<String> = app
+        .config
+        .channels
+        .read()
+        .unwrap()
+        .iter()
+        .filter(|channel_id| !app.has_opted_out(channel_id))
+        .cloned()
+        .collect();
+    let now = Utc::now().timestamp() as u32;
+
+    let mut streams = Vec::new();
+    for chunk in channel_ids.chunks(STREAMS_PER_REQUEST) {
+        let mut request = GetStreamsRequest::user_ids(chunk);
+        request.first = Some(STREAMS_PER_REQUEST);
+
+        let response = app.helix_client.req_get(request, &*app.token).await;
+        record_helix_request("streams", &response);
+        let response = response?;
+
+        for stream in response.data {
+            let started_at = DateTime::parse_from_rfc3339(stream.started_at.as_str())
+                .context("Invalid stream start date")?;
+
+            streams.push(StreamRow {
+                stream_id: stream.id.to_string(),
+                channel_id: stream.user_id.to_string(),
+                started_at: started_at.timestamp() as u32,
+                ended_at: now,
+                title: stream.title,
+                game_name: stream.game_name,
+                video_id: String::new(),
+                ended: false,
+                updated_at: now,
+            });
+        }
+    }
+
+    for stream in &mut streams {
+        let previous = live_streams.get(&stream.stream_id);
+        if let Some(previous) = previous.filter(|previous| !previous.video_id.is_empty()) {
+            stream.video_id.clone_from(&previous.video_id);
+            continue;
+        }
+
+        // The VOD may only be listed a while after the stream started, or never if the channel disabled them
+        match find_video_id(app, &stream.channel_id, &stream.stream_id).await {
+            Ok(Some(video_id)) => stream.video_id = video_id,
+            Ok(None) => (),
+            Err(err) => error!(
+                "Could not look up VOD of stream {}: {err}",
+                stream.stream_id
+            ),
+        }
+    }
+
+    // Streams of the last poll which are missing now have ended, `ended_at` stays the last time they were seen live
+    let ended_streams: Vec<StreamRow> = live_streams
+        .values()
+        .filter(|previous| {
+            !streams
+                .iter()
+                .any(|stream| stream.stream_id == previous.stream_id)
+        })
+        .map(|previous| StreamRow {
+            ended: true,
+            updated_at: now,
+            ..previous.clone()
+        })
+        .collect();
+
+    debug!(
+        "{} of {} channels are live, {} streams ended",
+        streams.len(),
+        channel_ids.len(),
+        ended_streams.len()
+    );
+    write_streams(&app.db, &streams).await?;
+    write_streams(&app.db, &ended_streams).await?;
+
+    *app.live_channels.write().unwrap() = streams
+        .iter()
+        .map(|stream| stream.channel_id.clone())
+        .collect();
+    *live_streams = streams
+        .into_iter()
+        .map(|stream| (stream.stream_id.clone(), stream))
+        .collect();
+
+    Ok(())
+}
+
+async fn find_video_id(
+    app: &App,
+    channel_id: &str,
+    stream_id: &str,
+) -> anyhow::Result<Option<String>> {
+    let mut request = GetVideosRequest::user_id(channel_id);
+    request.first = Some(VIDEOS_PER_REQUEST);
+
+    let response = app.helix_client.req_get(request, &*app.token).await;
+    record_helix_request("videos", &response);
+
+    let video_id = response?
+        .data
+        .into_iter()
+        .find(|video| {
+            video
+                .stream_id
+                .as_ref()
+                .is_some_and(|id| id.as_str() == stream_id)
+        })
+        .map(|video| video.id.to_string());
+    Ok(video_id)
+}
+
+/// Imports the channel's past broadcasts which are still available as VODs, so streams from before it
+/// was polled can be used too. The game of these streams is not known
+pub async fn backfill_streams(app: &App, channel_id: &ChannelId) -> Result<StreamBackfill> {
+    let known_ids = read_stream_ids(&app.db, channel_id).await?;
+
+    let mut request = GetVideosRequest::user_id(channel_id.as_str());
+    request.first = Some(BACKFILL_VIDEOS_PER_REQUEST);
+    request.type_ = Some(VideoTypeFilter::Archive);
+
+    let response = app.helix_client.req_get(request, &*app.token).await;
+    record_helix_request("videos", &response);
+    let mut page = Some(response?);
+
+    let mut streams = Vec::new();
+    let mut skipped = 0;
+    while let Some(response) = page {
+        for video in &response.data {
+            let Some(stream_id) = &video.stream_id else {
+                continue;
+            };
+            let started_at = DateTime::parse_from_rfc3339(video.created_at.as_str());
+            let duration = parse_video_duration(&video.duration);
+            let (Ok(started_at), Some(duration)) = (started_at, duration) else {
+                continue;
+            };
+            if known_ids.contains(stream_id.as_str()) {
+                skipped += 1;
+                continue;
+            }
+
+            let started_at = started_at.timestamp() as u32;
+            let ended_at = started_at + duration;
+            streams.push(StreamRow {
+                stream_id: stream_id.to_string(),
+                channel_id: channel_id.to_string(),
+                started_at,
+                ended_at,
+                title: video.title.clone(),
+                game_name: String::new(),
+                video_id: video.id.to_string(),
+                ended: true,
+                // Older than the rows of the poller, so they win if it records the stream as well
+                updated_at: ended_at,
+            });
+        }
+
+        let next = response.get_next(&app.helix_client, &*app.token).await;
+        record_helix_request("videos", &next);
+        page = next?;
+    }
+
+    write_streams(&app.db, &streams).await?;
+
+    Ok(StreamBackfill {
+        imported: streams.len(),
+        skipped,
+    })
+}
+
+/// Seconds of a video duration like `3h8m33s`
+fn parse_video_duration(duration: &str) -> Option<u32> {
+    let mut seconds = 0;
+    let mut value = 0;
+    for c in duration.chars() {
+        match c {
+            '0'..='9' => value = value * 10 + c.to_digit(10)?,
+            'h' => seconds += std::mem::take(&mut value) * 3600,
+            'm' => seconds += std::mem::take(&mut value) * 60,
+            's' => seconds += std::mem::take(&mut value),
+            _ => return None,
+        }
+    }
+
+    (value == 0).then_some(seconds)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_video_duration;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_video_durations() {
+        assert_eq!(parse_video_duration("3h8m33s"), Some(11313));
+        assert_eq!(parse_video_duration("45s"), Some(45));
+        assert_eq!(parse_video_duration("12m0s"), Some(720));
+        assert_eq!(parse_video_duration("1d"), None);
+        assert_eq!(parse_video_duration("12"), None);
+    }
+}
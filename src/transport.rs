@@ -0,0 +1,162 @@
+//! TLS transport for the IRC client which reads lines as bytes. The default transport rejects lines which are not
+//! valid UTF-8 and reconnects, so they would be lost. Here they are sanitized instead, and the original bytes are
+//! passed on to the bot in a tag
+
+use crate::logs::sanitize::sanitize_line;
+use async_trait::async_trait;
+use either::Either;
+use futures::{
+    sink,
+    stream::{self, FusedStream},
+    Sink, StreamExt,
+};
+use std::{fmt, io, pin::Pin, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader, ReadHalf, WriteHalf},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    client::TlsStream,
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+use twitch_irc::{
+    message::{AsRawIRC, IRCMessage, IRCParseError},
+    transport::Transport,
+};
+
+const IRC_HOST: &str = "irc.chat.twitch.tv";
+const IRC_PORT: u16 = 6697;
+/// Hex encoded original bytes of lines which had to be sanitized. Removed again by the bot before the line is stored
+const ORIGINAL_BYTES_TAG: &str = "rustlog-original-bytes";
+
+type Incoming = Pin<
+    Box<dyn FusedStream<Item = Result<IRCMessage, Either<io::Error, IRCParseError>>> + Send + Sync>,
+>;
+type Outgoing = Pin<Box<dyn Sink<IRCMessage, Error = io::Error> + Send + Sync>>;
+
+pub struct SanitizingTransport {
+    incoming: Incoming,
+    outgoing: Outgoing,
+}
+
+impl fmt::Debug for SanitizingTransport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SanitizingTransport")
+            .finish_non_exhaustive()
+    }
+}
+
+#[async_trait]
+impl Transport for SanitizingTransport {
+    type ConnectError = io::Error;
+    type IncomingError = io::Error;
+    type OutgoingError = io::Error;
+    type Incoming = Incoming;
+    type Outgoing = Outgoing;
+
+    async fn new() -> Result<Self, Self::ConnectError> {
+        let (read_half, write_half) = tokio::io::split(connect().await?);
+
+        Ok(Self {
+            incoming: Box::pin(incoming(read_half).fuse()),
+            outgoing: Box::pin(outgoing(write_half)),
+        })
+    }
+
+    fn split(self) -> (Self::Incoming, Self::Outgoing) {
+        (self.incoming, self.outgoing)
+    }
+}
+
+async fn connect() -> io::Result<TlsStream<TcpStream>> {
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let config = ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let stream = TcpStream::connect((IRC_HOST, IRC_PORT)).await?;
+    let server_name = ServerName::try_from(IRC_HOST)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+    TlsConnector::from(Arc::new(config))
+        .connect(server_name, stream)
+        .await
+}
+
+fn incoming(
+    read_half: ReadHalf<TlsStream<TcpStream>>,
+) -> impl stream::Stream<Item = Result<IRCMessage, Either<io::Error, IRCParseError>>> {
+    stream::unfold(BufReader::new(read_half), |mut reader| async move {
+        let mut line = Vec::new();
+        match reader.read_until(b'\n', &mut line).await {
+            Ok(0) => None,
+            Ok(_) => Some((parse_line(&line).map_err(Either::Right), reader)),
+            Err(err) => Some((Err(Either::Left(err)), reader)),
+        }
+    })
+}
+
+fn outgoing(
+    write_half: WriteHalf<TlsStream<TcpStream>>,
+) -> impl Sink<IRCMessage, Error = io::Error> {
+    sink::unfold(write_half, |mut writer, message: IRCMessage| async move {
+        let mut line = message.as_raw_irc();
+        line.push_str("\r\n");
+        writer.write_all(line.as_bytes()).await?;
+        writer.flush().await?;
+        Ok(writer)
+    })
+}
+
+fn parse_line(line: &[u8]) -> Result<IRCMessage, IRCParseError> {
+    let line = line.strip_suffix(b"\n").unwrap_or(line);
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+
+    let sanitized = sanitize_line(line);
+    let mut message = IRCMessage::parse(&sanitized.text)?;
+    if let Some(original) = sanitized.original {
+        let encoded = original.iter().map(|byte| format!("{byte:02x}")).collect();
+        message
+            .tags
+            .0
+            .insert(ORIGINAL_BYTES_TAG.to_owned(), encoded);
+    }
+
+    Ok(message)
+}
+
+/// Removes the original bytes added by the transport from the message, so they are not part of the stored line
+pub fn take_original_bytes(message: &mut IRCMessage) -> Option<Vec<u8>> {
+    let encoded = message.tags.0.remove(ORIGINAL_BYTES_TAG)?;
+
+    (0..encoded.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(encoded.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_line, take_original_bytes};
+    use pretty_assertions::assert_eq;
+    use twitch_irc::message::AsRawIRC;
+
+    #[test]
+    fn keeps_original_bytes_of_invalid_lines() {
+        let raw = b"@id=1 :a!a@a.tmi.twitch.tv PRIVMSG #forsen :a\xFFb\r\n";
+        let mut message = parse_line(raw).unwrap();
+
+        assert_eq!(
+            Some(raw[..raw.len() - 2].to_vec()),
+            take_original_bytes(&mut message)
+        );
+        assert_eq!(
+            "@id=1 :a!a@a.tmi.twitch.tv PRIVMSG #forsen :a\u{FFFD}b",
+            message.as_raw_irc()
+        );
+
+        let mut message = parse_line(b":a!a@a.tmi.twitch.tv PRIVMSG #forsen :ab\r\n").unwrap();
+        assert_eq!(None, take_original_bytes(&mut message));
+    }
+}
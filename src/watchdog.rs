@@ -0,0 +1,156 @@
+//! Liveness of the tasks which have to keep running for messages to be logged. It is reported to the systemd
+//! watchdog and the readiness endpoint, so a deadlocked bot or writer gets the process restarted
+
+use chrono::Utc;
+use sd_notify::NotifyState;
+use std::{
+    sync::{
+        atomic::{AtomicI64, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+use tokio::time::interval;
+use tracing::{error, info, warn};
+
+/// How often tasks which otherwise only wait for events set their heartbeat
+pub const HEARTBEAT_INTERVAL_SECONDS: u64 = 10;
+
+/// Set by the last iteration of a task's loop
+#[derive(Clone)]
+pub struct Heartbeat(Arc<AtomicI64>);
+
+impl Default for Heartbeat {
+    fn default() -> Self {
+        Self(Arc::new(AtomicI64::new(Utc::now().timestamp())))
+    }
+}
+
+impl Heartbeat {
+    pub fn beat(&self) {
+        self.0.store(Utc::now().timestamp(), Ordering::Relaxed);
+    }
+
+    pub fn seconds_since(&self) -> u64 {
+        (Utc::now().timestamp() - self.0.load(Ordering::Relaxed)).max(0) as u64
+    }
+}
+
+pub struct TaskLiveness {
+    pub name: &'static str,
+    pub seconds_since_heartbeat: u64,
+    pub alive: bool,
+}
+
+#[derive(Clone)]
+pub struct Liveness {
+    tasks: Arc<[(&'static str, Heartbeat)]>,
+    /// Seconds without a heartbeat after which a task is considered stuck
+    timeout: u64,
+}
+
+impl Liveness {
+    pub fn new(tasks: Vec<(&'static str, Heartbeat)>, timeout: u64) -> Self {
+        Self {
+            tasks: tasks.into(),
+            timeout,
+        }
+    }
+
+    pub fn tasks(&self) -> Vec<TaskLiveness> {
+        self.tasks
+            .iter()
+            .map(|(name, heartbeat)| {
+                let seconds_since_heartbeat = heartbeat.seconds_since();
+                TaskLiveness {
+                    name,
+                    seconds_since_heartbeat,
+                    alive: seconds_since_heartbeat < self.timeout,
+                }
+            })
+            .collect()
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.tasks().iter().all(|task| task.alive)
+    }
+}
+
+/// Does nothing if the process was not started by systemd with `Type=notify`
+pub fn notify_ready() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Ready]) {
+        warn!("Could not notify systemd of the startup: {err}");
+    }
+}
+
+pub fn notify_stopping() {
+    if let Err(err) = sd_notify::notify(false, &[NotifyState::Stopping]) {
+        warn!("Could not notify systemd of the shutdown: {err}");
+    }
+}
+
+/// Pings the systemd watchdog while all tasks are alive. Never returns, pending forever if the watchdog is not enabled
+pub async fn run(liveness: Liveness) {
+    let mut watchdog_usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut watchdog_usec) {
+        return std::future::pending().await;
+    }
+    info!(
+        "Pinging the systemd watchdog, the process is restarted if a task is stuck for {}s",
+        liveness.timeout
+    );
+
+    // systemd recommends pinging at half of the watchdog interval
+    let mut ticker = interval(Duration::from_micros(watchdog_usec / 2));
+    loop {
+        ticker.tick().await;
+
+        let stuck: Vec<_> = liveness
+            .tasks()
+            .into_iter()
+            .filter(|task| !task.alive)
+            .map(|task| task.name)
+            .collect();
+
+        let result = if stuck.is_empty() {
+            sd_notify::notify(false, &[NotifyState::Watchdog])
+        } else {
+            let status = format!("Stuck tasks: {}", stuck.join(", "));
+            error!("{status}, no longer pinging the systemd watchdog");
+            sd_notify::notify(false, &[NotifyState::Status(&status)])
+        };
+        if let Err(err) = result {
+            warn!("Could not notify the systemd watchdog: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Heartbeat, Liveness};
+    use chrono::Utc;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn tasks_without_recent_heartbeat_are_stuck() {
+        let (bot, writer) = (Heartbeat::default(), Heartbeat::default());
+        let liveness = Liveness::new(vec![("bot", bot.clone()), ("writer", writer.clone())], 60);
+        assert!(liveness.is_alive());
+
+        writer
+            .0
+            .store(Utc::now().timestamp() - 120, Ordering::Relaxed);
+        let stuck: Vec<_> = liveness
+            .tasks()
+            .into_iter()
+            .filter(|task| !task.alive)
+            .map(|task| task.name)
+            .collect();
+        assert_eq!(stuck, vec!["writer"]);
+        assert!(!liveness.is_alive());
+
+        writer.beat();
+        assert!(liveness.is_alive());
+    }
+}
@@ -1,4 +1,4 @@
-use crate::{app::App, bot::BotMessage, error::Error};
+use crate::{app::{cache_invalidation::{invalidate_cached_logs, read_cached_purge_ranges}, opt_out_denials::OPT_OUT_DENIALS, App}, backup::{self, BackupAlreadyRunning}, bot::BotMessage, config::{ApiKeyScope, RetentionConfig}, error::Error, ids::ChannelId, streams};
 use aide::{
     openapi::{
         HeaderStyle, Parameter, ParameterData, ParameterSchemaOrContent, ReferenceOr, SchemaObject,
@@ -11,35 +11,86 @@ use axum::{
     response::{IntoResponse, Response},
     Extension, Json,
 };
-use axum::extract::Query;
+use axum::extract::{Path, Query};
+use axum::http::HeaderMap;
+use chrono::{DateTime, Utc};
 use reqwest::StatusCode;
 use schemars::JsonSchema;
-use serde::Deserialize;
-use tokio::sync::mpsc::Sender;
-use crate::web::schema::{UserHasLogs, UserLogins, UserParam};
-use crate::db::{check_users_exist, search_user_logins};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{mpsc::Sender, oneshot};
+use tracing::info;
+use twitch_api::helix::streams::GetStreamsRequest;
+use crate::web::api_keys::request_api_key;
+use crate::web::schema::{
+    AuditLogEntry, AuditLogParams, BackupEntry, BulkJoinResult, Channel, ChannelDiagnosis, ChatOverlap, ChannelGaps, ChannelIdPath, ChannelVerification, DuplicatesCleanup, DuplicatesReport, RangeParams, Gap, GapsParams, IngestionStatus, LegalHoldEntry, LegalHoldKind, LegalHoldPath, LegalHoldRequest, MutationIdPath, OptOutDenials, OptOutEntry, OptOutSource, OverlapParams, PauseChannelRequest, PurgeJob, PurgeStatus, QueryIdPath, RetentionPolicies, RunningQuery, SchemaDrift, StorageStats, StreamBackfill, UnparsedMessageEntry, UnparsedMessagesParams, UnparsedRetryResult,
+    UsageConsumer, UsageParams, UserHasLogs, UserLogins, UserParam,
+};
+use crate::db::{
+    audit::{read_audit_log, write_audit_entry},
+    backups::read_backups,
+    check_users_exist,
+    duplicates::{read_duplicate_counts, remove_duplicates},
+    gaps::{group_gaps, read_silent_live_minutes},
+    latency::INGESTION_LATENCY,
+    opt_outs::{read_opt_outs, write_opt_out},
+    pool::QueryClass,
+    processes::{kill_query, read_running_queries},
+    purge::{purge_user_copies, purge_user_messages, read_purge_status, PurgeScope},
+    read_recent_messages, read_schema_drift, search_user_logins,
+    stats::read_chat_overlap,
+    storage::read_disk_usage,
+    unparsed::{read_unparsed_messages, retry_unparsed_messages},
+    usage::read_top_consumers,
+};
+
+/// Shorter silences are normal in small channels
+const DEFAULT_GAP_MINUTES: u32 = 5;
+const MAX_OVERLAP_SAMPLE: u64 = 1000;
+/// Live channels are expected to have received a message in this time
+const RECENT_MESSAGE_MINUTES: i64 = 10;
 
 pub async fn admin_auth(
     app: State<App>,
     request: Request,
     next: Next,
 ) -> Result<Response, impl IntoResponse> {
-    if let Some(admin_key) = &app.config.admin_api_key {
-        if request
-            .headers()
-            .get("X-Api-Key")
-            .and_then(|value| value.to_str().ok())
-            == Some(admin_key)
-        {
-            let response = next.run(request).await;
-            return Ok(response);
-        }
+    if is_admin_request(&app, &request) {
+        let response = next.run(request).await;
+        return Ok(response);
+    }
+
+    Err((StatusCode::FORBIDDEN, "No, I don't think so"))
+}
+
+/// Only requires the admin key if user stats are not configured to be public
+pub async fn user_stats_auth(
+    app: State<App>,
+    request: Request,
+    next: Next,
+) -> Result<Response, impl IntoResponse> {
+    if app.config.user_stats_public || is_admin_request(&app, &request) {
+        let response = next.run(request).await;
+        return Ok(response);
     }
 
     Err((StatusCode::FORBIDDEN, "No, I don't think so"))
 }
 
+pub fn is_admin_request(app: &App, request: &Request) -> bool {
+    request_api_key(request)
+        .and_then(|key| app.config.api_key(key))
+        .is_some_and(|grant| grant.has_scope(ApiKeyScope::Admin))
+}
+
 pub fn admin_auth_doc(op: &mut TransformOperation) {
+    api_key_doc(
+        op,
+        "Admin API key, or a key configured in `apiKeys` with the `admin` scope",
+    );
+}
+
+/// Documents the required `X-Api-Key` header
+pub fn api_key_doc(op: &mut TransformOperation, description: &str) {
     let schema = aide::gen::in_context(|ctx| ctx.schema.subschema_for::<String>());
 
     op.inner_mut()
@@ -47,7 +98,7 @@ pub fn admin_auth_doc(op: &mut TransformOperation) {
         .push(ReferenceOr::Item(Parameter::Header {
             parameter_data: ParameterData {
                 name: "X-Api-Key".to_owned(),
-                description: Some("Configured admin API key".to_owned()),
+                description: Some(description.to_owned()),
                 required: true,
                 deprecated: None,
                 format: ParameterSchemaOrContent::Schema(SchemaObject {
@@ -64,16 +115,61 @@ pub fn admin_auth_doc(op: &mut TransformOperation) {
         }));
 }
 
-#[derive(Deserialize, JsonSchema)]
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(example = "channels_request_example")]
 pub struct ChannelsRequest {
     /// List of channel ids
     pub channels: Vec<String>,
 }
 
+fn channels_request_example() -> ChannelsRequest {
+    ChannelsRequest {
+        channels: vec!["22484632".to_owned()],
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeRequest {
+    /// The user whose messages are deleted
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    /// Only delete the messages in this channel
+    #[serde(rename = "channelID")]
+    pub channel_id: Option<String>,
+    /// Only delete messages sent at or after this time
+    #[schemars(with = "Option<String>")]
+    pub from: Option<DateTime<Utc>>,
+    /// Only delete messages sent before this time
+    #[schemars(with = "Option<String>")]
+    pub to: Option<DateTime<Utc>>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct RetentionRequest {
+    /// Days the channel's messages are kept, `null` keeps them forever
+    pub days: Option<u32>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkJoinRequest {
+    /// Join the channels of all members of this Twitch team
+    pub team: Option<String>,
+    /// Join all channels this user follows. Requires `userToken`
+    #[serde(rename = "userID")]
+    pub user_id: Option<String>,
+    /// User access token of the user with the `user:read:follows` scope
+    pub user_token: Option<String>,
+    /// Only list the channels which would be joined
+    #[serde(default)]
+    pub dry_run: bool,
+}
+
 #[derive(Deserialize, JsonSchema)]
 pub struct UsersRequest {
     /// Channel id
-    pub channel: String,
+    pub channel: ChannelId,
     /// List of user ids
     pub users: Vec<String>,
 }
@@ -90,7 +186,7 @@ pub async fn add_channels(
     app: State<App>,
     Json(ChannelsRequest { channels }): Json<ChannelsRequest>,
 ) -> Result<(), Error> {
-    let users = app.get_users(channels, vec![], false).await?;
+    let users = app.get_current_channel_logins(channels).await?;
     let names = users.into_values().collect();
 
     bot_tx.send(BotMessage::JoinChannels(names)).await.unwrap();
@@ -103,7 +199,7 @@ pub async fn remove_channels(
     app: State<App>,
     Json(ChannelsRequest { channels }): Json<ChannelsRequest>,
 ) -> Result<(), Error> {
-    let users = app.get_users(channels, vec![], false).await?;
+    let users = app.get_current_channel_logins(channels).await?;
     let names = users.into_values().collect();
 
     bot_tx.send(BotMessage::PartChannels(names)).await.unwrap();
@@ -111,11 +207,87 @@ pub async fn remove_channels(
     Ok(())
 }
 
+/// Parts the channels but keeps their logs queryable, listing them as archived
+pub async fn archive_channels(
+    Extension(bot_tx): Extension<Sender<BotMessage>>,
+    app: State<App>,
+    Json(ChannelsRequest { channels }): Json<ChannelsRequest>,
+) -> Result<(), Error> {
+    let users = app.get_current_channel_logins(channels).await?;
+    let names = users.into_values().collect();
+
+    bot_tx
+        .send(BotMessage::ArchiveChannels(names))
+        .await
+        .unwrap();
+
+    Ok(())
+}
+
+pub async fn bulk_join_channels(
+    Extension(bot_tx): Extension<Sender<BotMessage>>,
+    app: State<App>,
+    Json(request): Json<BulkJoinRequest>,
+) -> Result<Json<BulkJoinResult>, Error> {
+    let channels = match (&request.team, &request.user_id, &request.user_token) {
+        (Some(team), None, _) => app.get_team_members(team).await?,
+        (None, Some(user_id), Some(user_token)) => {
+            app.get_followed_channels(user_id, user_token).await?
+        }
+        (None, Some(_), None) => {
+            return Err(Error::InvalidParam(
+                "Followed channels can only be read with a user access token".to_owned(),
+            ))
+        }
+        _ => {
+            return Err(Error::InvalidParam(
+                "Either a team or a user ID has to be given".to_owned(),
+            ))
+        }
+    };
+
+    let mut result = BulkJoinResult {
+        joined: Vec::new(),
+        already_joined: 0,
+        opted_out: 0,
+        dry_run: request.dry_run,
+    };
+    {
+        let joined_channels = app.config.channels.read().unwrap();
+        for (user_id, name) in channels {
+            if joined_channels.contains(&user_id) {
+                result.already_joined += 1;
+            } else if app.has_opted_out(&user_id) {
+                result.opted_out += 1;
+            } else {
+                result.joined.push(Channel {
+                    name,
+                    user_id,
+                    archived: false,
+                });
+            }
+        }
+    }
+    result.joined.sort_unstable_by(|a, b| a.name.cmp(&b.name));
+
+    if !request.dry_run && !result.joined.is_empty() {
+        let names = result
+            .joined
+            .iter()
+            .map(|channel| channel.name.clone())
+            .collect();
+        bot_tx.send(BotMessage::JoinChannels(names)).await.unwrap();
+        info!("Joining {} channels in bulk", result.joined.len());
+    }
+
+    Ok(Json(result))
+}
+
 pub async fn check_users_existence(
     app: State<App>,
     Json(UsersRequest { channel, users }): Json<UsersRequest>,
 ) -> Result<Json<Vec<UserHasLogs>>, Error> {
-    let users = check_users_exist(&app.db, &channel, &users).await?;
+    let users = check_users_exist(&app.db.profile(QueryClass::Admin), &channel, &users).await?;
     Ok(Json(users))
 }
 
@@ -125,4 +297,567 @@ pub async fn find_user_logins(
 ) -> Result<Json<UserLogins>, Error> {
     let logins = search_user_logins(&app, &user).await?;
     Ok(Json(logins))
-}
\ No newline at end of file
+}
+pub async fn api_usage(
+    app: State<App>,
+    Query(params): Query<UsageParams>,
+) -> Result<Json<Vec<UsageConsumer>>, Error> {
+    let consumers = read_top_consumers(&app.db.profile(QueryClass::Admin), &params).await?;
+    Ok(Json(consumers))
+}
+
+pub async fn list_queries(app: State<App>) -> Result<Json<Vec<RunningQuery>>, Error> {
+    let queries = read_running_queries(&app.db.profile(QueryClass::Admin)).await?;
+    Ok(Json(queries))
+}
+
+/// Credentials are redacted, all other values are returned as they are currently applied
+pub async fn get_config(app: State<App>) -> Result<Json<serde_json::Value>, Error> {
+    let config = app.config.redacted()?;
+    Ok(Json(config))
+}
+
+pub async fn get_retention(app: State<App>) -> Json<RetentionPolicies> {
+    let retention = &app.config.retention;
+    Json(RetentionPolicies {
+        default_days: retention.default_days,
+        channels: retention
+            .channels
+            .iter()
+            .map(|entry| (entry.key().clone(), *entry.value()))
+            .collect(),
+    })
+}
+
+/// Overrides the default retention of the channel. Expired messages are deleted by the next cleanup
+pub async fn set_channel_retention(
+    app: State<App>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+    Json(RetentionRequest { days }): Json<RetentionRequest>,
+) -> Result<(), Error> {
+    if days.is_some_and(|days| !RetentionConfig::is_valid_days(days)) {
+        return Err(Error::InvalidParam(format!(
+            "days has to be between 1 and {}",
+            RetentionConfig::MAX_DAYS
+        )));
+    }
+
+    app.config.retention.channels.insert(id.clone(), days);
+    app.config.save()?;
+    info!("Set retention of channel {id} to {days:?} days");
+    Ok(())
+}
+
+pub async fn remove_channel_retention(
+    app: State<App>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+) -> Result<(), Error> {
+    app.config
+        .retention
+        .channels
+        .remove(&id)
+        .ok_or(Error::NotFound)?;
+    app.config.save()?;
+    info!("Removed retention of channel {id}");
+    Ok(())
+}
+
+/// Owner of the API key the request was made with, for the audit log
+fn request_actor(app: &App, headers: &HeaderMap) -> String {
+    headers
+        .get("X-Api-Key")
+        .and_then(|value| value.to_str().ok())
+        .and_then(|key| app.config.api_key(key))
+        .map_or_else(|| "unknown".to_owned(), |grant| grant.owner.to_owned())
+}
+
+pub async fn list_legal_holds(app: State<App>) -> Json<Vec<LegalHoldEntry>> {
+    let holds = &app.config.legal_holds;
+    let entries = [LegalHoldKind::Channel, LegalHoldKind::User]
+        .into_iter()
+        .flat_map(|kind| {
+            holds.by_kind(kind).iter().map(move |entry| LegalHoldEntry {
+                kind,
+                id: entry.key().clone(),
+                reason: entry.value().clone(),
+            })
+        })
+        .collect();
+    Json(entries)
+}
+
+/// Messages under a legal hold are neither deleted by the retention nor by purges and opt-outs
+pub async fn place_legal_hold(
+    app: State<App>,
+    headers: HeaderMap,
+    Path(LegalHoldPath { kind, id }): Path<LegalHoldPath>,
+    Json(LegalHoldRequest { reason }): Json<LegalHoldRequest>,
+) -> Result<(), Error> {
+    if reason.trim().is_empty() {
+        return Err(Error::InvalidParam("reason is required".to_owned()));
+    }
+
+    // The hold is only placed if it can be traced back
+    let actor = request_actor(&app, &headers);
+    write_audit_entry(
+        &app.db.primary(),
+        &actor,
+        "legal_hold_placed",
+        &format!("{kind}/{id}"),
+        &reason,
+    )
+    .await?;
+
+    app.config
+        .legal_holds
+        .by_kind(kind)
+        .insert(id.clone(), reason);
+    app.config.save()?;
+    info!("{actor} placed a legal hold on {kind} {id}");
+    Ok(())
+}
+
+pub async fn lift_legal_hold(
+    app: State<App>,
+    headers: HeaderMap,
+    Path(LegalHoldPath { kind, id }): Path<LegalHoldPath>,
+) -> Result<(), Error> {
+    let holds = app.config.legal_holds.by_kind(kind);
+    let reason = holds.get(&id).ok_or(Error::NotFound)?.value().clone();
+
+    let actor = request_actor(&app, &headers);
+    write_audit_entry(
+        &app.db.primary(),
+        &actor,
+        "legal_hold_lifted",
+        &format!("{kind}/{id}"),
+        &reason,
+    )
+    .await?;
+
+    holds.remove(&id);
+    app.config.save()?;
+    info!("{actor} lifted the legal hold on {kind} {id}");
+    Ok(())
+}
+
+pub async fn audit_log(
+    app: State<App>,
+    Query(AuditLogParams { limit }): Query<AuditLogParams>,
+) -> Result<Json<Vec<AuditLogEntry>>, Error> {
+    let rows = read_audit_log(&app.db.profile(QueryClass::Admin), limit.unwrap_or(100)).await?;
+    Ok(Json(rows.into_iter().map(AuditLogEntry::from).collect()))
+}
+
+pub async fn schema_drift(app: State<App>) -> Result<Json<SchemaDrift>, Error> {
+    let differences = read_schema_drift(&app.db.profile(QueryClass::Admin)).await?;
+    Ok(Json(SchemaDrift {
+        in_sync: differences.is_empty(),
+        differences,
+    }))
+}
+
+pub async fn storage_stats(app: State<App>) -> Result<Json<StorageStats>, Error> {
+    let disks = read_disk_usage(&app.db.profile(QueryClass::Admin)).await?;
+    let tiering = app.config.storage_tiering.as_ref();
+
+    Ok(Json(StorageStats {
+        policy: tiering.map(|tiering| tiering.policy.clone()),
+        cold_volume: tiering.map(|tiering| tiering.volume.clone()),
+        move_after_days: tiering.map(|tiering| tiering.move_after_days),
+        disks,
+    }))
+}
+
+pub async fn ingestion_status(app: State<App>) -> Json<IngestionStatus> {
+    Json(IngestionStatus {
+        latency: INGESTION_LATENCY.total(),
+        overloaded: app.flush_buffer.is_overloaded(),
+        channels: INGESTION_LATENCY.channels(),
+        nodes: app.db.nodes(),
+    })
+}
+
+pub async fn verify_channel(
+    app: State<App>,
+    Extension(bot_tx): Extension<Sender<BotMessage>>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+) -> Result<Json<ChannelVerification>, Error> {
+    let channel_login = app
+        .get_users(vec![id.clone()], vec![], false)
+        .await?
+        .remove(&id)
+        .ok_or(Error::NotFound)?;
+    let configured = app.config.channels.read().unwrap().contains(&id);
+
+    let (status_tx, status_rx) = oneshot::channel();
+    bot_tx
+        .send(BotMessage::ChannelStatus(channel_login.clone(), status_tx))
+        .await
+        .map_err(|_| Error::Internal)?;
+    let (_, joined) = status_rx.await.map_err(|_| Error::Internal)?;
+
+    let request = GetStreamsRequest::user_ids(std::slice::from_ref(&id));
+    let response = app.helix_client.req_get(request, &*app.token).await?;
+    let live = !response.data.is_empty();
+
+    let now = Utc::now();
+    let last_message_at = read_recent_messages(
+        &app.db.profile(QueryClass::Admin),
+        &ChannelId::from(id.as_str()),
+        None,
+        now,
+        None,
+        1,
+        &app.flush_buffer,
+    )
+    .await?
+    .first()
+    .and_then(|msg| DateTime::from_timestamp_millis(msg.timestamp as i64));
+    let receiving = last_message_at.is_some_and(|last_message_at| {
+        now - last_message_at < chrono::Duration::minutes(RECENT_MESSAGE_MINUTES)
+    });
+
+    let paused = app.config.paused_channels.get(&id).map(|mode| *mode);
+
+    let diagnosis = if !configured {
+        ChannelDiagnosis::NotConfigured
+    } else if paused.is_some() {
+        ChannelDiagnosis::Paused
+    } else if !joined {
+        ChannelDiagnosis::NotJoined
+    } else if receiving {
+        ChannelDiagnosis::Receiving
+    } else if live {
+        ChannelDiagnosis::Silent
+    } else {
+        ChannelDiagnosis::Idle
+    };
+
+    Ok(Json(ChannelVerification {
+        channel_id: id,
+        channel_login,
+        diagnosis,
+        configured,
+        joined,
+        live,
+        paused,
+        last_message_at,
+    }))
+}
+
+/// Only configured channels can be paused, they stay configured while paused
+pub async fn pause_channel(
+    app: State<App>,
+    Extension(bot_tx): Extension<Sender<BotMessage>>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+    Json(PauseChannelRequest { mode }): Json<PauseChannelRequest>,
+) -> Result<(), Error> {
+    if !app.config.channels.read().unwrap().contains(&id) {
+        return Err(Error::NotFound);
+    }
+
+    let channel_login = app
+        .get_users(vec![id.clone()], vec![], false)
+        .await?
+        .remove(&id)
+        .ok_or(Error::NotFound)?;
+    bot_tx
+        .send(BotMessage::PauseChannels(vec![channel_login], mode))
+        .await
+        .map_err(|_| Error::Internal)?;
+
+    Ok(())
+}
+
+pub async fn resume_channel(
+    app: State<App>,
+    Extension(bot_tx): Extension<Sender<BotMessage>>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+) -> Result<(), Error> {
+    if !app.config.paused_channels.contains_key(&id) {
+        return Err(Error::NotFound);
+    }
+
+    let channel_login = app
+        .get_users(vec![id.clone()], vec![], false)
+        .await?
+        .remove(&id)
+        .ok_or(Error::NotFound)?;
+    bot_tx
+        .send(BotMessage::ResumeChannels(vec![channel_login]))
+        .await
+        .map_err(|_| Error::Internal)?;
+
+    Ok(())
+}
+
+pub async fn opt_out_denials() -> Json<OptOutDenials> {
+    Json(OPT_OUT_DENIALS.snapshot())
+}
+
+pub async fn list_opt_outs(app: State<App>) -> Result<Json<Vec<OptOutEntry>>, Error> {
+    let self_service = read_opt_outs(&app.db.profile(QueryClass::Admin))
+        .await?
+        .into_iter()
+        .map(|row| OptOutEntry {
+            id: row.user_id,
+            source: OptOutSource::SelfService,
+            opted_out_at: DateTime::from_timestamp(row.updated_at.into(), 0),
+            purged: row.purged == 1,
+        });
+    let configured = app.config.opt_out.iter().map(|entry| OptOutEntry {
+        id: entry.key().clone(),
+        source: OptOutSource::Config,
+        opted_out_at: None,
+        purged: false,
+    });
+
+    Ok(Json(configured.chain(self_service).collect()))
+}
+
+/// Removes the opt-out from the config and the self-service opt-outs.
+/// Deleted logs are not restored, messages are logged again from now on
+pub async fn revoke_opt_out(
+    app: State<App>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+) -> Result<(), Error> {
+    if !app.has_opted_out(&id) {
+        return Err(Error::NotFound);
+    }
+
+    if app.opted_out_users.remove(&id).is_some() {
+        write_opt_out(&app.db.primary(), &id, false, false).await?;
+    }
+    if app.config.opt_out.remove(&id).is_some() {
+        app.config.save()?;
+    }
+
+    info!("Revoked opt-out of {id}");
+    Ok(())
+}
+
+pub async fn backfill_streams(
+    app: State<App>,
+    Path(ChannelIdPath { id }): Path<ChannelIdPath>,
+) -> Result<Json<StreamBackfill>, Error> {
+    let backfill = streams::backfill_streams(&app, &ChannelId::from(id.as_str())).await?;
+    info!(
+        "Backfilled {} streams of channel {id}, skipped {} known streams",
+        backfill.imported, backfill.skipped
+    );
+    Ok(Json(backfill))
+}
+
+pub async fn list_backups(app: State<App>) -> Result<Json<Vec<BackupEntry>>, Error> {
+    let backups = read_backups(&app.db.profile(QueryClass::Admin)).await?;
+    Ok(Json(backups))
+}
+
+pub async fn start_backup(app: State<App>) -> Result<Json<BackupEntry>, Error> {
+    if app.config.backup.is_none() {
+        return Err(Error::InvalidParam(
+            "No backup destination configured".to_owned(),
+        ));
+    }
+
+    let backup = backup::create_backup(&app).await.map_err(|err| {
+        if err.is::<BackupAlreadyRunning>() {
+            Error::BackupRunning
+        } else {
+            Error::from(err)
+        }
+    })?;
+    Ok(Json(backup.into()))
+}
+
+pub async fn kill_running_query(
+    app: State<App>,
+    Path(QueryIdPath { id }): Path<QueryIdPath>,
+) -> Result<(), Error> {
+    kill_query(&app.db.profile(QueryClass::Admin), &id).await?;
+    info!("Killed query {id}");
+    Ok(())
+}
+
+/// Drops the user's buffered messages before starting the mutation, so none are written after it.
+/// The copies of the messages in other tables are deleted right away
+pub async fn purge_user(
+    app: State<App>,
+    Json(request): Json<PurgeRequest>,
+) -> Result<Json<PurgeJob>, Error> {
+    if request.user_id.is_empty() {
+        return Err(Error::InvalidParam("userID is required".to_owned()));
+    }
+    let legal_holds = &app.config.legal_holds;
+    let channel_held = request
+        .channel_id
+        .as_ref()
+        .is_some_and(|channel_id| legal_holds.channels.contains_key(channel_id));
+    if channel_held || legal_holds.users.contains_key(&request.user_id) {
+        return Err(Error::InvalidParam(
+            "The messages are under legal hold".to_owned(),
+        ));
+    }
+
+    let scope = PurgeScope {
+        user_id: request.user_id,
+        channel_id: request.channel_id,
+        from: request.from,
+        to: request.to,
+        held_channels: legal_holds.channel_ids(),
+    };
+    let evict_scope = scope.clone();
+    let evicted_messages = app
+        .flush_buffer
+        .evict(move |msg| evict_scope.matches(msg))
+        .await;
+    let cached_ranges = read_cached_purge_ranges(&app, &scope).await?;
+    let mutation_id = purge_user_messages(&app.db.primary(), &scope).await?;
+    purge_user_copies(&app.db.primary(), &scope).await?;
+    invalidate_cached_logs(&app, cached_ranges, true);
+
+    info!(
+        "Purging messages of {} with mutation {mutation_id}, evicted {evicted_messages} buffered messages",
+        scope.user_id
+    );
+    Ok(Json(PurgeJob {
+        mutation_id,
+        evicted_messages,
+    }))
+}
+
+pub async fn purge_status(
+    app: State<App>,
+    Path(MutationIdPath { mutation_id }): Path<MutationIdPath>,
+) -> Result<Json<PurgeStatus>, Error> {
+    let status = read_purge_status(&app.db.profile(QueryClass::Admin), &mutation_id).await?;
+    Ok(Json(status))
+}
+
+pub async fn list_unparsed_messages(
+    app: State<App>,
+    Query(UnparsedMessagesParams { limit }): Query<UnparsedMessagesParams>,
+) -> Result<Json<Vec<UnparsedMessageEntry>>, Error> {
+    let messages = read_unparsed_messages(&app.db.profile(QueryClass::Admin), limit)
+        .await?
+        .into_iter()
+        .map(|message| UnparsedMessageEntry {
+            id: message.id.to_string(),
+            channel_id: message.channel_id,
+            user_id: message.user_id,
+            timestamp: DateTime::from_timestamp_millis(message.timestamp as i64)
+                .unwrap_or_default(),
+            raw: message.raw,
+            error: message.error,
+        })
+        .collect();
+
+    Ok(Json(messages))
+}
+
+pub async fn retry_unparsed(app: State<App>) -> Result<Json<UnparsedRetryResult>, Error> {
+    let result = retry_unparsed_messages(&app.db.profile(QueryClass::Admin)).await?;
+    info!(
+        "Retried unparsed messages: {} parsed, {} failed",
+        result.parsed, result.failed
+    );
+    Ok(Json(result))
+}
+
+pub async fn channel_gaps(
+    app: State<App>,
+    Query(params): Query<GapsParams>,
+) -> Result<Json<ChannelGaps>, Error> {
+    let channel_id = app.resolve_channel_param(params.channel).await?;
+
+    let minutes = read_silent_live_minutes(
+        &app.db.profile(QueryClass::Admin),
+        &channel_id,
+        params.range,
+    )
+    .await?;
+    let min_minutes = params.min_minutes.unwrap_or(DEFAULT_GAP_MINUTES);
+    let gaps: Vec<Gap> = group_gaps(&minutes, min_minutes)
+        .into_iter()
+        .map(|(start, length)| Gap {
+            from: DateTime::from_timestamp(start.into(), 0).unwrap_or_default(),
+            to: DateTime::from_timestamp((start + length * 60).into(), 0).unwrap_or_default(),
+            minutes: length,
+        })
+        .collect();
+    let missing_minutes = gaps.iter().map(|gap| gap.minutes).sum();
+
+    Ok(Json(ChannelGaps {
+        channel_id,
+        gaps,
+        missing_minutes,
+    }))
+}
+
+pub async fn chat_overlap(
+    app: State<App>,
+    Query(params): Query<OverlapParams>,
+) -> Result<Json<ChatOverlap>, Error> {
+    for channel_id in [&params.first, &params.second] {
+        app.check_opted_out(channel_id, None)?;
+    }
+
+    // Opted out users are left out of the sample and the counts
+    let excluded_user_ids: Vec<String> = app
+        .config
+        .bot_user_ids
+        .iter()
+        .cloned()
+        .chain(app.opted_out_ids())
+        .collect();
+
+    let overlap = read_chat_overlap(
+        &app.db.profile(QueryClass::Stats),
+        &params.first,
+        &params.second,
+        params.range,
+        &excluded_user_ids,
+        params.sample.unwrap_or(0).min(MAX_OVERLAP_SAMPLE),
+    )
+    .await?;
+
+    Ok(Json(overlap))
+}
+
+pub async fn duplicates_report(
+    app: State<App>,
+    Query(params): Query<RangeParams>,
+) -> Result<Json<DuplicatesReport>, Error> {
+    let report = read_duplicates_report(&app, params).await?;
+    Ok(Json(report))
+}
+
+pub async fn remove_duplicate_messages(
+    app: State<App>,
+    Query(params): Query<RangeParams>,
+) -> Result<Json<DuplicatesCleanup>, Error> {
+    let report = read_duplicates_report(&app, params).await?;
+    let partitions = if report.extra_rows > 0 {
+        remove_duplicates(&app.db.primary(), params).await?
+    } else {
+        Vec::new()
+    };
+
+    info!(
+        "Removed {} duplicate rows from {} partitions",
+        report.extra_rows,
+        partitions.len()
+    );
+    Ok(Json(DuplicatesCleanup { report, partitions }))
+}
+
+async fn read_duplicates_report(app: &App, params: RangeParams) -> Result<DuplicatesReport, Error> {
+    let channels = read_duplicate_counts(&app.db.profile(QueryClass::Admin), params).await?;
+    let extra_rows = channels.iter().map(|count| count.extra_rows).sum();
+
+    Ok(DuplicatesReport {
+        channels,
+        extra_rows,
+    })
+}
@@ -0,0 +1,204 @@
+use super::{
+    api_keys::request_api_key,
+    pagination::Page,
+    schema::{
+        AlertMatch, AlertMatches, AlertMatchesParams, AlertPath, SavedSearch, SavedSearchBody,
+        SavedSearchesList,
+    },
+};
+use crate::{
+    alerts::resolve_webhook,
+    app::App,
+    config::ApiKeyScope,
+    db::{
+        alerts::{
+            read_alert_matches, read_saved_search, read_saved_searches, write_saved_search,
+            SavedSearchRow,
+        },
+        pool::QueryClass,
+    },
+    error::Error,
+};
+use aide::axum::IntoApiResponse;
+use axum::{
+    extract::{Path, Query, Request, State},
+    http::StatusCode,
+    middleware::Next,
+    response::{IntoResponse, Response},
+    Extension, Json,
+};
+use chrono::Utc;
+use tracing::info;
+use url::Url;
+use uuid::Uuid;
+
+const DEFAULT_MATCHES_LIMIT: u64 = 100;
+const MAX_MATCHES_LIMIT: u64 = 1000;
+
+/// Name of the API key the request was made with
+#[derive(Clone)]
+pub struct ApiKeyOwner(pub String);
+
+/// Requires a configured API key and makes its owner available to the handlers
+pub async fn api_key_auth(
+    app: State<App>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, impl IntoResponse> {
+    let owner = request_api_key(&request)
+        .and_then(|key| app.config.api_key(key))
+        .filter(|grant| grant.has_scope(ApiKeyScope::Search))
+        .map(|grant| grant.owner.to_owned());
+
+    match owner {
+        Some(owner) => {
+            request.extensions_mut().insert(ApiKeyOwner(owner));
+            Ok(next.run(request).await)
+        }
+        None => Err((StatusCode::FORBIDDEN, "No, I don't think so")),
+    }
+}
+
+pub async fn list_saved_searches(
+    app: State<App>,
+    Extension(ApiKeyOwner(owner)): Extension<ApiKeyOwner>,
+) -> Result<Json<SavedSearchesList>, Error> {
+    let searches = read_saved_searches(&app.db.profile(QueryClass::Search), Some(&owner))
+        .await?
+        .into_iter()
+        .map(SavedSearch::from)
+        .collect();
+
+    Ok(Json(SavedSearchesList { searches }))
+}
+
+pub async fn create_saved_search(
+    app: State<App>,
+    Extension(ApiKeyOwner(owner)): Extension<ApiKeyOwner>,
+    Json(body): Json<SavedSearchBody>,
+) -> Result<Json<SavedSearch>, Error> {
+    validate_search(&app, &body).await?;
+
+    let created_at = Utc::now().timestamp() as u32;
+    let search = search_row(Uuid::new_v4(), owner, created_at, body);
+    write_saved_search(&app.db, &search).await?;
+    info!("Created saved search {} for {}", search.id, search.owner);
+
+    Ok(Json(search.into()))
+}
+
+pub async fn get_saved_search(
+    app: State<App>,
+    Extension(ApiKeyOwner(owner)): Extension<ApiKeyOwner>,
+    Path(AlertPath { id }): Path<AlertPath>,
+) -> Result<Json<SavedSearch>, Error> {
+    let search = find_search(&app, &owner, &id).await?;
+    Ok(Json(search.into()))
+}
+
+pub async fn update_saved_search(
+    app: State<App>,
+    Extension(ApiKeyOwner(owner)): Extension<ApiKeyOwner>,
+    Path(AlertPath { id }): Path<AlertPath>,
+    Json(body): Json<SavedSearchBody>,
+) -> Result<Json<SavedSearch>, Error> {
+    validate_search(&app, &body).await?;
+
+    let existing = find_search(&app, &owner, &id).await?;
+    let search = search_row(existing.id, owner, existing.created_at, body);
+    write_saved_search(&app.db, &search).await?;
+
+    Ok(Json(search.into()))
+}
+
+pub async fn delete_saved_search(
+    app: State<App>,
+    Extension(ApiKeyOwner(owner)): Extension<ApiKeyOwner>,
+    Path(AlertPath { id }): Path<AlertPath>,
+) -> Result<(), Error> {
+    let search = find_search(&app, &owner, &id).await?;
+    let deleted = SavedSearchRow {
+        updated_at: Utc::now().timestamp_millis() as u64,
+        deleted: 1,
+        ..search
+    };
+    write_saved_search(&app.db, &deleted).await?;
+    info!("Deleted saved search {} of {}", deleted.id, deleted.owner);
+
+    Ok(())
+}
+
+pub async fn list_alert_matches(
+    app: State<App>,
+    Extension(ApiKeyOwner(owner)): Extension<ApiKeyOwner>,
+    Path(AlertPath { id }): Path<AlertPath>,
+    Query(params): Query<AlertMatchesParams>,
+) -> Result<impl IntoApiResponse, Error> {
+    let search = find_search(&app, &owner, &id).await?;
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_MATCHES_LIMIT)
+        .min(MAX_MATCHES_LIMIT);
+    let offset = params.offset.unwrap_or(0);
+
+    let matches: Vec<AlertMatch> = read_alert_matches(
+        &app.db.profile(QueryClass::Search),
+        search.id,
+        limit,
+        offset,
+    )
+    .await?
+    .into_iter()
+    .map(AlertMatch::from)
+    .collect();
+
+    let page = Page::new(Some(limit), Some(offset), matches.len() as u64 == limit);
+    Ok((page, Json(AlertMatches { matches })))
+}
+
+/// Searches of other API keys are treated as not existing
+async fn find_search(app: &App, owner: &str, id: &str) -> Result<SavedSearchRow, Error> {
+    let id =
+        Uuid::parse_str(id).map_err(|_| Error::InvalidParam("Invalid search id".to_owned()))?;
+    read_saved_search(&app.db.profile(QueryClass::Search), owner, id)
+        .await?
+        .ok_or(Error::NotFound)
+}
+
+async fn validate_search(app: &App, body: &SavedSearchBody) -> Result<(), Error> {
+    if body.query.trim().is_empty() {
+        return Err(Error::InvalidParam("Query must not be empty".to_owned()));
+    }
+
+    app.check_opted_out(&body.channel_id, body.user_id.as_ref())?;
+
+    if let Some(webhook_url) = &body.webhook_url {
+        let url = Url::parse(webhook_url)
+            .map_err(|_| Error::InvalidParam("Invalid webhook url".to_owned()))?;
+        if !matches!(url.scheme(), "http" | "https") {
+            return Err(Error::InvalidParam(
+                "Webhook url must use http or https".to_owned(),
+            ));
+        }
+        resolve_webhook(&url)
+            .await
+            .map_err(|err| Error::InvalidParam(format!("{err:#}")))?;
+    }
+
+    Ok(())
+}
+
+fn search_row(id: Uuid, owner: String, created_at: u32, body: SavedSearchBody) -> SavedSearchRow {
+    SavedSearchRow {
+        id,
+        owner,
+        channel_id: body.channel_id.into(),
+        user_id: body.user_id.map(String::from).unwrap_or_default(),
+        query: body.query,
+        webhook_url: body.webhook_url.unwrap_or_default(),
+        created_at,
+        updated_at: Utc::now().timestamp_millis() as u64,
+        deleted: 0,
+    }
+}
@@ -0,0 +1,155 @@
+//! Scopes and rate limits of API keys. Requests without a key are rate limited by their address,
+//! the admin and saved search routes reject them on their own
+
+use super::{endpoint_groups::SEARCH_PATHS, usage::client_ip};
+use crate::{app::App, config::ApiKeyScope};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    http::{header::RETRY_AFTER, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use dashmap::DashMap;
+use std::sync::Arc;
+
+const RATE_LIMIT_WINDOW_SECONDS: u64 = 60;
+/// Windows of earlier minutes are dropped once this many keys and addresses are tracked
+const MAX_TRACKED_WINDOWS: usize = 100_000;
+
+/// Counts the requests of rate limited keys in fixed one minute windows
+#[derive(Clone, Default)]
+pub struct RateLimiter {
+    /// Start of the current window and the requests made in it, by key owner or client address
+    windows: Arc<DashMap<String, (u64, u32)>>,
+}
+
+impl RateLimiter {
+    /// Counts the request, or returns the seconds until the key can be used again
+    fn acquire(&self, owner: &str, limit: u32, now: u64) -> Result<(), u64> {
+        let window_start = now - now % RATE_LIMIT_WINDOW_SECONDS;
+        if self.windows.len() >= MAX_TRACKED_WINDOWS {
+            self.windows.retain(|_, (start, _)| *start == window_start);
+        }
+        let mut entry = self
+            .windows
+            .entry(owner.to_owned())
+            .or_insert((window_start, 0));
+        let (start, count) = entry.value_mut();
+
+        if *start != window_start {
+            *start = window_start;
+            *count = 0;
+        }
+        if *count >= limit {
+            return Err(window_start + RATE_LIMIT_WINDOW_SECONDS - now);
+        }
+        *count += 1;
+
+        Ok(())
+    }
+}
+
+pub fn request_api_key(request: &Request) -> Option<&str> {
+    request
+        .headers()
+        .get("X-Api-Key")
+        .and_then(|value| value.to_str().ok())
+}
+
+pub async fn check_api_key(
+    State((app, limiter)): State<(App, RateLimiter)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(grant) = request_api_key(&request).and_then(|key| app.config.api_key(key)) else {
+        if let Some(limit) = app.config.anonymous_rate_limit {
+            let client = format!("address:{}", client_ip(&app, &request));
+            if let Err(retry_after) = limiter.acquire(&client, limit, now()) {
+                return rate_limited(
+                    retry_after,
+                    "The rate limit for requests without an API key has been exceeded",
+                );
+            }
+        }
+        return next.run(request).await;
+    };
+
+    let scope = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map_or(ApiKeyScope::LogsRead, |path| required_scope(path.as_str()));
+    if !grant.has_scope(scope) {
+        return (
+            StatusCode::FORBIDDEN,
+            "The API key is not allowed to use this endpoint",
+        )
+            .into_response();
+    }
+
+    if let Some(limit) = grant.rate_limit {
+        if let Err(retry_after) = limiter.acquire(grant.owner, limit, now()) {
+            return rate_limited(
+                retry_after,
+                "The rate limit of the API key has been exceeded",
+            );
+        }
+    }
+
+    next.run(request).await
+}
+
+fn now() -> u64 {
+    Utc::now().timestamp() as u64
+}
+
+fn rate_limited(retry_after: u64, message: &'static str) -> Response {
+    (
+        StatusCode::TOO_MANY_REQUESTS,
+        [(RETRY_AFTER, retry_after.to_string())],
+        message,
+    )
+        .into_response()
+}
+
+fn required_scope(path: &str) -> ApiKeyScope {
+    if path.starts_with("/admin") {
+        ApiKeyScope::Admin
+    } else if path.starts_with("/alerts") || SEARCH_PATHS.contains(&path) {
+        ApiKeyScope::Search
+    } else {
+        ApiKeyScope::LogsRead
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{required_scope, RateLimiter};
+    use crate::config::ApiKeyScope;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn scopes_of_paths() {
+        assert_eq!(required_scope("/admin/channels"), ApiKeyScope::Admin);
+        assert_eq!(
+            required_scope("/:channel_id_type/:channel/search"),
+            ApiKeyScope::Search
+        );
+        assert_eq!(required_scope("/alerts/:id"), ApiKeyScope::Search);
+        assert_eq!(
+            required_scope("/:channel_id_type/:channel/user/:user"),
+            ApiKeyScope::LogsRead
+        );
+    }
+
+    #[test]
+    fn rate_limit_resets_every_window() {
+        let limiter = RateLimiter::default();
+
+        assert_eq!(limiter.acquire("bot", 2, 120), Ok(()));
+        assert_eq!(limiter.acquire("bot", 2, 130), Ok(()));
+        assert_eq!(limiter.acquire("bot", 2, 135), Err(45));
+        assert_eq!(limiter.acquire("other", 2, 135), Ok(()));
+        assert_eq!(limiter.acquire("bot", 2, 180), Ok(()));
+    }
+}
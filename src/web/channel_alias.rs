@@ -0,0 +1,66 @@
+use crate::{
+    app::App,
+    db::{aliases::read_channel_alias, pool::QueryClass},
+    Result,
+};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Redirect, Response},
+};
+use tracing::{debug, error};
+
+/// Redirects routes which use a previous login of a renamed channel to its current login
+pub async fn redirect_renamed_channels(app: State<App>, request: Request, next: Next) -> Response {
+    let is_channel_route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .is_some_and(|path| path.as_str().starts_with("/:channel_id_type/:channel"));
+    let path = request.uri().path().to_owned();
+    let mut segments = path.split('/').skip(1);
+    let name = match (is_channel_route, segments.next(), segments.next()) {
+        (true, Some("channel"), Some(name)) => name.to_lowercase(),
+        _ => return next.run(request).await,
+    };
+
+    match current_login(&app, &name).await {
+        Ok(Some(login)) => {
+            debug!("Redirecting renamed channel {name} to {login}");
+            let mut uri = format!("/channel/{login}");
+            for segment in segments {
+                uri.push('/');
+                uri.push_str(segment);
+            }
+            if let Some(query) = request.uri().query() {
+                uri.push('?');
+                uri.push_str(query);
+            }
+            Redirect::permanent(&uri).into_response()
+        }
+        Ok(None) => next.run(request).await,
+        Err(err) => {
+            error!("Could not resolve channel alias {name}: {err}");
+            next.run(request).await
+        }
+    }
+}
+
+/// The current login of the logged channel which used to be called `name`, if it has been renamed
+async fn current_login(app: &App, name: &str) -> Result<Option<String>> {
+    if let Ok(channel_id) = app.get_user_id_by_name(name).await {
+        if app.channel_index.contains_id(&channel_id) {
+            return Ok(None);
+        }
+    }
+
+    let Some(channel_id) = read_channel_alias(&app.db.profile(QueryClass::Logs), name).await?
+    else {
+        return Ok(None);
+    };
+    let login = app
+        .get_users(vec![channel_id.clone()], vec![], false)
+        .await?
+        .remove(&channel_id);
+
+    Ok(login.filter(|login| login != name))
+}
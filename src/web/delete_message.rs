@@ -0,0 +1,107 @@
+//! Users deleting single messages they sent, a finer grained alternative to opting out. Users are identified
+//! by a Twitch user access token, which does not need any scopes
+
+use super::schema::MessagePath;
+use crate::{
+    app::{cache_invalidation::invalidate_cached_logs, App},
+    db::{
+        audit::write_audit_entry,
+        purge::{delete_message, read_message_author, ChannelMessageRange},
+    },
+    error::Error,
+    Result,
+};
+use axum::{
+    extract::{Path, State},
+    http::{header::AUTHORIZATION, HeaderMap},
+};
+use tracing::info;
+use twitch_api::twitch_oauth2::{AccessToken, TwitchToken, UserToken};
+use uuid::Uuid;
+
+pub async fn delete_own_message(
+    app: State<App>,
+    headers: HeaderMap,
+    Path(MessagePath {
+        channel_id_type,
+        channel,
+        id,
+    }): Path<MessagePath>,
+) -> Result<()> {
+    if !app.config.message_deletion {
+        return Err(Error::EndpointDisabled);
+    }
+
+    let access_token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .ok_or_else(|| Error::InvalidParam("A Twitch user access token is required".to_owned()))?;
+    let token = UserToken::from_token(
+        app.helix_client.get_client().inner(),
+        AccessToken::from(access_token.to_owned()),
+    )
+    .await
+    .map_err(|_| Error::InvalidParam("Invalid user access token".to_owned()))?;
+    // Tokens of other applications could be used by them to delete the user's messages
+    if token.client_id().as_str() != app.config.client_id {
+        return Err(Error::InvalidParam(
+            "The access token was issued to another application".to_owned(),
+        ));
+    }
+    let user_id = token.user_id.to_string();
+
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+    let id =
+        Uuid::parse_str(&id).map_err(|_| Error::InvalidParam("Invalid message id".to_owned()))?;
+
+    let legal_holds = &app.config.legal_holds;
+    if legal_holds.channels.contains_key(channel_id.as_str())
+        || legal_holds.users.contains_key(&user_id)
+    {
+        return Err(Error::LegalHold);
+    }
+
+    match read_message_author(&app.db.primary(), &channel_id, id).await? {
+        Some(author) if author.user_id != user_id => return Err(Error::NotMessageAuthor),
+        Some(author) => {
+            delete_message(&app.db.primary(), &channel_id, id, &author).await?;
+            let range = ChannelMessageRange {
+                channel_id: channel_id.to_string(),
+                first: author.timestamp,
+                last: author.timestamp,
+            };
+            invalidate_cached_logs(&app, vec![range], false);
+        }
+        // Messages which have not been written yet are only dropped from the buffer
+        None => {
+            let (evict_channel_id, evict_user_id) = (channel_id.to_string(), user_id.clone());
+            let evicted = app
+                .flush_buffer
+                .evict(move |msg| {
+                    msg.uuid() == Some(id)
+                        && msg.channel_id == evict_channel_id
+                        && msg.user_id == evict_user_id
+                })
+                .await;
+            if evicted == 0 {
+                return Err(Error::NotFound);
+            }
+        }
+    }
+
+    write_audit_entry(
+        &app.db.primary(),
+        &format!("user/{user_id}"),
+        "message_deleted",
+        &format!("message/{id}"),
+        &format!("Deleted by its author in channel {channel_id}"),
+    )
+    .await?;
+    info!(
+        "User {} ({user_id}) deleted their message {id}",
+        token.login
+    );
+
+    Ok(())
+}
@@ -0,0 +1,106 @@
+use crate::{app::App, config::EndpointGroup};
+use axum::{
+    extract::{MatchedPath, Request, State},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+
+pub(super) const SEARCH_PATHS: &[&str] = &[
+    "/:channel_id_type/:channel/search",
+    "/:channel_id_type/:channel/user/:user/search",
+    "/:channel_id_type/:channel/userid/:user/search",
+    "/:channel_id_type/:channel/users/search",
+];
+const NAME_HISTORY_PATHS: &[&str] = &["/admin/known-names"];
+const FIREHOSE_PATHS: &[&str] = &[
+    "/:channel_id_type/:channel/live",
+    "/:channel_id_type/:channel/replay",
+];
+const STATS_PATHS: &[&str] = &[
+    "/:channel_id_type/:channel/userid/:user/streaks",
+    "/:channel_id_type/:channel/user/:user/streaks",
+    "/:channel_id_type/:channel/userid/:user/watchtime",
+    "/:channel_id_type/:channel/user/:user/watchtime",
+];
+const ADMIN_IMPORT_PATHS: &[&str] = &[
+    "/admin/channels/:id/streams/backfill",
+    "/admin/unparsed/retry",
+];
+
+/// Rejects requests to endpoints whose group is disabled in the config.
+/// The config is checked on every request, so groups can be toggled without rebuilding the router
+pub async fn check_endpoint_group(app: State<App>, request: Request, next: Next) -> Response {
+    let group = request
+        .extensions()
+        .get::<MatchedPath>()
+        .and_then(|path| endpoint_group(path.as_str()));
+
+    match group.map(|group| app.check_endpoint_group(group)) {
+        Some(Err(err)) => err.into_response(),
+        _ => next.run(request).await,
+    }
+}
+
+fn endpoint_group(path: &str) -> Option<EndpointGroup> {
+    if SEARCH_PATHS.contains(&path) {
+        Some(EndpointGroup::Search)
+    } else if NAME_HISTORY_PATHS.contains(&path) {
+        Some(EndpointGroup::NameHistory)
+    } else if FIREHOSE_PATHS.contains(&path) {
+        Some(EndpointGroup::Firehose)
+    } else if ADMIN_IMPORT_PATHS.contains(&path) {
+        Some(EndpointGroup::AdminImport)
+    } else if STATS_PATHS.contains(&path)
+        || path
+            .split('/')
+            .any(|segment| segment == "stats" || segment == "reports")
+    {
+        Some(EndpointGroup::Stats)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::endpoint_group;
+    use crate::config::EndpointGroup;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn groups_of_paths() {
+        assert_eq!(
+            endpoint_group("/:channel_id_type/:channel/userid/:user/search"),
+            Some(EndpointGroup::Search)
+        );
+        assert_eq!(
+            endpoint_group("/:channel_id_type/:channel/streams/:stream_id/stats"),
+            Some(EndpointGroup::Stats)
+        );
+        assert_eq!(
+            endpoint_group("/:channel_id_type/:channel/reports/:period"),
+            Some(EndpointGroup::Stats)
+        );
+        assert_eq!(
+            endpoint_group("/userid/:user/stats"),
+            Some(EndpointGroup::Stats)
+        );
+        assert_eq!(
+            endpoint_group("/:channel_id_type/:channel/user/:user/watchtime"),
+            Some(EndpointGroup::Stats)
+        );
+        assert_eq!(
+            endpoint_group("/:channel_id_type/:channel/replay"),
+            Some(EndpointGroup::Firehose)
+        );
+        assert_eq!(
+            endpoint_group("/admin/channels/:id/streams/backfill"),
+            Some(EndpointGroup::AdminImport)
+        );
+        assert_eq!(endpoint_group("/channels/search"), None);
+        assert_eq!(
+            endpoint_group("/:channel_id_type/:channel/user/:user"),
+            None
+        );
+    }
+}
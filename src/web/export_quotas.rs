@@ -0,0 +1,328 @@
+//! Daily quotas of the bytes each client can download from a channel through the bulk log endpoints,
+//! so public instances can offer downloads without being drained by scrapers.
+//! The gRPC and GraphQL servers count their responses against the same quotas
+
+use super::{api_keys::request_api_key, usage::client_ip};
+use crate::app::App;
+use axum::{
+    body::{to_bytes, Body},
+    extract::{MatchedPath, Request, State},
+    http::{header::RETRY_AFTER, HeaderValue, StatusCode},
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use chrono::Utc;
+use dashmap::DashMap;
+use futures::StreamExt;
+use lazy_static::lazy_static;
+use prometheus::{register_int_counter, IntCounter};
+use serde::Deserialize;
+use std::{
+    io,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc,
+    },
+};
+
+const DAY_SECONDS: i64 = 24 * 3600;
+/// Bodies of `/query` requests are read to find the queried channels, larger ones are rejected
+const MAX_QUERY_BODY_BYTES: usize = 1024 * 1024;
+pub const QUOTA_EXCEEDED_MESSAGE: &str =
+    "The daily download quota for this channel has been used up";
+
+const EXPORT_PATHS: &[&str] = &[
+    "/:channel_id_type/:channel/user/:user",
+    "/:channel_id_type/:channel/userid/:user",
+    "/:channel_id_type/:channel/user/:user/:year/:month",
+    "/:channel_id_type/:channel/userid/:user/:year/:month",
+    "/:channel_id_type/:channel/search",
+    "/:channel_id_type/:channel/user/:user/search",
+    "/:channel_id_type/:channel/userid/:user/search",
+    "/:channel_id_type/:channel/moment",
+    "/:channel_id_type/:channel/streams/:stream_id",
+    "/:channel_id_type/:channel/replay",
+];
+/// The channels are part of the body
+const QUERY_PATH: &str = "/query";
+/// The channels are only known while the query is executed, see [`QuotaClient`]
+const GRAPHQL_PATH: &str = "/graphql";
+
+lazy_static! {
+    static ref QUOTA_REJECTIONS_COUNTER: IntCounter = register_int_counter!(
+        "rustlog_export_quota_rejections_total",
+        "Requests rejected because the client used up its daily download quota of the channel"
+    )
+    .unwrap();
+}
+
+/// Who sent a GraphQL request and their daily limit, so the resolvers can meter the channels they read
+#[derive(Clone)]
+pub struct QuotaClient {
+    pub client: String,
+    pub limit: u64,
+}
+
+/// Today's usage of the channels of one download, shared with the other downloads of the client
+pub struct QuotaUsage {
+    counters: Vec<Arc<AtomicU64>>,
+    limit: u64,
+}
+
+impl QuotaUsage {
+    /// Counts the bytes against the quota of every channel.
+    /// Fails once one of the quotas has been used up, the bytes are not counted then
+    pub fn consume(&self, len: u64) -> Result<(), QuotaExceeded> {
+        if self
+            .counters
+            .iter()
+            .any(|bytes| bytes.load(Ordering::Relaxed) >= self.limit)
+        {
+            QUOTA_REJECTIONS_COUNTER.inc();
+            return Err(QuotaExceeded);
+        }
+
+        for bytes in &self.counters {
+            bytes.fetch_add(len, Ordering::Relaxed);
+        }
+        Ok(())
+    }
+
+    fn remaining(&self) -> u64 {
+        self.counters
+            .iter()
+            .map(|bytes| self.limit.saturating_sub(bytes.load(Ordering::Relaxed)))
+            .min()
+            .unwrap_or(self.limit)
+    }
+}
+
+#[derive(Debug)]
+pub struct QuotaExceeded;
+
+impl From<QuotaExceeded> for io::Error {
+    fn from(_: QuotaExceeded) -> Self {
+        io::Error::other(QUOTA_EXCEEDED_MESSAGE)
+    }
+}
+
+/// Bytes downloaded per client and channel, counted in UTC days
+#[derive(Clone, Default)]
+pub struct ExportQuotas {
+    /// Day and the bytes downloaded in it, by client and channel id
+    usage: Arc<DashMap<(String, String), (i64, Arc<AtomicU64>)>>,
+    /// Day of the last request, entries of previous days are removed once it changes
+    day: Arc<AtomicI64>,
+}
+
+impl ExportQuotas {
+    /// The counter of today's bytes, or the seconds until the quota is reset if it has been used up
+    fn acquire(
+        &self,
+        client: &str,
+        channel_id: &str,
+        limit: u64,
+        now: i64,
+    ) -> Result<Arc<AtomicU64>, u64> {
+        let day = now.div_euclid(DAY_SECONDS);
+        if self.day.swap(day, Ordering::Relaxed) != day {
+            self.usage.retain(|_, (entry_day, _)| *entry_day == day);
+        }
+
+        let mut entry = self
+            .usage
+            .entry((client.to_owned(), channel_id.to_owned()))
+            .or_insert_with(|| (day, Arc::default()));
+        let (entry_day, bytes) = entry.value_mut();
+
+        if *entry_day != day {
+            *entry_day = day;
+            *bytes = Arc::default();
+        }
+        if bytes.load(Ordering::Relaxed) >= limit {
+            return Err((DAY_SECONDS - now.rem_euclid(DAY_SECONDS)) as u64);
+        }
+
+        Ok(bytes.clone())
+    }
+
+    /// Usage of today's quotas of the channels, or the seconds until they are reset if one has been used up
+    pub fn acquire_channels<S: AsRef<str>>(
+        &self,
+        client: &str,
+        channel_ids: &[S],
+        limit: u64,
+    ) -> Result<QuotaUsage, u64> {
+        let now = Utc::now().timestamp();
+        let counters = channel_ids
+            .iter()
+            .map(|channel_id| self.acquire(client, channel_id.as_ref(), limit, now))
+            .collect::<Result<_, _>>();
+
+        match counters {
+            Ok(counters) => Ok(QuotaUsage { counters, limit }),
+            Err(retry_after) => {
+                QUOTA_REJECTIONS_COUNTER.inc();
+                Err(retry_after)
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct QueryChannels {
+    channels: Vec<String>,
+}
+
+pub async fn check_export_quota(
+    State(app): State<App>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let matched_path = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    let is_export = EXPORT_PATHS.contains(&matched_path.as_str())
+        || matched_path == QUERY_PATH
+        || matched_path == GRAPHQL_PATH;
+    if !is_export {
+        return next.run(request).await;
+    }
+
+    let grant = request_api_key(&request).and_then(|key| app.config.api_key(key));
+    let Some(limit) = app.config.export_quotas.daily_limit(grant.as_ref()) else {
+        return next.run(request).await;
+    };
+    let client = match &grant {
+        Some(grant) => format!("key:{}", grant.owner),
+        None => format!("ip:{}", client_ip(&app, &request)),
+    };
+
+    if matched_path == GRAPHQL_PATH {
+        request
+            .extensions_mut()
+            .insert(QuotaClient { client, limit });
+        return next.run(request).await;
+    }
+
+    let channel_ids = if matched_path == QUERY_PATH {
+        let (parts, body) = request.into_parts();
+        let Ok(body) = to_bytes(body, MAX_QUERY_BODY_BYTES).await else {
+            return (StatusCode::PAYLOAD_TOO_LARGE, "The query is too large").into_response();
+        };
+        let channel_ids = serde_json::from_slice::<QueryChannels>(&body)
+            .map(|query| query.channels)
+            .ok();
+        request = Request::from_parts(parts, Body::from(body));
+        match channel_ids {
+            // Channels which are queried twice are only counted once
+            Some(mut channel_ids) => {
+                channel_ids.sort_unstable();
+                channel_ids.dedup();
+                channel_ids
+            }
+            None => return next.run(request).await,
+        }
+    } else {
+        let path = request.uri().path().to_owned();
+        let mut segments = path.split('/').skip(1);
+        match (segments.next(), segments.next()) {
+            (Some("channelid"), Some(id)) => vec![id.to_owned()],
+            (Some("channel"), Some(name)) => match app.get_user_id_by_name(name).await {
+                Ok(id) => vec![id],
+                // The handler responds with the error
+                Err(_) => return next.run(request).await,
+            },
+            _ => return next.run(request).await,
+        }
+    };
+
+    let usage = match app
+        .export_quotas
+        .acquire_channels(&client, &channel_ids, limit)
+    {
+        Ok(usage) => usage,
+        Err(retry_after) => {
+            return (
+                StatusCode::TOO_MANY_REQUESTS,
+                [(RETRY_AFTER, retry_after.to_string())],
+                QUOTA_EXCEEDED_MESSAGE,
+            )
+                .into_response();
+        }
+    };
+
+    let response = next.run(request).await;
+
+    // Parallel downloads share the counters, the responses are cut off once the quota has been used up.
+    // The chunk which crosses it is still sent
+    let (mut parts, body) = response.into_parts();
+    parts.headers.insert(
+        "x-rustlog-export-quota-remaining",
+        HeaderValue::from(usage.remaining()),
+    );
+    let body = body.into_data_stream().map(move |chunk| {
+        let chunk = chunk?;
+        usage
+            .consume(chunk.len() as u64)
+            .map_err(|err| axum::Error::new(io::Error::from(err)))?;
+        Ok::<_, axum::Error>(chunk)
+    });
+
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ExportQuotas;
+    use pretty_assertions::assert_eq;
+    use std::sync::atomic::Ordering;
+
+    #[test]
+    fn quota_resets_every_day() {
+        let quotas = ExportQuotas::default();
+
+        let bytes = quotas
+            .acquire("ip:1.2.3.4", "22484632", 100, 86_400)
+            .unwrap();
+        bytes.fetch_add(100, Ordering::Relaxed);
+        assert_eq!(
+            quotas.acquire("ip:1.2.3.4", "22484632", 100, 90_000).err(),
+            Some(82_800)
+        );
+        assert!(quotas
+            .acquire("ip:1.2.3.4", "71092938", 100, 90_000)
+            .is_ok());
+        assert!(quotas.acquire("key:bot", "22484632", 100, 90_000).is_ok());
+
+        let bytes = quotas
+            .acquire("ip:1.2.3.4", "22484632", 100, 172_800)
+            .unwrap();
+        assert_eq!(bytes.load(Ordering::Relaxed), 0);
+    }
+
+    #[test]
+    fn parallel_downloads_are_cut_off() {
+        let quotas = ExportQuotas::default();
+
+        let first = quotas
+            .acquire_channels("ip:1.2.3.4", &["22484632", "71092938"], 100)
+            .unwrap();
+        let second = quotas
+            .acquire_channels("ip:1.2.3.4", &["22484632"], 100)
+            .unwrap();
+
+        assert!(first.consume(60).is_ok());
+        assert!(second.consume(60).is_ok());
+        assert!(first.consume(1).is_err());
+        assert!(second.consume(1).is_err());
+        assert_eq!(first.remaining(), 0);
+
+        let other_channel = quotas
+            .acquire_channels("ip:1.2.3.4", &["71092938"], 100)
+            .unwrap();
+        assert_eq!(other_channel.remaining(), 40);
+    }
+}
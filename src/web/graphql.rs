@@ -0,0 +1,325 @@
+use crate::{
+    app::App,
+    config::EndpointGroup,
+    db::{
+        pool::QueryClass,
+        read_channel, read_user,
+        schema::StructuredMessage,
+        stats::{read_channel_summary, read_top_chatters},
+        streams::read_streams,
+    },
+    ids::{ChannelId, UserId},
+    logs::schema::LogRangeParams,
+    web::{
+        export_quotas::{QuotaClient, QUOTA_EXCEEDED_MESSAGE},
+        schema::{
+            ChannelSummary, FormatOptions, LogsParams, MessageFilter, MessageSort, RangeParams,
+            Stream, TopChatter,
+        },
+    },
+};
+use async_graphql::{
+    http::GraphiQLSource, Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject,
+};
+use async_graphql_axum::{GraphQLRequest, GraphQLResponse};
+use axum::{response::Html, Extension};
+use chrono::{DateTime, Utc};
+use futures::TryStreamExt;
+
+const MAX_MESSAGES_LIMIT: u64 = 1000;
+const MAX_TOP_CHATTERS: u64 = 100;
+const MAX_QUERY_DEPTH: usize = 8;
+
+pub type LogsSchema = Schema<QueryRoot, EmptyMutation, EmptySubscription>;
+
+pub fn build_schema(app: App) -> LogsSchema {
+    Schema::build(QueryRoot, EmptyMutation, EmptySubscription)
+        .data(app)
+        .limit_depth(MAX_QUERY_DEPTH)
+        .finish()
+}
+
+/// The quota client is set by the export quota middleware if downloads are limited
+pub async fn graphql_handler(
+    Extension(schema): Extension<LogsSchema>,
+    quota_client: Option<Extension<QuotaClient>>,
+    request: GraphQLRequest,
+) -> GraphQLResponse {
+    let mut request = request.into_inner();
+    if let Some(Extension(quota_client)) = quota_client {
+        request = request.data(quota_client);
+    }
+    schema.execute(request).await.into()
+}
+
+pub async fn graphiql() -> Html<String> {
+    Html(GraphiQLSource::build().endpoint("/graphql").finish())
+}
+
+pub struct QueryRoot;
+
+#[Object]
+impl QueryRoot {
+    /// Logged channels
+    async fn channels(&self, ctx: &Context<'_>) -> async_graphql::Result<Vec<Channel>> {
+        let app = ctx.data::<App>()?;
+        let channel_ids = app.config.channels.read().unwrap().clone();
+
+        let channels = app
+            .get_users(Vec::from_iter(channel_ids), vec![], false)
+            .await?
+            .into_iter()
+            .filter(|(id, _)| !app.has_opted_out(id))
+            .map(|(id, login)| Channel {
+                id: id.into(),
+                login,
+            })
+            .collect();
+        Ok(channels)
+    }
+
+    /// A logged channel by either its id or login
+    async fn channel(
+        &self,
+        ctx: &Context<'_>,
+        id: Option<String>,
+        login: Option<String>,
+    ) -> async_graphql::Result<Option<Channel>> {
+        let app = ctx.data::<App>()?;
+
+        let id = match (id, &login) {
+            (Some(id), _) => id,
+            (None, Some(login)) => app.get_user_id_by_name(login).await?,
+            (None, None) => return Err("Either id or login is required".into()),
+        };
+
+        if !app.config.channels.read().unwrap().contains(&id) {
+            return Ok(None);
+        }
+        let id = ChannelId::from(id);
+        app.check_opted_out(&id, None)?;
+
+        let login = match login {
+            Some(login) => login,
+            None => app
+                .get_users(vec![id.to_string()], vec![], false)
+                .await?
+                .remove(id.as_str())
+                .unwrap_or_default(),
+        };
+
+        Ok(Some(Channel { id, login }))
+    }
+}
+
+pub struct Channel {
+    id: ChannelId,
+    login: String,
+}
+
+#[Object]
+impl Channel {
+    async fn id(&self) -> &str {
+        self.id.as_str()
+    }
+
+    async fn login(&self) -> &str {
+        &self.login
+    }
+
+    /// Messages in the given range, optionally only from a single user
+    #[allow(clippy::too_many_arguments)]
+    async fn messages(
+        &self,
+        ctx: &Context<'_>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        #[graphql(name = "userID")] user_id: Option<String>,
+        #[graphql(default = 100)] limit: u64,
+        #[graphql(default)] offset: u64,
+        #[graphql(default)] reverse: bool,
+    ) -> async_graphql::Result<Vec<Message>> {
+        let app = ctx.data::<App>()?;
+        let user_id = user_id.map(UserId::from);
+        app.check_opted_out(&self.id, user_id.as_ref())?;
+        let quota_usage = match ctx.data_opt::<QuotaClient>() {
+            Some(QuotaClient { client, limit }) => Some(
+                app.export_quotas
+                    .acquire_channels(client, &[&self.id], *limit)
+                    .map_err(|_| QUOTA_EXCEEDED_MESSAGE)?,
+            ),
+            None => None,
+        };
+
+        let limit = limit.min(MAX_MESSAGES_LIMIT);
+        let params = LogRangeParams {
+            from,
+            to,
+            logs_params: LogsParams {
+                json: false,
+                json_basic: false,
+                raw: false,
+                reverse,
+                envelope: false,
+                ndjson: false,
+                arrow: false,
+                csv: false,
+                parquet: false,
+                vods: false,
+                sort: MessageSort::default(),
+                sample: None,
+                flagged: false,
+                format: FormatOptions::default(),
+                filter: MessageFilter::default(),
+                limit: Some(limit),
+                offset: Some(offset),
+            },
+        };
+
+        let mut stream = match &user_id {
+            Some(user_id) => {
+                read_user(
+                    &app.db.profile(QueryClass::Logs),
+                    &self.id,
+                    user_id,
+                    params,
+                    &[],
+                    &app.flush_buffer,
+                )
+                .await?
+            }
+            None => {
+                read_channel(
+                    &app.db.profile(QueryClass::Logs),
+                    &self.id,
+                    params,
+                    &[],
+                    &app.flush_buffer,
+                )
+                .await?
+            }
+        };
+
+        let mut messages = Vec::new();
+        while let Some(chunk) = stream.try_next().await? {
+            for msg in &chunk {
+                let message = Message::from_structured(msg)?;
+                if let Some(usage) = &quota_usage {
+                    usage
+                        .consume(message.len())
+                        .map_err(|_| QUOTA_EXCEEDED_MESSAGE)?;
+                }
+                messages.push(message);
+            }
+        }
+
+        Ok(messages)
+    }
+
+    /// Streams which were live at some point in the given range
+    async fn streams(
+        &self,
+        ctx: &Context<'_>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+    ) -> async_graphql::Result<Vec<Stream>> {
+        let app = ctx.data::<App>()?;
+
+        let streams = read_streams(
+            &app.db.profile(QueryClass::Stats),
+            &self.id,
+            RangeParams { from, to },
+        )
+        .await?
+        .into_iter()
+        .map(Stream::from)
+        .collect();
+        Ok(streams)
+    }
+
+    /// Message and chatter counts in the given range, unique chatters are estimated unless `exact` is set
+    async fn summary(
+        &self,
+        ctx: &Context<'_>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        #[graphql(default)] include_bots: bool,
+        #[graphql(default)] exact: bool,
+    ) -> async_graphql::Result<ChannelSummary> {
+        let app = ctx.data::<App>()?;
+        app.check_endpoint_group(EndpointGroup::Stats)?;
+
+        let summary = read_channel_summary(
+            &app.db.profile(QueryClass::Stats),
+            &self.id,
+            RangeParams { from, to },
+            app.stats_excluded_users(include_bots),
+            exact,
+            &app.flush_buffer,
+        )
+        .await?;
+        Ok(summary)
+    }
+
+    /// The users who sent the most messages in the given range
+    async fn top_chatters(
+        &self,
+        ctx: &Context<'_>,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        #[graphql(default = 10)] limit: u64,
+        #[graphql(default)] include_bots: bool,
+    ) -> async_graphql::Result<Vec<TopChatter>> {
+        let app = ctx.data::<App>()?;
+        app.check_endpoint_group(EndpointGroup::Stats)?;
+
+        let chatters = read_top_chatters(
+            &app.db.profile(QueryClass::Stats),
+            &self.id,
+            RangeParams { from, to },
+            app.stats_excluded_users(include_bots),
+            limit.min(MAX_TOP_CHATTERS),
+            0,
+            &app.flush_buffer,
+        )
+        .await?;
+        Ok(chatters)
+    }
+}
+
+#[derive(SimpleObject)]
+pub struct Message {
+    id: String,
+    timestamp: DateTime<Utc>,
+    message_type: String,
+    #[graphql(name = "userID")]
+    user_id: String,
+    user_login: String,
+    display_name: String,
+    text: String,
+    /// The message as it was received over IRC
+    raw: String,
+}
+
+impl Message {
+    fn from_structured(msg: &StructuredMessage) -> async_graphql::Result<Self> {
+        let timestamp = DateTime::from_timestamp_millis(msg.timestamp.try_into()?)
+            .ok_or("Invalid timestamp")?;
+
+        Ok(Self {
+            id: msg.id().unwrap_or_default(),
+            timestamp,
+            message_type: msg.message_type.to_string(),
+            user_id: msg.user_id.to_string(),
+            user_login: msg.user_login.to_string(),
+            display_name: msg.display_name().to_owned(),
+            text: msg.user_friendly_text().into_owned(),
+            raw: msg.to_raw_irc(),
+        })
+    }
+
+    /// Roughly the bytes of the message in the response, counted against the download quota
+    fn len(&self) -> u64 {
+        (self.raw.len() + self.text.len() + self.display_name.len()) as u64
+    }
+}
@@ -1,48 +1,146 @@
 use super::{
-    responders::logs::LogsResponse,
+    logs_source::LogsSource,
+    pagination::Page,
+    responders::logs::{
+        cache_logs_response, cached_logs_response, LiveFormat, LogsResponse, LogsResponseType,
+    },
     schema::{
-        AvailableLogs, AvailableLogsParams, Channel, ChannelIdType, ChannelLogsByDatePath,
-        ChannelParam, ChannelsList, LogsParams, LogsPathChannel, SearchParams, UserLogPathParams,
-        UserLogsPath, UserParam,
+        start_of_day, Announcement, AnnouncementsList, AvailableLogDate, AvailableLogs,
+        AvailableLogsParams, Channel, ChannelIdType, ChannelLogsByDatePath,
+        ChannelLogsSearchParams, ChannelSearchParams, ChannelUser, ChannelUsers,
+        ChannelUsersParams, ChannelsList, ChannelsParams, CombinedAvailableLogs,
+        CombinedAvailableLogsParams, ConsentCode, FormatOptions, HealthStatus, LatestLogsParams,
+        Link, LinksList, LinksParams, LogsParams, LogsPathChannel, LogsQueryBody, MessageFilter,
+        MessageSort, MomentParams, NoncePath, RangeParams, RecentLogsParams, ReplayParams,
+        ResolveUsersBody, ResolvedUser, ResolvedUsers, ScoredSearchMessage, ScoredSearchResults,
+        SearchParams, StreamPath, StreamsList, StreamsParams, SupibotRandomLine, TaskHealth,
+        UserLogPathParams, UserLogsPath,
     },
 };
 use crate::{
-    app::App,
+    app::{logs_cache::LogsCacheKey, App},
     db::{
-        self, read_available_channel_logs, read_available_user_logs, read_channel,
-        read_random_channel_line, read_random_user_line, read_user,
+        self,
+        announcements::read_announcements,
+        channel_users::{read_channel_users, read_user_ids_by_login},
+        links::read_links,
+        logs_query::read_logs_query,
+        nonces::read_messages_by_client_nonce,
+        pool::QueryClass,
+        read_available_user_logs, read_channel, read_channel_day_counts, read_first_time_chatters,
+        read_random_channel_line, read_random_user_line, read_recent_messages, read_user,
+        read_user_month_counts,
+        schema::{MessageType, StructuredMessage},
+        streams::{read_stream, read_stream_range, read_streams},
     },
     error::Error,
-    logs::{schema::LogRangeParams, stream::LogsStream},
+    ids::{ChannelId, UserId},
+    logs::{
+        schema::{
+            message::{FullMessage, ResponseMessage, StreamVods},
+            LogRangeParams,
+        },
+        stream::LogsStream,
+    },
     web::schema::LogsPathDate,
     Result,
 };
 use aide::axum::IntoApiResponse;
 use axum::{
     extract::{Path, Query, RawQuery, State},
-    response::{IntoResponse, Redirect, Response},
+    http::{header::ACCEPT_ENCODING, HeaderMap, StatusCode},
+    response::{
+        sse::{Event, KeepAlive, Sse},
+        IntoResponse, Redirect, Response,
+    },
     Json,
 };
 use axum_extra::{headers::CacheControl, TypedHeader};
-use chrono::{Days, Months, NaiveDate, NaiveTime, Utc};
-use std::time::Duration;
-use tracing::debug;
+use chrono::{DateTime, Days, Months, NaiveDate, Utc};
+use chrono_tz::Tz;
+use futures::{stream, StreamExt, TryStreamExt};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use std::{
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+    str::FromStr,
+    time::Duration,
+};
+use tokio::{
+    sync::broadcast::error::RecvError,
+    time::{sleep_until, Instant},
+};
+use tracing::{debug, error};
 
-pub async fn get_channels(app: State<App>) -> impl IntoApiResponse {
-    let channel_ids = app.config.channels.read().unwrap().clone();
+const DEFAULT_CHANNEL_SEARCH_LIMIT: u64 = 20;
+const MAX_CHANNEL_SEARCH_LIMIT: u64 = 100;
+const DEFAULT_USER_SEARCH_LIMIT: u64 = 20;
+const MAX_USER_SEARCH_LIMIT: u64 = 100;
+/// Helix resolves at most 100 logins per request
+const MAX_RESOLVED_USERS: usize = 100;
+/// The client nonce is not indexed, so lookups scan the whole channel in the range
+const MAX_NONCE_RANGE_DAYS: i64 = 7;
+const MAX_NONCE_MESSAGES: u64 = 100;
+const MAX_QUERY_CHANNELS: usize = 100;
+const DEFAULT_RECENT_LIMIT: u64 = 200;
+const MAX_RECENT_LIMIT: u64 = 1000;
+/// Deeper pages would load the whole history of a user, `before` pages back without reading the skipped messages
+const MAX_RECENT_OFFSET: u64 = 10_000;
+const DEFAULT_RELEVANCE_LIMIT: u64 = 100;
+const MAX_RELEVANCE_LIMIT: u64 = 1000;
+const DEFAULT_MOMENT_WINDOW_SECONDS: u64 = 60;
+const MAX_MOMENT_WINDOW_SECONDS: u64 = 600;
+/// Cache duration of today's logs while the channel is live
+const LIVE_LOGS_CACHE_SECONDS: u64 = 10;
+/// Cache duration of today's logs while the channel is offline
+const OFFLINE_LOGS_CACHE_SECONDS: u64 = 300;
+/// Time after the end of a day until its logs are cached, so late writes are included
+const LOGS_CACHE_DELAY_SECONDS: i64 = 3600;
+const MIN_REPLAY_SPEED: f64 = 0.1;
+const MAX_REPLAY_SPEED: f64 = 100.0;
+const MAX_REPLAY_HOURS: i64 = 48;
+/// Messages of a replay are read this many minutes at a time, just before they are sent
+const REPLAY_WINDOW_MINUTES: i64 = 5;
+const CONSENT_CODE_LENGTH: usize = 12;
 
-    let channels = app
-        .get_users(Vec::from_iter(channel_ids), vec![], false)
-        .await
-        .unwrap();
+pub async fn get_channels(
+    app: State<App>,
+    Query(params): Query<ChannelsParams>,
+) -> Result<impl IntoApiResponse> {
+    let mut channel_ids = app.config.channels.read().unwrap().clone();
+    let archived_ids = if params.include_archived {
+        app.config.archived_channels.read().unwrap().clone()
+    } else {
+        HashSet::new()
+    };
+    channel_ids.extend(archived_ids.iter().cloned());
+
+    let channels = app.get_channel_logins(Vec::from_iter(channel_ids)).await?;
 
     let json = Json(ChannelsList {
         channels: channels
             .into_iter()
-            .map(|(user_id, name)| Channel { name, user_id })
+            .map(|(user_id, name)| Channel {
+                archived: archived_ids.contains(&user_id),
+                name,
+                user_id,
+            })
             .collect(),
     });
-    (cache_header(600), json)
+    Ok((cache_header(600), json))
+}
+
+pub async fn search_channels(
+    app: State<App>,
+    Query(params): Query<ChannelSearchParams>,
+) -> impl IntoApiResponse {
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_CHANNEL_SEARCH_LIMIT)
+        .min(MAX_CHANNEL_SEARCH_LIMIT);
+    let channels = app.channel_index.search(&params.q, limit as usize);
+
+    (cache_header(600), Json(ChannelsList { channels }))
 }
 
 pub async fn get_channel_logs(
@@ -51,54 +149,92 @@ pub async fn get_channel_logs(
         channel,
     }): Path<LogsPathChannel>,
     range_params: Option<Query<LogRangeParams>>,
+    latest_params: Option<Query<LatestLogsParams>>,
     RawQuery(query): RawQuery,
     app: State<App>,
 ) -> Result<Response> {
-    let channel_id = match channel_id_type {
-        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
-        ChannelIdType::Id => channel.clone(),
-    };
+    let channel_id = app
+        .resolve_channel_id(channel_id_type, channel.clone())
+        .await?;
 
     if let Some(Query(params)) = range_params {
         let logs = get_channel_logs_inner(&app, &channel_id, params).await?;
         Ok(logs.into_response())
     } else {
-        let available_logs = read_available_channel_logs(&app.db, &channel_id).await?;
-        let latest_log = available_logs.first().ok_or(Error::NotFound)?;
+        let timezone = app.config.channel_timezone(channel_id.as_str());
+        let db = &app.db.profile(QueryClass::Logs);
+        let latest_log = latest_log_date(db, &channel_id, None, timezone)
+            .await
+            .map_err(|err| app.explain_not_found(err, &channel_id))?;
+
+        if let Some(Query(LatestLogsParams {
+            latest: true,
+            logs_params,
+        })) = latest_params
+        {
+            let (from, to) = latest_log.range(timezone).ok_or(Error::Internal)?;
+            let params = LogRangeParams {
+                from,
+                to,
+                logs_params,
+            };
+            let logs = get_channel_logs_inner(&app, &channel_id, params).await?;
+            return Ok(logs.into_response());
+        }
+
+        let new_uri = format!("/{channel_id_type}/{channel}/{latest_log}");
+        Ok(redirect_with_query(new_uri, query.as_deref()))
+    }
+}
 
-        let mut new_uri = format!("/{channel_id_type}/{channel}/{latest_log}");
-        if let Some(query) = query {
-            new_uri.push('?');
-            new_uri.push_str(&query);
+/// The newest day or month with logs of the channel, or of the user in it
+async fn latest_log_date(
+    source: &impl LogsSource,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    timezone: Tz,
+) -> Result<AvailableLogDate> {
+    let available_logs = match user_id {
+        Some(user_id) => {
+            source
+                .available_user_logs(channel_id, user_id, timezone)
+                .await?
         }
+        None => source.available_channel_logs(channel_id, timezone).await?,
+    };
+    available_logs.into_iter().next().ok_or(Error::NotFound)
+}
 
-        Ok(Redirect::to(&new_uri).into_response())
+/// Keeps the query of the original request, so the format params still apply
+fn redirect_with_query(mut uri: String, query: Option<&str>) -> Response {
+    if let Some(query) = query {
+        uri.push('?');
+        uri.push_str(query);
     }
+    Redirect::to(&uri).into_response()
 }
 
 pub async fn get_channel_logs_by_date(
     app: State<App>,
-    Path(channel_log_params): Path<ChannelLogsByDatePath>,
+    Path(ChannelLogsByDatePath { channel_info, date }): Path<ChannelLogsByDatePath>,
     Query(logs_params): Query<LogsParams>,
-) -> Result<impl IntoApiResponse> {
+    headers: HeaderMap,
+) -> Result<Response> {
     debug!("Params: {logs_params:?}");
 
-    let channel_id = match channel_log_params.channel_info.channel_id_type {
-        ChannelIdType::Name => {
-            app.get_user_id_by_name(&channel_log_params.channel_info.channel)
-                .await?
-        }
-        ChannelIdType::Id => channel_log_params.channel_info.channel.clone(),
-    };
+    let channel_id = app
+        .resolve_channel_id(channel_info.channel_id_type, channel_info.channel)
+        .await?;
 
-    let LogsPathDate { year, month, day } = channel_log_params.date;
+    let LogsPathDate { year, month, day } = date;
 
-    let from = NaiveDate::from_ymd_opt(year.parse()?, month.parse()?, day.parse()?)
-        .ok_or_else(|| Error::InvalidParam("Invalid date".to_owned()))?
-        .and_time(NaiveTime::default())
-        .and_utc();
-    let to = from
+    let date = NaiveDate::from_ymd_opt(year.parse()?, month.parse()?, day.parse()?)
+        .ok_or_else(|| Error::InvalidParam("Invalid date".to_owned()))?;
+    let timezone = app.config.channel_timezone(channel_id.as_str());
+    let from = start_of_day(date, timezone);
+    let to = date
         .checked_add_days(Days::new(1))
+        .map(|next_day| start_of_day(next_day, timezone))
         .ok_or_else(|| Error::InvalidParam("Date out of range".to_owned()))?;
 
     let params = LogRangeParams {
@@ -107,48 +243,120 @@ pub async fn get_channel_logs_by_date(
         logs_params,
     };
 
-    get_channel_logs_inner(&app, &channel_id, params).await
+    // Only complete days are cached, paginated requests are rare enough to always be read from the database
+    let cache = app.logs_cache.clone().filter(|_| {
+        Utc::now() - to > chrono::Duration::seconds(LOGS_CACHE_DELAY_SECONDS)
+            && logs_params.limit.is_none()
+            && logs_params.offset.is_none()
+            && !logs_params.flagged
+            && !logs_params.vods
+            && logs_params.sort == MessageSort::Timestamp
+            && logs_params.sample.is_none()
+            && logs_params.format == FormatOptions::default()
+            && logs_params.filter == MessageFilter::default()
+    });
+    let Some(cache) = cache else {
+        let logs = get_channel_logs_inner(&app, &channel_id, params).await?;
+        return Ok(logs.into_response());
+    };
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let response_type = logs_params.response_type(&app.config)?;
+    let key = LogsCacheKey {
+        channel_id: channel_id.to_string(),
+        date,
+        timezone,
+        format: response_type.name(),
+        reverse: logs_params.reverse,
+    };
+
+    if let Some(blob) = cache.get(&key).await {
+        let accepts_gzip = headers
+            .get_all(ACCEPT_ENCODING)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .any(|value| value.contains("gzip"));
+        let response = cached_logs_response(blob, &response_type, accepts_gzip);
+        return Ok((cache_header(36000), response).into_response());
+    }
+
+    let logs = get_channel_logs_inner(&app, &channel_id, params).await?;
+    Ok(cache_logs_response(logs.into_response(), cache, key))
 }
 
 async fn get_channel_logs_inner(
     app: &App,
-    channel_id: &str,
+    channel_id: &ChannelId,
     channel_log_params: LogRangeParams,
 ) -> Result<impl IntoApiResponse> {
     app.check_opted_out(channel_id, None)?;
 
-    let stream = read_channel(&app.db, channel_id, channel_log_params, &app.flush_buffer).await?;
+    let excluded_users = app.logs_excluded_users(&channel_log_params.logs_params);
+    let stream = app
+        .db
+        .read_with_failover(QueryClass::Logs, |db| async move {
+            read_channel(
+                &db,
+                channel_id,
+                channel_log_params,
+                excluded_users,
+                &app.flush_buffer,
+            )
+            .await
+        })
+        .await
+        .map_err(|err| app.explain_not_found(err, channel_id))?;
 
+    let page = channel_log_params.logs_params.page();
     let logs = LogsResponse {
-        response_type: channel_log_params.logs_params.response_type(),
+        response_type: logs_response_type(app, channel_id, &channel_log_params).await?,
         stream,
     };
 
-    let cache = if Utc::now() < channel_log_params.to {
-        no_cache_header()
-    } else {
-        cache_header(36000)
-    };
+    let cache = channel_logs_cache_header(app, channel_id, channel_log_params.to);
+
+    Ok((cache, page, logs))
+}
+
+/// Also reads the streams of the range if VOD links were requested
+async fn logs_response_type(
+    app: &App,
+    channel_id: &ChannelId,
+    params: &LogRangeParams,
+) -> Result<LogsResponseType> {
+    let mut response_type = params.logs_params.response_type(&app.config)?;
+
+    if params.logs_params.vods {
+        let range = RangeParams {
+            from: params.from,
+            to: params.to,
+        };
+        let streams = read_streams(&app.db.profile(QueryClass::Logs), channel_id, range).await?;
+        response_type.vods = Some(StreamVods::new(streams, app.config.streams.interval as u32));
+    }
 
-    Ok((cache, logs))
+    Ok(response_type)
 }
 
 pub async fn get_user_logs_by_name(
     path: Path<UserLogPathParams>,
     range_params: Option<Query<LogRangeParams>>,
+    latest_params: Option<Query<LatestLogsParams>>,
     query: RawQuery,
     app: State<App>,
 ) -> Result<impl IntoApiResponse> {
-    get_user_logs(path, range_params, query, false, app).await
+    get_user_logs(path, range_params, latest_params, query, false, app).await
 }
 
 pub async fn get_user_logs_id(
     path: Path<UserLogPathParams>,
     range_params: Option<Query<LogRangeParams>>,
+    latest_params: Option<Query<LatestLogsParams>>,
     query: RawQuery,
     app: State<App>,
 ) -> Result<impl IntoApiResponse> {
-    get_user_logs(path, range_params, query, true, app).await
+    get_user_logs(path, range_params, latest_params, query, true, app).await
 }
 
 async fn get_user_logs(
@@ -158,36 +366,49 @@ async fn get_user_logs(
         user,
     }): Path<UserLogPathParams>,
     range_params: Option<Query<LogRangeParams>>,
+    latest_params: Option<Query<LatestLogsParams>>,
     RawQuery(query): RawQuery,
     user_is_id: bool,
     app: State<App>,
 ) -> Result<impl IntoApiResponse> {
-    let channel_id = match channel_id_type {
-        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
-        ChannelIdType::Id => channel.clone(),
-    };
-    let user_id = if user_is_id {
+    let channel_id = app
+        .resolve_channel_id(channel_id_type, channel.clone())
+        .await?;
+    let user_id = UserId::from(if user_is_id {
         user.clone()
     } else {
         app.get_user_id_by_name(&user).await?
-    };
+    });
 
     if let Some(Query(params)) = range_params {
         let logs = get_user_logs_inner(&app, &channel_id, &user_id, params).await?;
         Ok(logs.into_response())
     } else {
-        let available_logs = read_available_user_logs(&app.db, &channel_id, &user_id).await?;
-        let latest_log = available_logs.first().ok_or(Error::NotFound)?;
+        let timezone = app.config.channel_timezone(channel_id.as_str());
+        let db = &app.db.profile(QueryClass::Logs);
+        let latest_log = latest_log_date(db, &channel_id, Some(&user_id), timezone)
+            .await
+            .map_err(|err| app.explain_not_found(err, &channel_id))?;
+
+        if let Some(Query(LatestLogsParams {
+            latest: true,
+            logs_params,
+        })) = latest_params
+        {
+            let (from, to) = latest_log.range(timezone).ok_or(Error::Internal)?;
+            let params = LogRangeParams {
+                from,
+                to,
+                logs_params,
+            };
+            let logs = get_user_logs_inner(&app, &channel_id, &user_id, params).await?;
+            return Ok(logs.into_response());
+        }
 
         let user_id_type = if user_is_id { "userid" } else { "user" };
 
-        let mut new_uri =
-            format!("/{channel_id_type}/{channel}/{user_id_type}/{user}/{latest_log}");
-        if let Some(query) = query {
-            new_uri.push('?');
-            new_uri.push_str(&query);
-        }
-        Ok(Redirect::to(&new_uri).into_response())
+        let new_uri = format!("/{channel_id_type}/{channel}/{user_id_type}/{user}/{latest_log}");
+        Ok(redirect_with_query(new_uri, query.as_deref()))
     }
 }
 
@@ -198,7 +419,7 @@ pub async fn get_user_logs_by_date_name(
 ) -> Result<impl IntoApiResponse> {
     let user_id = app.get_user_id_by_name(&path.user).await?;
 
-    get_user_logs_by_date(app, path, params, user_id).await
+    get_user_logs_by_date(app, path, params, user_id.into()).await
 }
 
 pub async fn get_user_logs_by_date_id(
@@ -206,7 +427,7 @@ pub async fn get_user_logs_by_date_id(
     path: Path<UserLogsPath>,
     params: Query<LogsParams>,
 ) -> Result<impl IntoApiResponse> {
-    let user_id = path.user.clone();
+    let user_id = UserId::from(path.user.as_str());
     get_user_logs_by_date(app, path, params, user_id).await
 }
 
@@ -214,25 +435,25 @@ async fn get_user_logs_by_date(
     app: State<App>,
     Path(user_logs_path): Path<UserLogsPath>,
     Query(logs_params): Query<LogsParams>,
-    user_id: String,
+    user_id: UserId,
 ) -> Result<impl IntoApiResponse> {
-    let channel_id = match user_logs_path.channel_info.channel_id_type {
-        ChannelIdType::Name => {
-            app.get_user_id_by_name(&user_logs_path.channel_info.channel)
-                .await?
-        }
-        ChannelIdType::Id => user_logs_path.channel_info.channel.clone(),
-    };
+    let channel_id = app
+        .resolve_channel_id(
+            user_logs_path.channel_info.channel_id_type,
+            user_logs_path.channel_info.channel,
+        )
+        .await?;
 
     let year = user_logs_path.year.parse()?;
     let month = user_logs_path.month.parse()?;
 
-    let from = NaiveDate::from_ymd_opt(year, month, 1)
-        .ok_or_else(|| Error::InvalidParam("Invalid date".to_owned()))?
-        .and_time(NaiveTime::default())
-        .and_utc();
-    let to = from
+    let timezone = app.config.channel_timezone(channel_id.as_str());
+    let date = NaiveDate::from_ymd_opt(year, month, 1)
+        .ok_or_else(|| Error::InvalidParam("Invalid date".to_owned()))?;
+    let from = start_of_day(date, timezone);
+    let to = date
         .checked_add_months(Months::new(1))
+        .map(|next_month| start_of_day(next_month, timezone))
         .ok_or_else(|| Error::InvalidParam("Date out of range".to_owned()))?;
 
     let params = LogRangeParams {
@@ -246,17 +467,33 @@ async fn get_user_logs_by_date(
 
 async fn get_user_logs_inner(
     app: &App,
-    channel_id: &str,
-    user_id: &str,
+    channel_id: &ChannelId,
+    user_id: &UserId,
     log_params: LogRangeParams,
 ) -> Result<impl IntoApiResponse> {
     app.check_opted_out(channel_id, Some(user_id))?;
 
-    let stream = read_user(&app.db, channel_id, user_id, log_params, &app.flush_buffer).await?;
+    let excluded_users = app.logs_excluded_users(&log_params.logs_params);
+    let stream = app
+        .db
+        .read_with_failover(QueryClass::Logs, |db| async move {
+            read_user(
+                &db,
+                channel_id,
+                user_id,
+                log_params,
+                excluded_users,
+                &app.flush_buffer,
+            )
+            .await
+        })
+        .await
+        .map_err(|err| app.explain_not_found(err, channel_id))?;
 
+    let page = log_params.logs_params.page();
     let logs = LogsResponse {
         stream,
-        response_type: log_params.logs_params.response_type(),
+        response_type: logs_response_type(app, channel_id, &log_params).await?,
     };
 
     let cache = if Utc::now() < log_params.to {
@@ -265,25 +502,26 @@ async fn get_user_logs_inner(
         cache_header(36000)
     };
 
-    Ok((cache, logs))
+    Ok((cache, page, logs))
 }
 
 pub async fn list_available_logs(
     Query(AvailableLogsParams { user, channel }): Query<AvailableLogsParams>,
     app: State<App>,
 ) -> Result<impl IntoApiResponse> {
-    let channel_id = match channel {
-        ChannelParam::ChannelId(id) => id,
-        ChannelParam::Channel(name) => app.get_user_id_by_name(&name).await?,
-    };
+    let channel_id = app.resolve_channel_param(channel).await?;
 
     let available_logs = if let Some(user) = user {
-        let user_id = match user {
-            UserParam::UserId(id) => id,
-            UserParam::User(name) => app.get_user_id_by_name(&name).await?,
-        };
+        let user_id = app.resolve_user_param(user).await?;
         app.check_opted_out(&channel_id, Some(&user_id))?;
-        read_available_user_logs(&app.db, &channel_id, &user_id).await?
+        let timezone = app.config.channel_timezone(channel_id.as_str());
+        read_available_user_logs(
+            &app.db.profile(QueryClass::Logs),
+            &channel_id,
+            &user_id,
+            timezone,
+        )
+        .await?
     } else {
         return Err(Error::NotFound);
         // app.check_opted_out(&channel_id, None)?;
@@ -293,8 +531,36 @@ pub async fn list_available_logs(
     if !available_logs.is_empty() {
         Ok((cache_header(600), Json(AvailableLogs { available_logs })))
     } else {
-        Err(Error::NotFound)
+        Err(app.explain_not_found(Error::NotFound, &channel_id))
+    }
+}
+
+/// The channel's days and the user's months in one response, which a log viewer needs to open a user page
+pub async fn combined_available_logs(
+    Query(CombinedAvailableLogsParams { channel, user }): Query<CombinedAvailableLogsParams>,
+    app: State<App>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_param(channel).await?;
+    let user_id = app.resolve_user_param(user).await?;
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let timezone = app.config.channel_timezone(channel_id.as_str());
+    let db = &app.db.profile(QueryClass::Logs);
+    let (channel_logs, user_logs) = futures::try_join!(
+        read_channel_day_counts(db, &channel_id, timezone),
+        read_user_month_counts(db, &channel_id, &user_id, timezone),
+    )?;
+
+    if channel_logs.is_empty() {
+        return Err(app.explain_not_found(Error::NotFound, &channel_id));
     }
+    Ok((
+        cache_header(600),
+        Json(CombinedAvailableLogs {
+            channel_logs,
+            user_logs,
+        }),
+    ))
 }
 
 pub async fn random_channel_line(
@@ -305,21 +571,332 @@ pub async fn random_channel_line(
     }): Path<LogsPathChannel>,
     Query(logs_params): Query<LogsParams>,
 ) -> Result<impl IntoApiResponse> {
-    let channel_id = match channel_id_type {
-        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
-        ChannelIdType::Id => channel,
-    };
-
-    let random_line = read_random_channel_line(&app.db, &channel_id).await?;
+    let random_line = read_random_channel_message(&app, channel_id_type, channel).await?;
     let stream = LogsStream::new_provided(vec![random_line])?;
 
     let logs = LogsResponse {
         stream,
-        response_type: logs_params.response_type(),
+        response_type: logs_params.response_type(&app.config)?,
     };
     Ok((no_cache_header(), logs))
 }
 
+pub async fn supibot_random_channel_line(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+) -> Result<impl IntoApiResponse> {
+    let random_line = read_random_channel_message(&app, channel_id_type, channel).await?;
+    Ok((no_cache_header(), Json(supibot_line(&random_line)?)))
+}
+
+async fn read_random_channel_message(
+    app: &App,
+    channel_id_type: ChannelIdType,
+    channel: String,
+) -> Result<StructuredMessage<'static>> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+    app.check_opted_out(&channel_id, None)?;
+
+    read_random_channel_line(
+        &app.db.profile(QueryClass::Logs),
+        &channel_id,
+        &app.opted_out_ids(),
+        app.consented_ids().as_deref(),
+    )
+    .await
+}
+
+pub async fn get_first_time_chatters(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<LogRangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let stream =
+        read_first_time_chatters(&app.db.profile(QueryClass::Logs), &channel_id, params).await?;
+
+    let page = params.logs_params.page();
+    let logs = LogsResponse {
+        stream,
+        response_type: params.logs_params.response_type(&app.config)?,
+    };
+
+    let cache = if Utc::now() < params.to {
+        no_cache_header()
+    } else {
+        cache_header(36000)
+    };
+
+    Ok((cache, page, logs))
+}
+
+pub async fn list_links(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<LinksParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let links = read_links(&app.db.profile(QueryClass::Logs), &channel_id, &params)
+        .await?
+        .into_iter()
+        .map(|row| Link {
+            user_id: row.user_id,
+            user_login: row.user_login,
+            timestamp: DateTime::from_timestamp_millis(row.timestamp as i64).unwrap_or_default(),
+            domain: row.domain,
+            url: row.url,
+        })
+        .collect();
+
+    let cache = if Utc::now() < params.range.to {
+        no_cache_header()
+    } else {
+        cache_header(36000)
+    };
+
+    Ok((cache, Json(LinksList { links })))
+}
+
+pub async fn list_announcements(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let announcements = read_announcements(&app.db.profile(QueryClass::Logs), &channel_id, params)
+        .await?
+        .into_iter()
+        .map(|row| Announcement {
+            user_id: row.user_id,
+            user_login: row.user_login,
+            timestamp: DateTime::from_timestamp_millis(row.timestamp as i64).unwrap_or_default(),
+            text: row.text,
+            color: row.color,
+        })
+        .collect();
+
+    let cache = if Utc::now() < params.to {
+        no_cache_header()
+    } else {
+        cache_header(36000)
+    };
+
+    Ok((cache, Json(AnnouncementsList { announcements })))
+}
+
+pub async fn health_ready(app: State<App>) -> impl IntoApiResponse {
+    let tasks: Vec<_> = app
+        .liveness
+        .tasks()
+        .into_iter()
+        .map(|task| TaskHealth {
+            name: task.name.to_owned(),
+            seconds_since_heartbeat: task.seconds_since_heartbeat,
+            alive: task.alive,
+        })
+        .collect();
+    // A stuck task would not recover on its own, so orchestrators should restart the process
+    let status = HealthStatus {
+        ready: app.db.is_ready() && tasks.iter().all(|task| task.alive),
+        nodes: app.db.health(),
+        tasks,
+    };
+    let status_code = if status.ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (status_code, no_cache_header(), Json(status))
+}
+
+pub async fn search_channel_users(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<ChannelUsersParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+    if app.config.user_logs_disabled(channel_id.as_str()) {
+        return Err(Error::UserLogsDisabled);
+    }
+
+    let prefix = params.prefix.trim().to_lowercase();
+    if prefix.is_empty() {
+        return Err(Error::InvalidParam("Prefix must not be empty".to_owned()));
+    }
+
+    let limit = params
+        .limit
+        .unwrap_or(DEFAULT_USER_SEARCH_LIMIT)
+        .min(MAX_USER_SEARCH_LIMIT);
+    let opted_out = app.opted_out_ids();
+
+    let users = read_channel_users(
+        &app.db.profile(QueryClass::Logs),
+        &channel_id,
+        &prefix,
+        &opted_out,
+        limit,
+    )
+    .await?
+    .into_iter()
+    .map(|row| ChannelUser {
+        user_id: row.user_id,
+        user_login: row.user_login,
+        last_seen: DateTime::from_timestamp(row.last_seen.into(), 0).unwrap_or_default(),
+    })
+    .collect();
+
+    Ok((cache_header(60), Json(ChannelUsers { users })))
+}
+
+pub async fn list_streams(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StreamsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let streams =
+        read_streams(&app.db.profile(QueryClass::Logs), &channel_id, params.range).await?;
+    let total = streams.len() as u64;
+    let offset = params.offset.unwrap_or(0);
+    let streams: Vec<_> = streams
+        .into_iter()
+        .skip(offset as usize)
+        .take(params.limit.unwrap_or(u64::MAX) as usize)
+        .map(Into::into)
+        .collect();
+
+    let has_more = offset.saturating_add(streams.len() as u64) < total;
+    let page = Page::new(params.limit, params.offset, has_more);
+
+    let cache = if Utc::now() < params.range.to {
+        no_cache_header()
+    } else {
+        cache_header(36000)
+    };
+
+    Ok((cache, page, Json(StreamsList { streams })))
+}
+
+pub async fn get_moment_logs(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<MomentParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let moment = match (params.timestamp, &params.stream_id) {
+        (Some(timestamp), _) => timestamp,
+        (None, Some(stream_id)) => {
+            let stream = read_stream(&app.db.profile(QueryClass::Logs), &channel_id, stream_id)
+                .await?
+                .ok_or(Error::NotFound)?;
+            let started_at =
+                DateTime::from_timestamp(stream.started_at.into(), 0).ok_or(Error::Internal)?;
+            i64::try_from(params.stream_offset.unwrap_or(0))
+                .ok()
+                .and_then(chrono::Duration::try_seconds)
+                .and_then(|offset| started_at.checked_add_signed(offset))
+                .ok_or_else(|| Error::InvalidParam("streamOffset is too large".to_owned()))?
+        }
+        (None, None) => {
+            return Err(Error::InvalidParam(
+                "Either timestamp or streamID is required".to_owned(),
+            ))
+        }
+    };
+
+    let window = params
+        .window
+        .unwrap_or(DEFAULT_MOMENT_WINDOW_SECONDS)
+        .min(MAX_MOMENT_WINDOW_SECONDS);
+    let window = chrono::Duration::seconds(window as i64);
+
+    let (Some(from), Some(to)) = (
+        moment.checked_sub_signed(window),
+        moment.checked_add_signed(window),
+    ) else {
+        return Err(Error::InvalidParam("The moment is out of range".to_owned()));
+    };
+
+    let range_params = LogRangeParams {
+        from,
+        to,
+        logs_params: params.logs_params,
+    };
+    get_channel_logs_inner(&app, &channel_id, range_params).await
+}
+
+/// Messages during the stream, from its start until it was last seen live.
+/// See [`read_stream_range`] for how the end is determined
+pub async fn get_stream_logs(
+    app: State<App>,
+    Path(StreamPath {
+        channel_id_type,
+        channel,
+        stream_id,
+    }): Path<StreamPath>,
+    Query(logs_params): Query<LogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let range = read_stream_range(
+        &app.db.profile(QueryClass::Logs),
+        &channel_id,
+        &stream_id,
+        app.config.streams.interval as u32,
+    )
+    .await?
+    .ok_or(Error::NotFound)?;
+
+    let range_params = LogRangeParams {
+        from: range.from,
+        to: range.to,
+        logs_params,
+    };
+    get_channel_logs_inner(&app, &channel_id, range_params).await
+}
+
 pub async fn random_user_line_by_name(
     app: State<App>,
     Path(UserLogPathParams {
@@ -330,7 +907,7 @@ pub async fn random_user_line_by_name(
     query: Query<LogsParams>,
 ) -> Result<impl IntoApiResponse> {
     let user_id = app.get_user_id_by_name(&user).await?;
-    random_user_line(app, channel_id_type, channel, user_id, query).await
+    random_user_line(app, channel_id_type, channel, user_id.into(), query).await
 }
 
 pub async fn random_user_line_by_id(
@@ -342,35 +919,521 @@ pub async fn random_user_line_by_id(
     }): Path<UserLogPathParams>,
     query: Query<LogsParams>,
 ) -> Result<impl IntoApiResponse> {
-    random_user_line(app, channel_id_type, channel, user, query).await
+    random_user_line(app, channel_id_type, channel, user.into(), query).await
 }
 
 async fn random_user_line(
     app: State<App>,
     channel_id_type: ChannelIdType,
     channel: String,
-    user_id: String,
+    user_id: UserId,
     Query(logs_params): Query<LogsParams>,
 ) -> Result<impl IntoApiResponse> {
-    let channel_id = match channel_id_type {
-        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
-        ChannelIdType::Id => channel,
+    let random_line = read_random_user_message(&app, channel_id_type, channel, user_id).await?;
+    let stream = LogsStream::new_provided(vec![random_line])?;
+
+    let logs = LogsResponse {
+        stream,
+        response_type: logs_params.response_type(&app.config)?,
     };
+    Ok((no_cache_header(), logs))
+}
+
+pub async fn supibot_random_user_line_by_name(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+) -> Result<impl IntoApiResponse> {
+    let user_id = app.get_user_id_by_name(&user).await?;
+    let random_line =
+        read_random_user_message(&app, channel_id_type, channel, user_id.into()).await?;
+    Ok((no_cache_header(), Json(supibot_line(&random_line)?)))
+}
+
+pub async fn supibot_random_user_line_by_id(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+) -> Result<impl IntoApiResponse> {
+    let random_line = read_random_user_message(&app, channel_id_type, channel, user.into()).await?;
+    Ok((no_cache_header(), Json(supibot_line(&random_line)?)))
+}
+
+async fn read_random_user_message(
+    app: &App,
+    channel_id_type: ChannelIdType,
+    channel: String,
+    user_id: UserId,
+) -> Result<StructuredMessage<'static>> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
 
     app.check_opted_out(&channel_id, Some(&user_id))?;
 
-    let random_line = read_random_user_line(&app.db, &channel_id, &user_id).await?;
-    let stream = LogsStream::new_provided(vec![random_line])?;
+    read_random_user_line(&app.db.profile(QueryClass::Logs), &channel_id, &user_id).await
+}
+
+fn supibot_line(msg: &StructuredMessage) -> Result<SupibotRandomLine> {
+    Ok(SupibotRandomLine {
+        channel: msg.channel_login.to_string(),
+        username: msg.user_login.to_string(),
+        display_name: msg.display_name().to_owned(),
+        text: msg.user_friendly_text().into_owned(),
+        timestamp: DateTime::from_timestamp_millis(msg.timestamp as i64).ok_or(Error::Internal)?,
+    })
+}
+
+pub async fn recent_channel_logs(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    query: Query<RecentLogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    recent_logs(app, channel_id, None, query).await
+}
+
+pub async fn recent_user_logs_by_name(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+    query: Query<RecentLogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let user_id = app.get_user_id_by_name(&user).await?;
+    recent_user_logs(app, channel_id_type, channel, user_id.into(), query).await
+}
+
+pub async fn recent_user_logs_by_id(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+    query: Query<RecentLogsParams>,
+) -> Result<impl IntoApiResponse> {
+    recent_user_logs(app, channel_id_type, channel, user.into(), query).await
+}
 
+async fn recent_user_logs(
+    app: State<App>,
+    channel_id_type: ChannelIdType,
+    channel: String,
+    user_id: UserId,
+    query: Query<RecentLogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    recent_logs(app, channel_id, Some(user_id), query).await
+}
+
+/// The latest messages without a date range. `offset` skips the newest messages.
+/// With `before` the messages are always returned newest first, so a client can keep scrolling back
+async fn recent_logs(
+    app: State<App>,
+    channel_id: ChannelId,
+    user_id: Option<UserId>,
+    Query(RecentLogsParams {
+        before,
+        before_id,
+        logs_params,
+    }): Query<RecentLogsParams>,
+) -> Result<impl IntoApiResponse> {
+    let limit = logs_params
+        .limit
+        .unwrap_or(DEFAULT_RECENT_LIMIT)
+        .min(MAX_RECENT_LIMIT);
+    let offset = logs_params.offset.unwrap_or(0);
+    if offset > MAX_RECENT_OFFSET {
+        return Err(Error::InvalidParam(format!(
+            "offset can be at most {MAX_RECENT_OFFSET}, use before to page further back"
+        )));
+    }
+
+    let mut messages = read_recent_messages(
+        &app.db.profile(QueryClass::Logs),
+        &channel_id,
+        user_id.as_ref(),
+        before.unwrap_or_else(Utc::now),
+        before_id,
+        limit.saturating_add(offset),
+        &app.flush_buffer,
+    )
+    .await?;
+    messages.drain(..messages.len().min(offset as usize));
+    if before.is_none() && !logs_params.reverse {
+        messages.reverse();
+    }
+
+    let page = Page::new(
+        Some(limit),
+        logs_params.offset,
+        messages.len() as u64 == limit,
+    );
     let logs = LogsResponse {
-        stream,
-        response_type: logs_params.response_type(),
+        stream: LogsStream::new_provided(messages)?,
+        response_type: logs_params.response_type(&app.config)?,
+    };
+
+    let cache = match before {
+        Some(before)
+            if Utc::now() - before > chrono::Duration::seconds(LOGS_CACHE_DELAY_SECONDS) =>
+        {
+            cache_header(36000)
+        }
+        _ => no_cache_header(),
+    };
+    Ok((cache, page, logs))
+}
+
+/// Streams the messages of the channel as server-sent events while they are logged.
+/// The message filters apply and messages of opted out users are skipped.
+/// If the client falls behind, a `lagged` event with the amount of skipped messages is sent instead
+pub async fn live_channel_logs(
+    State(app): State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(logs_params): Query<LogsParams>,
+) -> Result<impl IntoResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+    app.check_opted_out(&channel_id, None)?;
+
+    let response_type = logs_params.response_type(&app.config)?;
+    let format = LiveFormat::new(response_type.formatter.name(), response_type.options)?;
+
+    let live_rx = app.live_tx.subscribe();
+    let events = stream::unfold(live_rx, move |mut live_rx| {
+        let app = app.clone();
+        let channel_id = channel_id.clone();
+        async move {
+            loop {
+                let event = match live_rx.recv().await {
+                    Ok(msg) => {
+                        let excluded_users = app.logs_excluded_users(&logs_params);
+                        if *msg.channel_id != *channel_id.as_str()
+                            || !logs_params.filter.matches(&msg)
+                            || excluded_users.iter().any(|user_id| *user_id == msg.user_id)
+                            || app.has_opted_out(&msg.user_id)
+                        {
+                            continue;
+                        }
+                        match format.render(&msg) {
+                            Ok(data) => Event::default().data(data),
+                            Err(err) => {
+                                error!("Could not format live message {msg:?}: {err}");
+                                continue;
+                            }
+                        }
+                    }
+                    Err(RecvError::Lagged(count)) => {
+                        Event::default().event("lagged").data(count.to_string())
+                    }
+                    Err(RecvError::Closed) => return None,
+                };
+                return Some((Ok::<_, Infallible>(event), live_rx));
+            }
+        }
+    });
+
+    Ok((
+        no_cache_header(),
+        Sse::new(events).keep_alive(KeepAlive::default()),
+    ))
+}
+
+/// Replays the channel's messages in the range as server-sent events, paced by their original
+/// timestamps divided by `speed`. Messages are read in windows, so the query doesn't have to stay open
+/// for the whole replay. An `end` event is sent after the last message
+pub async fn replay_channel_logs(
+    State(app): State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<ReplayParams>,
+) -> Result<impl IntoResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+    app.check_opted_out(&channel_id, None)?;
+
+    let speed = params.speed.unwrap_or(1.0);
+    if !(MIN_REPLAY_SPEED..=MAX_REPLAY_SPEED).contains(&speed) {
+        return Err(Error::InvalidParam(format!(
+            "speed has to be between {MIN_REPLAY_SPEED} and {MAX_REPLAY_SPEED}"
+        )));
+    }
+    let from = params.from;
+    let max_to = from + chrono::Duration::hours(MAX_REPLAY_HOURS);
+    let to = params.to.map_or(max_to, |to| to.min(max_to));
+    if to <= from {
+        return Err(Error::InvalidParam("to has to be after from".to_owned()));
+    }
+
+    let response_type = params.logs_params.response_type(&app.config)?;
+    let format = LiveFormat::new(response_type.formatter.name(), response_type.options)?;
+    let logs_params = LogsParams {
+        reverse: false,
+        limit: None,
+        offset: None,
+        ..params.logs_params
+    };
+
+    let windows = stream::unfold(from, move |window_from| {
+        let app = app.clone();
+        let channel_id = channel_id.clone();
+        async move {
+            if window_from >= to {
+                return None;
+            }
+            let window_to =
+                (window_from + chrono::Duration::minutes(REPLAY_WINDOW_MINUTES)).min(to);
+            let range = LogRangeParams {
+                from: window_from,
+                to: window_to,
+                logs_params,
+            };
+            let messages = read_replay_window(&app, &channel_id, range).await;
+            Some((messages, window_to))
+        }
+    });
+
+    let started_at = Instant::now();
+    let events = windows
+        .flat_map(|messages| {
+            let messages = match messages {
+                Ok(messages) => messages.into_iter().map(Ok).collect(),
+                Err(err) => vec![Err(err)],
+            };
+            stream::iter(messages)
+        })
+        .then(move |msg| async move {
+            let msg = match msg {
+                Ok(msg) => msg,
+                Err(err) => {
+                    error!("Could not read replayed messages: {err}");
+                    return Event::default().event("error").data(err.to_string());
+                }
+            };
+
+            let offset_millis = msg.timestamp.saturating_sub(from.timestamp_millis() as u64);
+            let delay = Duration::from_secs_f64(offset_millis as f64 / 1000.0 / speed);
+            sleep_until(started_at + delay).await;
+
+            match format.render(&msg) {
+                Ok(data) => Event::default().data(data),
+                Err(err) => {
+                    error!("Could not format replayed message {msg:?}: {err}");
+                    Event::default().event("error").data(err.to_string())
+                }
+            }
+        })
+        .chain(stream::once(async {
+            Event::default().event("end").data("")
+        }))
+        .map(Ok::<_, Infallible>);
+
+    Ok((
+        no_cache_header(),
+        Sse::new(events).keep_alive(KeepAlive::default()),
+    ))
+}
+
+async fn read_replay_window(
+    app: &App,
+    channel_id: &ChannelId,
+    range: LogRangeParams,
+) -> Result<Vec<StructuredMessage<'static>>> {
+    let excluded_users = app.logs_excluded_users(&range.logs_params);
+    let stream = app
+        .db
+        .read_with_failover(QueryClass::Logs, |db| async move {
+            read_channel(&db, channel_id, range, excluded_users, &app.flush_buffer).await
+        })
+        .await?;
+
+    stream.try_concat().await
+}
+
+pub async fn messages_by_client_nonce(
+    app: State<App>,
+    Path(NoncePath {
+        channel_id_type,
+        channel,
+        nonce,
+    }): Path<NoncePath>,
+    Query(params): Query<LogRangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    if params.to - params.from > chrono::Duration::days(MAX_NONCE_RANGE_DAYS) {
+        return Err(Error::InvalidParam(format!(
+            "The range must not be longer than {MAX_NONCE_RANGE_DAYS} days"
+        )));
+    }
+
+    let mut messages = read_messages_by_client_nonce(
+        &app.db.profile(QueryClass::Logs),
+        &channel_id,
+        &nonce,
+        params.from,
+        params.to,
+        MAX_NONCE_MESSAGES,
+        &app.flush_buffer,
+    )
+    .await?;
+    messages.retain(|msg| !app.has_opted_out(&msg.user_id));
+    if messages.is_empty() {
+        return Err(Error::NotFound);
+    }
+    if params.logs_params.reverse {
+        messages.reverse();
+    }
+
+    let logs = LogsResponse {
+        stream: LogsStream::new_provided(messages)?,
+        response_type: params.logs_params.response_type(&app.config)?,
     };
     Ok((no_cache_header(), logs))
 }
 
-pub async fn optout(_app: State<App>) -> Json<String> {
-    Json("No, I don't think so".to_owned())
+pub async fn query_logs(
+    app: State<App>,
+    Json(body): Json<LogsQueryBody>,
+) -> Result<impl IntoApiResponse> {
+    if body.channels.is_empty() || body.channels.len() > MAX_QUERY_CHANNELS {
+        return Err(Error::InvalidParam(format!(
+            "Between 1 and {MAX_QUERY_CHANNELS} channels have to be queried"
+        )));
+    }
+    if body.from >= body.to {
+        return Err(Error::InvalidParam(
+            "The start of the range has to be before its end".to_owned(),
+        ));
+    }
+
+    for channel_id in &body.channels {
+        if body.users.is_empty() {
+            app.check_opted_out(channel_id, None)?;
+        } else {
+            for user_id in &body.users {
+                app.check_opted_out(channel_id, Some(user_id))?;
+            }
+        }
+    }
+
+    let message_types = body
+        .types
+        .iter()
+        .map(|name| {
+            MessageType::from_str(&name.to_uppercase())
+                .map_err(|_| Error::InvalidParam(format!("Unknown message type {name}")))
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    let logs_params = body.logs_params();
+    let stream = read_logs_query(&app.db.profile(QueryClass::Logs), &body, &message_types).await?;
+    let logs = LogsResponse {
+        stream,
+        response_type: logs_params.response_type(&app.config)?,
+    };
+    Ok((no_cache_header(), logs_params.page(), logs))
+}
+
+pub async fn resolve_users(
+    app: State<App>,
+    Json(body): Json<ResolveUsersBody>,
+) -> Result<Json<ResolvedUsers>> {
+    if body.logins.len() > MAX_RESOLVED_USERS {
+        return Err(Error::InvalidParam(format!(
+            "At most {MAX_RESOLVED_USERS} logins can be resolved at once"
+        )));
+    }
+
+    let mut requested: Vec<String> = Vec::with_capacity(body.logins.len());
+    for login in body.logins {
+        let login = login.trim().to_lowercase();
+        if !login.is_empty() && !requested.contains(&login) {
+            requested.push(login);
+        }
+    }
+
+    let mut resolved = HashMap::new();
+    let mut uncached = Vec::new();
+    for login in requested.iter().cloned() {
+        match app.users.get_id(&login) {
+            Some(Some(id)) => {
+                resolved.insert(login, id);
+            }
+            Some(None) => (),
+            None => uncached.push(login),
+        }
+    }
+
+    // Users who chatted in a logged channel are known without asking Helix
+    let stored = read_user_ids_by_login(&app.db.profile(QueryClass::Logs), &uncached).await?;
+    let mut unknown = Vec::new();
+    for login in uncached {
+        match stored.get(&login) {
+            Some(id) => {
+                app.users.insert(id.clone(), login.clone());
+                resolved.insert(login, id.clone());
+            }
+            None => unknown.push(login),
+        }
+    }
+
+    let fetched = app.get_users(vec![], unknown, false).await?;
+    for (id, login) in fetched {
+        resolved.insert(login, id);
+    }
+
+    // Requested order is kept, duplicates are only listed once
+    let mut users = Vec::with_capacity(resolved.len());
+    let mut not_found = Vec::new();
+    for login in requested {
+        match resolved.remove(&login) {
+            Some(user_id) if !app.has_opted_out(&user_id) => {
+                users.push(ResolvedUser { login, user_id });
+            }
+            _ => not_found.push(login),
+        }
+    }
+
+    Ok(Json(ResolvedUsers { users, not_found }))
+}
+
+/// Users consent to their logs being served by sending the code in chat,
+/// which proves that they control the account
+pub async fn consent(app: State<App>) -> Result<Json<ConsentCode>> {
+    let code: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(CONSENT_CODE_LENGTH)
+        .map(char::from)
+        .collect();
+    app.add_consent_code(code.clone())?;
+
+    Ok(Json(ConsentCode {
+        command: format!("!rustlog consent {code}"),
+        code,
+    }))
 }
 
 pub async fn search_user_logs_by_name(
@@ -383,7 +1446,7 @@ pub async fn search_user_logs_by_name(
     params: Query<SearchParams>,
 ) -> Result<impl IntoApiResponse> {
     let user_id = app.get_user_id_by_name(&user).await?;
-    search_user_logs(app, channel_id_type, channel, user_id, params).await
+    search_user_logs(app, channel_id_type, channel, user_id.into(), params).await
 }
 
 pub async fn search_user_logs_by_id(
@@ -395,40 +1458,176 @@ pub async fn search_user_logs_by_id(
     }): Path<UserLogPathParams>,
     params: Query<SearchParams>,
 ) -> Result<impl IntoApiResponse> {
-    search_user_logs(app, channel_id_type, channel, user, params).await
+    search_user_logs(app, channel_id_type, channel, user.into(), params).await
 }
 
 async fn search_user_logs(
     app: State<App>,
     channel_id_type: ChannelIdType,
     channel: String,
-    user_id: String,
+    user_id: UserId,
     params: Query<SearchParams>,
 ) -> Result<impl IntoApiResponse> {
-    let channel_id = match channel_id_type {
-        ChannelIdType::Name => app.get_user_id_by_name(&channel).await?,
-        ChannelIdType::Id => channel,
-    };
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
 
     app.check_opted_out(&channel_id, Some(&user_id))?;
 
-    let stream = db::search_user_logs(
-        &app.db,
-        &channel_id,
-        &user_id,
-        &params.q,
-        params.logs_params,
-    )
-    .await?;
+    let stream = app
+        .db
+        .read_with_failover(QueryClass::Search, |db| {
+            let (channel_id, user_id, params) = (&channel_id, &user_id, &params);
+            let flush_buffer = &app.flush_buffer;
+            async move {
+                db::search_user_logs(
+                    &db,
+                    channel_id,
+                    user_id,
+                    &params.q,
+                    params.last.map(|last| last.range().from),
+                    params.logs_params,
+                    flush_buffer,
+                )
+                .await
+            }
+        })
+        .await?;
+
+    let mut response_type = params.logs_params.response_type(&app.config)?;
+    response_type.highlight = params.highlight.highlighter(&params.q);
+
+    let logs = LogsResponse {
+        stream,
+        response_type,
+    };
+    Ok((params.logs_params.page(), logs))
+}
+
+pub async fn search_channel_logs(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<ChannelLogsSearchParams>,
+) -> Result<Response> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let since = params.last.map(|last| last.range().from);
+
+    if params.relevance {
+        let limit = params
+            .logs_params
+            .limit
+            .unwrap_or(DEFAULT_RELEVANCE_LIMIT)
+            .min(MAX_RELEVANCE_LIMIT);
+        let offset = params.logs_params.offset.unwrap_or(0);
+
+        let scored = app
+            .db
+            .read_with_failover(QueryClass::Search, |db| {
+                let (channel_id, search) = (&channel_id, &params.q);
+                async move {
+                    db::search_channel_logs_by_relevance(
+                        &db, channel_id, search, since, limit, offset,
+                    )
+                    .await
+                }
+            })
+            .await?;
+
+        let highlighter = params.highlight.highlighter(&params.q);
+        let messages = scored
+            .iter()
+            .filter_map(|scored| {
+                let mut message = FullMessage::from_structured(&scored.message).ok()?;
+                if let Some(highlighter) = &highlighter {
+                    message.set_highlight(highlighter);
+                }
+                Some(ScoredSearchMessage {
+                    message,
+                    score: scored.score,
+                })
+            })
+            .collect();
+        let page = Page::new(Some(limit), Some(offset), scored.len() as u64 == limit);
+        return Ok((page, Json(ScoredSearchResults { messages })).into_response());
+    }
+
+    let stream = app
+        .db
+        .read_with_failover(QueryClass::Search, |db| {
+            let (channel_id, params) = (&channel_id, &params);
+            let flush_buffer = &app.flush_buffer;
+            async move {
+                db::search_channel_logs(
+                    &db,
+                    channel_id,
+                    &params.q,
+                    since,
+                    params.logs_params,
+                    flush_buffer,
+                )
+                .await
+            }
+        })
+        .await?;
+
+    let mut response_type = params.logs_params.response_type(&app.config)?;
+    response_type.highlight = params.highlight.highlighter(&params.q);
 
     let logs = LogsResponse {
         stream,
-        response_type: params.logs_params.response_type(),
+        response_type,
     };
-    Ok(logs)
+    Ok((params.logs_params.page(), logs).into_response())
+}
+
+/// Logs of today change quickly while the channel is live, but only slowly while it is offline.
+/// Without stream tracking the live status is unknown, so unfinished ranges are not cached
+fn channel_logs_cache_header(
+    app: &App,
+    channel_id: &ChannelId,
+    to: DateTime<Utc>,
+) -> TypedHeader<CacheControl> {
+    let live = app
+        .config
+        .streams
+        .enabled
+        .then(|| app.is_live(channel_id.as_str()));
+    let timezone = app.config.channel_timezone(channel_id.as_str());
+    logs_cache_header(to, Utc::now(), timezone, live)
 }
 
-fn cache_header(secs: u64) -> TypedHeader<CacheControl> {
+/// `live` is unknown if streams are not tracked
+fn logs_cache_header(
+    to: DateTime<Utc>,
+    now: DateTime<Utc>,
+    timezone: Tz,
+    live: Option<bool>,
+) -> TypedHeader<CacheControl> {
+    let Some(live) = live else {
+        return if now < to {
+            no_cache_header()
+        } else {
+            cache_header(36000)
+        };
+    };
+
+    let today = start_of_day(now.with_timezone(&timezone).date_naive(), timezone);
+    if to <= today {
+        cache_header(36000)
+    } else if live {
+        cache_header(LIVE_LOGS_CACHE_SECONDS)
+    } else if now < to {
+        cache_header(OFFLINE_LOGS_CACHE_SECONDS)
+    } else {
+        cache_header(36000)
+    }
+}
+
+pub fn cache_header(secs: u64) -> TypedHeader<CacheControl> {
     TypedHeader(
         CacheControl::new()
             .with_public()
@@ -439,3 +1638,95 @@ fn cache_header(secs: u64) -> TypedHeader<CacheControl> {
 pub fn no_cache_header() -> TypedHeader<CacheControl> {
     TypedHeader(CacheControl::new().with_no_cache())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::{latest_log_date, logs_cache_header, redirect_with_query};
+    use crate::{
+        error::Error,
+        ids::{ChannelId, UserId},
+        web::{logs_source::MemoryLogsSource, schema::AvailableLogDate},
+    };
+    use axum::{
+        http::{
+            header::{CACHE_CONTROL, LOCATION},
+            StatusCode,
+        },
+        response::IntoResponse,
+    };
+    use chrono::{TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+
+    fn day(year: &str, month: &str, day: &str) -> AvailableLogDate {
+        AvailableLogDate {
+            year: year.to_owned(),
+            month: month.to_owned(),
+            day: Some(day.to_owned()),
+        }
+    }
+
+    #[tokio::test]
+    async fn redirects_to_latest_logs() {
+        let (channel_id, user_id) = (ChannelId::from("11111111"), UserId::from("22222222"));
+        let mut source = MemoryLogsSource::default();
+        source.channel_logs.insert(
+            "11111111".to_owned(),
+            vec![day("2024", "3", "2"), day("2024", "3", "1")],
+        );
+        source.user_logs.insert(
+            ("11111111".to_owned(), "22222222".to_owned()),
+            vec![day("2024", "2", "20")],
+        );
+
+        let latest = latest_log_date(&source, &channel_id, None, chrono_tz::UTC)
+            .await
+            .unwrap();
+        let response = redirect_with_query(format!("/channel/forsen/{latest}"), Some("json"));
+        assert_eq!(response.status(), StatusCode::SEE_OTHER);
+        assert_eq!(
+            response.headers()[LOCATION],
+            "/channel/forsen/2024/3/2?json"
+        );
+
+        let latest = latest_log_date(&source, &channel_id, Some(&user_id), chrono_tz::UTC)
+            .await
+            .unwrap();
+        let response = redirect_with_query(format!("/channel/forsen/user/supibot/{latest}"), None);
+        assert_eq!(
+            response.headers()[LOCATION],
+            "/channel/forsen/user/supibot/2024/2/20"
+        );
+    }
+
+    #[tokio::test]
+    async fn missing_logs_are_not_found() {
+        let (channel_id, user_id) = (ChannelId::from("11111111"), UserId::from("22222222"));
+        let source = MemoryLogsSource::default();
+
+        let result = latest_log_date(&source, &channel_id, Some(&user_id), chrono_tz::UTC).await;
+        assert!(matches!(result, Err(Error::NotFound)));
+    }
+
+    #[test]
+    fn caches_logs_by_age() {
+        let now = Utc.with_ymd_and_hms(2024, 3, 2, 12, 0, 0).unwrap();
+        let midnight = Utc.with_ymd_and_hms(2024, 3, 2, 0, 0, 0).unwrap();
+        let tonight = Utc.with_ymd_and_hms(2024, 3, 3, 0, 0, 0).unwrap();
+        let cache_control = |to, live| {
+            logs_cache_header(to, now, chrono_tz::UTC, live)
+                .into_response()
+                .headers()[CACHE_CONTROL]
+                .to_str()
+                .unwrap()
+                .to_owned()
+        };
+
+        assert_eq!(
+            cache_control(midnight, Some(false)),
+            "public, max-age=36000"
+        );
+        assert_eq!(cache_control(tonight, Some(true)), "public, max-age=10");
+        assert_eq!(cache_control(tonight, None), "no-cache");
+        assert_eq!(cache_control(midnight, None), "public, max-age=36000");
+    }
+}
@@ -0,0 +1,231 @@
+//! End to end tests of the write and read path. The fixture IRC lines go through the bot's message
+//! handler and the writer, then are requested from the router in every log format.
+//! These tests start a ClickHouse container and are ignored by default, run them with
+//! `cargo test integration -- --ignored`
+
+use super::{export_quotas::ExportQuotas, responders::logs::FORMATTERS, router};
+use crate::{
+    app::{cache::UsersCache, helix_queue::HelixHttpClient, App},
+    bot::Bot,
+    config::Config,
+    db::{pool::DbPool, setup_db, writer::create_writer},
+    watchdog::Liveness,
+};
+use arrow_ipc::reader::StreamReader;
+use axum::{
+    body::{to_bytes, Body, Bytes},
+    http::{header::CONTENT_TYPE, Request, StatusCode},
+};
+use clickhouse::Client;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use pretty_assertions::assert_eq;
+use serde_json::json;
+use std::{io::Cursor, sync::Arc, time::Duration};
+use testcontainers_modules::{
+    clickhouse::ClickHouse,
+    testcontainers::{runners::AsyncRunner, ContainerAsync},
+};
+use tmi::IrcMessageRef;
+use tokio::{
+    sync::{broadcast, mpsc, watch},
+    time::sleep,
+};
+use tower::ServiceExt;
+use twitch_api::{
+    twitch_oauth2::{AccessToken, AppAccessToken, ClientId, ClientSecret},
+    HelixClient,
+};
+use twitch_irc::message::{IRCMessage, ServerMessage};
+
+const CHANNEL_ID: &str = "22484632";
+/// A few minutes of chat on 2024-03-01 with messages, a reply, a resub and a timeout
+const FIXTURE: &str = include_str!("../../tests/fixtures/forsen.irc");
+
+async fn start_db() -> (ContainerAsync<ClickHouse>, String, Client) {
+    let container = ClickHouse::default()
+        .start()
+        .await
+        .expect("Could not start ClickHouse container");
+    let port = container
+        .get_host_port_ipv4(8123)
+        .await
+        .expect("ClickHouse HTTP port is not exposed");
+    let url = format!("http://127.0.0.1:{port}");
+    let db = Client::default().with_url(&url);
+
+    setup_db(&db, "default").await.unwrap();
+
+    (container, url, db)
+}
+
+/// Sends the fixture through the bot's message handler and waits until the writer has flushed it.
+/// The app is built like in `main`, without a logs cache and with a token which is never used
+async fn ingest_fixture(url: String, db: &Client) -> (App, u64) {
+    let config: Config = serde_json::from_value(json!({
+        "clickhouseUrl": url,
+        "clickhouseDb": "default",
+        "channels": [CHANNEL_ID],
+        "clientID": "client_id",
+        "clientSecret": "client_secret",
+        "admins": [],
+    }))
+    .unwrap();
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(());
+    let (writer_tx, flush_buffer, writer_handle) =
+        create_writer(db.clone(), shutdown_rx.clone(), 1, 10_000)
+            .await
+            .unwrap();
+    let (unparsed_tx, _, unparsed_writer_handle) =
+        create_writer(db.clone(), shutdown_rx, 1, 10_000)
+            .await
+            .unwrap();
+
+    let token = AppAccessToken::from_existing_unchecked(
+        AccessToken::new("access_token".to_owned()),
+        None,
+        ClientId::new(config.client_id.clone()),
+        ClientSecret::new(config.client_secret.expose().clone()),
+        None,
+        None,
+    );
+    let db_pool = DbPool::new(
+        vec![(url, db.clone())],
+        config.clickhouse_node_cooldown,
+        &config.query_profiles,
+    );
+    let app = App {
+        helix_client: HelixClient::with_client(HelixHttpClient::default()),
+        token: Arc::new(token),
+        users: UsersCache::default(),
+        liveness: Liveness::new(vec![], config.liveness_timeout),
+        config: Arc::new(config),
+        db: Arc::new(db_pool),
+        optout_codes: Arc::default(),
+        opted_out_users: Arc::default(),
+        consent_codes: Arc::default(),
+        consented_users: Arc::default(),
+        flush_buffer,
+        live_tx: broadcast::channel(1).0,
+        logs_cache: None,
+        channel_index: Arc::default(),
+        live_channels: Arc::default(),
+        export_quotas: ExportQuotas::default(),
+    };
+
+    let bot = Bot::new(app.clone(), writer_tx, unparsed_tx);
+    let mut count = 0;
+    for line in FIXTURE.lines() {
+        let irc_message = IRCMessage::parse(line).expect("Invalid fixture line");
+        let msg = ServerMessage::try_from(irc_message).expect("Unsupported fixture line");
+        bot.write_message(msg).await.unwrap();
+        count += 1;
+    }
+
+    // The messages have to be read from the database instead of the flush buffer
+    while count_messages(db).await < count {
+        sleep(Duration::from_millis(200)).await;
+    }
+    shutdown_tx.send(()).unwrap();
+    writer_handle.await.unwrap();
+    unparsed_writer_handle.await.unwrap();
+
+    (app, count)
+}
+
+async fn count_messages(db: &Client) -> u64 {
+    db.query("SELECT count() FROM message_structured")
+        .fetch_one()
+        .await
+        .unwrap()
+}
+
+/// Content type and body of the logs before the end of the fixture's day, newest first
+async fn read_logs(app: &App, format: &str) -> (String, Vec<u8>) {
+    let format_param = match format {
+        "text" => String::new(),
+        "json-basic" => "&jsonBasic".to_owned(),
+        format => format!("&{format}"),
+    };
+    let uri = format!("/channelid/{CHANNEL_ID}/recent?before=2024-03-02T00:00:00Z{format_param}");
+
+    let (bot_tx, _) = mpsc::channel(1);
+    let response = router(app.clone(), None, bot_tx)
+        .oneshot(Request::get(uri).body(Body::empty()).unwrap())
+        .await
+        .unwrap();
+    assert_eq!(response.status(), StatusCode::OK, "{format} logs failed");
+
+    let content_type = response.headers()[CONTENT_TYPE]
+        .to_str()
+        .unwrap()
+        .to_owned();
+    let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+    (content_type, body.to_vec())
+}
+
+fn message_count(format: &str, body: &[u8]) -> usize {
+    match format {
+        "text" | "raw" | "ndjson" => std::str::from_utf8(body).unwrap().lines().count(),
+        "json" | "json-basic" => {
+            let value: serde_json::Value = serde_json::from_slice(body).unwrap();
+            value["messages"].as_array().unwrap().len()
+        }
+        "arrow" => StreamReader::try_new(Cursor::new(body), None)
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum(),
+        // The header is the first line
+        "csv" => std::str::from_utf8(body).unwrap().lines().count() - 1,
+        "parquet" => ParquetRecordBatchReaderBuilder::try_new(Bytes::copy_from_slice(body))
+            .unwrap()
+            .build()
+            .unwrap()
+            .map(|batch| batch.unwrap().num_rows())
+            .sum(),
+        _ => panic!("No assertions for the {format} format"),
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires docker"]
+async fn ingested_logs_are_served_in_every_format() {
+    let (_container, url, db) = start_db().await;
+    let (app, count) = ingest_fixture(url, &db).await;
+
+    for formatter in FORMATTERS {
+        let format = formatter.name();
+        let (content_type, body) = read_logs(&app, format).await;
+
+        assert_eq!(content_type, formatter.content_type());
+        assert_eq!(
+            message_count(format, &body),
+            count as usize,
+            "Wrong amount of messages in {format} logs"
+        );
+    }
+}
+
+#[tokio::test]
+#[ignore = "requires docker"]
+async fn ingested_logs_keep_their_content() {
+    let (_container, url, db) = start_db().await;
+    let (app, _) = ingest_fixture(url, &db).await;
+
+    let (_, text) = read_logs(&app, "text").await;
+    let text = String::from_utf8(text).unwrap();
+    assert_eq!(
+        text.lines().last(),
+        Some("[2024-03-01 00:01:14] #forsen supibot: +join")
+    );
+
+    // The raw lines are rebuilt from the stored columns, so every line has to survive the roundtrip
+    let (_, raw) = read_logs(&app, "raw").await;
+    let raw = String::from_utf8(raw).unwrap();
+    for (line, fixture_line) in raw.lines().zip(FIXTURE.lines().rev()) {
+        let rebuilt = IrcMessageRef::parse(line).expect("Invalid raw line");
+        let original = IrcMessageRef::parse(fixture_line).unwrap();
+        assert_eq!(rebuilt.command(), original.command());
+        assert_eq!(rebuilt.params(), original.params());
+    }
+}
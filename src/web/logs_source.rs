@@ -0,0 +1,81 @@
+//! The database reads of the logs routes behind a trait, so the handling around them
+//! (redirects, status codes, headers) can be tested with an in-memory fake instead of ClickHouse
+
+use super::schema::AvailableLogDate;
+use crate::{
+    db::{read_available_channel_logs, read_available_user_logs},
+    ids::{ChannelId, UserId},
+    Result,
+};
+use chrono_tz::Tz;
+use clickhouse::Client;
+use std::future::Future;
+
+pub trait LogsSource: Sync {
+    /// Days or months with logs of the channel, newest first
+    fn available_channel_logs(
+        &self,
+        channel_id: &ChannelId,
+        timezone: Tz,
+    ) -> impl Future<Output = Result<Vec<AvailableLogDate>>> + Send;
+
+    /// Days with logs of the user in the channel, newest first
+    fn available_user_logs(
+        &self,
+        channel_id: &ChannelId,
+        user_id: &UserId,
+        timezone: Tz,
+    ) -> impl Future<Output = Result<Vec<AvailableLogDate>>> + Send;
+}
+
+impl LogsSource for Client {
+    fn available_channel_logs(
+        &self,
+        channel_id: &ChannelId,
+        timezone: Tz,
+    ) -> impl Future<Output = Result<Vec<AvailableLogDate>>> + Send {
+        read_available_channel_logs(self, channel_id, timezone)
+    }
+
+    fn available_user_logs(
+        &self,
+        channel_id: &ChannelId,
+        user_id: &UserId,
+        timezone: Tz,
+    ) -> impl Future<Output = Result<Vec<AvailableLogDate>>> + Send {
+        read_available_user_logs(self, channel_id, user_id, timezone)
+    }
+}
+
+/// Serves fixed dates, the timezone is ignored
+#[cfg(test)]
+#[derive(Default)]
+pub struct MemoryLogsSource {
+    /// Keyed by channel id
+    pub channel_logs: std::collections::HashMap<String, Vec<AvailableLogDate>>,
+    /// Keyed by channel and user id
+    pub user_logs: std::collections::HashMap<(String, String), Vec<AvailableLogDate>>,
+}
+
+#[cfg(test)]
+impl LogsSource for MemoryLogsSource {
+    fn available_channel_logs(
+        &self,
+        channel_id: &ChannelId,
+        _: Tz,
+    ) -> impl Future<Output = Result<Vec<AvailableLogDate>>> + Send {
+        let logs = self.channel_logs.get(channel_id.as_str()).cloned();
+        std::future::ready(Ok(logs.unwrap_or_default()))
+    }
+
+    fn available_user_logs(
+        &self,
+        channel_id: &ChannelId,
+        user_id: &UserId,
+        _: Tz,
+    ) -> impl Future<Output = Result<Vec<AvailableLogDate>>> + Send {
+        let key = (channel_id.to_string(), user_id.to_string());
+        let logs = self.user_logs.get(&key).cloned();
+        std::future::ready(Ok(logs.unwrap_or_default()))
+    }
+}
@@ -1,24 +1,42 @@
 mod admin;
+mod alerts;
+mod api_keys;
+mod channel_alias;
+mod delete_message;
+mod endpoint_groups;
+pub mod export_quotas;
 mod frontend;
+mod graphql;
 mod handlers;
-mod responders;
+#[cfg(test)]
+mod integration;
+mod logs_source;
+mod openapi;
+mod opt_out;
+mod pagination;
+mod permalink;
+mod query_debug;
+pub mod responders;
 pub mod schema;
+mod stats;
 mod trace_layer;
+mod usage;
 
-use self::handlers::no_cache_header;
+use self::{handlers::no_cache_header, usage::UsageTracker};
 use crate::{app::App, bot::BotMessage, web::admin::admin_auth, ShutdownRx};
 use aide::{
     axum::{
-        routing::{get, get_with, post, post_with},
+        routing::{delete_with, get, get_with, post_with, put_with},
         ApiRouter, IntoApiResponse,
     },
     openapi::OpenApi,
+    redoc::Redoc,
 };
 use axum::{
     extract::Request,
     middleware::{self, Next},
-    response::{IntoResponse, Response},
-    Extension, Json, ServiceExt,
+    response::Response,
+    Extension, Json, Router, ServiceExt,
 };
 use axum_prometheus::PrometheusMetricLayerBuilder;
 use prometheus::TextEncoder;
@@ -34,23 +52,67 @@ use tower_http::{
 };
 use tracing::{debug, info};
 
-const CAPABILITIES: &[&str] = &["arbitrary-range-query"];
+const CAPABILITIES: &[&str] = &["arbitrary-range-query", "live-logs"];
+const API_KEY_DESCRIPTION: &str =
+    "API key configured in `apiKeys` with the `search` scope, or the admin API key";
 
 pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessage>) {
+    metrics_prometheus::install();
+
+    let listen_address =
+        parse_listen_addr(&app.config.listen_address).expect("Invalid listen address");
+
+    let (usage_tracker, usage_handle) = if app.config.usage.enabled {
+        let (tracker, handle) = UsageTracker::new(&app, shutdown_rx.clone())
+            .await
+            .expect("Could not create usage writer");
+        (Some(tracker), Some(handle))
+    } else {
+        (None, None)
+    };
+
+    let app = router(app, usage_tracker, bot_tx);
+
+    info!("Listening on {listen_address}");
+
+    let listener = TcpListener::bind(&listen_address)
+        .await
+        .expect("Could not create TCP listener");
+
+    axum::serve(
+        listener,
+        ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app),
+    )
+    .with_graceful_shutdown(async move {
+        shutdown_rx.changed().await.ok();
+        debug!("Shutting down web task");
+    })
+    .await
+    .unwrap();
+
+    if let Some(handle) = usage_handle {
+        handle.await.ok();
+    }
+}
+
+/// Every route with its middleware, separate from the listener so tests can send requests to it directly
+fn router(
+    app: App,
+    usage_tracker: Option<UsageTracker>,
+    bot_tx: Sender<BotMessage>,
+) -> NormalizePath<Router> {
     aide::gen::on_error(|error| {
         panic!("Could not generate docs: {error}");
     });
     aide::gen::infer_responses(true);
     aide::gen::extract_schemas(true);
 
-    metrics_prometheus::install();
-
-    let listen_address =
-        parse_listen_addr(&app.config.listen_address).expect("Invalid listen address");
-
     let cors = CorsLayer::permissive();
 
-    let mut api = OpenApi::default();
+    let mut api = OpenApi {
+        info: openapi::info(),
+        ..Default::default()
+    };
 
     // TODO: move full channel log routes and metrics to admin
     let admin_routes = ApiRouter::new()
@@ -65,6 +127,45 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.tag("Admin").description("Leave the specified channels")
             }),
         )
+        .api_route(
+            "/channels/bulk-join",
+            post_with(admin::bulk_join_channels, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Join all channels of a Twitch team's members, or all channels a user follows. With `dryRun`, only list the channels which would be joined")
+            }),
+        )
+        .api_route(
+            "/channels/archive",
+            post_with(admin::archive_channels, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Leave the specified channels but keep their logs available. Archived channels are only listed with `includeArchived` and are joined again by adding them")
+            }),
+        )
+        .api_route(
+            "/channels/:id/verify",
+            get_with(admin::verify_channel, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Check whether the bot is joined to the channel and receiving its messages, and diagnose why not")
+            }),
+        )
+        .api_route(
+            "/channels/:id/pause",
+            post_with(admin::pause_channel, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Temporarily stop logging the channel while keeping it configured, e.g. during incidents. With the `part` mode the channel is also parted. The pause is shown by the channel verification")
+            })
+            .delete_with(admin::resume_channel, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Resume logging a paused channel, joining it again if it was parted")
+            }),
+        )
+        .api_route(
+            "/channels/:id/streams/backfill",
+            post_with(admin::backfill_streams, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Import the channel's past broadcasts which are still available as VODs into the streams, so logs from before streams were polled can be scoped to a stream")
+            }),
+        )
         .api_route(
             "/check-users",
             post_with(admin::check_users_existence, |mut op| {
@@ -76,18 +177,297 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
             "/known-names",
             get_with(admin::find_user_logins, |mut op| {
                 admin::admin_auth_doc(&mut op);
-                op.tag("Admin").description("Find all logged usernames of a specific user")
+                op.tag("Admin").description("Find all logged usernames and display names of a specific user")
+            }),
+        )
+        .api_route(
+            "/duplicates",
+            get_with(admin::duplicates_report, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Count messages which have been stored more than once in the given range, per channel")
+            }),
+        )
+        .api_route(
+            "/duplicates/cleanup",
+            post_with(admin::remove_duplicate_messages, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Remove identical copies of messages in the partitions which contain duplicates in the given range. Rewrites the affected partitions")
+            }),
+        )
+        .api_route(
+            "/gaps",
+            get_with(admin::channel_gaps, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Find periods in which the channel was live but no messages were logged, e.g. because of bot downtime")
+            }),
+        )
+        .api_route(
+            "/overlap",
+            get_with(admin::chat_overlap, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Count the chatters two channels share in the given range, optionally with a random sample of them")
+            }),
+        )
+        .api_route(
+            "/queries",
+            get_with(admin::list_queries, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List database queries which are currently running")
+            }),
+        )
+        .api_route(
+            "/queries/:id/kill",
+            post_with(admin::kill_running_query, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Terminate a running database query")
+            }),
+        )
+        .api_route(
+            "/purge",
+            post_with(admin::purge_user, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Delete the stored messages of a user, optionally only in one channel or range, including their links, alert matches and unparsed copies. The messages are deleted in the background, poll the returned mutation until it is done")
+            }),
+        )
+        .api_route(
+            "/purge/:mutation_id",
+            get_with(admin::purge_status, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Get whether a purge has finished deleting the messages")
+            }),
+        )
+        .api_route(
+            "/backups",
+            get_with(admin::list_backups, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List backups which have been created")
+            })
+            .post_with(admin::start_backup, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Create a backup now and wait for it to finish")
+            }),
+        )
+        .api_route(
+            "/config",
+            get_with(admin::get_config, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Get the configuration the process is running with, including changes made through the API since startup. Credentials are redacted and removed from connection URLs")
+            }),
+        )
+        .api_route(
+            "/retention",
+            get_with(admin::get_retention, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Get the default retention and the retention of each channel in days")
+            }),
+        )
+        .api_route(
+            "/retention/:id",
+            put_with(admin::set_channel_retention, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Set how many days the messages of a channel are kept, or keep them forever")
+            })
+            .delete_with(admin::remove_channel_retention, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Remove the retention of a channel, so the default retention applies to it")
+            }),
+        )
+        .api_route(
+            "/legal-holds",
+            get_with(admin::list_legal_holds, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List the channels and users under a legal hold")
+            }),
+        )
+        .api_route(
+            "/legal-holds/:kind/:id",
+            put_with(admin::place_legal_hold, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Place a legal hold on a channel or user. Their messages are kept regardless of the retention, purges and opt-outs until the hold is lifted")
+            })
+            .delete_with(admin::lift_legal_hold, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Lift the legal hold on a channel or user")
+            }),
+        )
+        .api_route(
+            "/audit-log",
+            get_with(admin::audit_log, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List the most recent audited admin actions, like placing and lifting legal holds")
+            }),
+        )
+        .api_route(
+            "/schema-drift",
+            get_with(admin::schema_drift, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Compare the columns, types, codecs and sorting keys of the tables with the schema created by the migrations, e.g. to find manual changes")
+            }),
+        )
+        .api_route(
+            "/status",
+            get_with(admin::ingestion_status, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Get the sampled latency from messages being sent until they are written to the database, in total and per channel, and the health of each database node")
+            }),
+        )
+        .api_route(
+            "/opt-out-denials",
+            get_with(admin::opt_out_denials, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Get how many requests for opted out channels and users were denied since startup, by hashed channel and user id")
+            }),
+        )
+        .api_route(
+            "/opt-outs",
+            get_with(admin::list_opt_outs, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List the opted out users and channels, from the config and the self-service opt-out")
+            }),
+        )
+        .api_route(
+            "/opt-outs/:id",
+            delete_with(admin::revoke_opt_out, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Revoke an opt-out, so the user or channel is logged again")
+            }),
+        )
+        .api_route(
+            "/storage",
+            get_with(admin::storage_stats, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Get the size of each table on each disk and the storage tiering settings")
+            }),
+        )
+        .api_route(
+            "/unparsed",
+            get_with(admin::list_unparsed_messages, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List the most recent messages which could not be parsed")
+            }),
+        )
+        .api_route(
+            "/unparsed/retry",
+            post_with(admin::retry_unparsed, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("Parse all stored unparsed messages again and add the ones which succeed to the logs")
+            }),
+        )
+        .api_route(
+            "/usage",
+            get_with(admin::api_usage, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Admin").description("List the heaviest API consumers by IP address, API key or route in the given range")
             }),
         )
         .route_layer(middleware::from_fn_with_state(app.clone(), admin_auth))
         .layer(Extension(bot_tx));
 
+    let graphql_routes = if app.config.graphql {
+        ApiRouter::new()
+            .route(
+                "/graphql",
+                axum::routing::get(graphql::graphiql).post(graphql::graphql_handler),
+            )
+            .layer(Extension(graphql::build_schema(app.clone())))
+    } else {
+        ApiRouter::new()
+    };
+
+    let language_routes = if app.config.language_stats {
+        ApiRouter::new().api_route(
+            "/:channel_id_type/:channel/stats/languages",
+            get_with(stats::language_stats, |op| {
+                op.tag("Stats").description(
+                    "Get the languages of randomly sampled chat messages in the given range",
+                )
+            }),
+        )
+    } else {
+        ApiRouter::new()
+    };
+
+    let alert_routes = ApiRouter::new()
+        .api_route(
+            "/alerts",
+            get_with(alerts::list_saved_searches, |mut op| {
+                admin::api_key_doc(&mut op, API_KEY_DESCRIPTION);
+                op.tag("Alerts").description("List the saved searches of the API key")
+            })
+            .post_with(alerts::create_saved_search, |mut op| {
+                admin::api_key_doc(&mut op, API_KEY_DESCRIPTION);
+                op.tag("Alerts").description("Save a search which is periodically run against new messages")
+            }),
+        )
+        .api_route(
+            "/alerts/:id",
+            get_with(alerts::get_saved_search, |mut op| {
+                admin::api_key_doc(&mut op, API_KEY_DESCRIPTION);
+                op.tag("Alerts").description("Get a saved search")
+            })
+            .put_with(alerts::update_saved_search, |mut op| {
+                admin::api_key_doc(&mut op, API_KEY_DESCRIPTION);
+                op.tag("Alerts").description("Replace a saved search. Messages sent before the update are not matched again")
+            })
+            .delete_with(alerts::delete_saved_search, |mut op| {
+                admin::api_key_doc(&mut op, API_KEY_DESCRIPTION);
+                op.tag("Alerts").description("Delete a saved search")
+            }),
+        )
+        .api_route(
+            "/alerts/:id/matches",
+            get_with(alerts::list_alert_matches, |mut op| {
+                admin::api_key_doc(&mut op, API_KEY_DESCRIPTION);
+                op.tag("Alerts").description("List messages matched by a saved search, newest first. Matches are kept for 30 days")
+            }),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app.clone(),
+            alerts::api_key_auth,
+        ));
+
+    let user_stats_routes = ApiRouter::new()
+        .api_route(
+            "/user/:user/stats",
+            get_with(stats::user_channel_stats_by_name, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Stats").description("Get the number of messages a user sent in each channel and the chat colors they used. Requires the admin API key unless user stats are public")
+            }),
+        )
+        .api_route(
+            "/userid/:user/stats",
+            get_with(stats::user_channel_stats_by_id, |mut op| {
+                admin::admin_auth_doc(&mut op);
+                op.tag("Stats").description("Get the number of messages a user sent in each channel and the chat colors they used. Requires the admin API key unless user stats are public")
+            }),
+        )
+        .route_layer(middleware::from_fn_with_state(
+            app.clone(),
+            admin::user_stats_auth,
+        ));
+
     let app = ApiRouter::new()
         .nest("/admin", admin_routes)
+        .merge(user_stats_routes)
+        .merge(alert_routes)
+        .merge(graphql_routes)
+        .merge(language_routes)
         .api_route(
             "/channels",
             get_with(handlers::get_channels, |op| {
-                op.description("List logged channels")
+                op.description("List logged channels. Archived channels, which are no longer logged, are included with `includeArchived`")
+            }),
+        )
+        .api_route(
+            "/channels/search",
+            get_with(handlers::search_channels, |op| {
+                op.description("Search logged channels by login or ID, best matches first")
+            }),
+        )
+        .api_route(
+            "/health/ready",
+            get_with(handlers::health_ready, |op| {
+                op.description("Check if at least one database node is reachable and the bot and writers are not stuck, and list the health of each node by its index and of each task")
             }),
         )
         .api_route(
@@ -96,23 +476,29 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.description("List available logs")
             }),
         )
+        .api_route(
+            "/list/combined",
+            get_with(handlers::combined_available_logs, |op| {
+                op.description("List the days with logs of the channel and the months with logs of the user in the channel in one request, with the amount of messages of each")
+            }),
+        )
         // .api_route(
         //     "/:channel_id_type/:channel",
         //     get_with(handlers::get_channel_logs, |op| {
-        //         op.description("Get channel logs. If the `to` and `from` query params are not given, redirect to latest available day")
+        //         op.description("Get channel logs. If the `to` and `from` query params are not given, redirect to latest available day, or return it directly with `latest`")
         //     }),
         // )
         // For some reason axum considers it a path overlap if user id type is dynamic
         .api_route(
             "/:channel_id_type/:channel/user/:user",
             get_with(handlers::get_user_logs_by_name, |op| {
-                op.description("Get user logs by name. If the `to` and `from` query params are not given, redirect to latest available month")
+                op.description("Get user logs by name. If the `to` and `from` query params are not given, redirect to latest available month, or return it directly with `latest`")
             }),
         )
         .api_route(
             "/:channel_id_type/:channel/userid/:user",
             get_with(handlers::get_user_logs_id, |op| {
-                op.description("Get user logs by id. If the `to` and `from` query params are not given, redirect to latest available month")
+                op.description("Get user logs by id. If the `to` and `from` query params are not given, redirect to latest available month, or return it directly with `latest`")
             }),
         )
         // .api_route(
@@ -139,6 +525,201 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.description("Get a random line from the channel's logs")
             }),
         )
+        .api_route(
+            "/:channel_id_type/:channel/random/supibot",
+            get_with(handlers::supibot_random_channel_line, |op| {
+                op.description("Get a random line as a JSON object with the channel, username and text, as expected by Supibot and similar bots")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/firsttimechatters",
+            get_with(handlers::get_first_time_chatters, |op| {
+                op.description("Get messages from first-time chatters in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/activity",
+            get_with(stats::activity_breakdown, |op| {
+                op.tag("Stats").description("Get the number of chatters grouped by how many messages they sent in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/compare",
+            get_with(stats::compare_ranges, |op| {
+                op.tag("Stats").description("Compare message counts, chatters and emotes between two ranges")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/ranges",
+            get_with(stats::range_summaries, |op| {
+                op.tag("Stats").description("Get message counts and unique chatters of several ranges at once, e.g. to compare weeks")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/subs",
+            get_with(stats::sub_stats, |op| {
+                op.tag("Stats").description("Get sub, resub and gifted sub counts, the top gifters and a daily breakdown in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/replies",
+            get_with(stats::reply_stats, |op| {
+                op.tag("Stats").description("Get the most common pairs of users replying to each other in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/quality",
+            get_with(stats::chat_quality, |op| {
+                op.tag("Stats").description("Get the average and median message length, the share of duplicate messages and the share of emotes in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/automod",
+            get_with(stats::automod_stats, |op| {
+                op.tag("Stats").description("Get how many messages were flagged by AutoMod in each category, in total and per day in the given range. Use `flagged` on the logs routes to list them")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/moderation",
+            get_with(stats::moderation_stats, |op| {
+                op.tag("Stats").description("Get ban and timeout counts with the average timeout length, in total and per day in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/emotes",
+            get_with(stats::emote_stats, |op| {
+                op.tag("Stats").description("Get the most used Twitch and third-party (7TV, BTTV, FFZ) emotes in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/moment",
+            get_with(handlers::get_moment_logs, |op| {
+                op.description("Get the chat around a moment of a stream, e.g. a clip or VOD timestamp")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/announcements",
+            get_with(handlers::list_announcements, |op| {
+                op.description("List announcements made in the channel in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/links",
+            get_with(handlers::list_links, |op| {
+                op.description("List links posted in the channel in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/chatters",
+            get_with(stats::top_chatters, |op| {
+                op.tag("Stats").description("Get the users who sent the most messages in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/users/search",
+            get_with(handlers::search_channel_users, |op| {
+                op.description("Search users who chatted in the channel by the beginning of their login")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/streams",
+            get_with(handlers::list_streams, |op| {
+                op.description("List streams of the channel which overlap the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/streams/:stream_id",
+            get_with(handlers::get_stream_logs, |op| {
+                op.description("Get the logs of a stream, from its start until it was last seen live")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/streams/:stream_id/stats",
+            get_with(stats::stream_stats, |op| {
+                op.tag("Stats").description("Get the message count, unique chatters and how many chatters were new to the channel or returning during a stream")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/stats/domains",
+            get_with(stats::domain_stats, |op| {
+                op.tag("Stats").description("Get the most linked domains in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/reports/:period",
+            get_with(stats::channel_report, |op| {
+                op.tag("Stats").description("Get the latest generated report of the given period")
+            }),
+        )
+        .api_route(
+            "/public/stats/:file",
+            get_with(stats::public_channel_stats, |op| {
+                op.tag("Stats").description("Get the exported daily message counts and top emotes of a channel, e.g. `/public/stats/22484632.json`. Only available if `publicStats` is configured")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/recent",
+            get_with(handlers::recent_channel_logs, |op| {
+                op.description("Get the latest messages of the channel, including ones which have not been written to the database yet. `limit` defaults to 200. Use `before` to scroll back through the logs")
+            }),
+        )
+        // Server-sent events are not described in the API docs
+        .route(
+            "/:channel_id_type/:channel/live",
+            axum::routing::get(handlers::live_channel_logs),
+        )
+        .route(
+            "/:channel_id_type/:channel/replay",
+            axum::routing::get(handlers::replay_channel_logs),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/userid/:user/recent",
+            get_with(handlers::recent_user_logs_by_id, |op| {
+                op.description("Get the latest messages of the user in a channel. `limit` defaults to 200. Use `before` to scroll back through the logs")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/user/:user/recent",
+            get_with(handlers::recent_user_logs_by_name, |op| {
+                op.description("Get the latest messages of the user in a channel. `limit` defaults to 200. Use `before` to scroll back through the logs")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/userid/:user/stats/emotes",
+            get_with(stats::user_emote_stats_by_id, |op| {
+                op.tag("Stats").description("Get the Twitch and third-party emotes the user used most in the channel in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/user/:user/stats/emotes",
+            get_with(stats::user_emote_stats_by_name, |op| {
+                op.tag("Stats").description("Get the Twitch and third-party emotes the user used most in the channel in the given range")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/userid/:user/streaks",
+            get_with(stats::user_streaks_by_id, |op| {
+                op.tag("Stats").description("Get the longest and the current streak of consecutive days on which the user chatted in the channel, in the channel's timezone")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/user/:user/streaks",
+            get_with(stats::user_streaks_by_name, |op| {
+                op.tag("Stats").description("Get the longest and the current streak of consecutive days on which the user chatted in the channel, in the channel's timezone")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/userid/:user/watchtime",
+            get_with(stats::user_watchtime_by_id, |op| {
+                op.tag("Stats").description("Approximate the time the user was present during the channel's streams in the range by counting the distinct 10 minute buckets in which they chatted")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/user/:user/watchtime",
+            get_with(stats::user_watchtime_by_name, |op| {
+                op.tag("Stats").description("Approximate the time the user was present during the channel's streams in the range by counting the distinct 10 minute buckets in which they chatted")
+            }),
+        )
         .api_route(
             "/:channel_id_type/:channel/userid/:user/random",
             get_with(handlers::random_user_line_by_id, |op| {
@@ -151,6 +732,48 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.description("Get a random line from the user's logs in a channel")
             }),
         )
+        .api_route(
+            "/:channel_id_type/:channel/userid/:user/random/supibot",
+            get_with(handlers::supibot_random_user_line_by_id, |op| {
+                op.description("Get a random line as a JSON object with the channel, username and text, as expected by Supibot and similar bots")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/user/:user/random/supibot",
+            get_with(handlers::supibot_random_user_line_by_name, |op| {
+                op.description("Get a random line as a JSON object with the channel, username and text, as expected by Supibot and similar bots")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/message/:id",
+            delete_with(delete_message::delete_own_message, |op| {
+                op.description("Delete a message sent by the user whose Twitch user access token is given as `Authorization: Bearer <token>`. The token does not need any scopes. Messages under legal hold can not be deleted. Disabled unless `messageDeletion` is enabled")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/message/:id/permalink",
+            get_with(permalink::get_message_permalink, |op| {
+                op.description("Show a message with the messages around it as an HTML page, or as JSON with `json`. The returned permalink stays valid when the channel is renamed")
+            }),
+        )
+        .api_route(
+            "/query",
+            post_with(handlers::query_logs, |op| {
+                op.description("Get the messages of several channels matching all given filters, in the requested format. Only includes messages which have been written to the database. Opted out channels and users are rejected like in the logs routes")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/nonce/:nonce",
+            get_with(handlers::messages_by_client_nonce, |op| {
+                op.description("Find the messages sent with the given client nonce in the range, including ones which have not been written to the database yet. The range must not be longer than 7 days")
+            }),
+        )
+        .api_route(
+            "/:channel_id_type/:channel/search",
+            get_with(handlers::search_channel_logs, |op| {
+                op.description("Search channel logs using the provided query. With `relevance`, results are ranked by how often the query matches the normalized text, weighted by recency, and returned as JSON with a `score`")
+            }),
+        )
         .api_route(
             "/:channel_id_type/:channel/user/:user/search",
             get_with(handlers::search_user_logs_by_name, |op| {
@@ -163,13 +786,54 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 op.description("Search user logs using the provided query")
             }),
         )
-        .api_route("/optout", post(handlers::optout))
+        .api_route(
+            "/resolve/users",
+            post_with(handlers::resolve_users, |op| {
+                op.description("Resolve up to 100 user logins to their IDs")
+            }),
+        )
+        // Browser redirects of the OAuth flow are not described in the API docs
+        .route("/optout", axum::routing::get(opt_out::start_opt_out))
+        .route("/optout/callback", axum::routing::get(opt_out::opt_out_callback))
+        .api_route(
+            "/consent",
+            post_with(handlers::consent, |op| {
+                op.description(
+                    "Get a code which the user sends in chat to consent to their logs being served",
+                )
+            }),
+        )
         .api_route("/capabilities", get(capabilities))
-        // .route("/docs", Redoc::new("/openapi.json").axum_route())
-        // .route("/openapi.json", get(serve_openapi))
+        .route("/docs", Redoc::new("/openapi.json").axum_route())
+        .route("/openapi.json", get(openapi::serve_openapi))
         .route("/assets/*asset", get(frontend::static_asset))
+        .route_layer(middleware::from_fn_with_state(
+            app.clone(),
+            export_quotas::check_export_quota,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            (app.clone(), api_keys::RateLimiter::default()),
+            api_keys::check_api_key,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            (app.clone(), usage_tracker),
+            usage::track_usage,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app.clone(),
+            query_debug::attach_query_stats,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app.clone(),
+            endpoint_groups::check_endpoint_group,
+        ))
+        .route_layer(middleware::from_fn_with_state(
+            app.clone(),
+            channel_alias::redirect_renamed_channels,
+        ))
         .fallback(frontend::static_asset)
         .layer(middleware::from_fn(capabilities_header_middleware))
+        .layer(middleware::from_fn(pagination::link_header_middleware))
         .layer(
             TraceLayer::new_for_http()
                 .make_span_with(trace_layer::make_span_with)
@@ -180,27 +844,13 @@ pub async fn run(app: App, mut shutdown_rx: ShutdownRx, bot_tx: Sender<BotMessag
                 .with_prefix("rustlog")
                 .build(),
         )
-        // .route("/metrics", get(metrics))
+        .route("/metrics", get(metrics))
         .finish_api(&mut api)
-        .layer(Extension(Arc::new(api)))
+        .layer(Extension(Arc::new(openapi::with_operation_ids(api))))
         .with_state(app)
         .layer(cors)
         .layer(CompressionLayer::new().quality(CompressionLevel::Fastest));
-    let app = NormalizePath::trim_trailing_slash(app);
-
-    info!("Listening on {listen_address}");
-
-    let listener = TcpListener::bind(&listen_address)
-        .await
-        .expect("Could not create TCP listener");
-
-    axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
-        .with_graceful_shutdown(async move {
-            shutdown_rx.changed().await.ok();
-            debug!("Shutting down web task");
-        })
-        .await
-        .unwrap();
+    NormalizePath::trim_trailing_slash(app)
 }
 
 pub fn parse_listen_addr(addr: &str) -> Result<SocketAddr, AddrParseError> {
@@ -231,6 +881,3 @@ async fn metrics() -> impl IntoApiResponse {
     let metrics = encoder.encode_to_string(&metric_families).unwrap();
     (no_cache_header(), metrics)
 }
-async fn serve_openapi(Extension(api): Extension<Arc<OpenApi>>) -> impl IntoApiResponse {
-    Json(api.as_ref()).into_response()
-}
@@ -0,0 +1,100 @@
+use aide::{
+    axum::IntoApiResponse,
+    openapi::{Info, OpenApi, ReferenceOr},
+};
+use axum::{response::IntoResponse, Extension, Json};
+use std::sync::Arc;
+
+/// The API follows the crate version, so clients can tell which API they were generated from
+pub const API_VERSION: &str = env!("CARGO_PKG_VERSION");
+const API_VERSION_HEADER: &str = "x-rustlog-api-version";
+
+pub fn info() -> Info {
+    Info {
+        title: "Rustlog".to_owned(),
+        description: Some("Logs of Twitch chats".to_owned()),
+        version: API_VERSION.to_owned(),
+        ..Default::default()
+    }
+}
+
+/// Client generators need an ID for every operation to name its method. Operations without one
+/// get an ID from their method and path, e.g. `getByChannelIdTypeByChannelUserByUser`
+pub fn with_operation_ids(mut api: OpenApi) -> OpenApi {
+    let Some(paths) = &mut api.paths else {
+        return api;
+    };
+
+    for (path, item) in &mut paths.paths {
+        let ReferenceOr::Item(item) = item else {
+            continue;
+        };
+
+        let operations = [
+            ("get", &mut item.get),
+            ("put", &mut item.put),
+            ("post", &mut item.post),
+            ("patch", &mut item.patch),
+            ("delete", &mut item.delete),
+        ];
+        for (method, operation) in operations {
+            if let Some(operation) = operation {
+                operation
+                    .operation_id
+                    .get_or_insert_with(|| operation_id(method, path));
+            }
+        }
+    }
+
+    api
+}
+
+fn operation_id(method: &str, path: &str) -> String {
+    let mut id = method.to_owned();
+
+    for segment in path.split('/').filter(|segment| !segment.is_empty()) {
+        let name = match segment
+            .strip_prefix('{')
+            .and_then(|segment| segment.strip_suffix('}'))
+        {
+            Some(param) => {
+                id.push_str("By");
+                param
+            }
+            None => segment,
+        };
+
+        for word in name.split(|c: char| !c.is_ascii_alphanumeric()) {
+            let mut chars = word.chars();
+            if let Some(first) = chars.next() {
+                id.push(first.to_ascii_uppercase());
+                id.push_str(chars.as_str());
+            }
+        }
+    }
+
+    id
+}
+
+pub async fn serve_openapi(Extension(api): Extension<Arc<OpenApi>>) -> impl IntoApiResponse {
+    ([(API_VERSION_HEADER, API_VERSION)], Json(api.as_ref())).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::operation_id;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn operation_ids_from_paths() {
+        assert_eq!(
+            operation_id("get", "/{channel_id_type}/{channel}/user/{user}"),
+            "getByChannelIdTypeByChannelUserByUser"
+        );
+        assert_eq!(
+            operation_id("post", "/admin/channels/bulk-join"),
+            "postAdminChannelsBulkJoin"
+        );
+        assert_eq!(operation_id("get", "/"), "get");
+    }
+}
@@ -0,0 +1,226 @@
+//! Self-service opt-out. Users are sent to Twitch to log in, which proves they own the account,
+//! and are opted out once Twitch redirects them back to the callback.
+//! The OAuth state is also stored in a cookie, so only the browser which started the opt-out can finish it
+
+use crate::{
+    app::{
+        cache_invalidation::{invalidate_cached_logs, read_cached_user_ranges},
+        App, PendingOptOut,
+    },
+    db::{deleted_users::purge_users, opt_outs::write_opt_out},
+    error::Error,
+    Result,
+};
+use axum::{
+    extract::{Query, State},
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Response},
+};
+use axum_extra::extract::cookie::{Cookie, CookieJar, SameSite};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use schemars::JsonSchema;
+use serde::Deserialize;
+use std::time::{Duration, Instant};
+use tracing::{error, info};
+use twitch_api::twitch_oauth2::{AccessToken, UserToken};
+use url::Url;
+
+const AUTHORIZE_URL: &str = "https://id.twitch.tv/oauth2/authorize";
+const TOKEN_URL: &str = "https://id.twitch.tv/oauth2/token";
+const STATE_LENGTH: usize = 32;
+const STATE_COOKIE: &str = "rustlog_optout_state";
+/// How long users have to log in with Twitch before the opt-out has to be started again
+const PENDING_OPT_OUT_TIMEOUT: Duration = Duration::from_secs(600);
+/// Started opt-outs which are kept, further ones are rejected until some have finished or expired
+const MAX_PENDING_OPT_OUTS: usize = 10_000;
+
+#[derive(Deserialize, JsonSchema)]
+pub struct OptOutParams {
+    /// Also delete the messages which have already been logged
+    #[serde(default)]
+    pub purge: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct OptOutCallbackParams {
+    pub code: Option<String>,
+    pub state: String,
+    /// Set by Twitch if the user did not authorize the application
+    pub error_description: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+}
+
+pub async fn start_opt_out(
+    app: State<App>,
+    jar: CookieJar,
+    Query(OptOutParams { purge }): Query<OptOutParams>,
+) -> Result<Response> {
+    let redirect_url = app
+        .config
+        .opt_out_redirect_url
+        .as_ref()
+        .ok_or(Error::EndpointDisabled)?;
+
+    let state: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(STATE_LENGTH)
+        .map(char::from)
+        .collect();
+
+    app.optout_codes
+        .retain(|_, pending| pending.started_at.elapsed() < PENDING_OPT_OUT_TIMEOUT);
+    if app.optout_codes.len() >= MAX_PENDING_OPT_OUTS {
+        return Ok((
+            StatusCode::SERVICE_UNAVAILABLE,
+            "Too many opt-outs are in progress, try again later",
+        )
+            .into_response());
+    }
+    app.optout_codes.insert(
+        state.clone(),
+        PendingOptOut {
+            purge,
+            started_at: Instant::now(),
+        },
+    );
+
+    // No scopes are needed, the token only has to identify the user.
+    // The login is always asked for, so a link can't opt out users who authorized the application before
+    let url = Url::parse_with_params(
+        AUTHORIZE_URL,
+        [
+            ("client_id", app.config.client_id.as_str()),
+            ("force_verify", "true"),
+            ("redirect_uri", redirect_url),
+            ("response_type", "code"),
+            ("scope", ""),
+            ("state", &state),
+        ],
+    )
+    .map_err(|_| Error::Internal)?;
+
+    // Lax, because the callback is opened by a redirect from Twitch
+    let cookie = Cookie::build((STATE_COOKIE, state))
+        .path("/")
+        .http_only(true)
+        .secure(redirect_url.starts_with("https://"))
+        .same_site(SameSite::Lax);
+
+    Ok((jar.add(cookie), Redirect::to(url.as_str())).into_response())
+}
+
+pub async fn opt_out_callback(
+    app: State<App>,
+    jar: CookieJar,
+    Query(params): Query<OptOutCallbackParams>,
+) -> Result<(CookieJar, String)> {
+    let redirect_url = app
+        .config
+        .opt_out_redirect_url
+        .as_ref()
+        .ok_or(Error::EndpointDisabled)?;
+
+    if jar.get(STATE_COOKIE).map(Cookie::value) != Some(params.state.as_str()) {
+        return Err(Error::InvalidParam(
+            "The opt-out was started in another browser".to_owned(),
+        ));
+    }
+    let jar = jar.remove(Cookie::build(STATE_COOKIE).path("/"));
+    let (_, PendingOptOut { purge, started_at }) = app
+        .optout_codes
+        .remove(&params.state)
+        .ok_or_else(|| Error::InvalidParam("Unknown or already used state".to_owned()))?;
+    if started_at.elapsed() >= PENDING_OPT_OUT_TIMEOUT {
+        return Err(Error::InvalidParam(
+            "The opt-out has expired, please start again".to_owned(),
+        ));
+    }
+    let code = match (params.code, params.error_description) {
+        (Some(code), _) => code,
+        (None, description) => {
+            return Err(Error::InvalidParam(description.unwrap_or_else(|| {
+                "The authorization was not completed".to_owned()
+            })))
+        }
+    };
+
+    let access_token = exchange_code(&app, &code, redirect_url).await?;
+    let token = UserToken::from_token(app.helix_client.get_client().inner(), access_token)
+        .await
+        .map_err(|_| Error::InvalidParam("Invalid user access token".to_owned()))?;
+    let user_id = token.user_id.to_string();
+    // Cached channel logs still contain the user's messages, whether they are deleted or only hidden
+    let cached_ranges = read_cached_user_ranges(&app, &[user_id.clone()]).await?;
+    // The messages of users under legal hold are kept, the opt-out still hides them
+    let legal_holds = &app.config.legal_holds;
+    let held = purge && legal_holds.users.contains_key(&user_id);
+    let purge = purge && !held;
+
+    write_opt_out(&app.db.primary(), &user_id, true, purge).await?;
+    app.opted_out_users.insert(user_id.clone());
+    info!("User {} ({user_id}) opted out", token.login);
+
+    if purge {
+        let held_channels = legal_holds.channel_ids();
+        let evict_user_id = user_id.clone();
+        app.flush_buffer
+            .evict(move |msg| {
+                msg.user_id == evict_user_id
+                    && !held_channels
+                        .iter()
+                        .any(|channel_id| msg.channel_id == *channel_id)
+            })
+            .await;
+        purge_users(&app.db.primary(), &[user_id], &legal_holds.channel_ids()).await?;
+        invalidate_cached_logs(&app, cached_ranges, true);
+        Ok((
+            jar,
+            "You have been opted out, your logs are being deleted".to_owned(),
+        ))
+    } else if held {
+        invalidate_cached_logs(&app, cached_ranges, false);
+        Ok((
+            jar,
+            "You have been opted out, your logs can not be deleted at this time".to_owned(),
+        ))
+    } else {
+        invalidate_cached_logs(&app, cached_ranges, false);
+        Ok((jar, "You have been opted out".to_owned()))
+    }
+}
+
+async fn exchange_code(app: &App, code: &str, redirect_url: &str) -> Result<AccessToken> {
+    let response = reqwest::Client::new()
+        .post(TOKEN_URL)
+        .form(&[
+            ("client_id", app.config.client_id.as_str()),
+            ("client_secret", app.config.client_secret.expose().as_str()),
+            ("code", code),
+            ("grant_type", "authorization_code"),
+            ("redirect_uri", redirect_url),
+        ])
+        .send()
+        .await
+        .and_then(|response| response.error_for_status());
+
+    let body = match response {
+        Ok(response) => response.bytes().await.map_err(|err| {
+            error!("Could not read token response: {err}");
+            Error::Internal
+        })?,
+        Err(err) => {
+            error!("Could not exchange authorization code: {err}");
+            return Err(Error::InvalidParam(
+                "The authorization code is invalid or expired".to_owned(),
+            ));
+        }
+    };
+    let TokenResponse { access_token } = serde_json::from_slice(&body)
+        .map_err(|err| anyhow::anyhow!("Invalid token response: {err}"))?;
+
+    Ok(AccessToken::from(access_token))
+}
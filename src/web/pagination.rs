@@ -0,0 +1,114 @@
+use axum::{
+    extract::Request,
+    http::{header::LINK, HeaderValue, Uri},
+    middleware::Next,
+    response::{IntoResponseParts, Response, ResponseParts},
+};
+use std::convert::Infallible;
+use url::form_urlencoded;
+
+/// Marks a response as one page of a list paginated with `limit` and `offset`.
+/// The `Link` header is added by [`link_header_middleware`], which knows the request uri
+#[derive(Clone, Copy, Debug)]
+pub struct Page {
+    limit: Option<u64>,
+    offset: u64,
+    /// Whether a next page may exist. Streamed logs don't know this upfront, so they always link to the next page
+    has_more: bool,
+}
+
+impl Page {
+    pub fn new(limit: Option<u64>, offset: Option<u64>, has_more: bool) -> Self {
+        Self {
+            limit,
+            offset: offset.unwrap_or(0),
+            has_more,
+        }
+    }
+
+    /// RFC 5988 links to the next and previous page, `None` if the response is not paginated
+    fn link_header(&self, uri: &Uri) -> Option<HeaderValue> {
+        let limit = self.limit.filter(|limit| *limit > 0)?;
+
+        let mut links = Vec::with_capacity(2);
+        if self.has_more {
+            let next = page_url(uri, self.offset.saturating_add(limit));
+            links.push(format!("<{next}>; rel=\"next\""));
+        }
+        if self.offset > 0 {
+            let prev = page_url(uri, self.offset.saturating_sub(limit));
+            links.push(format!("<{prev}>; rel=\"prev\""));
+        }
+
+        if links.is_empty() {
+            None
+        } else {
+            HeaderValue::from_str(&links.join(", ")).ok()
+        }
+    }
+}
+
+impl IntoResponseParts for Page {
+    type Error = Infallible;
+
+    fn into_response_parts(self, mut res: ResponseParts) -> Result<ResponseParts, Self::Error> {
+        res.extensions_mut().insert(self);
+        Ok(res)
+    }
+}
+
+pub async fn link_header_middleware(request: Request, next: Next) -> Response {
+    let uri = request.uri().clone();
+    let mut response = next.run(request).await;
+
+    let page = response.extensions().get::<Page>().copied();
+    if let Some(link) = page.and_then(|page| page.link_header(&uri)) {
+        response.headers_mut().insert(LINK, link);
+    }
+    response
+}
+
+/// The request uri with the offset replaced
+fn page_url(uri: &Uri, offset: u64) -> String {
+    let query = uri.query().unwrap_or_default();
+
+    let mut serializer = form_urlencoded::Serializer::new(String::new());
+    for (key, value) in form_urlencoded::parse(query.as_bytes()) {
+        if key != "offset" {
+            serializer.append_pair(&key, &value);
+        }
+    }
+    serializer.append_pair("offset", &offset.to_string());
+
+    format!("{}?{}", uri.path(), serializer.finish())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Page;
+    use axum::http::Uri;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn links_to_next_and_prev_page() {
+        let uri: Uri = "/channel/forsen/user/supibot?json&limit=100&offset=150"
+            .parse()
+            .unwrap();
+        let link = Page::new(Some(100), Some(150), true)
+            .link_header(&uri)
+            .unwrap();
+
+        assert_eq!(
+            "</channel/forsen/user/supibot?json=&limit=100&offset=250>; rel=\"next\", </channel/forsen/user/supibot?json=&limit=100&offset=50>; rel=\"prev\"",
+            link.to_str().unwrap()
+        );
+    }
+
+    #[test]
+    fn no_links_without_limit() {
+        let uri: Uri = "/channel/forsen/user/supibot?json".parse().unwrap();
+
+        assert!(Page::new(None, Some(10), true).link_header(&uri).is_none());
+        assert!(Page::new(Some(10), None, false).link_header(&uri).is_none());
+    }
+}
@@ -0,0 +1,150 @@
+use super::{
+    handlers::{cache_header, no_cache_header},
+    schema::{MessagePath, MessagePermalink, PermalinkParams},
+};
+use crate::{
+    app::App,
+    db::{
+        context::{read_message_context, MessageContext},
+        pool::QueryClass,
+        schema::StructuredMessage,
+    },
+    error::Error,
+    export::escape_html,
+    logs::schema::message::{FullMessage, ResponseMessage},
+    Result,
+};
+use axum::{
+    extract::{Path, Query, State},
+    response::{Html, IntoResponse, Response},
+    Json,
+};
+use chrono::{DateTime, Utc};
+use std::fmt::Write;
+use uuid::Uuid;
+
+const DEFAULT_CONTEXT: u64 = 10;
+const MAX_CONTEXT: u64 = 100;
+/// Messages following a recent message may still be written, so it is not cached until then
+const CACHE_DELAY_SECONDS: i64 = 3600;
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
+
+pub async fn get_message_permalink(
+    app: State<App>,
+    Path(MessagePath {
+        channel_id_type,
+        channel,
+        id,
+    }): Path<MessagePath>,
+    Query(params): Query<PermalinkParams>,
+) -> Result<Response> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let id =
+        Uuid::parse_str(&id).map_err(|_| Error::InvalidParam("Invalid message id".to_owned()))?;
+    let context_size = params.context.unwrap_or(DEFAULT_CONTEXT).min(MAX_CONTEXT);
+
+    let mut context =
+        app.db
+            .read_with_failover(QueryClass::Logs, |db| {
+                let channel_id = &channel_id;
+                let flush_buffer = &app.flush_buffer;
+                async move {
+                    read_message_context(&db, channel_id, id, context_size, flush_buffer).await
+                }
+            })
+            .await?;
+
+    if app.has_opted_out(&context.message.user_id) {
+        return Err(Error::UserOptedOut);
+    }
+    let opted_out = |msg: &StructuredMessage| app.has_opted_out(&msg.user_id);
+    context.before.retain(|msg| !opted_out(msg));
+    context.after.retain(|msg| !opted_out(msg));
+
+    let sent_at =
+        DateTime::from_timestamp_millis(context.message.timestamp as i64).ok_or(Error::Internal)?;
+    let cache = if (Utc::now() - sent_at).num_seconds() > CACHE_DELAY_SECONDS {
+        cache_header(36000)
+    } else {
+        no_cache_header()
+    };
+
+    let permalink = permalink_url(channel_id.as_str(), &id.to_string());
+
+    if params.json {
+        let body = MessagePermalink {
+            message: FullMessage::from_structured(&context.message)?,
+            before: full_messages(&context.before),
+            after: full_messages(&context.after),
+            permalink,
+        };
+        Ok((cache, Json(body)).into_response())
+    } else {
+        let page = render_page(&permalink, &context);
+        Ok((cache, Html(page)).into_response())
+    }
+}
+
+fn permalink_url(channel_id: &str, message_id: &str) -> String {
+    format!("/channelid/{channel_id}/message/{message_id}/permalink")
+}
+
+/// Messages which can't be parsed are left out of the context
+fn full_messages<'a>(messages: &'a [StructuredMessage<'a>]) -> Vec<FullMessage<'a>> {
+    messages
+        .iter()
+        .filter_map(|msg| FullMessage::from_structured(msg).ok())
+        .collect()
+}
+
+fn render_page(permalink: &str, context: &MessageContext) -> String {
+    let message = &context.message;
+    let title = escape_html(&format!(
+        "Message by {} in #{}",
+        message.display_name(),
+        message.channel_login
+    ));
+
+    let mut html = format!(
+        "<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>{title}</title>\n</head>\n<body>\n<h1>{title}</h1>\n\
+        <p><a href=\"{permalink}\">Permalink</a></p>\n<pre>\n"
+    );
+
+    for msg in &context.before {
+        render_line(&mut html, msg);
+    }
+    html.push_str("<mark id=\"message\">");
+    render_line(&mut html, message);
+    html.push_str("</mark>");
+    for msg in &context.after {
+        render_line(&mut html, msg);
+    }
+
+    html.push_str("</pre>\n</body>\n</html>\n");
+    html
+}
+
+fn render_line(html: &mut String, msg: &StructuredMessage) {
+    let time = DateTime::from_timestamp_millis(msg.timestamp as i64)
+        .unwrap_or_default()
+        .format(TIMESTAMP_FORMAT);
+    let text = escape_html(&msg.user_friendly_text());
+
+    let time = match msg.id() {
+        Some(id) => format!(
+            "<a href=\"{}\">[{time}]</a>",
+            permalink_url(&msg.channel_id, &id)
+        ),
+        None => format!("[{time}]"),
+    };
+
+    if msg.user_login.is_empty() {
+        let _ = writeln!(html, "{time} {text}");
+    } else {
+        let user = escape_html(msg.display_name());
+        let _ = writeln!(html, "{time} <b>{user}</b>: {text}");
+    }
+}
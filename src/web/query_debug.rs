@@ -0,0 +1,66 @@
+use super::admin::is_admin_request;
+use crate::{
+    app::App,
+    db::{
+        pool::{sync_with_log_comment, with_log_comment},
+        processes::read_query_stats,
+    },
+};
+use axum::{
+    body::Body,
+    extract::{Request, State},
+    http::HeaderValue,
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use futures::{stream, StreamExt};
+use tracing::error;
+use uuid::Uuid;
+
+/// Adds statistics of the database queries run for the request as response headers,
+/// if the admin API key is used and `debug` is set
+pub async fn attach_query_stats(app: State<App>, request: Request, next: Next) -> Response {
+    if !is_debug_request(&request) || !is_admin_request(&app, &request) {
+        return next.run(request).await;
+    }
+
+    // Only the queries of this request are tagged, so concurrent requests are not counted
+    let log_comment = format!("rustlog-debug-{}", Uuid::new_v4());
+    let started_at = Utc::now();
+    let mut response = with_log_comment(log_comment.clone(), next.run(request)).await;
+
+    // Streamed responses may still be reading, so only queries finished so far are included
+    match read_query_stats(&app.db, &log_comment, started_at).await {
+        Ok(stats) => {
+            let headers = response.headers_mut();
+            for (name, value) in [
+                ("x-query-count", stats.query_count),
+                ("x-query-rows-read", stats.read_rows),
+                ("x-query-bytes-read", stats.read_bytes),
+                ("x-query-elapsed-ms", stats.elapsed_ms),
+            ] {
+                headers.insert(name, HeaderValue::from(value));
+            }
+        }
+        Err(err) => error!("Could not read query statistics: {err}"),
+    }
+
+    // Queries started while the body is streamed are tagged as well
+    let (parts, body) = response.into_parts();
+    let mut body = body.into_data_stream();
+    let body = stream::poll_fn(move |cx| {
+        sync_with_log_comment(log_comment.clone(), || body.poll_next_unpin(cx))
+    });
+
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+/// Like the other boolean parameters, `debug` is set by being present
+fn is_debug_request(request: &Request) -> bool {
+    request
+        .uri()
+        .query()
+        .map(|query| url::form_urlencoded::parse(query.as_bytes()).any(|(key, _)| key == "debug"))
+        .unwrap_or(false)
+}
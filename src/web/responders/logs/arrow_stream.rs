@@ -0,0 +1,135 @@
+use crate::{db::schema::StructuredMessage, error::Error, logs::stream::LogsStream, Result};
+use arrow_array::{
+    ArrayRef, RecordBatch, StringArray, TimestampMillisecondArray, UInt16Array, UInt32Array,
+};
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::{ArrowError, DataType, Field, Schema, SchemaRef, TimeUnit};
+use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::pin;
+use tracing::error;
+
+const CHUNK_SIZE: usize = 3000;
+
+/// Streams messages as Arrow IPC record batches, one batch per chunk of messages
+pub struct ArrowLogsStream {
+    inner: TryChunks<LogsStream>,
+    schema: SchemaRef,
+    /// Taken once the end of stream marker has been written
+    writer: Option<StreamWriter<Vec<u8>>>,
+}
+
+impl ArrowLogsStream {
+    pub fn new(stream: LogsStream) -> Result<Self> {
+        let schema = Arc::new(message_schema());
+        let writer = StreamWriter::try_new(Vec::new(), &schema).map_err(arrow_error)?;
+
+        Ok(Self {
+            inner: stream.try_chunks(CHUNK_SIZE),
+            schema,
+            writer: Some(writer),
+        })
+    }
+
+    fn write_chunk(&mut self, chunk: &[StructuredMessage]) -> Result<Vec<u8>> {
+        let batch = build_batch(self.schema.clone(), chunk).map_err(arrow_error)?;
+
+        let writer = self.writer.as_mut().ok_or(Error::Internal)?;
+        writer.write(&batch).map_err(arrow_error)?;
+        Ok(std::mem::take(writer.get_mut()))
+    }
+
+    fn finish(&mut self) -> Option<Result<Vec<u8>>> {
+        let mut writer = self.writer.take()?;
+        let result = writer
+            .finish()
+            .and_then(|()| writer.into_inner())
+            .map_err(arrow_error);
+        Some(result)
+    }
+}
+
+impl Stream for ArrowLogsStream {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = {
+            let fut = self.inner.next();
+            pin!(fut);
+            fut.poll(cx)
+        };
+
+        match polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let chunk: Vec<StructuredMessage> = chunk.into_iter().flatten().collect();
+                Poll::Ready(Some(self.write_chunk(&chunk)))
+            }
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.1))),
+            Poll::Ready(None) => Poll::Ready(self.finish()),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+pub(super) fn message_schema() -> Schema {
+    Schema::new(vec![
+        Field::new("channel_id", DataType::Utf8, false),
+        Field::new("channel_login", DataType::Utf8, false),
+        Field::new(
+            "timestamp",
+            DataType::Timestamp(TimeUnit::Millisecond, Some("UTC".into())),
+            false,
+        ),
+        Field::new("id", DataType::Utf8, false),
+        Field::new("message_type", DataType::Utf8, false),
+        Field::new("user_id", DataType::Utf8, false),
+        Field::new("user_login", DataType::Utf8, false),
+        Field::new("display_name", DataType::Utf8, false),
+        Field::new("color", DataType::UInt32, true),
+        Field::new("message_flags", DataType::UInt16, false),
+        Field::new("text", DataType::Utf8, false),
+        Field::new("raw", DataType::Utf8, false),
+    ])
+}
+
+pub(super) fn build_batch(
+    schema: SchemaRef,
+    messages: &[StructuredMessage],
+) -> std::result::Result<RecordBatch, ArrowError> {
+    let strings = |f: fn(&StructuredMessage) -> String| -> ArrayRef {
+        Arc::new(StringArray::from_iter_values(messages.iter().map(f)))
+    };
+
+    let columns: Vec<ArrayRef> = vec![
+        strings(|msg| msg.channel_id.to_string()),
+        strings(|msg| msg.channel_login.to_string()),
+        Arc::new(
+            TimestampMillisecondArray::from_iter_values(
+                messages.iter().map(|msg| msg.timestamp as i64),
+            )
+            .with_timezone("UTC"),
+        ),
+        strings(|msg| msg.id().unwrap_or_default()),
+        strings(|msg| msg.message_type.to_string()),
+        strings(|msg| msg.user_id.to_string()),
+        strings(|msg| msg.user_login.to_string()),
+        strings(|msg| msg.display_name().to_owned()),
+        Arc::new(UInt32Array::from_iter(messages.iter().map(|msg| msg.color))),
+        Arc::new(UInt16Array::from_iter_values(
+            messages.iter().map(|msg| msg.message_flags.bits()),
+        )),
+        strings(|msg| msg.user_friendly_text().into_owned()),
+        strings(|msg| msg.to_raw_irc()),
+    ];
+
+    RecordBatch::try_new(schema, columns)
+}
+
+fn arrow_error(err: ArrowError) -> Error {
+    error!("Could not write Arrow IPC stream: {err}");
+    Error::Internal
+}
@@ -0,0 +1,114 @@
+use super::LogsResponseType;
+use crate::{
+    app::logs_cache::{LogsCache, LogsCacheKey},
+    error::Error,
+};
+use axum::{
+    body::{Body, BodyDataStream, Bytes},
+    http::{
+        header::{CONTENT_ENCODING, CONTENT_TYPE, VARY},
+        HeaderValue,
+    },
+    response::{IntoResponse, Response},
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{Stream, StreamExt};
+use std::{
+    io::{Read, Write},
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tracing::error;
+
+/// Serves logs from the cache. The compressed blob is sent as is if the client accepts gzip
+pub fn cached_logs_response(
+    blob: Bytes,
+    response_type: &LogsResponseType,
+    accepts_gzip: bool,
+) -> Response {
+    let content_type = HeaderValue::from_static(response_type.content_type());
+    let vary = HeaderValue::from_static("accept-encoding");
+
+    if accepts_gzip {
+        let encoding = HeaderValue::from_static("gzip");
+        (
+            [
+                (CONTENT_TYPE, content_type),
+                (CONTENT_ENCODING, encoding),
+                (VARY, vary),
+            ],
+            blob,
+        )
+            .into_response()
+    } else {
+        let mut body = Vec::new();
+        if let Err(err) = GzDecoder::new(blob.as_ref()).read_to_end(&mut body) {
+            error!("Could not decompress cached logs: {err}");
+            return Error::Internal.into_response();
+        }
+        ([(CONTENT_TYPE, content_type), (VARY, vary)], body).into_response()
+    }
+}
+
+/// Compresses a copy of the response body while it is sent, and caches it once the body is complete
+pub fn cache_logs_response(
+    response: Response,
+    cache: Arc<LogsCache>,
+    key: LogsCacheKey,
+) -> Response {
+    if !response.status().is_success() {
+        return response;
+    }
+
+    let (parts, body) = response.into_parts();
+    let stream = CachingStream {
+        inner: body.into_data_stream(),
+        encoder: Some(GzEncoder::new(Vec::new(), Compression::default())),
+        cache,
+        key: Some(key),
+    };
+    Response::from_parts(parts, Body::from_stream(stream))
+}
+
+struct CachingStream {
+    inner: BodyDataStream,
+    /// Dropped if the body could not be read completely, so incomplete logs are never cached
+    encoder: Option<GzEncoder<Vec<u8>>>,
+    cache: Arc<LogsCache>,
+    key: Option<LogsCacheKey>,
+}
+
+impl Stream for CachingStream {
+    type Item = Result<Bytes, axum::Error>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let poll = self.inner.poll_next_unpin(cx);
+
+        match &poll {
+            Poll::Ready(Some(Ok(chunk))) => {
+                if let Some(encoder) = &mut self.encoder {
+                    if let Err(err) = encoder.write_all(chunk) {
+                        error!("Could not compress logs for caching: {err}");
+                        self.encoder = None;
+                    }
+                }
+            }
+            Poll::Ready(Some(Err(_))) => self.encoder = None,
+            Poll::Ready(None) => {
+                if let (Some(encoder), Some(key)) = (self.encoder.take(), self.key.take()) {
+                    match encoder.finish() {
+                        Ok(blob) => {
+                            let cache = self.cache.clone();
+                            tokio::spawn(async move { cache.put(&key, blob).await });
+                        }
+                        Err(err) => error!("Could not compress logs for caching: {err}"),
+                    }
+                }
+            }
+            Poll::Pending => (),
+        }
+
+        poll
+    }
+}
@@ -0,0 +1,153 @@
+use crate::{
+    db::schema::StructuredMessage, logs::stream::LogsStream, web::schema::FormatOptions, Result,
+};
+use chrono::{DateTime, SecondsFormat};
+use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
+use std::{
+    pin::Pin,
+    task::{Context, Poll},
+};
+use tokio::pin;
+
+const CHUNK_SIZE: usize = 3000;
+const FORMULA_PREFIXES: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+const HEADER: &str = "timestamp,channel_id,channel_login,id,message_type,user_id,user_login,display_name,badges,flags,text\r\n";
+
+/// Streams messages as CSV rows with a header, one row per message
+pub struct CsvLogsStream {
+    inner: TryChunks<LogsStream>,
+    options: FormatOptions,
+    header_written: bool,
+}
+
+impl CsvLogsStream {
+    pub fn new(stream: LogsStream, options: FormatOptions) -> Self {
+        Self {
+            inner: stream.try_chunks(CHUNK_SIZE),
+            options,
+            header_written: false,
+        }
+    }
+}
+
+impl Stream for CsvLogsStream {
+    type Item = Result<String>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        if !self.header_written {
+            self.header_written = true;
+            return Poll::Ready(Some(Ok(HEADER.to_owned())));
+        }
+
+        let options = self.options;
+        let fut = self.inner.next();
+        pin!(fut);
+
+        fut.poll(cx).map(|maybe_result| {
+            maybe_result.map(|result| match result {
+                Ok(chunk) => {
+                    let mut buf = String::new();
+                    for msg in chunk.iter().flatten() {
+                        write_csv_row(&mut buf, msg, &options);
+                    }
+                    Ok(buf)
+                }
+                Err(err) => Err(err.1),
+            })
+        })
+    }
+}
+
+fn write_csv_row(output: &mut String, msg: &StructuredMessage, options: &FormatOptions) {
+    let timestamp = DateTime::from_timestamp_millis(msg.timestamp as i64)
+        .unwrap_or_default()
+        .with_timezone(&options.timezone.unwrap_or(chrono_tz::UTC))
+        .to_rfc3339_opts(SecondsFormat::Millis, false);
+    let badges = msg.badges.join(",");
+    let flags = msg
+        .message_flags
+        .iter_names()
+        .map(|(name, _)| name.to_lowercase())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    let id = msg.id().unwrap_or_default();
+    let message_type = msg.message_type.to_string();
+    let text = msg.user_friendly_text();
+
+    let fields: [&str; 11] = [
+        &timestamp,
+        &msg.channel_id,
+        &msg.channel_login,
+        &id,
+        &message_type,
+        &msg.user_id,
+        &msg.user_login,
+        msg.display_name(),
+        &badges,
+        &flags,
+        &text,
+    ];
+    for (i, field) in fields.into_iter().enumerate() {
+        if i > 0 {
+            output.push(',');
+        }
+        write_csv_field(output, field);
+    }
+    output.push_str("\r\n");
+}
+
+/// Quotes the field if it contains a separator, quote or line break, as described in RFC 4180.
+/// Fields which spreadsheets would evaluate as a formula are prefixed with `'`
+fn write_csv_field(output: &mut String, field: &str) {
+    let escaped;
+    let field = if field.starts_with(FORMULA_PREFIXES) {
+        escaped = format!("'{field}");
+        &escaped
+    } else {
+        field
+    };
+
+    if field.contains([',', '"', '\r', '\n']) {
+        output.push('"');
+        output.push_str(&field.replace('"', "\"\""));
+        output.push('"');
+    } else {
+        output.push_str(field);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::write_csv_field;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn quotes_special_fields() {
+        let mut output = String::new();
+        write_csv_field(&mut output, "plain");
+        output.push(',');
+        write_csv_field(&mut output, "a, \"quoted\" text");
+        output.push(',');
+        write_csv_field(&mut output, "two\nlines");
+
+        assert_eq!(output, "plain,\"a, \"\"quoted\"\" text\",\"two\nlines\"");
+    }
+
+    #[test]
+    fn escapes_formulas() {
+        let mut output = String::new();
+        write_csv_field(&mut output, "=HYPERLINK(\"https://example.com\")");
+        output.push(',');
+        write_csv_field(&mut output, "@SUM(A1)");
+        output.push(',');
+        write_csv_field(&mut output, "-1+1");
+        output.push(',');
+        write_csv_field(&mut output, "a=b");
+
+        assert_eq!(
+            output,
+            "\"'=HYPERLINK(\"\"https://example.com\"\")\",'@SUM(A1),'-1+1,a=b"
+        );
+    }
+}
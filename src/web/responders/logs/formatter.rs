@@ -0,0 +1,201 @@
+use super::{
+    arrow_stream::ArrowLogsStream,
+    csv_stream::CsvLogsStream,
+    json_stream::{JsonLogsStream, JsonOptions, JsonResponseType},
+    ndjson_stream::NdJsonLogsStream,
+    parquet_stream::ParquetLogsStream,
+    text_stream::TextLogsStream,
+};
+use crate::{error::Error, logs::stream::LogsStream, web::schema::FormatOptions, Result};
+use axum::body::Body;
+use futures::TryStreamExt;
+use std::collections::HashSet;
+
+/// Renders a stream of messages in one output format
+pub trait LogFormatter: Send + Sync {
+    /// Identifies the format in cache keys and in the `disabledFormats` config
+    fn name(&self) -> &'static str;
+
+    fn content_type(&self) -> &'static str;
+
+    fn body(&self, stream: LogsStream, options: FormatOptions, json: JsonOptions) -> Result<Body>;
+}
+
+/// Every available format. New formats only need to be added here and selected in `LogsParams`
+pub static FORMATTERS: &[&dyn LogFormatter] = &[
+    &TextFormatter,
+    &RawFormatter,
+    &JsonFormatter(JsonResponseType::Full),
+    &JsonFormatter(JsonResponseType::Basic),
+    &NdJsonFormatter,
+    &ArrowFormatter,
+    &CsvFormatter,
+    &ParquetFormatter,
+];
+
+/// Looks up an enabled format by its name
+pub fn formatter(name: &str, disabled: &HashSet<String>) -> Result<&'static dyn LogFormatter> {
+    if disabled.contains(name) {
+        return Err(Error::InvalidParam(format!(
+            "The {name} format is disabled on this instance"
+        )));
+    }
+
+    FORMATTERS
+        .iter()
+        .copied()
+        .find(|formatter| formatter.name() == name)
+        .ok_or_else(|| Error::InvalidParam(format!("Unknown format {name}")))
+}
+
+pub struct TextFormatter;
+
+impl LogFormatter for TextFormatter {
+    fn name(&self) -> &'static str {
+        "text"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "text/plain; charset=utf-8"
+    }
+
+    fn body(&self, stream: LogsStream, options: FormatOptions, _: JsonOptions) -> Result<Body> {
+        Ok(Body::from_stream(TextLogsStream::new(stream, options)))
+    }
+}
+
+pub struct RawFormatter;
+
+impl LogFormatter for RawFormatter {
+    fn name(&self) -> &'static str {
+        "raw"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "text/plain; charset=utf-8"
+    }
+
+    fn body(&self, stream: LogsStream, _: FormatOptions, _: JsonOptions) -> Result<Body> {
+        let stream = stream.map_ok(|chunk| {
+            let mut buf = String::new();
+            for msg in chunk {
+                buf.push_str(&msg.to_raw_irc());
+                buf.push_str("\r\n");
+            }
+            buf
+        });
+        Ok(Body::from_stream(stream))
+    }
+}
+
+/// Wrapped in an envelope with metadata if set
+pub struct JsonFormatter(pub JsonResponseType);
+
+impl LogFormatter for JsonFormatter {
+    fn name(&self) -> &'static str {
+        match self.0 {
+            JsonResponseType::Full => "json",
+            JsonResponseType::Basic => "json-basic",
+        }
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/json"
+    }
+
+    fn body(&self, stream: LogsStream, _: FormatOptions, json: JsonOptions) -> Result<Body> {
+        let stream = JsonLogsStream::new(stream, self.0, json);
+        Ok(Body::from_stream(stream))
+    }
+}
+
+pub struct NdJsonFormatter;
+
+impl LogFormatter for NdJsonFormatter {
+    fn name(&self) -> &'static str {
+        "ndjson"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/x-ndjson"
+    }
+
+    fn body(&self, stream: LogsStream, _: FormatOptions, _: JsonOptions) -> Result<Body> {
+        Ok(Body::from_stream(NdJsonLogsStream::new(stream)))
+    }
+}
+
+pub struct ArrowFormatter;
+
+impl LogFormatter for ArrowFormatter {
+    fn name(&self) -> &'static str {
+        "arrow"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/vnd.apache.arrow.stream"
+    }
+
+    fn body(&self, stream: LogsStream, _: FormatOptions, _: JsonOptions) -> Result<Body> {
+        Ok(Body::from_stream(ArrowLogsStream::new(stream)?))
+    }
+}
+
+pub struct CsvFormatter;
+
+impl LogFormatter for CsvFormatter {
+    fn name(&self) -> &'static str {
+        "csv"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "text/csv; charset=utf-8"
+    }
+
+    fn body(&self, stream: LogsStream, options: FormatOptions, _: JsonOptions) -> Result<Body> {
+        Ok(Body::from_stream(CsvLogsStream::new(stream, options)))
+    }
+}
+
+pub struct ParquetFormatter;
+
+impl LogFormatter for ParquetFormatter {
+    fn name(&self) -> &'static str {
+        "parquet"
+    }
+
+    fn content_type(&self) -> &'static str {
+        "application/vnd.apache.parquet"
+    }
+
+    fn body(&self, stream: LogsStream, _: FormatOptions, _: JsonOptions) -> Result<Body> {
+        Ok(Body::from_stream(ParquetLogsStream::new(stream)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{formatter, FORMATTERS};
+    use std::collections::HashSet;
+
+    #[test]
+    fn formatter_names_are_unique() {
+        let names: HashSet<_> = FORMATTERS
+            .iter()
+            .map(|formatter| formatter.name())
+            .collect();
+        assert_eq!(names.len(), FORMATTERS.len());
+    }
+
+    #[test]
+    fn skips_disabled_formatters() {
+        let disabled = HashSet::from(["arrow".to_owned()]);
+
+        assert!(formatter("arrow", &disabled).is_err());
+        assert!(formatter("unknown", &disabled).is_err());
+        assert_eq!(
+            formatter("json-basic", &disabled).unwrap().name(),
+            "json-basic"
+        );
+    }
+}
@@ -1,13 +1,18 @@
 use crate::{
     db::schema::StructuredMessage,
     logs::{
-        schema::message::{BasicMessage, FullMessage, ResponseMessage},
+        schema::message::{
+            BasicMessage, FullMessage, ResponseMessage, SearchHighlighter, StreamVods,
+        },
         stream::LogsStream,
     },
     Result,
 };
+use chrono::{DateTime, Utc};
 use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
 use rayon::prelude::{IntoParallelIterator, ParallelIterator};
+use schemars::JsonSchema;
+use serde::Serialize;
 use std::{
     collections::VecDeque,
     pin::Pin,
@@ -18,33 +23,116 @@ use tracing::error;
 
 const HEADER: &str = r#"{"messages":["#;
 const FOOTER: &str = r#"]}"#;
+const ENVELOPE_FOOTER: &str = r#"],"meta":"#;
 /// Rough estimation of how big a single message is in JSON format
 const JSON_MESSAGE_SIZE: usize = 1024;
 const CHUNK_SIZE: usize = 3000;
 
+#[derive(Clone, Copy)]
 pub enum JsonResponseType {
     Basic,
     Full,
 }
 
+/// Options which only apply to the JSON formats
+#[derive(Clone, Default)]
+pub struct JsonOptions {
+    pub envelope: Option<Envelope>,
+    pub vods: Option<StreamVods>,
+    pub highlight: Option<SearchHighlighter>,
+}
+
+/// Requested pagination, reported in the `meta` object of envelope responses
+#[derive(Clone, Copy)]
+pub struct Envelope {
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LogsMeta {
+    /// Amount of messages in the response
+    pub count: u64,
+    #[schemars(with = "Option<String>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// Start of the requested range
+    pub from: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<String>")]
+    #[serde(skip_serializing_if = "Option::is_none")]
+    /// End of the requested range
+    pub to: Option<DateTime<Utc>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<u64>,
+    pub offset: u64,
+    /// Offset of the next page, not set once there are no more messages
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub next_offset: Option<u64>,
+}
+
 pub struct JsonLogsStream {
     inner: TryChunks<LogsStream>,
     is_start: bool,
     is_end: bool,
     response_type: JsonResponseType,
+    envelope: Option<Envelope>,
+    vods: Option<StreamVods>,
+    highlight: Option<SearchHighlighter>,
+    range: Option<(DateTime<Utc>, DateTime<Utc>)>,
+    count: u64,
 }
 
 impl JsonLogsStream {
-    pub fn new(stream: LogsStream, response_type: JsonResponseType) -> Self {
+    pub fn new(stream: LogsStream, response_type: JsonResponseType, options: JsonOptions) -> Self {
+        let range = stream.range();
         let inner = stream.try_chunks(CHUNK_SIZE);
         Self {
             inner,
             is_start: true,
             is_end: false,
             response_type,
+            envelope: options.envelope,
+            vods: options.vods,
+            highlight: options.highlight,
+            range,
+            count: 0,
         }
     }
 
+    /// Closes the messages array, followed by the metadata for envelope responses.
+    /// The metadata comes last because the amount of messages is only known once they have been sent
+    fn footer(&self, envelope: Envelope) -> Vec<u8> {
+        let offset = envelope.offset.unwrap_or(0);
+        // Searches are not limited to a range, which is represented by the smallest and largest dates
+        let (from, to) = match self.range {
+            Some((from, to)) => (
+                Some(from).filter(|from| *from != DateTime::UNIX_EPOCH),
+                Some(to).filter(|to| *to != DateTime::<Utc>::MAX_UTC),
+            ),
+            None => (None, None),
+        };
+        let meta = LogsMeta {
+            count: self.count,
+            from,
+            to,
+            limit: envelope.limit,
+            offset,
+            next_offset: envelope
+                .limit
+                .filter(|limit| self.count == *limit)
+                .map(|limit| offset + limit),
+        };
+
+        let mut buf = Vec::new();
+        if self.is_start {
+            buf.extend_from_slice(HEADER.as_bytes());
+        }
+        buf.extend_from_slice(ENVELOPE_FOOTER.as_bytes());
+        serde_json::to_writer(&mut buf, &meta).unwrap();
+        buf.push(b'}');
+        buf
+    }
+
     fn serialize_chunk<'a, T: ResponseMessage<'a>>(
         &mut self,
         messages: &'a [StructuredMessage<'a>],
@@ -52,13 +140,23 @@ impl JsonLogsStream {
         let mut messages: VecDeque<T> = messages
             .iter()
             .filter_map(|msg| match T::from_structured(msg) {
-                Ok(parsed) => Some(parsed),
+                Ok(mut parsed) => {
+                    if let Some(vod) = self.vods.as_ref().and_then(|vods| vods.link(msg.timestamp))
+                    {
+                        parsed.set_vod(vod);
+                    }
+                    if let Some(highlighter) = &self.highlight {
+                        parsed.set_highlight(highlighter);
+                    }
+                    Some(parsed)
+                }
                 Err(err) => {
                     error!("Could not parse message {msg:?} from DB: {err}");
                     None
                 }
             })
             .collect();
+        self.count += messages.len() as u64;
 
         let mut buf = Vec::with_capacity(JSON_MESSAGE_SIZE * messages.len());
 
@@ -118,8 +216,10 @@ impl Stream for JsonLogsStream {
             },
             Poll::Ready(None) => {
                 self.is_end = true;
-                // No lines were retrieved
-                if self.is_start {
+                if let Some(envelope) = self.envelope {
+                    Poll::Ready(Some(Ok(self.footer(envelope))))
+                } else if self.is_start {
+                    // No lines were retrieved
                     Poll::Ready(None)
                 } else {
                     Poll::Ready(Some(Ok(FOOTER.as_bytes().to_vec())))
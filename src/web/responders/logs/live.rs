@@ -0,0 +1,49 @@
+use super::{text_stream::write_text_line, JsonResponseType};
+use crate::{
+    db::schema::StructuredMessage,
+    error::Error,
+    logs::schema::message::{BasicMessage, FullMessage, ResponseMessage},
+    web::schema::FormatOptions,
+    Result,
+};
+
+/// Formats of single messages in the live feed. Every event is one message,
+/// so JSON messages are not wrapped in a `messages` array
+#[derive(Clone, Copy)]
+pub enum LiveFormat {
+    Text(FormatOptions),
+    Raw,
+    Json(JsonResponseType),
+}
+
+impl LiveFormat {
+    pub fn new(name: &str, options: FormatOptions) -> Result<Self> {
+        match name {
+            "text" => Ok(Self::Text(options)),
+            "raw" => Ok(Self::Raw),
+            "json" => Ok(Self::Json(JsonResponseType::Full)),
+            "json-basic" | "ndjson" => Ok(Self::Json(JsonResponseType::Basic)),
+            _ => Err(Error::InvalidParam(format!(
+                "The {name} format is not available for live logs"
+            ))),
+        }
+    }
+
+    pub fn render(&self, msg: &StructuredMessage) -> anyhow::Result<String> {
+        match self {
+            Self::Text(options) => {
+                let mut line = String::new();
+                write_text_line(&mut line, msg, options);
+                line.truncate(line.trim_end().len());
+                Ok(line)
+            }
+            Self::Raw => Ok(msg.to_raw_irc()),
+            Self::Json(JsonResponseType::Full) => {
+                Ok(serde_json::to_string(&FullMessage::from_structured(msg)?)?)
+            }
+            Self::Json(JsonResponseType::Basic) => {
+                Ok(serde_json::to_string(&BasicMessage::from_structured(msg)?)?)
+            }
+        }
+    }
+}
@@ -1,36 +1,66 @@
+mod arrow_stream;
+mod cached;
+mod csv_stream;
+mod formatter;
 mod json_stream;
+mod live;
 mod ndjson_stream;
+mod parquet_stream;
 mod text_stream;
 
-pub use json_stream::JsonResponseType;
+pub use cached::{cache_logs_response, cached_logs_response};
+pub use formatter::{formatter, LogFormatter, FORMATTERS};
+pub use json_stream::{Envelope, JsonResponseType};
+pub use live::LiveFormat;
+pub use text_stream::write_text_line;
 
 use self::{
-    json_stream::JsonLogsStream, ndjson_stream::NdJsonLogsStream, text_stream::TextLogsStream,
+    formatter::{
+        ArrowFormatter, CsvFormatter, JsonFormatter, NdJsonFormatter, ParquetFormatter,
+        TextFormatter,
+    },
+    json_stream::{JsonOptions, LogsMeta},
+};
+use crate::{
+    logs::{
+        schema::message::{BasicMessage, FullMessage, SearchHighlighter, StreamVods},
+        stream::LogsStream,
+    },
+    web::{
+        schema::{FormatOptions, SampleRate},
+        usage::StreamedRows,
+    },
+};
+use aide::{
+    openapi::{Example, MediaType, ReferenceOr, SchemaObject},
+    OperationOutput,
 };
-use crate::logs::{schema::message::FullMessage, stream::LogsStream};
-use aide::OperationOutput;
 use axum::{
-    body::Body,
     http::HeaderValue,
     response::{IntoResponse, IntoResponseParts, Response},
-    Json,
 };
-use futures::TryStreamExt;
 use indexmap::IndexMap;
-use mime_guess::mime::{APPLICATION_JSON, TEXT_PLAIN_UTF_8};
 use reqwest::header::CONTENT_TYPE;
-use schemars::JsonSchema;
+use schemars::{schema::InstanceType, JsonSchema};
+
+const SAMPLE_RATE_HEADER: &str = "x-rustlog-sample-rate";
 
 pub struct LogsResponse {
     pub stream: LogsStream,
     pub response_type: LogsResponseType,
 }
 
-pub enum LogsResponseType {
-    Raw,
-    Text,
-    Json(JsonResponseType),
-    NdJson,
+pub struct LogsResponseType {
+    pub formatter: &'static dyn LogFormatter,
+    pub options: FormatOptions,
+    /// Only used by the JSON formats
+    pub envelope: Option<Envelope>,
+    /// Only used by the full JSON format
+    pub vods: Option<StreamVods>,
+    /// Only used by the JSON formats
+    pub highlight: Option<SearchHighlighter>,
+    /// Reported in a header if the messages were sampled
+    pub sample: Option<SampleRate>,
 }
 
 /// Used for schema only, actual serialization is manual
@@ -39,58 +69,79 @@ pub struct JsonLogsResponse<'a> {
     pub messages: Vec<FullMessage<'a>>,
 }
 
+/// Used for schema only, returned with `jsonBasic`
+#[derive(JsonSchema)]
+pub struct JsonBasicLogsResponse<'a> {
+    pub messages: Vec<BasicMessage<'a>>,
+}
+
+/// Used for schema only, returned with `envelope`
+#[derive(JsonSchema)]
+pub struct JsonEnvelopeLogsResponse<'a> {
+    /// Full messages, or basic messages with `jsonBasic`
+    pub messages: Vec<FullMessage<'a>>,
+    pub meta: LogsMeta,
+}
+
+/// Used for schema only
+#[derive(JsonSchema)]
+#[serde(untagged)]
+pub enum JsonLogsBody<'a> {
+    Full(JsonLogsResponse<'a>),
+    Basic(JsonBasicLogsResponse<'a>),
+    Envelope(JsonEnvelopeLogsResponse<'a>),
+}
+
 impl IntoResponse for LogsResponse {
     fn into_response(self) -> Response {
-        match self.response_type {
-            LogsResponseType::Raw => {
-                let stream = self.stream.map_ok(|chunk| {
-                    let mut buf = String::new();
-                    for msg in chunk {
-                        buf.push_str(&msg.to_raw_irc());
-                        buf.push_str("\r\n");
-                    }
-                    buf
-                });
-
-                (
-                    set_content_type(&TEXT_PLAIN_UTF_8),
-                    Body::from_stream(stream),
-                )
-                    .into_response()
-            }
-            LogsResponseType::Text => {
-                let stream = TextLogsStream::new(self.stream);
-                (
-                    set_content_type(&TEXT_PLAIN_UTF_8),
-                    Body::from_stream(stream),
-                )
-                    .into_response()
-            }
-            LogsResponseType::Json(response_type) => {
-                let stream = JsonLogsStream::new(self.stream, response_type);
-                (
-                    set_content_type(&APPLICATION_JSON),
-                    Body::from_stream(stream),
-                )
-                    .into_response()
-            }
-            LogsResponseType::NdJson => {
-                let stream = NdJsonLogsStream::new(self.stream);
-                (
-                    set_content_type(&"application/x-ndjson"),
-                    Body::from_stream(stream),
-                )
-                    .into_response()
-            }
+        let rows = StreamedRows::default();
+        let stream = self.stream.counted(rows.0.clone());
+        let LogsResponseType {
+            formatter,
+            options,
+            envelope,
+            vods,
+            highlight,
+            sample,
+        } = self.response_type;
+
+        let json = JsonOptions {
+            envelope,
+            vods,
+            highlight,
+        };
+        let mut response = match formatter.body(stream, options, json) {
+            Ok(body) => (set_content_type(formatter.content_type()), body).into_response(),
+            Err(err) => err.into_response(),
+        };
+
+        if let Some(sample) = sample {
+            response.headers_mut().insert(
+                SAMPLE_RATE_HEADER,
+                HeaderValue::from_str(&sample.effective().to_string()).unwrap(),
+            );
         }
+        response.extensions_mut().insert(rows);
+        response
     }
 }
 
-fn set_content_type(content_type: &'static impl AsRef<str>) -> impl IntoResponseParts {
-    [(
-        CONTENT_TYPE,
-        HeaderValue::from_static(content_type.as_ref()),
-    )]
+impl LogsResponseType {
+    pub fn content_type(&self) -> &'static str {
+        self.formatter.content_type()
+    }
+
+    /// Identifies the format in cache keys
+    pub fn name(&self) -> String {
+        match self.envelope {
+            Some(_) => format!("{}-envelope", self.formatter.name()),
+            None => self.formatter.name().to_owned(),
+        }
+    }
+}
+
+fn set_content_type(content_type: &'static str) -> impl IntoResponseParts {
+    [(CONTENT_TYPE, HeaderValue::from_static(content_type))]
 }
 
 impl OperationOutput for LogsResponse {
@@ -98,19 +149,69 @@ impl OperationOutput for LogsResponse {
 
     fn operation_response(
         ctx: &mut aide::gen::GenContext,
-        operation: &mut aide::openapi::Operation,
+        _: &mut aide::openapi::Operation,
     ) -> Option<aide::openapi::Response> {
-        let mut content = IndexMap::with_capacity(2);
-
-        let json_operation_response =
-            Json::<JsonLogsResponse>::operation_response(ctx, operation).unwrap();
-        content.extend(json_operation_response.content);
+        let text = MediaType {
+            schema: Some(schema_object(ctx.schema.subschema_for::<String>())),
+            examples: IndexMap::from_iter([
+                example("text", "[2024-03-01 00:01:14] #forsen supibot: +join"),
+                example(
+                    "raw",
+                    "@display-name=Supibot;tmi-sent-ts=1709251274940 :supibot!supibot@supibot.tmi.twitch.tv PRIVMSG #forsen :+join",
+                ),
+            ]),
+            ..Default::default()
+        };
+        let json = MediaType {
+            schema: Some(schema_object(ctx.schema.subschema_for::<JsonLogsBody>())),
+            ..Default::default()
+        };
+        let ndjson = MediaType {
+            schema: Some(schema_object(ctx.schema.subschema_for::<BasicMessage>())),
+            ..Default::default()
+        };
+        let arrow = MediaType {
+            schema: Some(schema_object(
+                schemars::schema::SchemaObject {
+                    instance_type: Some(InstanceType::String.into()),
+                    format: Some("binary".to_owned()),
+                    ..Default::default()
+                }
+                .into(),
+            )),
+            ..Default::default()
+        };
+        let csv = MediaType {
+            schema: Some(schema_object(ctx.schema.subschema_for::<String>())),
+            examples: IndexMap::from_iter([example(
+                "csv",
+                "timestamp,channel_id,channel_login,id,message_type,user_id,user_login,display_name,badges,flags,text\r\n\
+                2024-03-01T00:01:14.940+00:00,22484632,forsen,5f0ab7c1-3c6e-4c48-9d2a-1e8b7f6d4a21,PRIVMSG,68136884,supibot,Supibot,bot-badge/1,,+join",
+            )]),
+            ..Default::default()
+        };
 
-        let plain_response = String::operation_response(ctx, operation).unwrap();
-        content.extend(plain_response.content);
+        let content = IndexMap::from_iter([
+            (TextFormatter.content_type().to_owned(), text),
+            (
+                JsonFormatter(JsonResponseType::Full)
+                    .content_type()
+                    .to_owned(),
+                json,
+            ),
+            (NdJsonFormatter.content_type().to_owned(), ndjson),
+            (ArrowFormatter.content_type().to_owned(), arrow.clone()),
+            (CsvFormatter.content_type().to_owned(), csv),
+            (ParquetFormatter.content_type().to_owned(), arrow),
+        ]);
 
         Some(aide::openapi::Response {
-            description: "Logs response".into(),
+            description: "Logs in the format selected by the query params. Plain text by default, \
+                IRC messages with `raw`, a JSON object with `json` or `jsonBasic` \
+                (with a `meta` object after the messages with `envelope`), \
+                one JSON message per line with `ndjson`, an Arrow IPC stream with `arrow`, \
+                CSV rows with `csv` and a Parquet file with `parquet`"
+                .into(),
             content,
             ..Default::default()
         })
@@ -125,3 +226,19 @@ impl OperationOutput for LogsResponse {
         vec![(Some(200), res)]
     }
 }
+
+fn example(name: &str, value: &str) -> (String, ReferenceOr<Example>) {
+    let example = Example {
+        value: Some(value.into()),
+        ..Default::default()
+    };
+    (name.to_owned(), ReferenceOr::Item(example))
+}
+
+fn schema_object(json_schema: schemars::schema::Schema) -> SchemaObject {
+    SchemaObject {
+        json_schema,
+        external_docs: None,
+        example: None,
+    }
+}
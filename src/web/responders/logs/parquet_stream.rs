@@ -0,0 +1,91 @@
+use super::arrow_stream::{build_batch, message_schema};
+use crate::{db::schema::StructuredMessage, error::Error, logs::stream::LogsStream, Result};
+use arrow_schema::SchemaRef;
+use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
+use parquet::{
+    arrow::ArrowWriter,
+    basic::{Compression, ZstdLevel},
+    errors::ParquetError,
+    file::properties::WriterProperties,
+};
+use std::{
+    pin::Pin,
+    sync::Arc,
+    task::{Context, Poll},
+};
+use tokio::pin;
+use tracing::error;
+
+/// Rows per row group. Larger groups compress better, but are buffered before they are sent
+const CHUNK_SIZE: usize = 10_000;
+
+/// Streams messages as a Parquet file, one row group per chunk of messages.
+/// The written bytes are taken from the writer after every row group, only the footer
+/// metadata is kept until the end
+pub struct ParquetLogsStream {
+    inner: TryChunks<LogsStream>,
+    schema: SchemaRef,
+    /// Taken once the footer has been written
+    writer: Option<ArrowWriter<Vec<u8>>>,
+}
+
+impl ParquetLogsStream {
+    pub fn new(stream: LogsStream) -> Result<Self> {
+        let properties = WriterProperties::builder()
+            .set_compression(Compression::ZSTD(ZstdLevel::default()))
+            .build();
+        let schema = Arc::new(message_schema());
+        let writer = ArrowWriter::try_new(Vec::new(), schema.clone(), Some(properties))
+            .map_err(parquet_error)?;
+
+        Ok(Self {
+            inner: stream.try_chunks(CHUNK_SIZE),
+            schema,
+            writer: Some(writer),
+        })
+    }
+
+    fn write_chunk(&mut self, chunk: &[StructuredMessage]) -> Result<Vec<u8>> {
+        let batch = build_batch(self.schema.clone(), chunk).map_err(|err| {
+            error!("Could not build record batch: {err}");
+            Error::Internal
+        })?;
+
+        let writer = self.writer.as_mut().ok_or(Error::Internal)?;
+        writer.write(&batch).map_err(parquet_error)?;
+        writer.flush().map_err(parquet_error)?;
+        Ok(std::mem::take(writer.inner_mut()))
+    }
+
+    fn finish(&mut self) -> Option<Result<Vec<u8>>> {
+        let writer = self.writer.take()?;
+        Some(writer.into_inner().map_err(parquet_error))
+    }
+}
+
+impl Stream for ParquetLogsStream {
+    type Item = Result<Vec<u8>>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let polled = {
+            let fut = self.inner.next();
+            pin!(fut);
+            fut.poll(cx)
+        };
+
+        match polled {
+            Poll::Ready(Some(Ok(chunk))) => {
+                let chunk: Vec<StructuredMessage> = chunk.into_iter().flatten().collect();
+                Poll::Ready(Some(self.write_chunk(&chunk)))
+            }
+            Poll::Ready(Some(Err(err))) => Poll::Ready(Some(Err(err.1))),
+            Poll::Ready(None) => Poll::Ready(self.finish()),
+            Poll::Pending => Poll::Pending,
+        }
+    }
+}
+
+fn parquet_error(err: ParquetError) -> Error {
+    error!("Could not write Parquet file: {err}");
+    Error::Internal
+}
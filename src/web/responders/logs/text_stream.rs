@@ -1,4 +1,10 @@
-use crate::{logs::stream::LogsStream, Result};
+use crate::{
+    db::schema::StructuredMessage,
+    logs::stream::LogsStream,
+    web::schema::{FormatOptions, TimestampFormat},
+    Result,
+};
+use chrono::{DateTime, SecondsFormat};
 use futures::{stream::TryChunks, Future, Stream, StreamExt, TryStreamExt};
 use std::{
     fmt::Write,
@@ -12,19 +18,57 @@ const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 pub struct TextLogsStream {
     inner: TryChunks<LogsStream>,
+    options: FormatOptions,
 }
 
 impl TextLogsStream {
-    pub fn new(stream: LogsStream) -> Self {
+    pub fn new(stream: LogsStream, options: FormatOptions) -> Self {
         let inner = stream.try_chunks(CHUNK_SIZE);
-        Self { inner }
+        Self { inner, options }
+    }
+}
+
+fn format_timestamp(msg: &StructuredMessage, options: &FormatOptions) -> String {
+    let timestamp = DateTime::from_timestamp_millis(msg.timestamp as i64).unwrap_or_default();
+    let timestamp = timestamp.with_timezone(&options.timezone.unwrap_or(chrono_tz::UTC));
+
+    match options.timestamp_format {
+        TimestampFormat::Plain => timestamp.format(TIMESTAMP_FORMAT).to_string(),
+        TimestampFormat::Rfc3339 => timestamp.to_rfc3339_opts(SecondsFormat::Millis, false),
+        TimestampFormat::Unix => timestamp.timestamp().to_string(),
+        TimestampFormat::UnixMillis => msg.timestamp.to_string(),
     }
 }
 
+/// Appends the message as a text log line, including the line break
+pub fn write_text_line(output: &mut String, msg: &StructuredMessage, options: &FormatOptions) {
+    let timestamp = format_timestamp(msg, options);
+    let text = msg.user_friendly_text();
+    let channel = &msg.channel_login;
+    let username = if options.display_names {
+        msg.display_name()
+    } else {
+        &msg.user_login
+    };
+
+    let _ = if username.is_empty() {
+        write!(output, "[{timestamp}] #{channel} {text}\r\n")
+    } else if options.ids {
+        write!(
+            output,
+            "[{timestamp}] #{channel} {username} ({}): {text}\r\n",
+            msg.user_id
+        )
+    } else {
+        write!(output, "[{timestamp}] #{channel} {username}: {text}\r\n")
+    };
+}
+
 impl Stream for TextLogsStream {
     type Item = Result<String>;
 
     fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let options = self.options;
         let fut = self.inner.next();
         pin!(fut);
 
@@ -34,20 +78,7 @@ impl Stream for TextLogsStream {
                     let mut output = String::with_capacity(chunk.len() * 16);
 
                     for msg in chunk.into_iter().flatten() {
-                        let timestamp =
-                            chrono::DateTime::from_timestamp_millis(msg.timestamp as i64)
-                                .unwrap_or_default()
-                                .format(TIMESTAMP_FORMAT);
-                        let text = msg.user_friendly_text();
-                        let channel = &msg.channel_login;
-                        let username = &msg.user_login;
-
-                        if !username.is_empty() {
-                            let _ =
-                                write!(output, "[{timestamp}] #{channel} {username}: {text}\r\n");
-                        } else {
-                            let _ = write!(output, "[{timestamp}] #{channel} {text}\r\n");
-                        }
+                        write_text_line(&mut output, &msg, &options);
                     }
 
                     Ok(output)
@@ -1,23 +1,88 @@
-use std::fmt::Display;
+use std::{collections::HashMap, fmt::Display, str::FromStr};
 
-use schemars::JsonSchema;
+use async_graphql::SimpleObject;
+use chrono::{DateTime, Days, Months, NaiveDate, NaiveTime, TimeZone, Utc};
+use chrono_tz::Tz;
+use clickhouse::Row;
+use schemars::{gen::SchemaGenerator, schema::Schema, JsonSchema};
 use serde::{Deserialize, Deserializer, Serialize};
+use strum::AsRefStr;
+use uuid::Uuid;
 
-use super::responders::logs::{JsonResponseType, LogsResponseType};
+use super::{
+    pagination::Page,
+    responders::logs::{formatter, Envelope, LogsResponseType},
+};
+use crate::{
+    config::Config,
+    db::{
+        alerts::{AlertMatchRow, SavedSearchRow},
+        audit::AuditLogRow,
+        backups::BackupRow,
+        schema::{MessageFlags, MessageTypes, StructuredMessage},
+        stats::UserColorRow,
+        streams::StreamRow,
+    },
+    ids::{ChannelId, UserId},
+    logs::schema::message::{FullMessage, SearchHighlighter},
+};
 
 #[derive(Serialize, JsonSchema)]
+#[schemars(example = "channels_list_example")]
 pub struct ChannelsList {
     pub channels: Vec<Channel>,
 }
 
-#[derive(Serialize, JsonSchema)]
+fn channels_list_example() -> ChannelsList {
+    ChannelsList {
+        channels: vec![Channel {
+            name: "forsen".to_owned(),
+            user_id: "22484632".to_owned(),
+            archived: false,
+        }],
+    }
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
 pub struct Channel {
     pub name: String,
     #[serde(rename = "userID")]
     pub user_id: String,
+    /// The channel is no longer logged, but its logs can still be queried
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub archived: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BulkJoinResult {
+    /// Channels which have been joined, or would be joined in a dry run
+    pub joined: Vec<Channel>,
+    /// Channels which were skipped because they are already logged
+    pub already_joined: usize,
+    /// Channels which were skipped because they opted out
+    pub opted_out: usize,
+    pub dry_run: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelsParams {
+    /// Also list archived channels
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_archived: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ChannelSearchParams {
+    /// Part of the channel login or the beginning of its ID
+    pub q: String,
+    /// Maximum amount of channels to return. Defaults to 20
+    pub limit: Option<u64>,
 }
 
-#[derive(Debug, Deserialize, JsonSchema)]
+/// Whether the channel is given by its login (`channel`) or ID (`channelid`)
+#[derive(Debug, Clone, Copy, Deserialize, JsonSchema)]
 pub enum ChannelIdType {
     #[serde(rename = "channel")]
     Name,
@@ -62,40 +127,155 @@ pub struct LogsPathDate {
 #[derive(Deserialize, JsonSchema)]
 pub struct LogsPathChannel {
     pub channel_id_type: ChannelIdType,
+    /// Channel login or ID
     pub channel: String,
 }
 
-#[derive(Deserialize, Debug, JsonSchema, Clone, Copy)]
+#[derive(Deserialize, Debug, JsonSchema, Clone, Copy, Default)]
 #[serde(rename_all = "camelCase")]
 pub struct LogsParams {
+    /// Return a JSON object with all message fields. Any value enables it, e.g. `?json`
     #[serde(default, deserialize_with = "deserialize_bool_param")]
     pub json: bool,
+    /// Return a JSON object with only the basic message fields
     #[serde(default, deserialize_with = "deserialize_bool_param")]
     pub json_basic: bool,
+    /// Return the messages as they were received over IRC, one per line
     #[serde(default, deserialize_with = "deserialize_bool_param")]
     pub raw: bool,
+    /// Return the newest messages first
     #[serde(default, deserialize_with = "deserialize_bool_param")]
     pub reverse: bool,
+    /// Return a JSON object with a `meta` object after the messages, containing the amount of messages,
+    /// the requested range and the offset of the next page. Implies `json` unless `jsonBasic` is set
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub envelope: bool,
+    /// Return one JSON message per line
     #[serde(default, deserialize_with = "deserialize_bool_param")]
     pub ndjson: bool,
+    /// Stream the messages as Arrow IPC record batches
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub arrow: bool,
+    /// Return CSV rows with a header, the timestamps use `timezone`
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub csv: bool,
+    /// Return a Parquet file with the same columns as `arrow`
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub parquet: bool,
+    /// Only return messages which were flagged by AutoMod
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub flagged: bool,
+    /// Add `vodOffset` and `vodUrl` to full JSON messages which were sent during a stream with a known VOD
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub vods: bool,
+    /// Order the messages by `length`, `user` or `random` instead of the timestamp, `reverse` inverts the order.
+    /// Only messages which have been written to the database are sorted, the limit defaults to 100 and is at most 1000
+    #[serde(default)]
+    pub sort: MessageSort,
+    /// Only return a sample of about this fraction of the messages, e.g. `0.01`. The same messages are sampled on every
+    /// request and messages which have not been written to the database yet are left out.
+    /// The applied rate is returned in the `x-rustlog-sample-rate` header
+    #[schemars(with = "Option<f64>")]
+    pub sample: Option<SampleRate>,
+    #[serde(flatten)]
+    pub format: FormatOptions,
+    #[serde(flatten)]
+    pub filter: MessageFilter,
+    /// Maximum amount of messages to return, counting messages which have not been written to the database yet
     pub limit: Option<u64>,
+    /// Amount of messages to skip. Recent unwritten messages come last, or first with `reverse`
     pub offset: Option<u64>,
 }
 
+#[derive(Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum MessageSort {
+    /// Oldest first
+    #[default]
+    Timestamp,
+    /// Longest text first
+    Length,
+    /// Alphabetically by user login
+    User,
+    /// A different sample on every request
+    Random,
+}
+
 impl LogsParams {
-    pub fn response_type(&self) -> LogsResponseType {
+    pub fn page(&self) -> Page {
+        // Messages are streamed, so it is not known if there are more
+        Page::new(self.limit, self.offset, true)
+    }
+
+    /// Fails if the selected format is disabled in the config
+    pub fn response_type(&self, config: &Config) -> crate::Result<LogsResponseType> {
+        Ok(LogsResponseType {
+            formatter: formatter(self.format_name(), &config.disabled_formats)?,
+            options: self.format,
+            envelope: self.envelope(),
+            vods: None,
+            highlight: None,
+            sample: self.sample,
+        })
+    }
+
+    fn format_name(&self) -> &'static str {
         if self.raw {
-            LogsResponseType::Raw
+            "raw"
         } else if self.json_basic {
-            LogsResponseType::Json(JsonResponseType::Basic)
-        } else if self.json {
-            LogsResponseType::Json(JsonResponseType::Full)
+            "json-basic"
+        } else if self.json || self.envelope {
+            "json"
         } else if self.ndjson {
-            LogsResponseType::NdJson
+            "ndjson"
+        } else if self.arrow {
+            "arrow"
+        } else if self.csv {
+            "csv"
+        } else if self.parquet {
+            "parquet"
         } else {
-            LogsResponseType::Text
+            "text"
         }
     }
+
+    fn envelope(&self) -> Option<Envelope> {
+        self.envelope.then_some(Envelope {
+            limit: self.limit,
+            offset: self.offset,
+        })
+    }
+}
+
+/// How messages are presented in text logs, the other formats keep all fields as they are
+#[derive(Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct FormatOptions {
+    /// Timezone of the timestamps, e.g. `Europe/Berlin`. Defaults to UTC
+    #[schemars(with = "Option<String>")]
+    pub timezone: Option<Tz>,
+    #[serde(default)]
+    pub timestamp_format: TimestampFormat,
+    /// Show the user ID next to the user
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub ids: bool,
+    /// Show display names instead of logins
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub display_names: bool,
+}
+
+#[derive(Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum TimestampFormat {
+    /// `2024-03-01 00:01:14`
+    #[default]
+    Plain,
+    /// `2024-03-01T00:01:14.940+00:00`
+    Rfc3339,
+    /// Seconds since the Unix epoch
+    Unix,
+    /// Milliseconds since the Unix epoch
+    UnixMillis,
 }
 
 fn deserialize_bool_param<'de, D>(deserializer: D) -> Result<bool, D::Error>
@@ -105,20 +285,271 @@ where
     Ok(Option::<&str>::deserialize(deserializer)?.is_some())
 }
 
+/// Filters of the logs which are applied in the database query
+#[derive(Deserialize, Debug, JsonSchema, Clone, Copy, PartialEq, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct MessageFilter {
+    /// Only return messages of these types, comma separated, e.g. `privmsg,clearchat`
+    #[schemars(with = "Option<String>")]
+    #[serde(default, deserialize_with = "deserialize_message_types")]
+    pub message_type: MessageTypes,
+    /// Only return messages with all of these flags, comma separated, e.g. `subscriber,first_msg`
+    #[schemars(with = "Option<String>")]
+    #[serde(default, deserialize_with = "deserialize_message_flags")]
+    pub flags: MessageFlags,
+    /// Skip messages of the bots configured in `botUserIDs`
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub exclude_bots: bool,
+}
+
+impl MessageFilter {
+    /// Same as the query conditions, for messages which have not been written to the database yet
+    pub fn matches(&self, msg: &StructuredMessage) -> bool {
+        (self.message_type.is_empty() || self.message_type.contains(msg.message_type))
+            && msg.message_flags.contains(self.flags)
+    }
+}
+
+fn deserialize_message_types<'de, D>(deserializer: D) -> Result<MessageTypes, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(value) => value.parse().map_err(serde::de::Error::custom),
+        None => Ok(MessageTypes::default()),
+    }
+}
+
+fn deserialize_message_flags<'de, D>(deserializer: D) -> Result<MessageFlags, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let value = Option::<String>::deserialize(deserializer)?.unwrap_or_default();
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty())
+        .try_fold(MessageFlags::empty(), |flags, name| {
+            MessageFlags::from_name(&name.to_uppercase())
+                .map(|flag| flags | flag)
+                .ok_or_else(|| serde::de::Error::custom(format!("Unknown flag {name}")))
+        })
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LogsQueryBody {
+    /// IDs of the channels to read
+    pub channels: Vec<ChannelId>,
+    /// Only return messages of these user IDs
+    #[serde(default)]
+    pub users: Vec<UserId>,
+    /// Only return messages of these types, e.g. `["PRIVMSG", "USERNOTICE"]`
+    #[serde(default)]
+    pub types: Vec<String>,
+    /// Only return messages which were flagged by AutoMod
+    #[serde(default)]
+    pub flagged: bool,
+    /// Only return messages containing this text, case insensitive
+    pub text: Option<String>,
+    #[schemars(with = "String")]
+    /// RFC 3339 start of the range
+    pub from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    /// RFC 3339 end of the range, exclusive
+    pub to: DateTime<Utc>,
+    /// Maximum amount of messages to return
+    pub limit: Option<u64>,
+    /// Amount of messages to skip
+    pub offset: Option<u64>,
+    /// Return the newest messages first
+    #[serde(default)]
+    pub reverse: bool,
+    #[serde(default)]
+    pub format: LogsQueryFormat,
+}
+
+impl LogsQueryBody {
+    pub fn logs_params(&self) -> LogsParams {
+        LogsParams {
+            json: self.format == LogsQueryFormat::Json,
+            json_basic: self.format == LogsQueryFormat::JsonBasic,
+            raw: self.format == LogsQueryFormat::Raw,
+            ndjson: self.format == LogsQueryFormat::Ndjson,
+            arrow: self.format == LogsQueryFormat::Arrow,
+            csv: self.format == LogsQueryFormat::Csv,
+            parquet: self.format == LogsQueryFormat::Parquet,
+            reverse: self.reverse,
+            flagged: self.flagged,
+            limit: self.limit,
+            offset: self.offset,
+            ..Default::default()
+        }
+    }
+}
+
+/// Response format of a logs query, like the format params of the logs routes
+#[derive(Deserialize, JsonSchema, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum LogsQueryFormat {
+    #[default]
+    Text,
+    Json,
+    JsonBasic,
+    Raw,
+    Ndjson,
+    Arrow,
+    Csv,
+    Parquet,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+pub struct ChannelLogsSearchParams {
+    /// Text to search for, case insensitive
+    pub q: String,
+    #[schemars(with = "Option<String>")]
+    /// Only search messages sent within this duration until now, e.g. `24h` or `7d`
+    pub last: Option<RelativeDuration>,
+    /// Rank the results by how often they contain the text and how recent they are instead of by time.
+    /// Always returns JSON with a `score` for each message and only includes messages which have been written to the database
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub relevance: bool,
+    #[serde(flatten)]
+    pub highlight: HighlightParams,
+    #[serde(flatten)]
+    pub logs_params: LogsParams,
+}
+
+#[derive(Deserialize, Debug, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct HighlightParams {
+    /// Add the character offsets of the matches as `matches` to JSON messages
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub highlight: bool,
+    /// Also add `highlightedText` to JSON messages, the text with every match wrapped in `highlightPre` and
+    /// `highlightPost`. Implies `highlight`, the text is not escaped
+    pub highlight_pre: Option<String>,
+    pub highlight_post: Option<String>,
+}
+
+impl HighlightParams {
+    pub fn highlighter(&self, search: &str) -> Option<SearchHighlighter> {
+        let markers = match (&self.highlight_pre, &self.highlight_post) {
+            (None, None) => None,
+            (pre, post) => Some((
+                pre.clone().unwrap_or_default(),
+                post.clone().unwrap_or_default(),
+            )),
+        };
+        (self.highlight || markers.is_some()).then(|| SearchHighlighter::new(search, markers))
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ScoredSearchResults<'a> {
+    /// Most relevant first
+    pub messages: Vec<ScoredSearchMessage<'a>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ScoredSearchMessage<'a> {
+    #[serde(flatten)]
+    pub message: FullMessage<'a>,
+    pub score: f64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MessagePath {
+    pub channel_id_type: ChannelIdType,
+    /// Channel login or ID
+    pub channel: String,
+    /// Message ID
+    pub id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct NoncePath {
+    pub channel_id_type: ChannelIdType,
+    /// Channel login or ID
+    pub channel: String,
+    /// Value of the `client-nonce` tag the message was sent with
+    pub nonce: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PermalinkParams {
+    /// Return a JSON object instead of an HTML page
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub json: bool,
+    /// Messages to show before and after the message. Defaults to 10, at most 100
+    pub context: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct MessagePermalink<'a> {
+    /// Stable URL of the message, which does not change when the channel is renamed
+    pub permalink: String,
+    pub message: FullMessage<'a>,
+    /// Oldest first
+    pub before: Vec<FullMessage<'a>>,
+    /// Oldest first
+    pub after: Vec<FullMessage<'a>>,
+}
+
 #[derive(Deserialize, Debug, JsonSchema)]
 pub struct SearchParams {
+    /// Text to search for, case insensitive
     pub q: String,
+    #[schemars(with = "Option<String>")]
+    /// Only search messages sent within this duration until now, e.g. `24h` or `7d`
+    pub last: Option<RelativeDuration>,
+    #[serde(flatten)]
+    pub highlight: HighlightParams,
     #[serde(flatten)]
     pub logs_params: LogsParams,
 }
 
 #[derive(Serialize, JsonSchema)]
 #[serde(rename_all = "camelCase")]
+#[schemars(example = "available_logs_example")]
 pub struct AvailableLogs {
     pub available_logs: Vec<AvailableLogDate>,
 }
 
+fn available_logs_example() -> AvailableLogs {
+    AvailableLogs {
+        available_logs: vec![
+            AvailableLogDate {
+                year: "2024".to_owned(),
+                month: "3".to_owned(),
+                day: Some("2".to_owned()),
+            },
+            AvailableLogDate {
+                year: "2024".to_owned(),
+                month: "3".to_owned(),
+                day: Some("1".to_owned()),
+            },
+        ],
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CombinedAvailableLogs {
+    /// Days with logs of the channel, newest first
+    pub channel_logs: Vec<AvailableLogCount>,
+    /// Months with logs of the user in the channel, newest first
+    pub user_logs: Vec<AvailableLogCount>,
+}
+
 #[derive(Serialize, JsonSchema)]
+pub struct AvailableLogCount {
+    #[serde(flatten)]
+    pub date: AvailableLogDate,
+    pub messages: u64,
+}
+
+#[derive(Serialize, JsonSchema, Clone)]
 pub struct AvailableLogDate {
     pub year: String,
     pub month: String,
@@ -126,6 +557,40 @@ pub struct AvailableLogDate {
     pub day: Option<String>,
 }
 
+/// Midnight of the date in the timezone. Days whose midnight is skipped by a DST change start an hour later
+pub fn start_of_day(date: NaiveDate, timezone: Tz) -> DateTime<Utc> {
+    let midnight = date.and_time(NaiveTime::default());
+    timezone
+        .from_local_datetime(&midnight)
+        .earliest()
+        .or_else(|| {
+            let after_gap = midnight + chrono::Duration::hours(1);
+            timezone.from_local_datetime(&after_gap).earliest()
+        })
+        .map_or_else(|| midnight.and_utc(), |start| start.with_timezone(&Utc))
+}
+
+impl AvailableLogDate {
+    /// Start and end of the day, or the month if there is no day, in the given timezone
+    pub fn range(&self, timezone: Tz) -> Option<(DateTime<Utc>, DateTime<Utc>)> {
+        let year = self.year.parse().ok()?;
+        let month = self.month.parse().ok()?;
+
+        let (date, end) = match &self.day {
+            Some(day) => {
+                let date = NaiveDate::from_ymd_opt(year, month, day.parse().ok()?)?;
+                (date, date.checked_add_days(Days::new(1))?)
+            }
+            None => {
+                let date = NaiveDate::from_ymd_opt(year, month, 1)?;
+                (date, date.checked_add_months(Months::new(1))?)
+            }
+        };
+
+        Some((start_of_day(date, timezone), start_of_day(end, timezone)))
+    }
+}
+
 impl Display for AvailableLogDate {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "{}/{}", self.year, self.month)?;
@@ -138,6 +603,31 @@ impl Display for AvailableLogDate {
     }
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct RecentLogsParams {
+    /// RFC 3339 date. Only return messages sent before it, newest first, to scroll back through the logs
+    #[schemars(with = "Option<String>")]
+    pub before: Option<DateTime<Utc>>,
+    /// ID of the oldest message of the previous page. Used together with `before` so messages sent in the same
+    /// millisecond are not skipped
+    #[serde(rename = "beforeID")]
+    #[schemars(with = "Option<String>")]
+    pub before_id: Option<Uuid>,
+    #[serde(flatten)]
+    pub logs_params: LogsParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LatestLogsParams {
+    /// Return the latest available logs instead of redirecting to them
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub latest: bool,
+    // Skipped in the docs, as the same params are already listed for the range query
+    #[serde(flatten)]
+    #[schemars(skip)]
+    pub logs_params: LogsParams,
+}
+
 #[derive(Serialize, JsonSchema)]
 pub struct UserHasLogs {
     /// User ID
@@ -147,9 +637,97 @@ pub struct UserHasLogs {
 }
 
 #[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
 pub struct UserLogins {
     /// List of user logins
     pub logins: Vec<String>,
+    /// Display names the user has chatted with, including changes in casing or localized names. Oldest first
+    pub display_names: Vec<DisplayNameEntry>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DisplayNameEntry {
+    pub display_name: String,
+    /// The first message with this display name
+    #[schemars(with = "String")]
+    pub first_seen: DateTime<Utc>,
+    /// The last message with this display name
+    #[schemars(with = "String")]
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ChannelUsersParams {
+    /// Beginning of the user login
+    pub prefix: String,
+    /// Maximum amount of users to return. Defaults to 20
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelUser {
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    /// The last time the user sent a message in the channel
+    #[schemars(with = "String")]
+    pub last_seen: DateTime<Utc>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ChannelUsers {
+    /// An exact login match first, then the most recently seen users
+    pub users: Vec<ChannelUser>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[schemars(example = "resolve_users_body_example")]
+pub struct ResolveUsersBody {
+    /// At most 100 logins
+    pub logins: Vec<String>,
+}
+
+fn resolve_users_body_example() -> ResolveUsersBody {
+    ResolveUsersBody {
+        logins: vec!["forsen".to_owned(), "notarealuser".to_owned()],
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ResolvedUser {
+    pub login: String,
+    #[serde(rename = "userID")]
+    pub user_id: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+#[schemars(example = "resolved_users_example")]
+pub struct ResolvedUsers {
+    pub users: Vec<ResolvedUser>,
+    /// Logins which don't exist, are banned or opted out
+    pub not_found: Vec<String>,
+}
+
+fn resolved_users_example() -> ResolvedUsers {
+    ResolvedUsers {
+        users: vec![ResolvedUser {
+            login: "forsen".to_owned(),
+            user_id: "22484632".to_owned(),
+        }],
+        not_found: vec!["notarealuser".to_owned()],
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ConsentCode {
+    pub code: String,
+    /// Has to be sent in a logged channel by the consenting user
+    pub command: String,
 }
 
 #[derive(Deserialize, JsonSchema)]
@@ -160,23 +738,1618 @@ pub struct AvailableLogsParams {
     pub user: Option<UserParam>,
 }
 
+#[derive(Deserialize, JsonSchema)]
+pub struct CombinedAvailableLogsParams {
+    #[serde(flatten)]
+    pub channel: ChannelParam,
+    #[serde(flatten)]
+    pub user: UserParam,
+}
+
 #[derive(Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum UserParam {
+    /// User login
     User(String),
+    /// User ID
     UserId(String),
 }
 
 #[derive(Deserialize, JsonSchema)]
 #[serde(rename_all = "lowercase")]
 pub enum ChannelParam {
+    /// Channel login
     Channel(String),
+    /// Channel ID
     ChannelId(String),
 }
 
 #[derive(Deserialize, JsonSchema)]
 pub struct UserLogPathParams {
     pub channel_id_type: ChannelIdType,
+    /// Channel login or ID
     pub channel: String,
+    /// User login or ID
     pub user: String,
 }
+
+/// A random line in the shape expected by Supibot and similar bots
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SupibotRandomLine {
+    /// Channel login
+    pub channel: String,
+    /// User login
+    pub username: String,
+    pub display_name: String,
+    pub text: String,
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+}
+
+#[derive(Deserialize, Clone, Copy)]
+#[serde(try_from = "RangeQuery")]
+pub struct RangeParams {
+    pub from: DateTime<Utc>,
+    pub to: DateTime<Utc>,
+}
+
+impl JsonSchema for RangeParams {
+    fn schema_name() -> String {
+        RangeQuery::schema_name()
+    }
+
+    fn json_schema(gen: &mut SchemaGenerator) -> Schema {
+        RangeQuery::json_schema(gen)
+    }
+}
+
+/// A range is either given by `from` and `to` or relative to now by `last`
+#[derive(Deserialize, JsonSchema)]
+pub struct RangeQuery {
+    #[schemars(with = "Option<String>")]
+    /// RFC 3339 start date
+    pub from: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<String>")]
+    /// RFC 3339 end date
+    pub to: Option<DateTime<Utc>>,
+    #[schemars(with = "Option<String>")]
+    /// Range ending now instead of `from` and `to`, e.g. `30m`, `24h`, `7d` or `2w`
+    pub last: Option<RelativeDuration>,
+}
+
+impl TryFrom<RangeQuery> for RangeParams {
+    type Error = String;
+
+    fn try_from(query: RangeQuery) -> Result<Self, Self::Error> {
+        match (query.from, query.to, query.last) {
+            (Some(from), Some(to), None) => Ok(Self { from, to }),
+            (None, None, Some(last)) => Ok(last.range()),
+            (_, _, Some(_)) => Err("`last` can't be combined with `from` or `to`".to_owned()),
+            _ => Err("Either `from` and `to` or `last` is required".to_owned()),
+        }
+    }
+}
+
+/// Length of a range ending now, given as an amount and a unit like `24h`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RelativeDuration(chrono::Duration);
+
+impl RelativeDuration {
+    /// The range ends at the start of the next minute, so it stays the same for a minute
+    /// and is never treated as finished when deciding how long responses are cached
+    pub fn range(self) -> RangeParams {
+        let next_minute = (Utc::now().timestamp() / 60 + 1) * 60;
+        let to = DateTime::from_timestamp(next_minute, 0).unwrap_or_default();
+        let from = to
+            .checked_sub_signed(self.0)
+            .unwrap_or(DateTime::UNIX_EPOCH);
+
+        RangeParams { from, to }
+    }
+}
+
+impl FromStr for RelativeDuration {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid duration `{value}`, expected e.g. `24h` or `7d`");
+
+        let unit_start = value
+            .find(|c: char| !c.is_ascii_digit())
+            .ok_or_else(invalid)?;
+        let (amount, unit) = value.split_at(unit_start);
+        let amount: u32 = amount.parse().map_err(|_| invalid())?;
+
+        let seconds_per_unit = match unit {
+            "m" => 60,
+            "h" => 3600,
+            "d" => 86400,
+            "w" => 604_800,
+            _ => return Err(invalid()),
+        };
+        if amount == 0 {
+            return Err(invalid());
+        }
+
+        Ok(Self(chrono::Duration::seconds(
+            i64::from(amount) * seconds_per_unit,
+        )))
+    }
+}
+
+impl<'de> Deserialize<'de> for RelativeDuration {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Fraction of the messages returned by a sampled request, in millionths
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SampleRate(u32);
+
+impl SampleRate {
+    pub const SCALE: u32 = 1_000_000;
+
+    /// Messages whose hash modulo [`Self::SCALE`] is below the threshold are sampled
+    pub fn threshold(self) -> u32 {
+        self.0
+    }
+
+    /// The requested rate rounded to the precision of the sampling
+    pub fn effective(self) -> f64 {
+        f64::from(self.0) / f64::from(Self::SCALE)
+    }
+}
+
+impl FromStr for SampleRate {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid sample rate `{value}`, expected a fraction like `0.01`");
+
+        let rate: f64 = value.parse().map_err(|_| invalid())?;
+        if !(rate > 0.0 && rate <= 1.0) {
+            return Err(invalid());
+        }
+        // Tiny rates still sample some messages
+        let threshold = (rate * f64::from(Self::SCALE)).round().max(1.0);
+        Ok(Self(threshold as u32))
+    }
+}
+
+impl<'de> Deserialize<'de> for SampleRate {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ActivityBreakdown {
+    /// Chatters who sent a single message
+    pub one_message: u64,
+    /// Chatters who sent 2 to 10 messages
+    pub two_to_ten: u64,
+    /// Chatters who sent 11 to 100 messages
+    pub eleven_to_hundred: u64,
+    /// Chatters who sent more than 100 messages
+    pub over_hundred: u64,
+}
+
+impl ActivityBreakdown {
+    /// The bucket chatters with the given message count fall into, none for 0 messages
+    pub fn bucket_mut(&mut self, message_count: u64) -> Option<&mut u64> {
+        match message_count {
+            0 => None,
+            1 => Some(&mut self.one_message),
+            2..=10 => Some(&mut self.two_to_ten),
+            11..=100 => Some(&mut self.eleven_to_hundred),
+            _ => Some(&mut self.over_hundred),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserChannelMessageCount {
+    /// Channel ID
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    pub message_count: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct UserChannelStats {
+    /// Message counts per channel, ordered by the most active channel
+    pub channels: Vec<UserChannelStatsEntry>,
+    /// Chat colors the user sent messages with, in the order they were first used
+    pub colors: Vec<UserColor>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserColor {
+    /// Hex color, e.g. `#1E90FF`
+    pub color: String,
+    #[schemars(with = "String")]
+    pub first_seen: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub last_seen: DateTime<Utc>,
+    pub message_count: u64,
+}
+
+impl From<UserColorRow> for UserColor {
+    fn from(row: UserColorRow) -> Self {
+        Self {
+            color: format!("#{:06X}", row.color.unwrap_or_default()),
+            first_seen: DateTime::from_timestamp_millis(row.first_seen as i64).unwrap_or_default(),
+            last_seen: DateTime::from_timestamp_millis(row.last_seen as i64).unwrap_or_default(),
+            message_count: row.message_count,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserChannelStatsEntry {
+    /// Channel name, if it could be resolved
+    pub name: Option<String>,
+    #[serde(flatten)]
+    pub count: UserChannelMessageCount,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct UserStatsPath {
+    pub user: String,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema, SimpleObject, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelSummary {
+    pub message_count: u64,
+    pub unique_chatters: u64,
+    /// The unique chatters are an estimate
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate: bool,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct TopChatter {
+    #[serde(rename = "userID")]
+    #[graphql(name = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    pub message_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+pub struct SubCounts {
+    pub subs: u64,
+    pub resubs: u64,
+    /// Gifted subs, including each sub of a community gift
+    pub gifts: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyMessageCount {
+    /// Day in `YYYY-MM-DD` format
+    pub date: String,
+    pub message_count: u64,
+    /// Approximate amount of different chatters
+    pub unique_chatters: u64,
+}
+
+/// Aggregates of a channel which are periodically written to files, so they can be served
+/// without querying the database
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PublicChannelStats {
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    #[schemars(with = "String")]
+    pub generated_at: DateTime<Utc>,
+    /// Messages per day, oldest first
+    pub days: Vec<DailyMessageCount>,
+    /// Most used third-party emotes in the same days
+    pub top_emotes: Vec<EmoteCount>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PublicStatsPath {
+    /// Channel ID followed by `.json`
+    pub file: String,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+pub struct DailySubCounts {
+    /// Day in `YYYY-MM-DD` format
+    pub date: String,
+    pub subs: u64,
+    pub resubs: u64,
+    pub gifts: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TopGifter {
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    pub gift_count: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SubStats {
+    #[serde(flatten)]
+    pub totals: SubCounts,
+    pub top_gifters: Vec<TopGifter>,
+    /// Days without any subs are omitted
+    pub daily: Vec<DailySubCounts>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatQualityStats {
+    pub message_count: u64,
+    /// Average message length in characters
+    pub average_length: f64,
+    /// Approximate median message length in characters
+    pub median_length: f64,
+    /// Share of messages repeating the text of an earlier message in the range, between 0 and 1
+    pub duplicate_share: f64,
+    /// Share of message characters which are Twitch emotes, between 0 and 1. Third party emotes are not included
+    pub emote_share: f64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageStats {
+    /// Amount of randomly sampled messages the languages were detected in
+    pub sampled: u64,
+    /// Messages whose language could not be detected reliably, e.g. because they only contain emotes
+    pub undetected: u64,
+    /// Most used first
+    pub languages: Vec<LanguageShare>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LanguageShare {
+    /// ISO 639-3 code, e.g. `eng`
+    pub code: String,
+    /// English name, e.g. `English`
+    pub name: String,
+    pub message_count: u64,
+    /// Share of the sampled messages with a detected language, between 0 and 1
+    pub share: f64,
+}
+
+/// Messages flagged by AutoMod, a message with several categories is counted in each of them
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+pub struct AutomodCounts {
+    pub flagged: u64,
+    pub aggressive: u64,
+    pub identity: u64,
+    pub profanity: u64,
+    pub sexual: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+pub struct DailyAutomodCounts {
+    /// Day in `YYYY-MM-DD` format
+    pub date: String,
+    pub flagged: u64,
+    pub aggressive: u64,
+    pub identity: u64,
+    pub profanity: u64,
+    pub sexual: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AutomodStats {
+    #[serde(flatten)]
+    pub totals: AutomodCounts,
+    /// Days without any flagged messages are omitted
+    pub daily: Vec<DailyAutomodCounts>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ModerationCounts {
+    pub bans: u64,
+    pub timeouts: u64,
+    /// Average length of the timeouts in seconds, 0 if there were none
+    pub average_timeout_seconds: f64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DailyModerationCounts {
+    /// Day in `YYYY-MM-DD` format
+    pub date: String,
+    pub bans: u64,
+    pub timeouts: u64,
+    pub average_timeout_seconds: f64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ModerationStats {
+    #[serde(flatten)]
+    pub totals: ModerationCounts,
+    /// Days without any bans or timeouts are omitted
+    pub daily: Vec<DailyModerationCounts>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum ReportPeriod {
+    Weekly,
+    Monthly,
+}
+
+impl Display for ReportPeriod {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            ReportPeriod::Weekly => "weekly",
+            ReportPeriod::Monthly => "monthly",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelReport {
+    pub period: ReportPeriod,
+    #[schemars(with = "String")]
+    pub from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub to: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub generated_at: DateTime<Utc>,
+    #[serde(flatten)]
+    pub summary: ChannelSummary,
+    pub top_chatters: Vec<TopChatter>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ChannelReportPath {
+    #[serde(flatten)]
+    pub channel_info: LogsPathChannel,
+    pub period: ReportPeriod,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsParams {
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Include known bot accounts
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_bots: bool,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct StatsLimitParams {
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Include known bot accounts
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_bots: bool,
+    /// Maximum amount of entries to return. Defaults to 100
+    pub limit: Option<u64>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct StatsPageParams {
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Include known bot accounts
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_bots: bool,
+    /// Maximum amount of entries to return. Defaults to 100
+    pub limit: Option<u64>,
+    /// Amount of entries to skip, used with `limit` to page through the results
+    pub offset: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct TopChatters {
+    /// Chatters with the most messages, most active first
+    pub chatters: Vec<TopChatter>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmoteCount {
+    /// Emote name
+    pub name: String,
+    /// Emote provider (`7tv`, `bttv` or `ffz`)
+    pub provider: String,
+    pub usage_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TwitchEmoteCount {
+    /// Twitch emote ID
+    pub id: String,
+    /// Emote name as it was written in one of the messages
+    pub name: String,
+    pub usage_count: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct EmoteStats {
+    /// Third-party emotes
+    pub emotes: Vec<EmoteCount>,
+    pub twitch_emotes: Vec<TwitchEmoteCount>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LinksParams {
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Only return links to this domain
+    pub domain: Option<String>,
+    /// Maximum amount of links to return. Defaults to 1000
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Link {
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+    pub domain: String,
+    pub url: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct LinksList {
+    /// Links, newest first
+    pub links: Vec<Link>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema, Clone, Debug, PartialEq)]
+pub struct ActivityStreak {
+    /// Consecutive days with at least one message
+    pub days: u64,
+    /// First day in `YYYY-MM-DD` format
+    pub start: String,
+    /// Last day in `YYYY-MM-DD` format
+    pub end: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+pub struct UserStreaks {
+    /// The most recent one if there are several of the same length
+    pub longest: Option<ActivityStreak>,
+    /// Streak ending today or yesterday, as the user may still chat today
+    pub current: Option<ActivityStreak>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UserWatchtime {
+    /// Active chat time during the streams in the range, i.e. the number of distinct
+    /// buckets with a message multiplied by the bucket length
+    pub active_seconds: u64,
+    pub bucket_seconds: u64,
+    /// Streams which overlap the given range, oldest first
+    pub streams: Vec<StreamWatchtime>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamWatchtime {
+    #[serde(flatten)]
+    pub stream: Stream,
+    pub active_seconds: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct StreamPath {
+    pub channel_id_type: ChannelIdType,
+    /// Channel login or ID
+    pub channel: String,
+    pub stream_id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StreamStatsParams {
+    /// Include known bot accounts
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_bots: bool,
+    /// Count unique chatters exactly instead of estimating them
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub exact: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StreamStats {
+    pub stream: Stream,
+    #[serde(flatten)]
+    pub summary: ChannelSummary,
+    /// Chatters are new if their first message in the channel was sent during the stream
+    #[serde(flatten)]
+    pub chatters: NewAndReturningChatters,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct StreamsParams {
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Maximum amount of streams to return
+    pub limit: Option<u64>,
+    /// Amount of streams to skip, used with `limit` to page through the results
+    pub offset: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema, SimpleObject)]
+#[serde(rename_all = "camelCase")]
+pub struct Stream {
+    pub id: String,
+    #[schemars(with = "String")]
+    pub started_at: DateTime<Utc>,
+    /// The last time the stream was seen live
+    #[schemars(with = "String")]
+    pub ended_at: DateTime<Utc>,
+    /// Seconds between the start and `endedAt`
+    pub duration: u32,
+    /// Whether the stream was still live at the last poll
+    pub live: bool,
+    pub title: String,
+    pub game_name: String,
+    /// ID of the VOD on Twitch, if it was found while the stream was live
+    #[serde(rename = "videoID")]
+    #[graphql(name = "videoID")]
+    pub video_id: Option<String>,
+}
+
+impl From<StreamRow> for Stream {
+    fn from(row: StreamRow) -> Self {
+        Self {
+            id: row.stream_id,
+            started_at: DateTime::from_timestamp(row.started_at.into(), 0).unwrap_or_default(),
+            ended_at: DateTime::from_timestamp(row.ended_at.into(), 0).unwrap_or_default(),
+            duration: row.ended_at.saturating_sub(row.started_at),
+            live: !row.ended,
+            title: row.title,
+            game_name: row.game_name,
+            video_id: Some(row.video_id).filter(|id| !id.is_empty()),
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StreamBackfill {
+    /// Past broadcasts which were added as streams
+    pub imported: usize,
+    /// Past broadcasts whose stream was already known
+    pub skipped: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct StreamsList {
+    /// Streams which overlap the given range, oldest first
+    pub streams: Vec<Stream>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Announcement {
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+    pub text: String,
+    /// Announcement color, e.g. `PRIMARY` or `BLUE`
+    pub color: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AnnouncementsList {
+    /// Announcements, newest first
+    pub announcements: Vec<Announcement>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DomainCount {
+    pub domain: String,
+    pub link_count: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplyPair {
+    /// The user who replied
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    /// The user who was replied to
+    #[serde(rename = "parentUserID")]
+    pub parent_user_id: String,
+    pub parent_user_login: String,
+    pub reply_count: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ReplyStats {
+    /// Most common replier and repliee pairs, most replies first
+    pub pairs: Vec<ReplyPair>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DomainStats {
+    pub domains: Vec<DomainCount>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub struct NewAndReturningChatters {
+    /// Chatters who sent their first message in the channel during the range
+    pub new_chatters: u64,
+    /// Chatters who have already sent messages in the channel before the range
+    pub returning_chatters: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct CompareRangesParams {
+    #[serde(flatten)]
+    pub base: RangeParams,
+    #[schemars(with = "String")]
+    /// RFC 3339 start date of the range to compare against
+    pub compare_from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    /// RFC 3339 end date of the range to compare against
+    pub compare_to: DateTime<Utc>,
+    /// Include known bot accounts
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_bots: bool,
+    /// Count unique chatters exactly instead of estimating them, which is slower on large channels
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub exact: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MultiRangeParams {
+    /// Comma separated `start/end` ranges, e.g. `2024-01-01/2024-01-07,2024-02-01/2024-02-07`.
+    /// Dates are whole days in the channel's timezone, including the end date. RFC 3339 dates can be used for exact bounds
+    pub ranges: String,
+    /// Include known bot accounts
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub include_bots: bool,
+    /// Count unique chatters exactly instead of estimating them, which is slower on large channels
+    #[serde(default, deserialize_with = "deserialize_bool_param")]
+    pub exact: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct RangeSummaries {
+    /// In the requested order
+    pub ranges: Vec<RangeSummary>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeSummary {
+    #[schemars(with = "String")]
+    pub from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub to: DateTime<Utc>,
+    #[serde(flatten)]
+    pub summary: ChannelSummary,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeSnapshot {
+    #[schemars(with = "String")]
+    pub from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub to: DateTime<Utc>,
+    #[serde(flatten)]
+    pub summary: ChannelSummary,
+    #[serde(flatten)]
+    pub chatters: NewAndReturningChatters,
+    pub top_emotes: Vec<EmoteCount>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RangeDelta {
+    pub message_count: i64,
+    pub unique_chatters: i64,
+    pub new_chatters: i64,
+    pub returning_chatters: i64,
+}
+
+impl RangeDelta {
+    pub fn between(base: &RangeSnapshot, compared: &RangeSnapshot) -> Self {
+        fn diff(base: u64, compared: u64) -> i64 {
+            compared as i64 - base as i64
+        }
+
+        Self {
+            message_count: diff(base.summary.message_count, compared.summary.message_count),
+            unique_chatters: diff(
+                base.summary.unique_chatters,
+                compared.summary.unique_chatters,
+            ),
+            new_chatters: diff(base.chatters.new_chatters, compared.chatters.new_chatters),
+            returning_chatters: diff(
+                base.chatters.returning_chatters,
+                compared.chatters.returning_chatters,
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct RangeComparison {
+    pub base: RangeSnapshot,
+    pub compared: RangeSnapshot,
+    /// Difference of the compared range relative to the base range
+    pub delta: RangeDelta,
+}
+
+#[derive(Deserialize, JsonSchema, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum UsageGroup {
+    #[default]
+    Ip,
+    Key,
+    Route,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageParams {
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// What to group the usage by. Defaults to `ip`
+    #[serde(default)]
+    pub group_by: UsageGroup,
+    /// Maximum amount of consumers to return. Defaults to 100
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UsageConsumer {
+    /// IP address, API key label or route, depending on the grouping
+    pub consumer: String,
+    pub requests: u64,
+    /// Total log messages streamed
+    pub total_rows: u64,
+    /// Total response body bytes
+    pub total_bytes: u64,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RunningQuery {
+    pub query_id: String,
+    /// Seconds since the query started
+    pub elapsed: f64,
+    pub read_rows: u64,
+    pub read_bytes: u64,
+    pub memory_usage: i64,
+    pub query: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeJob {
+    /// Poll `/admin/purge/{mutation_id}` until the purge is done
+    pub mutation_id: String,
+    /// Messages which had not been written to the database yet and were dropped right away
+    pub evicted_messages: usize,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct PurgeStatus {
+    pub mutation_id: String,
+    pub is_done: bool,
+    /// Parts of the messages table which still have to be rewritten
+    pub parts_to_do: i64,
+    /// Empty unless rewriting a part failed, failed parts are retried
+    pub latest_fail_reason: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct StorageStats {
+    /// Storage policy of the messages table, if tiering is configured
+    pub policy: Option<String>,
+    /// Volume partitions are moved to once they are old enough
+    pub cold_volume: Option<String>,
+    pub move_after_days: Option<u64>,
+    pub disks: Vec<TableDiskUsage>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct RetentionPolicies {
+    /// Days messages of channels without their own retention are kept
+    pub default_days: Option<u32>,
+    /// Days messages are kept by channel ID, `null` if they are kept forever
+    pub channels: HashMap<String, Option<u32>>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum LegalHoldKind {
+    Channel,
+    User,
+}
+
+impl Display for LegalHoldKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let s = match self {
+            LegalHoldKind::Channel => "channel",
+            LegalHoldKind::User => "user",
+        };
+        f.write_str(s)
+    }
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LegalHoldPath {
+    pub kind: LegalHoldKind,
+    /// Channel or user ID
+    pub id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct LegalHoldRequest {
+    /// Why the messages have to be kept, e.g. a case number
+    pub reason: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct LegalHoldEntry {
+    pub kind: LegalHoldKind,
+    pub id: String,
+    pub reason: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AuditLogParams {
+    /// Defaults to 100
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AuditLogEntry {
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+    /// Owner of the API key the action was made with, or `user/<id>` for actions of users
+    pub actor: String,
+    pub action: String,
+    pub target: String,
+    pub details: String,
+}
+
+impl From<AuditLogRow> for AuditLogEntry {
+    fn from(row: AuditLogRow) -> Self {
+        Self {
+            timestamp: DateTime::from_timestamp(row.timestamp.into(), 0).unwrap_or_default(),
+            actor: row.actor,
+            action: row.action,
+            target: row.target,
+            details: row.details,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaDrift {
+    /// Whether the tables match the schema created by the migrations
+    pub in_sync: bool,
+    pub differences: Vec<SchemaDifference>,
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct SchemaDifference {
+    pub table: String,
+    /// Not set for differences of the whole table
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub column: Option<String>,
+    pub kind: SchemaDifferenceKind,
+    /// Empty if the column should not exist, or if the codec is the default compression
+    pub expected: String,
+    /// Empty if the table or column does not exist, or if the codec is the default compression
+    pub actual: String,
+}
+
+#[derive(Serialize, JsonSchema, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum SchemaDifferenceKind {
+    MissingTable,
+    MissingColumn,
+    UnexpectedColumn,
+    Type,
+    Codec,
+    SortingKey,
+}
+
+impl Display for SchemaDifference {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let table = &self.table;
+        let column = self.column.as_deref().unwrap_or_default();
+        let (expected, actual) = (&self.expected, &self.actual);
+        match self.kind {
+            SchemaDifferenceKind::MissingTable => write!(f, "table {table} is missing"),
+            SchemaDifferenceKind::MissingColumn => {
+                write!(f, "column {table}.{column} ({expected}) is missing")
+            }
+            SchemaDifferenceKind::UnexpectedColumn => {
+                write!(
+                    f,
+                    "column {table}.{column} ({actual}) is not part of the schema"
+                )
+            }
+            SchemaDifferenceKind::Type => write!(
+                f,
+                "column {table}.{column} has type {actual}, expected {expected}"
+            ),
+            SchemaDifferenceKind::Codec => write!(
+                f,
+                "column {table}.{column} has codec '{actual}', expected '{expected}'"
+            ),
+            SchemaDifferenceKind::SortingKey => write!(
+                f,
+                "table {table} is ordered by ({actual}), expected ({expected})"
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TableDiskUsage {
+    pub table: String,
+    pub disk: String,
+    pub parts: u64,
+    pub partitions: u64,
+    pub oldest_partition: String,
+    pub newest_partition: String,
+    pub rows: u64,
+    pub bytes: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct BackupEntry {
+    pub name: String,
+    pub url: String,
+    #[schemars(with = "String")]
+    pub started_at: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub finished_at: DateTime<Utc>,
+    pub success: bool,
+    /// Empty if the backup was successful
+    pub error: String,
+}
+
+impl From<BackupRow> for BackupEntry {
+    fn from(row: BackupRow) -> Self {
+        Self {
+            name: row.name,
+            url: row.url,
+            started_at: DateTime::from_timestamp(row.started_at.into(), 0).unwrap_or_default(),
+            finished_at: DateTime::from_timestamp(row.finished_at.into(), 0).unwrap_or_default(),
+            success: row.success,
+            error: row.error,
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct HealthStatus {
+    pub ready: bool,
+    pub nodes: Vec<NodeHealth>,
+    /// Tasks which have to keep running for messages to be logged
+    pub tasks: Vec<TaskHealth>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct NodeHealth {
+    /// Position of the node, the primary first and then the fallbacks in the configured order
+    pub index: usize,
+    pub healthy: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct TaskHealth {
+    pub name: String,
+    pub seconds_since_heartbeat: u64,
+    /// False if the task has not set its heartbeat within `livenessTimeout`
+    pub alive: bool,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct QueryIdPath {
+    pub id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct MutationIdPath {
+    pub mutation_id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct ChannelIdPath {
+    pub id: String,
+}
+
+/// How logging of a paused channel is stopped
+#[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "camelCase")]
+pub enum PauseMode {
+    /// Stay joined but drop the channel's messages
+    #[default]
+    Drop,
+    /// Part the channel, it is joined again once logging is resumed
+    Part,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PauseChannelRequest {
+    /// Defaults to `drop`
+    #[serde(default)]
+    pub mode: PauseMode,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelVerification {
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    pub channel_login: String,
+    pub diagnosis: ChannelDiagnosis,
+    /// Whether the channel is in the configured channels
+    pub configured: bool,
+    /// Whether the bot is currently joined to the channel's IRC chat
+    pub joined: bool,
+    /// Whether Twitch reports the channel as live
+    pub live: bool,
+    /// How logging is paused, if it is
+    pub paused: Option<PauseMode>,
+    #[schemars(with = "Option<String>")]
+    /// Date of the latest message received from the channel, including messages which have not been written yet
+    pub last_message_at: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema, Clone, Copy, PartialEq, Eq, Debug)]
+#[serde(rename_all = "camelCase")]
+pub enum ChannelDiagnosis {
+    /// Messages have been received recently
+    Receiving,
+    /// The bot is joined but the channel is offline and quiet, which is normal
+    Idle,
+    /// The channel is live but no messages have been received recently, e.g. because the bot is banned
+    Silent,
+    /// The channel is configured but the bot is not joined to it
+    NotJoined,
+    /// The channel is not in the configured channels
+    NotConfigured,
+    /// Logging of the channel has been paused by an admin
+    Paused,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ReplayParams {
+    #[schemars(with = "String")]
+    /// RFC 3339 date the replay starts at, e.g. the start of a VOD
+    pub from: DateTime<Utc>,
+    #[schemars(with = "Option<String>")]
+    /// RFC 3339 date the replay ends at. Defaults to 48 hours after `from`, which is also the maximum
+    pub to: Option<DateTime<Utc>>,
+    /// How much faster than the original chat messages are sent, e.g. `2` for a VOD played at double speed.
+    /// Defaults to 1, between 0.1 and 100
+    pub speed: Option<f64>,
+    #[serde(flatten)]
+    pub logs_params: LogsParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct MomentParams {
+    #[schemars(with = "Option<String>")]
+    /// RFC 3339 date of the moment, e.g. the creation date of a clip
+    pub timestamp: Option<DateTime<Utc>>,
+    /// Stream the moment is from, used together with `streamOffset` if no timestamp is given
+    #[serde(rename = "streamID")]
+    pub stream_id: Option<String>,
+    /// Seconds since the start of the stream, e.g. a VOD timestamp
+    pub stream_offset: Option<u64>,
+    /// Seconds of chat to return before and after the moment. Defaults to 60, at most 600
+    pub window: Option<u64>,
+    #[serde(flatten)]
+    pub logs_params: LogsParams,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct UnparsedMessagesParams {
+    /// Maximum amount of messages to return. Defaults to 100
+    pub limit: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct UnparsedMessageEntry {
+    pub id: String,
+    pub channel_id: String,
+    pub user_id: String,
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+    pub raw: String,
+    /// Why the message could not be parsed
+    pub error: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct UnparsedRetryResult {
+    /// Messages which were parsed successfully and moved to the logs
+    pub parsed: u64,
+    /// Messages which still could not be parsed
+    pub failed: u64,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlapParams {
+    /// ID of the first channel
+    pub first: ChannelId,
+    /// ID of the second channel
+    pub second: ChannelId,
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Amount of randomly sampled shared chatters to list. Defaults to 0, at most 1000
+    pub sample: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChatOverlap {
+    /// Chatters who sent a message in both channels
+    pub shared_chatters: u64,
+    pub first_chatters: u64,
+    pub second_chatters: u64,
+    /// Shared chatters divided by the chatters of either channel, between 0 and 1
+    pub jaccard_index: f64,
+    pub sample: Vec<OverlapChatter>,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OverlapChatter {
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct GapsParams {
+    #[serde(flatten)]
+    pub channel: ChannelParam,
+    #[serde(flatten)]
+    pub range: RangeParams,
+    /// Shortest gap to report in minutes. Defaults to 5
+    pub min_minutes: Option<u32>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Gap {
+    #[schemars(with = "String")]
+    pub from: DateTime<Utc>,
+    #[schemars(with = "String")]
+    pub to: DateTime<Utc>,
+    pub minutes: u32,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ChannelGaps {
+    #[serde(rename = "channelID")]
+    pub channel_id: ChannelId,
+    /// Periods without any logged message while the channel was live, oldest first
+    pub gaps: Vec<Gap>,
+    pub missing_minutes: u32,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct LatencyQuantiles {
+    pub p50_ms: u64,
+    pub p99_ms: u64,
+    /// How many recent samples the quantiles are based on
+    pub samples: usize,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct ChannelLatency {
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    #[serde(flatten)]
+    pub latency: LatencyQuantiles,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct IngestionStatus {
+    /// Time from a message being sent until it has been written to the database
+    pub latency: LatencyQuantiles,
+    /// Whether the database writer is falling behind
+    pub overloaded: bool,
+    /// Latency per channel, slowest first
+    pub channels: Vec<ChannelLatency>,
+    /// Database nodes, the primary first
+    pub nodes: Vec<DatabaseNode>,
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct DatabaseNode {
+    /// Without the credentials
+    pub url: String,
+    pub healthy: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OptOutDenials {
+    #[schemars(with = "String")]
+    /// Denials are counted since this date, when the server was started
+    pub since: DateTime<Utc>,
+    pub total: u64,
+    /// Most denied first
+    pub denials: Vec<OptOutDenial>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OptOutDenial {
+    pub reason: OptOutDenialReason,
+    /// Start of the hex encoded HMAC-SHA256 of the channel id, keyed per process
+    pub channel: String,
+    /// Start of the hex encoded HMAC-SHA256 of the user id, if user logs were requested
+    pub user: Option<String>,
+    pub count: u64,
+    #[schemars(with = "String")]
+    pub last_denied_at: DateTime<Utc>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct OptOutEntry {
+    /// ID of the opted out user or channel
+    pub id: String,
+    pub source: OptOutSource,
+    /// When the user opted out through `/optout`
+    #[schemars(with = "Option<String>")]
+    pub opted_out_at: Option<DateTime<Utc>>,
+    /// Whether the user's logs were deleted when opting out
+    pub purged: bool,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub enum OptOutSource {
+    /// Listed in the config's `optOut`
+    Config,
+    /// Opted out through `/optout`
+    SelfService,
+}
+
+#[derive(Serialize, JsonSchema, AsRefStr, Clone, Copy, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "camelCase")]
+#[strum(serialize_all = "camelCase")]
+pub enum OptOutDenialReason {
+    ChannelOptedOut,
+    UserOptedOut,
+    UserLogsDisabled,
+    UserNotConsented,
+}
+
+#[derive(Serialize, Deserialize, Row, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicateCount {
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    /// Message IDs which have been stored more than once
+    pub duplicate_ids: u64,
+    /// Rows which would be removed by a cleanup
+    pub extra_rows: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicatesReport {
+    /// Affected channels, most duplicates first
+    pub channels: Vec<DuplicateCount>,
+    pub extra_rows: u64,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct DuplicatesCleanup {
+    /// Duplicates which were found before the cleanup
+    pub report: DuplicatesReport,
+    /// Monthly partitions which have been deduplicated, e.g. `202403`
+    pub partitions: Vec<u32>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedSearchBody {
+    #[serde(rename = "channelID")]
+    pub channel_id: ChannelId,
+    /// Only match messages of this user. Messages of all users are matched if not set
+    #[serde(rename = "userID")]
+    pub user_id: Option<UserId>,
+    /// Case insensitive text to search for, also matched against the normalized message text
+    pub query: String,
+    /// Receives a POST request with the new matches whenever the search matches
+    pub webhook_url: Option<String>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct SavedSearch {
+    pub id: String,
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    #[serde(rename = "userID")]
+    pub user_id: Option<String>,
+    pub query: String,
+    pub webhook_url: Option<String>,
+    #[schemars(with = "String")]
+    pub created_at: DateTime<Utc>,
+}
+
+impl From<SavedSearchRow> for SavedSearch {
+    fn from(row: SavedSearchRow) -> Self {
+        Self {
+            id: row.id.to_string(),
+            channel_id: row.channel_id,
+            user_id: Some(row.user_id).filter(|user_id| !user_id.is_empty()),
+            query: row.query,
+            webhook_url: Some(row.webhook_url).filter(|url| !url.is_empty()),
+            created_at: DateTime::from_timestamp(row.created_at.into(), 0).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct SavedSearchesList {
+    pub searches: Vec<SavedSearch>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AlertPath {
+    pub id: String,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct AlertMatchesParams {
+    /// Maximum amount of matches to return. Defaults to 100, at most 1000
+    pub limit: Option<u64>,
+    pub offset: Option<u64>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct AlertMatch {
+    #[serde(rename = "channelID")]
+    pub channel_id: String,
+    #[serde(rename = "userID")]
+    pub user_id: String,
+    pub user_login: String,
+    #[schemars(with = "String")]
+    pub timestamp: DateTime<Utc>,
+    pub text: String,
+    /// When the saved search found the message
+    #[schemars(with = "String")]
+    pub matched_at: DateTime<Utc>,
+}
+
+impl From<AlertMatchRow> for AlertMatch {
+    fn from(row: AlertMatchRow) -> Self {
+        Self {
+            channel_id: row.channel_id,
+            user_id: row.user_id,
+            user_login: row.user_login,
+            timestamp: DateTime::from_timestamp_millis(row.timestamp as i64).unwrap_or_default(),
+            text: row.text,
+            matched_at: DateTime::from_timestamp(row.matched_at.into(), 0).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize, JsonSchema)]
+pub struct AlertMatches {
+    /// Newest first
+    pub matches: Vec<AlertMatch>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RangeParams, RangeQuery, RelativeDuration, SampleRate};
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn parses_relative_durations() {
+        assert_eq!(
+            RelativeDuration(chrono::Duration::hours(24)),
+            "24h".parse().unwrap()
+        );
+        assert_eq!(
+            RelativeDuration(chrono::Duration::weeks(2)),
+            "2w".parse().unwrap()
+        );
+
+        for invalid in ["", "24", "h", "0d", "-1d", "1.5h", "7 d", "7days"] {
+            assert!(invalid.parse::<RelativeDuration>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn parses_sample_rates() {
+        let rate: SampleRate = "0.01".parse().unwrap();
+        assert_eq!(10_000, rate.threshold());
+        assert_eq!(0.01, rate.effective());
+
+        let tiny: SampleRate = "0.0000001".parse().unwrap();
+        assert_eq!(0.000001, tiny.effective());
+
+        for invalid in ["", "0", "-0.5", "1.5", "NaN", "1%"] {
+            assert!(invalid.parse::<SampleRate>().is_err(), "{invalid}");
+        }
+    }
+
+    #[test]
+    fn relative_range_ends_after_now() {
+        let query = RangeQuery {
+            from: None,
+            to: None,
+            last: Some("7d".parse().unwrap()),
+        };
+        let range = RangeParams::try_from(query).unwrap();
+
+        assert!(range.to > chrono::Utc::now());
+        assert_eq!(chrono::Duration::days(7), range.to - range.from);
+    }
+}
@@ -0,0 +1,934 @@
+use super::{
+    handlers::{cache_header, no_cache_header},
+    pagination::Page,
+    schema::{
+        start_of_day, ActivityStreak, AutomodStats, ChannelIdType, ChannelReportPath,
+        ChatQualityStats, CompareRangesParams, DomainStats, EmoteStats, LanguageShare,
+        LanguageStats, LogsPathChannel, ModerationStats, MultiRangeParams, PublicStatsPath,
+        RangeComparison, RangeDelta, RangeParams, RangeSnapshot, RangeSummaries, RangeSummary,
+        ReplyStats, StatsLimitParams, StatsPageParams, StatsParams, StreamPath, StreamStats,
+        StreamStatsParams, StreamWatchtime, SubStats, TopChatters, UserChannelStats,
+        UserChannelStatsEntry, UserColor, UserLogPathParams, UserStatsPath, UserStreaks,
+        UserWatchtime,
+    },
+};
+use crate::{
+    app::App,
+    db::{
+        links::read_top_domains,
+        pool::QueryClass,
+        reports::read_latest_report,
+        stats::{
+            read_activity_breakdown, read_automod_counts, read_channel_summary, read_chat_quality,
+            read_daily_automod_counts, read_daily_moderation_counts, read_daily_sub_counts,
+            read_message_text_sample, read_moderation_counts, read_new_and_returning_chatters,
+            read_range_summaries, read_sub_counts, read_third_party_emote_counts,
+            read_top_chatters, read_top_gifters, read_top_reply_pairs, read_twitch_emote_counts,
+            read_user_active_buckets, read_user_activity_streaks, read_user_channel_message_counts,
+            read_user_colors,
+        },
+        streams::{read_stream, read_streams},
+    },
+    error::Error,
+    ids::{ChannelId, UserId},
+    logs::language::count_languages,
+    Result,
+};
+use aide::axum::IntoApiResponse;
+use axum::{
+    extract::{Path, Query, State},
+    http::header,
+    Json,
+};
+use chrono::{DateTime, Days, NaiveDate, Utc};
+use chrono_tz::Tz;
+
+const DEFAULT_STATS_LIMIT: u64 = 100;
+const COMPARISON_TOP_EMOTES: u64 = 10;
+const MAX_RANGES: usize = 12;
+/// Detection runs at query time, so only a sample of the messages is looked at
+const LANGUAGE_SAMPLE_SIZE: u64 = 10_000;
+/// Chatting at least once within a bucket counts as being present for all of it
+const WATCHTIME_BUCKET_SECONDS: u64 = 600;
+
+pub async fn activity_breakdown(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let breakdown = read_activity_breakdown(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        params.range,
+        excluded_users,
+        &app.flush_buffer,
+    )
+    .await?;
+
+    Ok((cache_header(600), Json(breakdown)))
+}
+
+pub async fn user_channel_stats_by_name(
+    app: State<App>,
+    Path(UserStatsPath { user }): Path<UserStatsPath>,
+    params: Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let user_id = app.get_user_id_by_name(&user).await?;
+    user_channel_stats(app, user_id.into(), params).await
+}
+
+pub async fn user_channel_stats_by_id(
+    app: State<App>,
+    Path(UserStatsPath { user }): Path<UserStatsPath>,
+    params: Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    user_channel_stats(app, user.into(), params).await
+}
+
+async fn user_channel_stats(
+    app: State<App>,
+    user_id: UserId,
+    Query(params): Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    if app.has_opted_out(user_id.as_str()) {
+        return Err(Error::UserOptedOut);
+    }
+    if !app.has_consented(user_id.as_str()) {
+        return Err(Error::UserNotConsented);
+    }
+
+    let counts: Vec<_> =
+        read_user_channel_message_counts(&app.db.profile(QueryClass::Stats), &user_id, params)
+            .await?
+            .into_iter()
+            .filter(|count| {
+                !app.has_opted_out(&count.channel_id)
+                    && !app.config.user_logs_disabled(&count.channel_id)
+            })
+            .collect();
+
+    if counts.is_empty() {
+        return Err(Error::NotFound);
+    }
+
+    let channel_ids: Vec<String> = counts
+        .iter()
+        .map(|count| count.channel_id.clone())
+        .collect();
+    let colors = read_user_colors(
+        &app.db.profile(QueryClass::Stats),
+        &user_id,
+        &channel_ids,
+        params,
+    )
+    .await?
+    .into_iter()
+    .map(UserColor::from)
+    .collect();
+    let channel_names = app.get_users(channel_ids, vec![], false).await?;
+
+    let channels = counts
+        .into_iter()
+        .map(|count| UserChannelStatsEntry {
+            name: channel_names.get(&count.channel_id).cloned(),
+            count,
+        })
+        .collect();
+
+    Ok((
+        cache_header(600),
+        Json(UserChannelStats { channels, colors }),
+    ))
+}
+
+pub async fn channel_report(
+    app: State<App>,
+    Path(ChannelReportPath {
+        channel_info,
+        period,
+    }): Path<ChannelReportPath>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app
+        .resolve_channel_id(channel_info.channel_id_type, channel_info.channel)
+        .await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let report = read_latest_report(&app.db.profile(QueryClass::Stats), &channel_id, period)
+        .await?
+        .ok_or(Error::NotFound)?;
+
+    Ok((cache_header(600), Json(report)))
+}
+
+pub async fn emote_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsLimitParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let emotes = read_emote_stats(&app, &channel_id, None, params).await?;
+    Ok((cache_header(600), Json(emotes)))
+}
+
+pub async fn user_emote_stats_by_name(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+    Query(params): Query<StatsLimitParams>,
+) -> Result<impl IntoApiResponse> {
+    let user_id = app.get_user_id_by_name(&user).await?;
+    user_emote_stats(app, channel_id_type, channel, user_id.into(), params).await
+}
+
+pub async fn user_emote_stats_by_id(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+    Query(params): Query<StatsLimitParams>,
+) -> Result<impl IntoApiResponse> {
+    user_emote_stats(app, channel_id_type, channel, user.into(), params).await
+}
+
+async fn user_emote_stats(
+    app: State<App>,
+    channel_id_type: ChannelIdType,
+    channel: String,
+    user_id: UserId,
+    params: StatsLimitParams,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let emotes = read_emote_stats(&app, &channel_id, Some(&user_id), params).await?;
+    Ok((cache_header(600), Json(emotes)))
+}
+
+async fn read_emote_stats(
+    app: &App,
+    channel_id: &ChannelId,
+    user_id: Option<&UserId>,
+    params: StatsLimitParams,
+) -> Result<EmoteStats> {
+    let limit = params.limit.unwrap_or(DEFAULT_STATS_LIMIT);
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let db = &app.db.profile(QueryClass::Stats);
+    let (emotes, twitch_emotes) = futures::try_join!(
+        read_third_party_emote_counts(db, channel_id, user_id, params.range, excluded_users, limit),
+        read_twitch_emote_counts(db, channel_id, user_id, params.range, excluded_users, limit),
+    )?;
+
+    Ok(EmoteStats {
+        emotes,
+        twitch_emotes,
+    })
+}
+
+pub async fn top_chatters(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsPageParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_STATS_LIMIT);
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let chatters = read_top_chatters(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        params.range,
+        excluded_users,
+        limit,
+        params.offset.unwrap_or(0),
+        &app.flush_buffer,
+    )
+    .await?;
+
+    let page = Page::new(Some(limit), params.offset, chatters.len() as u64 == limit);
+    Ok((cache_header(600), page, Json(TopChatters { chatters })))
+}
+
+pub async fn domain_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsLimitParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_STATS_LIMIT);
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let domains = read_top_domains(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        params.range,
+        excluded_users,
+        limit,
+    )
+    .await?;
+
+    Ok((cache_header(600), Json(DomainStats { domains })))
+}
+
+pub async fn reply_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsLimitParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    // Opted out users are not logged, but can still be replied to
+    let excluded_users: Vec<String> = app
+        .stats_excluded_users(params.include_bots)
+        .iter()
+        .cloned()
+        .chain(app.opted_out_ids())
+        .collect();
+    let pairs = read_top_reply_pairs(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        params.range,
+        &excluded_users,
+        params.limit.unwrap_or(DEFAULT_STATS_LIMIT),
+    )
+    .await?;
+
+    Ok((cache_header(600), Json(ReplyStats { pairs })))
+}
+
+pub async fn sub_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsLimitParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let limit = params.limit.unwrap_or(DEFAULT_STATS_LIMIT);
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+
+    let db = &app.db.profile(QueryClass::Stats);
+    let (totals, top_gifters, daily) = futures::try_join!(
+        read_sub_counts(db, &channel_id, params.range, excluded_users),
+        read_top_gifters(db, &channel_id, params.range, excluded_users, limit),
+        read_daily_sub_counts(db, &channel_id, params.range, excluded_users),
+    )?;
+
+    Ok((
+        cache_header(600),
+        Json(SubStats {
+            totals,
+            top_gifters,
+            daily,
+        }),
+    ))
+}
+
+pub async fn chat_quality(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let stats = read_chat_quality(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        params.range,
+        app.stats_excluded_users(params.include_bots),
+    )
+    .await?;
+
+    Ok((cache_header(600), Json(stats)))
+}
+
+pub async fn language_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<StatsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let texts = read_message_text_sample(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        params.range,
+        app.stats_excluded_users(params.include_bots),
+        LANGUAGE_SAMPLE_SIZE,
+    )
+    .await?;
+    let sampled = texts.len() as u64;
+
+    let counts =
+        tokio::task::spawn_blocking(move || count_languages(texts.iter().map(String::as_str)))
+            .await
+            .map_err(|_| Error::Internal)?;
+
+    let detected = sampled - counts.undetected;
+    let languages = counts
+        .languages
+        .into_iter()
+        .map(|(lang, message_count)| LanguageShare {
+            code: lang.code().to_owned(),
+            name: lang.eng_name().to_owned(),
+            message_count,
+            share: message_count as f64 / detected as f64,
+        })
+        .collect();
+
+    Ok((
+        cache_header(600),
+        Json(LanguageStats {
+            sampled,
+            undetected: counts.undetected,
+            languages,
+        }),
+    ))
+}
+
+pub async fn automod_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let db = &app.db.profile(QueryClass::Stats);
+    let (totals, daily) = futures::try_join!(
+        read_automod_counts(db, &channel_id, params),
+        read_daily_automod_counts(db, &channel_id, params),
+    )?;
+
+    Ok((cache_header(600), Json(AutomodStats { totals, daily })))
+}
+
+pub async fn moderation_stats(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let db = &app.db.profile(QueryClass::Stats);
+    let (totals, daily) = futures::try_join!(
+        read_moderation_counts(db, &channel_id, params),
+        read_daily_moderation_counts(db, &channel_id, params),
+    )?;
+
+    Ok((cache_header(600), Json(ModerationStats { totals, daily })))
+}
+
+pub async fn user_streaks_by_name(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+) -> Result<impl IntoApiResponse> {
+    let user_id = app.get_user_id_by_name(&user).await?;
+    user_streaks(app, channel_id_type, channel, user_id.into()).await
+}
+
+pub async fn user_streaks_by_id(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+) -> Result<impl IntoApiResponse> {
+    user_streaks(app, channel_id_type, channel, user.into()).await
+}
+
+async fn user_streaks(
+    app: State<App>,
+    channel_id_type: ChannelIdType,
+    channel: String,
+    user_id: UserId,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let timezone = app.config.channel_timezone(channel_id.as_str());
+    let streaks = read_user_activity_streaks(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        &user_id,
+        timezone,
+    )
+    .await?;
+    let today = Utc::now().with_timezone(&timezone).date_naive();
+
+    Ok((no_cache_header(), Json(summarize_streaks(streaks, today))))
+}
+
+/// Presence is approximated by the chat activity, as viewers can't be tracked without being a bot
+pub async fn user_watchtime_by_name(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+    Query(params): Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let user_id = app.get_user_id_by_name(&user).await?;
+    user_watchtime(app, channel_id_type, channel, user_id.into(), params).await
+}
+
+pub async fn user_watchtime_by_id(
+    app: State<App>,
+    Path(UserLogPathParams {
+        channel_id_type,
+        channel,
+        user,
+    }): Path<UserLogPathParams>,
+    Query(params): Query<RangeParams>,
+) -> Result<impl IntoApiResponse> {
+    user_watchtime(app, channel_id_type, channel, user.into(), params).await
+}
+
+async fn user_watchtime(
+    app: State<App>,
+    channel_id_type: ChannelIdType,
+    channel: String,
+    user_id: UserId,
+    params: RangeParams,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, Some(&user_id))?;
+
+    let streams = read_streams(&app.db.profile(QueryClass::Stats), &channel_id, params).await?;
+    // Same as the stream stats, a stream may have ended any time until the next poll
+    let poll_interval = app.config.streams.interval as u32;
+    let windows: Vec<(u32, u32)> = streams
+        .iter()
+        .map(|stream| {
+            (
+                stream.started_at.max(params.from.timestamp() as u32),
+                (stream.ended_at + poll_interval).min(params.to.timestamp() as u32),
+            )
+        })
+        .collect();
+
+    let buckets = read_user_active_buckets(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        &user_id,
+        &windows,
+        WATCHTIME_BUCKET_SECONDS,
+    )
+    .await?;
+
+    let streams: Vec<StreamWatchtime> = streams
+        .into_iter()
+        .enumerate()
+        .map(|(i, stream)| StreamWatchtime {
+            stream: stream.into(),
+            active_seconds: buckets.get(&i).copied().unwrap_or_default() * WATCHTIME_BUCKET_SECONDS,
+        })
+        .collect();
+
+    Ok((
+        cache_header(600),
+        Json(UserWatchtime {
+            active_seconds: streams.iter().map(|stream| stream.active_seconds).sum(),
+            bucket_seconds: WATCHTIME_BUCKET_SECONDS,
+            streams,
+        }),
+    ))
+}
+
+fn summarize_streaks(streaks: Vec<ActivityStreak>, today: NaiveDate) -> UserStreaks {
+    let yesterday = today.pred_opt().unwrap_or(today);
+    let current = streaks
+        .iter()
+        .find(|streak| {
+            NaiveDate::parse_from_str(&streak.end, "%Y-%m-%d")
+                .is_ok_and(|end| end == today || end == yesterday)
+        })
+        .cloned();
+    // Streaks are ordered by their start, so the last one of the longest is the most recent
+    let longest = streaks.into_iter().max_by_key(|streak| streak.days);
+
+    UserStreaks { longest, current }
+}
+
+/// The stream range ends one poll interval after it was last seen live, as it may have ended any time until the next poll
+pub async fn stream_stats(
+    app: State<App>,
+    Path(StreamPath {
+        channel_id_type,
+        channel,
+        stream_id,
+    }): Path<StreamPath>,
+    Query(params): Query<StreamStatsParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let stream = read_stream(&app.db.profile(QueryClass::Stats), &channel_id, &stream_id)
+        .await?
+        .ok_or(Error::NotFound)?;
+    let from = DateTime::from_timestamp(stream.started_at.into(), 0).ok_or(Error::Internal)?;
+    let to = DateTime::from_timestamp(stream.ended_at.into(), 0).ok_or(Error::Internal)?
+        + chrono::Duration::seconds(app.config.streams.interval as i64);
+    let range = RangeParams { from, to };
+
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let db = &app.db.profile(QueryClass::Stats);
+    let (summary, chatters) = futures::try_join!(
+        read_channel_summary(
+            db,
+            &channel_id,
+            range,
+            excluded_users,
+            params.exact,
+            &app.flush_buffer
+        ),
+        read_new_and_returning_chatters(db, &channel_id, range, excluded_users),
+    )?;
+
+    let cache = if Utc::now() < to {
+        no_cache_header()
+    } else {
+        cache_header(3600)
+    };
+    Ok((
+        cache,
+        Json(StreamStats {
+            stream: stream.into(),
+            summary,
+            chatters,
+        }),
+    ))
+}
+
+pub async fn compare_ranges(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<CompareRangesParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let compared_range = RangeParams {
+        from: params.compare_from,
+        to: params.compare_to,
+    };
+
+    let (base, compared) = futures::try_join!(
+        range_snapshot(&app, &channel_id, params.base, excluded_users, params.exact),
+        range_snapshot(
+            &app,
+            &channel_id,
+            compared_range,
+            excluded_users,
+            params.exact
+        ),
+    )?;
+    let delta = RangeDelta::between(&base, &compared);
+
+    let to = params.base.to.max(params.compare_to);
+    let cache = if Utc::now() < to {
+        no_cache_header()
+    } else {
+        cache_header(36000)
+    };
+
+    Ok((
+        cache,
+        Json(RangeComparison {
+            base,
+            compared,
+            delta,
+        }),
+    ))
+}
+
+pub async fn range_summaries(
+    app: State<App>,
+    Path(LogsPathChannel {
+        channel_id_type,
+        channel,
+    }): Path<LogsPathChannel>,
+    Query(params): Query<MultiRangeParams>,
+) -> Result<impl IntoApiResponse> {
+    let channel_id = app.resolve_channel_id(channel_id_type, channel).await?;
+
+    app.check_opted_out(&channel_id, None)?;
+
+    let timezone = app.config.channel_timezone(channel_id.as_str());
+    let ranges = parse_ranges(&params.ranges, timezone)?;
+
+    let excluded_users = app.stats_excluded_users(params.include_bots);
+    let summaries = read_range_summaries(
+        &app.db.profile(QueryClass::Stats),
+        &channel_id,
+        &ranges,
+        excluded_users,
+        params.exact,
+        &app.flush_buffer,
+    )
+    .await?;
+
+    let ranges = ranges
+        .into_iter()
+        .zip(summaries)
+        .map(|(range, summary)| RangeSummary {
+            from: range.from,
+            to: range.to,
+            summary,
+        })
+        .collect();
+
+    Ok((cache_header(600), Json(RangeSummaries { ranges })))
+}
+
+/// Parses comma separated `start/end` ranges, where the end date of a range is included
+fn parse_ranges(value: &str, timezone: Tz) -> Result<Vec<RangeParams>> {
+    let ranges = value
+        .split(',')
+        .map(|range| {
+            let (from, to) = range.split_once('/').ok_or_else(|| {
+                Error::InvalidParam(format!("Invalid range `{range}`, expected `start/end`"))
+            })?;
+            let from = parse_range_bound(from, timezone, false)?;
+            let to = parse_range_bound(to, timezone, true)?;
+            if from >= to {
+                return Err(Error::InvalidParam(format!(
+                    "Range `{range}` ends before it starts"
+                )));
+            }
+            Ok(RangeParams { from, to })
+        })
+        .collect::<Result<Vec<_>>>()?;
+
+    if ranges.len() > MAX_RANGES {
+        return Err(Error::InvalidParam(format!(
+            "At most {MAX_RANGES} ranges can be requested at once"
+        )));
+    }
+    Ok(ranges)
+}
+
+fn parse_range_bound(value: &str, timezone: Tz, end: bool) -> Result<DateTime<Utc>> {
+    if let Ok(date) = NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let date = if end {
+            date.checked_add_days(Days::new(1))
+                .ok_or_else(|| Error::InvalidParam("Date out of range".to_owned()))?
+        } else {
+            date
+        };
+        return Ok(start_of_day(date, timezone));
+    }
+
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .map_err(|_| Error::InvalidParam(format!("Invalid date `{value}`")))
+}
+
+async fn range_snapshot(
+    app: &App,
+    channel_id: &ChannelId,
+    range: RangeParams,
+    excluded_users: &[String],
+    exact: bool,
+) -> Result<RangeSnapshot> {
+    let db = &app.db.profile(QueryClass::Stats);
+    let (summary, chatters, top_emotes) = futures::try_join!(
+        read_channel_summary(
+            db,
+            channel_id,
+            range,
+            excluded_users,
+            exact,
+            &app.flush_buffer
+        ),
+        read_new_and_returning_chatters(db, channel_id, range, excluded_users),
+        read_third_party_emote_counts(
+            db,
+            channel_id,
+            None,
+            range,
+            excluded_users,
+            COMPARISON_TOP_EMOTES
+        ),
+    )?;
+
+    Ok(RangeSnapshot {
+        from: range.from,
+        to: range.to,
+        summary,
+        chatters,
+        top_emotes,
+    })
+}
+
+/// Serves the files written by the public stats export, see [`crate::public_stats`]
+pub async fn public_channel_stats(
+    app: State<App>,
+    Path(PublicStatsPath { file }): Path<PublicStatsPath>,
+) -> Result<impl IntoApiResponse> {
+    let config = app
+        .config
+        .public_stats
+        .as_ref()
+        .ok_or(Error::EndpointDisabled)?;
+
+    // Only plain IDs are accepted, so the path can't point outside of the directory
+    let channel_id = file
+        .strip_suffix(".json")
+        .filter(|id| !id.is_empty() && id.chars().all(|c| c.is_ascii_digit()))
+        .ok_or(Error::NotFound)?;
+    if app.has_opted_out(channel_id) {
+        return Err(Error::NotFound);
+    }
+
+    let path = std::path::Path::new(&config.dir).join(format!("{channel_id}.json"));
+    let body = match tokio::fs::read(&path).await {
+        Ok(body) => body,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Err(Error::NotFound),
+        Err(err) => return Err(err.into()),
+    };
+
+    Ok((
+        cache_header(config.interval),
+        [(header::CONTENT_TYPE, "application/json")],
+        body,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_ranges, summarize_streaks};
+    use crate::web::schema::{ActivityStreak, UserStreaks};
+    use chrono::{NaiveDate, TimeZone, Utc};
+    use pretty_assertions::assert_eq;
+
+    fn streak(days: u64, start: &str, end: &str) -> ActivityStreak {
+        ActivityStreak {
+            days,
+            start: start.to_owned(),
+            end: end.to_owned(),
+        }
+    }
+
+    #[test]
+    fn summarizes_streaks() {
+        let streaks = vec![
+            streak(3, "2024-01-01", "2024-01-03"),
+            streak(1, "2024-01-10", "2024-01-10"),
+            streak(3, "2024-01-20", "2024-01-22"),
+            streak(2, "2024-01-30", "2024-01-31"),
+        ];
+        let today = NaiveDate::from_ymd_opt(2024, 2, 1).unwrap();
+
+        assert_eq!(
+            summarize_streaks(streaks, today),
+            UserStreaks {
+                longest: Some(streak(3, "2024-01-20", "2024-01-22")),
+                current: Some(streak(2, "2024-01-30", "2024-01-31")),
+            }
+        );
+        assert_eq!(
+            summarize_streaks(vec![streak(1, "2024-01-10", "2024-01-10")], today).current,
+            None
+        );
+    }
+
+    #[test]
+    fn parses_ranges() {
+        let ranges = parse_ranges(
+            "2024-01-01/2024-01-07,2024-02-01T12:00:00Z/2024-02-01T13:00:00Z",
+            chrono_tz::Europe::Berlin,
+        )
+        .unwrap();
+
+        assert_eq!(2, ranges.len());
+        assert_eq!(
+            Utc.with_ymd_and_hms(2023, 12, 31, 23, 0, 0).unwrap(),
+            ranges[0].from
+        );
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 1, 7, 23, 0, 0).unwrap(),
+            ranges[0].to
+        );
+        assert_eq!(
+            Utc.with_ymd_and_hms(2024, 2, 1, 12, 0, 0).unwrap(),
+            ranges[1].from
+        );
+
+        assert!(parse_ranges("2024-01-07/2024-01-01", chrono_tz::UTC).is_err());
+        assert!(parse_ranges("2024-01-01", chrono_tz::UTC).is_err());
+        assert!(parse_ranges("", chrono_tz::UTC).is_err());
+    }
+}
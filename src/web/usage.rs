@@ -0,0 +1,175 @@
+use super::api_keys::request_api_key;
+use crate::{
+    app::App,
+    db::{usage::UsageRecord, writer::create_writer},
+    ShutdownRx,
+};
+use axum::{
+    body::Body,
+    extract::{ConnectInfo, MatchedPath, Request, State},
+    middleware::Next,
+    response::Response,
+};
+use chrono::Utc;
+use futures::TryStreamExt;
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
+use tokio::{sync::mpsc::Sender, task::JoinHandle};
+use tracing::warn;
+
+/// Set by responses which stream log messages, so the amount of returned rows can be recorded
+#[derive(Clone, Default)]
+pub struct StreamedRows(pub Arc<AtomicU64>);
+
+#[derive(Clone)]
+pub struct UsageTracker {
+    tx: Sender<UsageRecord>,
+}
+
+impl UsageTracker {
+    pub async fn new(app: &App, shutdown_rx: ShutdownRx) -> anyhow::Result<(Self, JoinHandle<()>)> {
+        let (tx, _, handle) = create_writer(
+            app.db.primary().into_owned(),
+            shutdown_rx,
+            app.config.clickhouse_flush_interval,
+            app.config.writer_backlog_limit,
+        )
+        .await?;
+
+        Ok((Self { tx }, handle))
+    }
+}
+
+pub async fn track_usage(
+    State((app, tracker)): State<(App, Option<UsageTracker>)>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let Some(tracker) = tracker else {
+        return next.run(request).await;
+    };
+
+    let route = request
+        .extensions()
+        .get::<MatchedPath>()
+        .map(|path| path.as_str().to_owned())
+        .unwrap_or_default();
+    let api_key = api_key_label(&app, &request).to_owned();
+    let ip = client_ip(&app, &request);
+
+    let response = next.run(request).await;
+
+    let rows = response
+        .extensions()
+        .get::<StreamedRows>()
+        .map(|rows| rows.0.clone());
+
+    let guard = UsageGuard {
+        tracker,
+        record: Some(UsageRecord {
+            timestamp: Utc::now().timestamp() as u32,
+            route,
+            api_key,
+            ip,
+            status: response.status().as_u16(),
+            rows: 0,
+            bytes: 0,
+        }),
+        rows,
+    };
+
+    let (parts, body) = response.into_parts();
+    let body = body.into_data_stream().map_ok(move |chunk| {
+        if let Some(record) = &mut guard.record {
+            record.bytes += chunk.len() as u64;
+        }
+        chunk
+    });
+
+    Response::from_parts(parts, Body::from_stream(body))
+}
+
+fn api_key_label<'a>(app: &'a App, request: &Request) -> &'a str {
+    match request_api_key(request) {
+        Some(key) => app.config.api_key_owner(key).unwrap_or("invalid"),
+        None => "",
+    }
+}
+
+pub(super) fn client_ip(app: &App, request: &Request) -> String {
+    if app.config.usage.trust_forwarded_for {
+        let forwarded_ip = request
+            .headers()
+            .get("X-Forwarded-For")
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| forwarded_client(value, &app.config.usage.trusted_proxies));
+
+        if let Some(ip) = forwarded_ip {
+            return ip.to_owned();
+        }
+    }
+
+    request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|info| info.0.ip().to_string())
+        .unwrap_or_default()
+}
+
+/// Clients can send their own `X-Forwarded-For` header which the proxies append to, so only the rightmost
+/// address which was not added by a trusted proxy can be relied on
+fn forwarded_client<'a>(header: &'a str, trusted_proxies: &[IpAddr]) -> Option<&'a str> {
+    header
+        .split(',')
+        .map(str::trim)
+        .rev()
+        .find(|ip| {
+            ip.parse::<IpAddr>()
+                .map_or(true, |ip| !trusted_proxies.contains(&ip))
+        })
+        .filter(|ip| !ip.is_empty())
+}
+
+/// Sends the record once the response body has been fully sent or dropped
+struct UsageGuard {
+    tracker: UsageTracker,
+    record: Option<UsageRecord>,
+    rows: Option<Arc<AtomicU64>>,
+}
+
+impl Drop for UsageGuard {
+    fn drop(&mut self) {
+        if let Some(mut record) = self.record.take() {
+            if let Some(rows) = &self.rows {
+                record.rows = rows.load(Ordering::Relaxed);
+            }
+
+            if self.tracker.tx.try_send(record).is_err() {
+                warn!("API usage buffer is full, dropping record");
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::forwarded_client;
+    use pretty_assertions::assert_eq;
+
+    #[test]
+    fn ignores_spoofed_forwarded_addresses() {
+        let header = "1.1.1.1, 2.2.2.2, 10.0.0.1";
+
+        assert_eq!(forwarded_client(header, &[]), Some("10.0.0.1"));
+        assert_eq!(
+            forwarded_client(header, &["10.0.0.1".parse().unwrap()]),
+            Some("2.2.2.2")
+        );
+        assert_eq!(forwarded_client("", &[]), None);
+    }
+}